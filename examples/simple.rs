@@ -8,7 +8,6 @@ use lwnsim_api_rs::*;
 
 use std::{thread, time};
 use std::sync::atomic::{AtomicBool, Ordering};
-use ctrlc;
 
 // log
 use env_logger::{Builder, Env};
@@ -19,8 +18,6 @@ static DEV_EUI: &str = "359ac7cd01bc8aff";
 static APP_KEY: &str = "f1c4081b61e9bee79bef58b5347e78a5"; // set as device info in LWNSim
 static JOIN_EUI: &str = "0000000000000000"; // set as device info in LWNSim
 
-static CTRLC_SEEN : AtomicBool = AtomicBool::new(false);
-
 // log init
 fn configure_log() {
     let env = Env::default()
@@ -38,23 +35,20 @@ fn main() {
     // The `Env` lets us tweak what the environment
     // variables to read are and what the default
     // value is if they're missing
-    ctrlc::set_handler( || {
-        info!("[EXAMPLE] received Ctrl+C!");
-        CTRLC_SEEN.store(true,Ordering::Relaxed);
-    })
-    .expect("Error setting Ctrl-C handler");
+    // Ctrl+C stops the loop, the device is unlinked when the session is closed
+    let stop = handle_signals(time::Duration::from_secs(10));
 
     let dur_1s = time::Duration::from_secs(1);
 
     configure_log();
 // creates lazy static LWNSIM and connects to LWN simulator
-    LWNSIM.lock().unwrap().connect(URL, DEV_EUI); // panic if socketio client connection fails
+    let session = LwnsimBuilder::new(URL, DEV_EUI).session().expect("Connection failed");
 
     thread::sleep(dur_1s);
   
     //   lora=LoRa.LoRa( mode=LoRa.LORAWAN, region=LoRa.EU868, log_enable=True)
     info!("[EXAMPLE] link to dev {:?}", DEV_EUI);
-    LORA.lock().unwrap().activate().map_err(|e| {error!("Could not activate device (error : {:?})",e); /* LWNSIM.lock().unwrap().disconnect() */; return 1;});
+    LORA.lock().unwrap().activate().map_err(|e| {error!("Could not activate device (error : {:?})",e); /* LWNSIM.lock().unwrap().disconnect() */; 1});
 
 
 
@@ -72,8 +66,8 @@ fn main() {
     LORA.lock().unwrap().join(
         OTAA,
         (JOIN_EUI.to_string(), APP_KEY.to_string()),
-        Some(0),  // not used 
-        Some(0), // not used LWNSim manages DR depending on device info
+        Some(0),  // non blocking, see has_joined()
+        Some(0), // DR0, sent to LWNSim as set-dr-txpower
     );
 
     while !LORA.lock().unwrap().has_joined() {
@@ -85,7 +79,7 @@ fn main() {
     s.setsockopt(SOL_LORA, SO_DR, 5);
     s.setsockopt(SOL_LORA, SO_CONFIRMED, 1);
 
-    while true {
+    while !stop.load(Ordering::Relaxed) {
         s.settimeout(Some(3));
         s.setblocking(true);
 
@@ -117,13 +111,12 @@ fn main() {
             }
         };
 
-        if CTRLC_SEEN.load(Ordering::Relaxed) {  
-            LWNSIM.lock().unwrap().disconnect();
-        }
-
     }
 
-    LWNSIM.lock().unwrap().disconnect();
+    info!("[EXAMPLE] stopping, unlink device");
+    if let Err(e) = session.close() {
+        error!("[EXAMPLE] unlink error : {:?}", e);
+    }
 
     
 }
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use super::lwnsim_cmd::DevAckCmd;
+use log::{debug, trace, warn};
+
+lazy_static! {
+    pub static ref PENDING_ACKS: Mutex<AckTracker> = Mutex::new(AckTracker::new());
+}
+
+#[derive(Debug)]
+struct PendingCmd {
+    cmd: &'static str,
+    sent_at: Instant,
+    // the command is considered lost and forgotten once this age is reached
+    max_age: Duration,
+    // set when the sender waits for the ack
+    waiter: Option<Sender<()>>,
+}
+
+impl PendingCmd {
+    fn is_lost(&self) -> bool {
+        return self.sent_at.elapsed() >= self.max_age;
+    }
+}
+
+/// commands sent with Ack=true and not yet acknowledged by an "ack-cmd" event
+#[derive(Debug)]
+pub struct AckTracker {
+    next_id: u64,
+    pending: HashMap<u64, PendingCmd>,
+}
+
+impl AckTracker {
+    pub fn new() -> AckTracker {
+        AckTracker {
+            next_id: 1,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// registers a command about to be sent and returns its id, its ack is expected within max_age
+    pub fn register(&mut self, cmd: &'static str, max_age: Duration) -> u64 {
+        return self.insert(cmd, max_age, None);
+    }
+
+    /// registers a command about to be sent, the returned channel receives the ack
+    pub fn register_waiting(&mut self, cmd: &'static str, max_age: Duration) -> (u64, Receiver<()>) {
+        let (tx, rx) = channel();
+        let cmd_id = self.insert(cmd, max_age, Some(tx));
+        return (cmd_id, rx);
+    }
+
+    /// forgets a command whose ack is no longer expected (e.g. after an ack timeout)
+    pub fn cancel(&mut self, cmd_id: u64) {
+        self.pending.remove(&cmd_id);
+    }
+
+    /// number of commands whose ack is still expected
+    pub fn outstanding(&self) -> usize {
+        return self.pending.values().filter(|p| !p.is_lost()).count();
+    }
+
+    /// matches an ack with an outstanding command, by id if the simulator echoes a known one,
+    /// by command name (oldest first) otherwise
+    pub fn handle_ack(&mut self, ack: &DevAckCmd) {
+        self.prune();
+        let cmd_id = match ack.cmd_id {
+            Some(id) if self.pending.contains_key(&id) => Some(id),
+            _ => self
+                .pending
+                .iter()
+                .filter(|(_, p)| p.cmd == ack.cmd)
+                .min_by_key(|(id, _)| **id)
+                .map(|(id, _)| *id),
+        };
+        match cmd_id.and_then(|id| self.pending.remove(&id).map(|p| (id, p))) {
+            Some((id, pending)) => {
+                debug!(
+                    "[ACK_TRACKER][ack][{}]id= {} after {:?}",
+                    pending.cmd,
+                    id,
+                    pending.sent_at.elapsed()
+                );
+                if let Some(waiter) = pending.waiter {
+                    // the waiter may have timed out in the meantime
+                    let _ = waiter.send(());
+                }
+            }
+            None => warn!("[ACK_TRACKER][unmatched ack]{:?}", ack),
+        }
+    }
+
+    // forgets the commands whose ack did not arrive in time
+    fn prune(&mut self) {
+        self.pending.retain(|id, p| {
+            let lost = p.is_lost();
+            if lost {
+                warn!("[ACK_TRACKER][no ack][{}]id= {}", p.cmd, id);
+            }
+            !lost
+        });
+    }
+
+    fn insert(&mut self, cmd: &'static str, max_age: Duration, waiter: Option<Sender<()>>) -> u64 {
+        self.prune();
+        let cmd_id = self.next_id;
+        self.next_id += 1;
+        trace!("[ACK_TRACKER][register][{}]id= {}", cmd, cmd_id);
+        self.pending.insert(
+            cmd_id,
+            PendingCmd {
+                cmd,
+                sent_at: Instant::now(),
+                max_age,
+                waiter,
+            },
+        );
+        return cmd_id;
+    }
+}
+
+impl Default for AckTracker {
+    fn default() -> Self {
+        AckTracker::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    static MAX_AGE: Duration = Duration::from_secs(60);
+
+    fn ack(cmd: &str, cmd_id: Option<u64>) -> DevAckCmd {
+        DevAckCmd {
+            cmd: cmd.to_string(),
+            args: Value::Null,
+            cmd_id,
+        }
+    }
+
+    #[test]
+    fn ack_matched_by_id() {
+        let mut tracker = AckTracker::new();
+        let first = tracker.register("send-uplink", MAX_AGE);
+        let (second, rx) = tracker.register_waiting("send-uplink", MAX_AGE);
+        tracker.handle_ack(&ack("send-uplink", Some(second)));
+        assert!(rx.try_recv().is_ok());
+        assert_eq!(tracker.outstanding(), 1);
+        assert!(tracker.pending.contains_key(&first));
+    }
+
+    #[test]
+    fn ack_matched_by_name_oldest_first() {
+        let mut tracker = AckTracker::new();
+        let join = tracker.register("join-request", MAX_AGE);
+        let first = tracker.register("send-uplink", MAX_AGE);
+        let second = tracker.register("send-uplink", MAX_AGE);
+        tracker.handle_ack(&ack("send-uplink", None));
+        assert!(!tracker.pending.contains_key(&first));
+        // an id the tracker does not know (e.g. cancelled) falls back to the name
+        tracker.handle_ack(&ack("send-uplink", Some(second + 100)));
+        assert!(!tracker.pending.contains_key(&second));
+        assert_eq!(tracker.outstanding(), 1);
+        assert!(tracker.pending.contains_key(&join));
+        // no command with that name
+        tracker.handle_ack(&ack("send-uplink", None));
+        assert_eq!(tracker.outstanding(), 1);
+    }
+
+    #[test]
+    fn lost_cmds_are_pruned() {
+        let mut tracker = AckTracker::new();
+        let lost = tracker.register("send-uplink", Duration::ZERO);
+        assert_eq!(tracker.outstanding(), 0);
+        // a late ack does not match the lost command
+        tracker.handle_ack(&ack("send-uplink", Some(lost)));
+        assert!(tracker.pending.is_empty());
+
+        let cmd_id = tracker.register("join-request", MAX_AGE);
+        tracker.cancel(cmd_id);
+        assert_eq!(tracker.outstanding(), 0);
+    }
+}
@@ -0,0 +1,70 @@
+use serde_derive::*;
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use super::lora_events::{LoraEvents, LORA_EVENTS};
+use log::debug;
+
+lazy_static! {
+    pub static ref ADR_STATE: Mutex<AdrState> = Mutex::new(AdrState::new());
+}
+
+/// ADR setting, data rate and TX power of the device
+/// values are None until set by the application or reported by the simulator
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct AdrState {
+    #[serde(rename = "ADR")]
+    pub adr: bool,
+    #[serde(rename = "DataRate")]
+    pub data_rate: Option<u8>,
+    #[serde(rename = "TXPower")]
+    pub tx_power: Option<u8>,
+}
+
+impl AdrState {
+    pub fn new() -> AdrState {
+        AdrState {
+            adr: false,
+            data_rate: None,
+            tx_power: None,
+        }
+    }
+
+    /// handles an "adr-change" event sent by the simulator when ADR (or a LinkADRReq) changed the device settings
+    pub fn handle_adr_change(&mut self, new_state: AdrState) {
+        debug!("[ADR_STATE]{:?} -> {:?}", self, new_state);
+        *self = new_state;
+        LORA_EVENTS.lock().unwrap().handle_lora_event(LoraEvents::ADR_CHANGE_EVENT);
+    }
+}
+
+impl Default for AdrState {
+    fn default() -> Self {
+        AdrState::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lora_events::subscribe_lora_events;
+
+    #[test]
+    fn adr_change_event_parsed() {
+        let state: AdrState = serde_json::from_str(r#"{"ADR": true, "DataRate": 3, "TXPower": 2}"#).unwrap();
+        assert_eq!(state, AdrState { adr: true, data_rate: Some(3), tx_power: Some(2) });
+        let state: AdrState = serde_json::from_str(r#"{"ADR": false, "DataRate": null, "TXPower": null}"#).unwrap();
+        assert_eq!(state, AdrState::new());
+    }
+
+    #[test]
+    fn adr_change_applied() {
+        let events = subscribe_lora_events();
+        let mut state = AdrState::new();
+        let new_state = AdrState { adr: true, data_rate: Some(5), tx_power: Some(1) };
+        state.handle_adr_change(new_state);
+        assert_eq!(state, new_state);
+        assert!(events.try_iter().any(|e| e == LoraEvents::ADR_CHANGE_EVENT));
+    }
+}
@@ -0,0 +1,326 @@
+//! Load generation: one worker process per device (the client drives a single device),
+//! the workers report their LoadEvents as json lines aggregated by the parent.
+//!
+//! ```text
+//! lwnsim-cli loadgen --mock --devices 20 --interval 10 --jitter 2 --payload-size 8-32 --confirmed-ratio 0.2
+//! ```
+
+use lwnsim_api_rs::*;
+
+use clap::Args;
+use serde_json::{json, Value};
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::{Cli, POLL_PERIOD};
+
+// DevEUI of the first device when the mock is used without device profile
+static MOCK_DEV_EUI: &str = "0000000000000001";
+
+/// traffic of each device
+#[derive(Args, Debug, Clone)]
+pub struct LoadArgs {
+    /// seconds between two uplinks of a device
+    #[arg(long, default_value_t = 60.0)]
+    interval: f64,
+    /// the interval is drawn in interval +/- jitter seconds
+    #[arg(long, default_value_t = 0.0)]
+    jitter: f64,
+    /// payload size in bytes: fixed ("12"), uniform ("8-32") or one of ("4,12,51")
+    #[arg(long, default_value = "12", value_parser = parse_payload_size)]
+    payload_size: PayloadSize,
+    /// share of ConfirmedDataUp uplinks (0..=1)
+    #[arg(long, default_value_t = 0.0, value_parser = parse_ratio)]
+    confirmed_ratio: f64,
+    /// FPort (1..=223)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=223))]
+    port: Option<u8>,
+    /// join attempts after the first one
+    #[arg(long, default_value_t = 2)]
+    join_retries: u32,
+    /// seconds to wait for the TX event of an uplink
+    #[arg(long, default_value_t = 10)]
+    tx_timeout: u64,
+    /// seed of the payloads and intervals (the device index is added)
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+/// behavior of the mock simulator
+#[derive(Args, Debug, Clone)]
+pub struct MockArgs {
+    /// ms between a join request and the JoinAccept
+    #[arg(long, default_value_t = 500)]
+    join_delay_ms: u64,
+    /// share of the join requests accepted (0..=1)
+    #[arg(long, default_value_t = 1.0, value_parser = parse_ratio)]
+    join_accept_ratio: f64,
+    /// ms between an uplink and its TX event
+    #[arg(long, default_value_t = 200)]
+    tx_delay_ms: u64,
+    /// share of the uplinks failing (0..=1)
+    #[arg(long, default_value_t = 0.0, value_parser = parse_ratio)]
+    tx_failed_ratio: f64,
+    /// share of the uplinks answered by a downlink echoing them (0..=1)
+    #[arg(long, default_value_t = 0.0, value_parser = parse_ratio)]
+    downlink_ratio: f64,
+    /// ms between the TX event and the downlink
+    #[arg(long, default_value_t = 1000)]
+    rx_delay_ms: u64,
+    #[arg(long, default_value_t = 0)]
+    mock_seed: u64,
+}
+
+#[derive(Args, Debug)]
+pub struct LoadgenArgs {
+    /// number of simulated devices, the configuration profiles are used first,
+    /// then DevEUIs following the one of the first profile
+    #[arg(long, default_value_t = 1)]
+    devices: usize,
+    #[command(flatten)]
+    load: LoadArgs,
+    /// stop after this number of seconds (Ctrl-C otherwise)
+    #[arg(long)]
+    duration: Option<u64>,
+    /// seconds between two statistics lines
+    #[arg(long, default_value_t = 10)]
+    report_interval: u64,
+    /// run against an in-process mock simulator instead of the configured one
+    #[arg(long)]
+    mock: bool,
+    #[command(flatten)]
+    mock_args: MockArgs,
+}
+
+fn parse_payload_size(s: &str) -> Result<PayloadSize, String> {
+    return s.parse().map_err(|e: LwnsimError| e.to_string());
+}
+
+fn parse_ratio(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(r) if (0.0..=1.0).contains(&r) => Ok(r),
+        _ => Err(format!("{} is not in 0..=1", s)),
+    }
+}
+
+impl LoadArgs {
+    fn profile(&self) -> LoadProfile {
+        return LoadProfile {
+            interval: Duration::from_secs_f64(self.interval),
+            jitter: Duration::from_secs_f64(self.jitter),
+            payload_size: self.payload_size.clone(),
+            confirmed_ratio: self.confirmed_ratio,
+            fport: self.port,
+            join_retries: self.join_retries,
+            tx_timeout: Duration::from_secs(self.tx_timeout),
+            seed: self.seed,
+            ..LoadProfile::default()
+        };
+    }
+
+    // worker command line of the device index
+    fn to_args(&self, index: usize) -> Vec<String> {
+        let mut args = vec![
+            "--interval".to_string(),
+            self.interval.to_string(),
+            "--jitter".to_string(),
+            self.jitter.to_string(),
+            "--payload-size".to_string(),
+            payload_size_arg(&self.payload_size),
+            "--confirmed-ratio".to_string(),
+            self.confirmed_ratio.to_string(),
+            "--join-retries".to_string(),
+            self.join_retries.to_string(),
+            "--tx-timeout".to_string(),
+            self.tx_timeout.to_string(),
+        ];
+        if let Some(port) = self.port {
+            args.extend(["--port".to_string(), port.to_string()]);
+        }
+        if let Some(seed) = self.seed {
+            args.extend(["--seed".to_string(), seed.wrapping_add(index as u64).to_string()]);
+        }
+        return args;
+    }
+}
+
+fn payload_size_arg(size: &PayloadSize) -> String {
+    return match size {
+        PayloadSize::Fixed(n) => n.to_string(),
+        PayloadSize::Uniform(min, max) => format!("{}-{}", min, max),
+        PayloadSize::Choice(sizes) => sizes.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(","),
+    };
+}
+
+impl MockArgs {
+    pub fn options(&self) -> MockOptions {
+        return MockOptions {
+            join_delay: Duration::from_millis(self.join_delay_ms),
+            join_accept_ratio: self.join_accept_ratio,
+            tx_delay: Duration::from_millis(self.tx_delay_ms),
+            tx_failed_ratio: self.tx_failed_ratio,
+            downlink_ratio: self.downlink_ratio,
+            rx_delay: Duration::from_millis(self.rx_delay_ms),
+            seed: self.mock_seed,
+            ..MockOptions::default()
+        };
+    }
+}
+
+/// runs the device of the worker process until its stdin is closed or Ctrl-C
+pub fn run_worker(cli: &Cli, profile: &DeviceProfile, args: &LoadArgs) -> Result<(), LwnsimError> {
+    let stop = handle_signals(DEFAULT_SIGNAL_DEADLINE);
+    thread::spawn(|| {
+        // the parent closes stdin to stop the worker
+        let _ = std::io::stdin().read_to_end(&mut Vec::new());
+        request_shutdown();
+    });
+
+    let load = LoadProfile {
+        join_timeout: Duration::from_secs(cli.join_timeout),
+        ..args.profile()
+    };
+    let mut stdout = std::io::stdout();
+    return run_device(profile, &load, stop, &mut |ev| {
+        let _ = writeln!(stdout, "{}", json!(ev));
+        let _ = stdout.flush();
+    });
+}
+
+/// spawns the workers and prints the statistics until the duration is elapsed or Ctrl-C
+pub fn run(cli: &Cli, config: &LwnsimConfig, args: &LoadgenArgs) -> Result<Value, LwnsimError> {
+    let mock = if args.mock {
+        Some(MockSimulator::start("127.0.0.1:0", args.mock_args.options())?)
+    } else {
+        None
+    };
+    let url = mock.as_ref().map_or(config.simulator.url.clone(), |m| m.url());
+    let devices = device_euis(config, args)?;
+
+    let stop = handle_signals(DEFAULT_SIGNAL_DEADLINE);
+
+    let (tx, rx) = mpsc::channel();
+    let mut workers = Vec::new();
+    for (i, (name, dev_eui)) in devices.iter().enumerate() {
+        let mut worker = spawn_worker(cli, &url, name.as_deref(), dev_eui, &args.load.to_args(i))?;
+        let stdout = worker.stdout.take().expect("worker stdout");
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(|l| l.ok()) {
+                // the last line is the worker result
+                if let Ok(ev) = serde_json::from_str::<LoadEvent>(&line) {
+                    if tx.send(ev).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        workers.push(worker);
+    }
+    drop(tx);
+
+    let mut stats = LoadStats::new();
+    let start = Instant::now();
+    let report_interval = Duration::from_secs(args.report_interval.max(1));
+    let mut next_report = start + report_interval;
+    let duration = args.duration.map(Duration::from_secs);
+    while !stop.load(Ordering::Relaxed) && duration.is_none_or(|d| start.elapsed() < d) {
+        match rx.recv_timeout(POLL_PERIOD) {
+            Ok(ev) => stats.record(&ev),
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            // all the workers are done
+            Err(mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+        if Instant::now() >= next_report {
+            println!("{}", json!({ "time": chrono::Utc::now(), "report": stats.report() }));
+            next_report += report_interval;
+        }
+    }
+
+    // the workers unlink their device when their stdin is closed
+    for worker in workers.iter_mut() {
+        drop(worker.stdin.take());
+    }
+    let mut failed_workers = 0;
+    for mut worker in workers {
+        if !worker.wait().is_ok_and(|s| s.success()) {
+            failed_workers += 1;
+        }
+    }
+    for ev in rx.iter() {
+        stats.record(&ev);
+    }
+    if let Some(mock) = mock {
+        mock.stop();
+    }
+    return Ok(json!({
+        "url": url,
+        "devices": devices.len(),
+        "failed_workers": failed_workers,
+        "report": stats.report(),
+    }));
+}
+
+// profile name (if any) and DevEUI of the devices
+fn device_euis(config: &LwnsimConfig, args: &LoadgenArgs) -> Result<Vec<(Option<String>, String)>, LwnsimError> {
+    let mut devices: Vec<_> = config
+        .devices
+        .iter()
+        .take(args.devices)
+        .map(|d| (Some(d.name.clone()), d.dev_eui.clone()))
+        .collect();
+    let first = match config.devices.first() {
+        Some(d) => d.dev_eui.clone(),
+        None if args.mock => {
+            devices.push((None, MOCK_DEV_EUI.to_string()));
+            MOCK_DEV_EUI.to_string()
+        }
+        None => return Err(LwnsimError::ConfigError("no device profile defined".to_string())),
+    };
+    let mut next = u64::from_str_radix(&first, 16)
+        .map_err(|_| LwnsimError::ConfigError(format!("invalid dev_eui {}", first)))?;
+    while devices.len() < args.devices {
+        next = next.wrapping_add(1);
+        let dev_eui = format!("{:016x}", next);
+        if !devices.iter().any(|(_, d)| d.eq_ignore_ascii_case(&dev_eui)) {
+            devices.push((None, dev_eui));
+        }
+    }
+    return Ok(devices);
+}
+
+fn spawn_worker(cli: &Cli, url: &str, profile: Option<&str>, dev_eui: &str, load_args: &[String]) -> Result<Child, LwnsimError> {
+    let mut cmd = Command::new(std::env::current_exe()?);
+    if let Some(config) = &cli.config {
+        cmd.arg("--config").arg(config);
+    }
+    // devices without profile use the first one
+    if let Some(name) = profile.or(cli.device.as_deref()) {
+        cmd.args(["--device", name]);
+    }
+    cmd.args(["--url", url, "--dev-eui", dev_eui])
+        .args(["--join-timeout", &cli.join_timeout.to_string()])
+        .arg("loadgen-worker")
+        .args(load_args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped());
+    return Ok(cmd.spawn()?);
+}
+
+/// runs a mock simulator until Ctrl-C
+pub fn run_mock(listen: &str, args: &MockArgs) -> Result<Value, LwnsimError> {
+    let stop = handle_signals(DEFAULT_SIGNAL_DEADLINE);
+
+    let mock = MockSimulator::start(listen, args.options())?;
+    println!("{}", json!({ "url": mock.url() }));
+    while !stop.load(Ordering::Relaxed) {
+        thread::sleep(POLL_PERIOD);
+    }
+    mock.stop();
+    return Ok(json!({ "url": mock.url() }));
+}
@@ -0,0 +1,528 @@
+//! Command line tool to drive a simulated device: link, join, send one frame, read a downlink...
+//!
+//! Each command prints a json object on stdout (json lines for watch-events), e.g.
+//! `lwnsim-cli --config examples/lwnsim.toml send --text Hello --confirmed --port 2`
+
+#![allow(clippy::needless_return)]
+#![allow(clippy::result_large_err)]
+
+mod loadgen;
+mod repl;
+
+use lwnsim_api_rs::*;
+
+use base64::Engine;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// polling period when waiting for the simulator
+static POLL_PERIOD: Duration = Duration::from_millis(200);
+
+#[derive(Parser, Debug)]
+#[command(name = "lwnsim-cli", version, about = "Drive a LWN-Simulator device from the command line")]
+struct Cli {
+    /// TOML configuration file (LWNSIM_* environment variables are also read)
+    #[arg(long, short)]
+    config: Option<PathBuf>,
+    /// device profile of the configuration
+    #[arg(long, short)]
+    device: Option<String>,
+    /// simulator url, overrides the configuration
+    #[arg(long)]
+    url: Option<String>,
+    /// device EUI, overrides the device profile
+    #[arg(long)]
+    dev_eui: Option<String>,
+    /// seconds to wait for the JoinAccept
+    #[arg(long, default_value_t = 30)]
+    join_timeout: u64,
+    /// do not link and join, the device is assumed already joined in the simulator
+    #[arg(long)]
+    assume_joined: bool,
+    /// record the traffic with the simulator to this JSON Lines file
+    #[arg(long, conflicts_with = "replay")]
+    record: Option<PathBuf>,
+    /// replay a recording instead of connecting to the simulator
+    #[arg(long)]
+    replay: Option<PathBuf>,
+    /// replay speed factor of the recorded delays ("inf" for no delay)
+    #[arg(long, default_value_t = 1.0)]
+    replay_speed: f64,
+    /// serve the Prometheus metrics of the client on this address (GET /metrics) while the command runs
+    /// (loadgen devices run in worker processes, their metrics are not served)
+    #[arg(long)]
+    metrics_listen: Option<String>,
+    #[command(subcommand)]
+    cmd: Cmd,
+}
+
+#[derive(Subcommand, Debug)]
+enum Cmd {
+    /// link the device
+    Link,
+    /// unlink the device
+    Unlink,
+    /// link the device and join the network
+    Join,
+    /// send an uplink (the device is linked and joined first)
+    Send(SendArgs),
+    /// wait for a downlink (the device is linked and joined first)
+    Recv {
+        /// seconds to wait for a downlink
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+        #[arg(long, default_value_t = 256)]
+        buffersize: usize,
+    },
+    /// print the lora events, MAC commands and simulator logs as they arrive (until Ctrl-C)
+    WatchEvents {
+        /// join the network before watching
+        #[arg(long)]
+        join: bool,
+        /// stop after this number of seconds
+        #[arg(long)]
+        duration: Option<u64>,
+        /// also print the on-air uplink and downlink frames
+        #[arg(long)]
+        raw_frames: bool,
+    },
+    /// print the client view of the device
+    Status,
+    /// interactive session with Pycom-like commands (lora.join(...), s.send(...), ...)
+    Repl {
+        /// command history file (default ~/.lwnsim_history)
+        #[arg(long)]
+        history: Option<PathBuf>,
+    },
+    /// send the readings of the device profile sensors periodically, printed as json lines (until Ctrl-C)
+    Sensors {
+        /// seconds between two readings
+        #[arg(long, default_value_t = 60)]
+        interval: u64,
+        /// stop after this number of readings
+        #[arg(long)]
+        count: Option<u64>,
+        /// payload encoding of the readings
+        #[arg(long, value_enum, default_value_t = SensorCodec::Lpp)]
+        codec: SensorCodec,
+    },
+    /// run the scenarios of a file and print the report (the file is also the default configuration)
+    Scenario {
+        file: PathBuf,
+        /// also write a JUnit XML report to this file
+        #[arg(long)]
+        junit: Option<PathBuf>,
+    },
+    /// generate traffic from several devices and print statistics as it runs (until Ctrl-C)
+    Loadgen(loadgen::LoadgenArgs),
+    /// run a mock simulator for offline tests (until Ctrl-C)
+    Mock {
+        /// listening address
+        #[arg(long, default_value = "127.0.0.1:8000")]
+        listen: String,
+        #[command(flatten)]
+        args: loadgen::MockArgs,
+    },
+    /// device process of loadgen, prints its load events as json lines
+    #[command(hide = true)]
+    LoadgenWorker(loadgen::LoadArgs),
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum SensorCodec {
+    Lpp,
+    Json,
+    Cbor,
+}
+
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("payload").required(true).multiple(false)))]
+struct SendArgs {
+    /// text payload
+    #[arg(long, group = "payload")]
+    text: Option<String>,
+    /// hex encoded binary payload
+    #[arg(long, group = "payload")]
+    hex: Option<String>,
+    /// base64 encoded binary payload
+    #[arg(long, group = "payload")]
+    base64: Option<String>,
+    /// send a ConfirmedDataUp
+    #[arg(long)]
+    confirmed: bool,
+    /// FPort (1..=223)
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=223))]
+    port: Option<u8>,
+    /// seconds to wait for the TX event
+    #[arg(long, default_value_t = 10)]
+    tx_timeout: usize,
+}
+
+fn main() -> ExitCode {
+    env_logger::Builder::from_env(env_logger::Env::default().filter_or("LWNSIM_LOG", "warn"))
+        .target(env_logger::Target::Stderr)
+        .init();
+
+    let cli = Cli::parse();
+    let cmd_name = cmd_name(&cli.cmd);
+    let metrics = match cli.metrics_listen.as_deref().map(MetricsServer::start).transpose() {
+        Ok(server) => server,
+        Err(e) => {
+            println!("{}", error_json(cmd_name, &e));
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = load_config(&cli).and_then(|config| run(&cli, &config));
+    drop(metrics);
+    LWNSIM.lock().unwrap().disconnect();
+    match result {
+        Ok(mut out) => {
+            // a command may report a failure without error (e.g. a failed scenario)
+            let ok = out.get("ok").and_then(Value::as_bool).unwrap_or(true);
+            out["cmd"] = json!(cmd_name);
+            out["ok"] = json!(ok);
+            println!("{}", out);
+            if ok {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            println!("{}", error_json(cmd_name, &e));
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn cmd_name(cmd: &Cmd) -> &'static str {
+    match cmd {
+        Cmd::Link => "link",
+        Cmd::Unlink => "unlink",
+        Cmd::Join => "join",
+        Cmd::Send(_) => "send",
+        Cmd::Recv { .. } => "recv",
+        Cmd::WatchEvents { .. } => "watch-events",
+        Cmd::Status => "status",
+        Cmd::Repl { .. } => "repl",
+        Cmd::Sensors { .. } => "sensors",
+        Cmd::Scenario { .. } => "scenario",
+        Cmd::Loadgen(_) => "loadgen",
+        Cmd::Mock { .. } => "mock",
+        Cmd::LoadgenWorker(_) => "loadgen-worker",
+    }
+}
+
+fn error_json(cmd_name: &str, e: &LwnsimError) -> Value {
+    let kind = match e {
+        LwnsimError::CmdError(k) => json!(format!("{:?}", k)),
+        _ => Value::Null,
+    };
+    json!({ "cmd": cmd_name, "ok": false, "error": e.to_string(), "error_kind": kind })
+}
+
+fn load_config(cli: &Cli) -> Result<LwnsimConfig, LwnsimError> {
+    let path = match &cli.cmd {
+        Cmd::Scenario { file, .. } if cli.config.is_none() => Some(file),
+        _ => cli.config.as_ref(),
+    };
+    let mut config = LwnsimConfig::load(path)?;
+    if let Some(url) = &cli.url {
+        config.simulator.url = url.clone();
+    }
+    if let Some(path) = &cli.record {
+        config.simulator.record = Some(path.clone());
+    }
+    if let Some(dev_eui) = &cli.dev_eui {
+        let profile = match &cli.device {
+            Some(name) => config.devices.iter_mut().find(|d| &d.name == name),
+            None => config.devices.first_mut(),
+        };
+        match profile {
+            Some(profile) => profile.dev_eui = dev_eui.clone(),
+            None => config.devices.push(DeviceProfile::new(
+                cli.device.as_deref().unwrap_or("cli"),
+                dev_eui,
+            )),
+        }
+    }
+    config.validate()?;
+    return Ok(config);
+}
+
+fn run(cli: &Cli, config: &LwnsimConfig) -> Result<Value, LwnsimError> {
+    // each scenario connects to its own device
+    if let Cmd::Scenario { file, junit } = &cli.cmd {
+        let report = ScenarioFile::from_file(file)?.run(config);
+        if let Some(path) = junit {
+            std::fs::write(path, report.to_junit_xml())?;
+        }
+        return Ok(json!({ "ok": report.passed, "report": report }));
+    }
+    // the workers connect to their device
+    if let Cmd::Loadgen(args) = &cli.cmd {
+        return loadgen::run(cli, config, args);
+    }
+    if let Cmd::Mock { listen, args } = &cli.cmd {
+        return loadgen::run_mock(listen, args);
+    }
+
+    let profile = match &cli.replay {
+        Some(path) => {
+            replay(path, cli.replay_speed)?;
+            let dev_eui = LWNSIM.lock().unwrap().get_dev_eui().to_string();
+            // the recorded device, with the profile keys if any
+            let mut profile = config.device(cli.device.as_deref()).cloned().unwrap_or_else(|_| DeviceProfile::new("replay", &dev_eui));
+            profile.dev_eui = dev_eui;
+            profile
+        }
+        None => {
+            let profile = config.device(cli.device.as_deref())?;
+            config.connect(Some(&profile.name))?;
+            profile.clone()
+        }
+    };
+    let profile = &profile;
+    let dev = json!(profile.dev_eui);
+
+    match &cli.cmd {
+        Cmd::Link => {
+            ensure_linked()?;
+            Ok(json!({ "dev_eui": dev }))
+        }
+        Cmd::Unlink => {
+            // the simulator tells whether the device was linked
+            LORA.lock().unwrap().unlink_dev()?;
+            Ok(json!({ "dev_eui": dev }))
+        }
+        Cmd::Join => {
+            let elapsed = ensure_joined(cli, profile)?;
+            Ok(json!({ "dev_eui": dev, "join_time_ms": elapsed.as_millis() as u64 }))
+        }
+        Cmd::Send(args) => {
+            ensure_joined(cli, profile)?;
+            send(profile, args)
+        }
+        Cmd::Recv { timeout, buffersize } => {
+            ensure_joined(cli, profile)?;
+            let dl = recv(Duration::from_secs(*timeout), *buffersize)?;
+            Ok(json!({ "dev_eui": dev, "downlink": dl }))
+        }
+        Cmd::WatchEvents { join, duration, raw_frames } => {
+            if *join {
+                ensure_joined(cli, profile)?;
+            } else {
+                ensure_linked()?;
+            }
+            if *raw_frames {
+                LORA.lock().unwrap().set_raw_frames(true)?;
+            }
+            watch_events(duration.map(Duration::from_secs))?;
+            Ok(json!({ "dev_eui": dev }))
+        }
+        Cmd::Sensors { interval, count, codec } => {
+            if profile.sensors.is_empty() {
+                return Err(LwnsimError::ConfigError(format!("{}: no sensor in the device profile", profile.name)));
+            }
+            ensure_joined(cli, profile)?;
+            let interval = Duration::from_secs(*interval);
+            let sent = match codec {
+                SensorCodec::Lpp => run_sensors(profile, LppCodec, interval, *count)?,
+                SensorCodec::Json => run_sensors(profile, JsonCodec, interval, *count)?,
+                SensorCodec::Cbor => run_sensors(profile, CborCodec, interval, *count)?,
+            };
+            Ok(json!({ "dev_eui": dev, "readings": sent }))
+        }
+        Cmd::Repl { history } => {
+            repl::run(profile, history.clone())?;
+            Ok(json!({ "dev_eui": dev }))
+        }
+        Cmd::LoadgenWorker(args) => {
+            loadgen::run_worker(cli, profile, args)?;
+            Ok(json!({ "dev_eui": dev }))
+        }
+        Cmd::Scenario { .. } | Cmd::Loadgen(_) | Cmd::Mock { .. } => unreachable!(),
+        Cmd::Status => {
+            let connection = LWNSIM.lock().unwrap().get_status();
+            let mut lora = LORA.lock().unwrap();
+            Ok(json!({
+                "dev_eui": dev,
+                "connected": connection == LwnsimStatus::ConnOK,
+                "connection": connection,
+                "status": lora.get_status(),
+                "events": event_names(lora.get_events()),
+                "adr": { "adr": lora.get_adr(), "data_rate": lora.get_dr(), "tx_power": lora.get_tx_power() },
+                "channels": lora.channels(),
+                "pending_downlinks": lora.pending_downlinks(),
+                "dropped_downlinks": lora.dropped_downlinks(),
+                "outstanding_cmds": LWNSIM.lock().unwrap().outstanding_cmds(),
+                "energy": lora.energy_report(),
+            }))
+        }
+    }
+}
+
+// links the device, a device already linked in the simulator is accepted
+fn ensure_linked() -> Result<(), LwnsimError> {
+    let mut lora = LORA.lock().unwrap();
+    match lora.activate() {
+        Ok(()) => Ok(()),
+        Err(LwnsimError::CmdError(CmdErrorKind::DeviceLinked)) => {
+            lora.set_status(LoraDevStatus::Active);
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+// links and joins the device, returns the time taken by the join procedure
+fn ensure_joined(cli: &Cli, profile: &DeviceProfile) -> Result<Duration, LwnsimError> {
+    if cli.assume_joined {
+        LORA.lock().unwrap().set_status(LoraDevStatus::Joined);
+        return Ok(Duration::ZERO);
+    }
+    ensure_linked()?;
+    let start = Instant::now();
+    profile.join(&mut LORA.lock().unwrap())?;
+    // the lock is released between polls, the JoinAccept event handler needs it
+    while !LORA.lock().unwrap().has_joined() {
+        if start.elapsed() > Duration::from_secs(cli.join_timeout) {
+            return Err(LwnsimError::CmdError(CmdErrorKind::DevCmdTimeout));
+        }
+        thread::sleep(POLL_PERIOD);
+    }
+    return Ok(start.elapsed());
+}
+
+fn send(profile: &DeviceProfile, args: &SendArgs) -> Result<Value, LwnsimError> {
+    let mut s = profile.socket()?;
+    if args.confirmed {
+        s.setsockopt(SOL_LORA, SO_CONFIRMED, 1);
+    }
+    if let Some(port) = args.port {
+        s.bind(port as usize)?;
+    }
+    s.setblocking(true);
+    s.settimeout(Some(args.tx_timeout));
+
+    let size = if let Some(text) = &args.text {
+        s.send(text)?;
+        text.len()
+    } else {
+        let data = if let Some(h) = &args.hex {
+            decode_hex(h)?
+        } else {
+            let b = args.base64.as_deref().unwrap_or_default();
+            base64::engine::general_purpose::STANDARD
+                .decode(b)
+                .map_err(|_| LwnsimError::CmdError(CmdErrorKind::InvalidArgument))?
+        };
+        s.send_bytes(&data)?;
+        data.len()
+    };
+    return Ok(json!({
+        "dev_eui": profile.dev_eui,
+        "confirmed": args.confirmed || profile.confirmed,
+        "port": args.port.or(profile.fport),
+        "size": size,
+    }));
+}
+
+fn recv(timeout: Duration, buffersize: usize) -> Result<Downlink, LwnsimError> {
+    let start = Instant::now();
+    loop {
+        match LORA.lock().unwrap().recv_downlink(buffersize) {
+            Err(LwnsimError::CmdError(CmdErrorKind::NoDataDWrecv)) if start.elapsed() < timeout => {}
+            res => return res,
+        }
+        thread::sleep(POLL_PERIOD);
+    }
+}
+
+// returns the number of readings sent
+fn run_sensors<C: Codec<Vec<LppRecord>>>(
+    profile: &DeviceProfile,
+    codec: C,
+    interval: Duration,
+    count: Option<u64>,
+) -> Result<u64, LwnsimError> {
+    let stop = handle_signals(DEFAULT_SIGNAL_DEADLINE);
+
+    let mut driver = SensorDriver::new(profile.sensor_board(), profile.socket()?, codec, interval);
+    let mut sent = 0;
+    driver.run(stop, |readings| {
+        println!("{}", json!({ "time": chrono::Utc::now(), "readings": readings }));
+        sent += 1;
+        if count.is_some_and(|c| sent >= c) {
+            stop.store(true, Ordering::Relaxed);
+        }
+    })?;
+    return Ok(sent);
+}
+
+fn watch_events(duration: Option<Duration>) -> Result<(), LwnsimError> {
+    let stop = handle_signals(DEFAULT_SIGNAL_DEADLINE);
+
+    let (events, dev_logs) = {
+        let lwnsim = LWNSIM.lock().unwrap();
+        (lwnsim.subscribe_lora_events(), lwnsim.subscribe_dev_logs())
+    };
+    let start = Instant::now();
+    while !stop.load(Ordering::Relaxed) && duration.is_none_or(|d| start.elapsed() < d) {
+        if let Ok(ev) = events.recv_timeout(POLL_PERIOD) {
+            let mut out = json!({ "time": chrono::Utc::now(), "event": event_names(ev) });
+            if ev.contains(LoraEvents::MAC_CMD_EVENT) {
+                out["mac_commands"] = json!(LORA.lock().unwrap().mac_commands());
+            }
+            if ev.contains(LoraEvents::LINK_CHECK_ANS_EVENT) {
+                out["link_check_ans"] = json!(LORA.lock().unwrap().link_check_ans());
+            }
+            if ev.contains(LoraEvents::DEVICE_TIME_ANS_EVENT) {
+                out["device_time_ans"] = json!(LORA.lock().unwrap().device_time_ans());
+            }
+            if ev.contains(LoraEvents::ADR_CHANGE_EVENT) {
+                out["adr"] = json!(LORA.lock().unwrap().adr_state());
+            }
+            if ev.contains(LoraEvents::RAW_FRAME_EVENT) {
+                let frames: Vec<Value> = LORA.lock().unwrap().raw_frames().iter().map(raw_frame_json).collect();
+                out["raw_frames"] = json!(frames);
+            }
+            println!("{}", out);
+        }
+        for record in dev_logs.try_iter() {
+            println!("{}", json!({ "time": record.timestamp, "dev_log": record }));
+        }
+    }
+    return Ok(());
+}
+
+// the PHYPayload in hex and decoded, the FRMPayload is left encrypted
+fn raw_frame_json(frame: &RawFrame) -> Value {
+    let mut out = json!({
+        "direction": frame.direction,
+        "phy_payload": frame.hex(),
+        "frequency": frame.frequency,
+        "data_rate": frame.data_rate,
+        "rssi": frame.rssi,
+        "snr": frame.snr,
+    });
+    match frame.frame() {
+        Ok(phy) => out["frame"] = json!(phy),
+        Err(e) => out["error"] = json!(e.to_string()),
+    }
+    return out;
+}
+
+fn event_names(ev: LoraEvents) -> Vec<String> {
+    if ev.is_empty() {
+        return Vec::new();
+    }
+    return format!("{:?}", ev).split(" | ").map(String::from).collect();
+}
+
@@ -0,0 +1,1027 @@
+//! Interactive session modeled on the Pycom MicroPython LoRa REPL
+//!
+//! ```text
+//! >>> lora = LoRa(mode=LoRa.LORAWAN, region=LoRa.EU868)
+//! >>> lora.join(activation=LoRa.OTAA, auth=(app_eui, app_key), timeout=0)
+//! >>> s = socket.socket(socket.AF_LORA, socket.SOCK_RAW)
+//! >>> s.setsockopt(socket.SOL_LORA, socket.SO_CONFIRMED, True)
+//! >>> s.send(b'\x01\x02')
+//! >>> s.recv(64)
+//! >>> lora.stats()
+//! ```
+//!
+//! Lora events are printed inline as they arrive.
+
+use lwnsim_api_rs::*;
+
+use rustyline::error::ReadlineError;
+use rustyline::{DefaultEditor, ExternalPrinter};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+static PROMPT: &str = ">>> ";
+// history file in the home directory when none is given
+static HISTORY_FILE: &str = ".lwnsim_history";
+
+static HELP: &str = "\
+lora = LoRa(mode=LoRa.LORAWAN, region=LoRa.EU868)   the simulated device (predefined as lora)
+lora.join()                                         join with the device profile keys
+lora.join(activation=LoRa.OTAA, auth=(app_eui, app_key), timeout=0, dr=None)
+lora.has_joined()  lora.stats()  lora.events()
+lora.add_channel(index, frequency=, dr_min=, dr_max=)  lora.remove_channel(index)
+lora.activate()  lora.unlink()                      link / unlink the device in the simulator
+s = socket.socket(socket.AF_LORA, socket.SOCK_RAW)
+s.setsockopt(socket.SOL_LORA, socket.SO_CONFIRMED | socket.SO_DR, value)
+s.setblocking(flag)  s.settimeout(seconds)  s.bind(port)  s.close()
+s.send('text' | b'bytes')  s.recv(buffersize)
+bytes([1, 2])  ubinascii.hexlify(b)  ubinascii.unhexlify('0102')  time.sleep(seconds)
+help()  exit()";
+
+/// runs the REPL until exit() or Ctrl-D
+pub fn run(profile: &DeviceProfile, history: Option<PathBuf>) -> Result<(), LwnsimError> {
+    let mut rl = DefaultEditor::new().map_err(|e| LwnsimError::ConfigError(e.to_string()))?;
+    let history = history.or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(HISTORY_FILE)));
+    if let Some(path) = &history {
+        // no history yet
+        let _ = rl.load_history(path);
+    }
+    spawn_event_printer(&mut rl);
+
+    super::ensure_linked()?;
+    println!("lwnsim-cli REPL, device {} ({})", profile.name, profile.dev_eui);
+    println!("Type \"help()\" for more information.");
+
+    let mut session = Session::new(profile);
+    loop {
+        match rl.readline(PROMPT) {
+            Ok(line) => {
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let _ = rl.add_history_entry(line);
+                match session.exec(line) {
+                    Ok(Flow::Continue) => {}
+                    Ok(Flow::Exit) => break,
+                    Err(e) => println!("{}", e),
+                }
+            }
+            Err(ReadlineError::Interrupted) => println!("KeyboardInterrupt"),
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(LwnsimError::ConfigError(e.to_string())),
+        }
+    }
+    if let Some(path) = &history {
+        if let Err(e) = rl.save_history(path) {
+            log::warn!("[REPL][history]{}", e);
+        }
+    }
+    return Ok(());
+}
+
+// prints the lora events above the prompt as they arrive
+fn spawn_event_printer(rl: &mut DefaultEditor) {
+    let events = LWNSIM.lock().unwrap().subscribe_lora_events();
+    let mut print: Box<dyn FnMut(String) + Send> = match rl.create_external_printer() {
+        Ok(mut printer) => Box::new(move |msg| {
+            let _ = printer.print(msg);
+        }),
+        // not a terminal
+        Err(_) => Box::new(|msg| println!("{}", msg)),
+    };
+    thread::spawn(move || {
+        for ev in events {
+            print(format!("[event] {}", super::event_names(ev).join(" | ")));
+        }
+    });
+}
+
+enum Flow {
+    Continue,
+    Exit,
+}
+
+/// python-like exception raised by a statement
+#[derive(Debug)]
+struct ReplError {
+    kind: &'static str,
+    msg: String,
+}
+
+impl ReplError {
+    fn new(kind: &'static str, msg: impl Into<String>) -> ReplError {
+        ReplError { kind, msg: msg.into() }
+    }
+
+    fn syntax(msg: impl Into<String>) -> ReplError {
+        ReplError::new("SyntaxError", msg)
+    }
+
+    fn type_err(msg: impl Into<String>) -> ReplError {
+        ReplError::new("TypeError", msg)
+    }
+
+    fn value_err(msg: impl Into<String>) -> ReplError {
+        ReplError::new("ValueError", msg)
+    }
+}
+
+impl fmt::Display for ReplError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.kind, self.msg)
+    }
+}
+
+// the lora module errors are raised as OSError, as on a Pycom board
+impl From<LwnsimError> for ReplError {
+    fn from(e: LwnsimError) -> Self {
+        ReplError::new("OSError", e.to_string())
+    }
+}
+
+type EvalResult<T> = std::result::Result<T, ReplError>;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    None,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Bytes(Vec<u8>),
+    Tuple(Vec<Value>),
+    List(Vec<Value>),
+    // named fields, printed as a namedtuple
+    Record(&'static str, Vec<(&'static str, Value)>),
+    Lora,
+    // index in Session::sockets
+    Socket(usize),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::None => write!(f, "None"),
+            Value::Bool(true) => write!(f, "True"),
+            Value::Bool(false) => write!(f, "False"),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Str(s) => {
+                write!(f, "'")?;
+                for c in s.chars() {
+                    match c {
+                        '\\' => write!(f, "\\\\")?,
+                        '\'' => write!(f, "\\'")?,
+                        '\n' => write!(f, "\\n")?,
+                        '\r' => write!(f, "\\r")?,
+                        '\t' => write!(f, "\\t")?,
+                        c if c.is_control() => write!(f, "\\x{:02x}", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                write!(f, "'")
+            }
+            Value::Bytes(b) => {
+                write!(f, "b'")?;
+                for c in b {
+                    match c {
+                        b'\\' => write!(f, "\\\\")?,
+                        b'\'' => write!(f, "\\'")?,
+                        0x20..=0x7e => write!(f, "{}", *c as char)?,
+                        _ => write!(f, "\\x{:02x}", c)?,
+                    }
+                }
+                write!(f, "'")
+            }
+            Value::Tuple(items) if items.len() == 1 => write!(f, "({},)", items[0]),
+            Value::Tuple(items) => write!(f, "({})", join(items)),
+            Value::List(items) => write!(f, "[{}]", join(items)),
+            Value::Record(name, fields) => {
+                let fields: Vec<String> = fields.iter().map(|(k, v)| format!("{}={}", k, v)).collect();
+                write!(f, "{}({})", name, fields.join(", "))
+            }
+            Value::Lora => write!(f, "<LoRa>"),
+            Value::Socket(i) => write!(f, "<socket {}>", i),
+        }
+    }
+}
+
+fn join(items: &[Value]) -> String {
+    let items: Vec<String> = items.iter().map(|v| v.to_string()).collect();
+    return items.join(", ");
+}
+
+impl Value {
+    fn as_int(&self, what: &str) -> EvalResult<i64> {
+        return match self {
+            Value::Int(i) => Ok(*i),
+            Value::Bool(b) => Ok(*b as i64),
+            v => Err(ReplError::type_err(format!("{} must be an integer, not {}", what, v))),
+        };
+    }
+
+    fn as_usize(&self, what: &str) -> EvalResult<usize> {
+        let i = self.as_int(what)?;
+        return usize::try_from(i).map_err(|_| ReplError::value_err(format!("{} out of range: {}", what, i)));
+    }
+
+    fn as_u8(&self, what: &str) -> EvalResult<u8> {
+        let i = self.as_int(what)?;
+        return u8::try_from(i).map_err(|_| ReplError::value_err(format!("{} out of range: {}", what, i)));
+    }
+
+    fn is_true(&self) -> bool {
+        return !matches!(
+            self,
+            Value::None | Value::Bool(false) | Value::Int(0)
+        );
+    }
+
+    // keys given as text or as unhexlified bytes
+    fn as_key(&self) -> EvalResult<String> {
+        return match self {
+            Value::Str(s) => Ok(s.clone()),
+            Value::Bytes(b) => Ok(b.iter().map(|c| format!("{:02x}", c)).collect()),
+            v => Err(ReplError::type_err(format!("key must be str or bytes, not {}", v))),
+        };
+    }
+}
+
+// ---------------------------------------------------------------------------
+// parsing
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    // dotted name, e.g. socket.SOL_LORA
+    Name(String),
+    Int(i64),
+    Str(String),
+    Bytes(Vec<u8>),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Comma,
+    Assign,
+}
+
+fn tokenize(line: &str) -> EvalResult<Vec<Token>> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            ' ' | '\t' => i += 1,
+            '#' => break,
+            '(' | ')' | '[' | ']' | ',' | '=' => {
+                tokens.push(match c {
+                    '(' => Token::LParen,
+                    ')' => Token::RParen,
+                    '[' => Token::LBracket,
+                    ']' => Token::RBracket,
+                    ',' => Token::Comma,
+                    _ => Token::Assign,
+                });
+                i += 1;
+            }
+            '\'' | '"' => {
+                let (s, next) = read_string(&chars, i, false)?;
+                let s = String::from_utf8(s).map_err(|_| ReplError::syntax("invalid utf-8 string"))?;
+                tokens.push(Token::Str(s));
+                i = next;
+            }
+            'b' | 'B' if matches!(chars.get(i + 1), Some('\'') | Some('"')) => {
+                let (b, next) = read_string(&chars, i + 1, true)?;
+                tokens.push(Token::Bytes(b));
+                i = next;
+            }
+            '-' | '0'..='9' => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let lit: String = chars[start..i].iter().filter(|c| **c != '_').collect();
+                tokens.push(Token::Int(parse_int(&lit)?));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.') {
+                    i += 1;
+                }
+                tokens.push(Token::Name(chars[start..i].iter().collect()));
+            }
+            c => return Err(ReplError::syntax(format!("invalid character '{}'", c))),
+        }
+    }
+    return Ok(tokens);
+}
+
+fn parse_int(lit: &str) -> EvalResult<i64> {
+    let (neg, digits) = match lit.strip_prefix('-') {
+        Some(d) => (true, d),
+        None => (false, lit),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16)
+    } else {
+        digits.parse::<i64>()
+    }
+    .map_err(|_| ReplError::syntax(format!("invalid number {}", lit)))?;
+    return Ok(if neg { -value } else { value });
+}
+
+// reads a quoted string starting at the opening quote, returns its utf-8 bytes and the index after the closing quote
+// \xNN is a byte in a bytes literal and the U+00NN character in a string, as in python
+fn read_string(chars: &[char], start: usize, bytes: bool) -> EvalResult<(Vec<u8>, usize)> {
+    let quote = chars[start];
+    let mut out = Vec::new();
+    let mut i = start + 1;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == quote {
+            return Ok((out, i + 1));
+        }
+        if c == '\\' {
+            i += 1;
+            let esc = *chars.get(i).ok_or_else(|| ReplError::syntax("unterminated string"))?;
+            match esc {
+                'n' => out.push(b'\n'),
+                'r' => out.push(b'\r'),
+                't' => out.push(b'\t'),
+                '0' => out.push(0),
+                'x' => {
+                    let hex: String = chars.get(i + 1..i + 3).map(|h| h.iter().collect()).unwrap_or_default();
+                    let b = u8::from_str_radix(&hex, 16)
+                        .map_err(|_| ReplError::syntax(format!("invalid escape \\x{}", hex)))?;
+                    if bytes {
+                        out.push(b);
+                    } else {
+                        push_char(&mut out, b as char);
+                    }
+                    i += 2;
+                }
+                '\\' | '\'' | '"' => out.push(esc as u8),
+                // unknown escapes are kept
+                c => {
+                    out.push(b'\\');
+                    push_char(&mut out, c);
+                }
+            }
+        } else if bytes && !c.is_ascii() {
+            return Err(ReplError::syntax("bytes can only contain ASCII literal characters"));
+        } else {
+            push_char(&mut out, c);
+        }
+        i += 1;
+    }
+    return Err(ReplError::syntax("unterminated string"));
+}
+
+fn push_char(out: &mut Vec<u8>, c: char) {
+    let mut buf = [0; 4];
+    out.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Lit(Value),
+    Name(String),
+    Call {
+        func: String,
+        args: Vec<Expr>,
+        kwargs: Vec<(String, Expr)>,
+    },
+    Tuple(Vec<Expr>),
+    List(Vec<Expr>),
+}
+
+// positional and keyword arguments of a call
+type CallArgs = (Vec<Expr>, Vec<(String, Expr)>);
+
+#[derive(Debug)]
+struct Stmt {
+    target: Option<String>,
+    expr: Expr,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn parse_stmt(tokens: Vec<Token>) -> EvalResult<Stmt> {
+        let mut p = Parser { tokens, pos: 0 };
+        let target = match (p.tokens.first(), p.tokens.get(1)) {
+            (Some(Token::Name(n)), Some(Token::Assign)) if !n.contains('.') => {
+                let n = n.clone();
+                p.pos = 2;
+                Some(n)
+            }
+            _ => None,
+        };
+        let expr = p.parse_expr()?;
+        if p.pos != p.tokens.len() {
+            return Err(ReplError::syntax("invalid syntax"));
+        }
+        return Ok(Stmt { target, expr });
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let t = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        return t;
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        return self.tokens.get(self.pos);
+    }
+
+    fn parse_expr(&mut self) -> EvalResult<Expr> {
+        return match self.next() {
+            Some(Token::Int(i)) => Ok(Expr::Lit(Value::Int(i))),
+            Some(Token::Str(s)) => Ok(Expr::Lit(Value::Str(s))),
+            Some(Token::Bytes(b)) => Ok(Expr::Lit(Value::Bytes(b))),
+            Some(Token::Name(name)) => {
+                if self.peek() == Some(&Token::LParen) {
+                    self.pos += 1;
+                    let (args, kwargs) = self.parse_args(Token::RParen)?;
+                    Ok(Expr::Call { func: name, args, kwargs })
+                } else {
+                    Ok(Expr::Name(name))
+                }
+            }
+            Some(Token::LParen) => {
+                let (mut items, kwargs) = self.parse_args(Token::RParen)?;
+                if !kwargs.is_empty() {
+                    return Err(ReplError::syntax("invalid syntax"));
+                }
+                // a parenthesized expression unless followed by a comma
+                let is_tuple = items.len() != 1 || self.tokens.get(self.pos - 2) == Some(&Token::Comma);
+                if is_tuple {
+                    Ok(Expr::Tuple(items))
+                } else {
+                    Ok(items.remove(0))
+                }
+            }
+            Some(Token::LBracket) => {
+                let (items, kwargs) = self.parse_args(Token::RBracket)?;
+                if !kwargs.is_empty() {
+                    return Err(ReplError::syntax("invalid syntax"));
+                }
+                Ok(Expr::List(items))
+            }
+            _ => Err(ReplError::syntax("invalid syntax")),
+        };
+    }
+
+    // comma separated expressions and keyword arguments up to the closing token (consumed)
+    fn parse_args(&mut self, close: Token) -> EvalResult<CallArgs> {
+        let mut args = Vec::new();
+        let mut kwargs = Vec::new();
+        loop {
+            if self.peek() == Some(&close) {
+                self.pos += 1;
+                return Ok((args, kwargs));
+            }
+            match (self.peek().cloned(), self.tokens.get(self.pos + 1)) {
+                (Some(Token::Name(n)), Some(Token::Assign)) => {
+                    self.pos += 2;
+                    kwargs.push((n, self.parse_expr()?));
+                }
+                _ => {
+                    if !kwargs.is_empty() {
+                        return Err(ReplError::syntax("positional argument follows keyword argument"));
+                    }
+                    args.push(self.parse_expr()?);
+                }
+            }
+            match self.next() {
+                Some(Token::Comma) => {}
+                Some(t) if t == close => return Ok((args, kwargs)),
+                _ => return Err(ReplError::syntax("invalid syntax")),
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// evaluation
+
+struct Args {
+    func: String,
+    pos: Vec<Value>,
+    kw: Vec<(String, Value)>,
+}
+
+impl Args {
+    // positional argument i or keyword argument name
+    fn get(&self, i: usize, name: &str) -> Option<&Value> {
+        return self
+            .pos
+            .get(i)
+            .or_else(|| self.kw.iter().find(|(k, _)| k == name).map(|(_, v)| v));
+    }
+
+    fn required(&self, i: usize, name: &str) -> EvalResult<&Value> {
+        return self.get(i, name).ok_or_else(|| {
+            ReplError::type_err(format!("{}() missing required argument: '{}'", self.func, name))
+        });
+    }
+
+    fn check_count(&self, max: usize) -> EvalResult<()> {
+        if self.pos.len() > max {
+            return Err(ReplError::type_err(format!(
+                "{}() takes at most {} positional arguments but {} were given",
+                self.func,
+                max,
+                self.pos.len()
+            )));
+        }
+        return Ok(());
+    }
+}
+
+struct Session<'a> {
+    profile: &'a DeviceProfile,
+    vars: HashMap<String, Value>,
+    sockets: Vec<Socket>,
+}
+
+impl<'a> Session<'a> {
+    fn new(profile: &'a DeviceProfile) -> Session<'a> {
+        let mut vars = HashMap::new();
+        vars.insert("lora".to_string(), Value::Lora);
+        // the profile keys, for lora.join(activation=LoRa.OTAA, auth=(app_eui, app_key))
+        let (_, (key1, key2)) = profile.join_params();
+        vars.insert("app_eui".to_string(), Value::Str(key1.clone()));
+        vars.insert("app_key".to_string(), Value::Str(key2.clone()));
+        vars.insert("nwk_swkey".to_string(), Value::Str(key1));
+        vars.insert("app_swkey".to_string(), Value::Str(key2));
+        Session {
+            profile,
+            vars,
+            sockets: Vec::new(),
+        }
+    }
+
+    fn exec(&mut self, line: &str) -> EvalResult<Flow> {
+        let tokens = tokenize(line)?;
+        if tokens.is_empty() {
+            return Ok(Flow::Continue);
+        }
+        if matches!(tokens.as_slice(), [Token::Name(n)] | [Token::Name(n), Token::LParen, Token::RParen] if n == "exit" || n == "quit")
+        {
+            return Ok(Flow::Exit);
+        }
+        let stmt = Parser::parse_stmt(tokens)?;
+        let value = self.eval(&stmt.expr)?;
+        match stmt.target {
+            Some(name) => {
+                self.vars.insert(name, value);
+            }
+            None if value != Value::None => println!("{}", value),
+            None => {}
+        }
+        return Ok(Flow::Continue);
+    }
+
+    fn eval(&mut self, expr: &Expr) -> EvalResult<Value> {
+        return match expr {
+            Expr::Lit(v) => Ok(v.clone()),
+            Expr::Name(n) => self.lookup(n),
+            Expr::Tuple(items) => Ok(Value::Tuple(self.eval_all(items)?)),
+            Expr::List(items) => Ok(Value::List(self.eval_all(items)?)),
+            Expr::Call { func, args, kwargs } => {
+                let args = Args {
+                    func: func.clone(),
+                    pos: self.eval_all(args)?,
+                    kw: kwargs
+                        .iter()
+                        .map(|(k, e)| Ok((k.clone(), self.eval(e)?)))
+                        .collect::<EvalResult<Vec<_>>>()?,
+                };
+                self.call(func, &args)
+            }
+        };
+    }
+
+    fn eval_all(&mut self, exprs: &[Expr]) -> EvalResult<Vec<Value>> {
+        return exprs.iter().map(|e| self.eval(e)).collect();
+    }
+
+    fn lookup(&self, name: &str) -> EvalResult<Value> {
+        if let Some(v) = self.vars.get(name) {
+            return Ok(v.clone());
+        }
+        let v = match name {
+            "None" => Value::None,
+            "True" => Value::Bool(true),
+            "False" => Value::Bool(false),
+            "LoRa.LORAWAN" => Value::Int(LORAWAN as i64),
+            "LoRa.EU868" => Value::Int(EU868 as i64),
+            "LoRa.OTAA" => Value::Int(OTAA as i64),
+            "LoRa.ABP" => Value::Int(ABP as i64),
+            "LoRa.RX_PACKET_EVENT" => Value::Int(LoraEvents::RX_PACKET_EVENT.bits() as i64),
+            "LoRa.TX_PACKET_EVENT" => Value::Int(LoraEvents::TX_PACKET_EVENT.bits() as i64),
+            "LoRa.TX_FAILED_EVENT" => Value::Int(LoraEvents::TX_FAILED_EVENT.bits() as i64),
+            "socket.AF_LORA" => Value::Int(AF_LORA as i64),
+            "socket.SOCK_RAW" => Value::Int(SOCK_RAW as i64),
+            "socket.SOL_LORA" => Value::Int(SOL_LORA as i64),
+            "socket.SO_CONFIRMED" => Value::Int(SO_CONFIRMED as i64),
+            "socket.SO_DR" => Value::Int(SO_DR as i64),
+            _ => return Err(ReplError::new("NameError", format!("name '{}' is not defined", name))),
+        };
+        return Ok(v);
+    }
+
+    fn call(&mut self, func: &str, args: &Args) -> EvalResult<Value> {
+        if let Some((obj, method)) = func.rsplit_once('.') {
+            match self.vars.get(obj) {
+                Some(Value::Lora) => return self.call_lora(method, args),
+                Some(Value::Socket(i)) => return self.call_socket(*i, method, args),
+                Some(v) => {
+                    return Err(ReplError::new("AttributeError", format!("{} has no attribute '{}'", v, method)))
+                }
+                None => {}
+            }
+        }
+        return match func {
+            "help" => {
+                println!("{}", HELP);
+                Ok(Value::None)
+            }
+            "LoRa" => {
+                args.check_count(2)?;
+                if let Some(mode) = args.get(0, "mode") {
+                    if mode.as_usize("mode")? != LORAWAN {
+                        return Err(ReplError::value_err("only LoRa.LORAWAN mode is supported"));
+                    }
+                }
+                if let Some(region) = args.get(1, "region") {
+                    if region.as_usize("region")? != EU868 {
+                        return Err(ReplError::value_err("only LoRa.EU868 region is supported"));
+                    }
+                }
+                Ok(Value::Lora)
+            }
+            "socket.socket" => {
+                let af = args.get(0, "af").map(|v| v.as_usize("af")).transpose()?.unwrap_or(AF_LORA);
+                let t = args.get(1, "type").map(|v| v.as_usize("type")).transpose()?.unwrap_or(SOCK_RAW);
+                if af != AF_LORA || t != SOCK_RAW {
+                    return Err(ReplError::value_err("only AF_LORA / SOCK_RAW sockets are supported"));
+                }
+                self.sockets.push(Socket::new(af, t));
+                Ok(Value::Socket(self.sockets.len() - 1))
+            }
+            "bytes" => match args.get(0, "source") {
+                None => Ok(Value::Bytes(Vec::new())),
+                Some(Value::Bytes(b)) => Ok(Value::Bytes(b.clone())),
+                Some(Value::Str(s)) => Ok(Value::Bytes(s.as_bytes().to_vec())),
+                Some(Value::List(items)) | Some(Value::Tuple(items)) => Ok(Value::Bytes(
+                    items.iter().map(|v| v.as_u8("byte")).collect::<EvalResult<Vec<u8>>>()?,
+                )),
+                Some(v) => Ok(Value::Bytes(vec![0; v.as_usize("size")?])),
+            },
+            "ubinascii.hexlify" => match args.required(0, "data")? {
+                Value::Bytes(b) => Ok(Value::Bytes(
+                    b.iter().map(|c| format!("{:02x}", c)).collect::<String>().into_bytes(),
+                )),
+                v => Err(ReplError::type_err(format!("a bytes-like object is required, not {}", v))),
+            },
+            "ubinascii.unhexlify" => {
+                let hex = match args.required(0, "data")? {
+                    Value::Str(s) => s.clone(),
+                    Value::Bytes(b) => String::from_utf8_lossy(b).to_string(),
+                    v => return Err(ReplError::type_err(format!("str or bytes expected, not {}", v))),
+                };
+                let data = decode_hex(&hex).map_err(|_| ReplError::value_err("invalid hex string"))?;
+                Ok(Value::Bytes(data))
+            }
+            "time.sleep" => {
+                thread::sleep(Duration::from_secs(args.required(0, "seconds")?.as_int("seconds")?.max(0) as u64));
+                Ok(Value::None)
+            }
+            _ => Err(ReplError::new("NameError", format!("name '{}' is not defined", func))),
+        };
+    }
+
+    fn call_lora(&mut self, method: &str, args: &Args) -> EvalResult<Value> {
+        let mut lora = LORA.lock().unwrap();
+        return match method {
+            "join" => {
+                if args.pos.is_empty() && args.kw.is_empty() {
+                    self.profile.join(&mut lora)?;
+                    return Ok(Value::None);
+                }
+                let activation = args.required(0, "activation")?.as_usize("activation")?;
+                let auth = match args.required(1, "auth")? {
+                    Value::Tuple(keys) if keys.len() >= 2 => (keys[0].as_key()?, keys[keys.len() - 1].as_key()?),
+                    v => return Err(ReplError::type_err(format!("auth must be a tuple of keys, not {}", v))),
+                };
+                let timeout = match args.get(2, "timeout") {
+                    None | Some(Value::None) => None,
+                    Some(v) => Some(v.as_usize("timeout")?),
+                };
+                let dr = match args.get(3, "dr") {
+                    None | Some(Value::None) => None,
+                    Some(v) => Some(v.as_usize("dr")?),
+                };
+                lora.join(activation, auth, timeout, dr)?;
+                Ok(Value::None)
+            }
+            "has_joined" => Ok(Value::Bool(lora.has_joined())),
+            "stats" => {
+                let stats = lora.stats();
+                let opt = |v: Option<u8>| v.map_or(Value::None, |v| Value::Int(v as i64));
+                Ok(Value::Record(
+                    "stats",
+                    vec![
+                        (
+                            "rx_timestamp",
+                            stats
+                                .rx_timestamp
+                                .map_or(Value::None, |t| Value::Int(t.timestamp_millis())),
+                        ),
+                        ("rx_counter", Value::Int(stats.rx_counter as i64)),
+                        ("tx_counter", Value::Int(stats.tx_counter as i64)),
+                        ("tx_failed", Value::Int(stats.tx_failed as i64)),
+                        ("data_rate", opt(stats.data_rate)),
+                        ("tx_power", opt(stats.tx_power)),
+                    ],
+                ))
+            }
+            "events" => Ok(Value::Int(lora.events().bits() as i64)),
+            "add_channel" => {
+                lora.add_channel(
+                    args.required(0, "index")?.as_usize("index")?,
+                    args.required(1, "frequency")?.as_int("frequency")? as u32,
+                    args.required(2, "dr_min")?.as_u8("dr_min")?,
+                    args.required(3, "dr_max")?.as_u8("dr_max")?,
+                )?;
+                Ok(Value::None)
+            }
+            "remove_channel" => {
+                lora.remove_channel(args.required(0, "index")?.as_usize("index")?)?;
+                Ok(Value::None)
+            }
+            "activate" => {
+                drop(lora);
+                super::ensure_linked()?;
+                Ok(Value::None)
+            }
+            "unlink" => {
+                lora.unlink_dev()?;
+                Ok(Value::None)
+            }
+            _ => Err(ReplError::new("AttributeError", format!("'LoRa' object has no attribute '{}'", method))),
+        };
+    }
+
+    fn call_socket(&mut self, i: usize, method: &str, args: &Args) -> EvalResult<Value> {
+        let s = &mut self.sockets[i];
+        return match method {
+            "setsockopt" => {
+                let level = args.required(0, "level")?.as_usize("level")?;
+                let optname = args.required(1, "optname")?.as_usize("optname")?;
+                let value = args.required(2, "value")?.as_usize("value")?;
+                if level != SOL_LORA || (optname != SO_CONFIRMED && optname != SO_DR) {
+                    return Err(ReplError::new("OSError", "unsupported socket option"));
+                }
+                s.setsockopt(level, optname, value);
+                Ok(Value::None)
+            }
+            "setblocking" => {
+                s.setblocking(args.required(0, "flag")?.is_true());
+                Ok(Value::None)
+            }
+            "settimeout" => {
+                match args.required(0, "value")? {
+                    Value::None => s.settimeout(None),
+                    v => s.settimeout(Some(v.as_usize("value")?)),
+                }
+                Ok(Value::None)
+            }
+            "bind" => {
+                let port = args.required(0, "port")?.as_usize("port")?;
+                if !(1..=223).contains(&port) {
+                    return Err(ReplError::value_err(format!("invalid port {}", port)));
+                }
+                s.bind(port)?;
+                Ok(Value::None)
+            }
+            "send" => match args.required(0, "data")? {
+                Value::Str(text) => {
+                    s.send(text)?;
+                    Ok(Value::Int(text.len() as i64))
+                }
+                Value::Bytes(data) => {
+                    s.send_bytes(data)?;
+                    Ok(Value::Int(data.len() as i64))
+                }
+                v => Err(ReplError::type_err(format!("object with buffer protocol required, not {}", v))),
+            },
+            "recv" => {
+                let buffersize = args.required(0, "bufsize")?.as_usize("bufsize")?;
+                match s.recv_bytes(buffersize) {
+                    Ok(payload) => Ok(Value::Bytes(payload)),
+                    // non blocking socket without data, as Pycom
+                    Err(LwnsimError::CmdError(CmdErrorKind::NoDataDWrecv)) => Ok(Value::Bytes(Vec::new())),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            "close" => {
+                s.close();
+                Ok(Value::None)
+            }
+            _ => Err(ReplError::new("AttributeError", format!("'socket' object has no attribute '{}'", method))),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile() -> DeviceProfile {
+        let mut p = DeviceProfile::new("repl", "359ac7cd01bc8aff");
+        p.join_eui = Some("0000000000000000".to_string());
+        p.app_key = Some("f1c4081b61e9bee79bef58b5347e78a5".to_string());
+        return p;
+    }
+
+    fn eval(session: &mut Session, line: &str) -> EvalResult<Value> {
+        let stmt = Parser::parse_stmt(tokenize(line)?)?;
+        return session.eval(&stmt.expr);
+    }
+
+    fn error_kind<T: fmt::Debug>(res: EvalResult<T>) -> &'static str {
+        return match res {
+            Err(e) => e.kind,
+            Ok(v) => panic!("{:?}", v),
+        };
+    }
+
+    fn str_token(line: &str) -> Token {
+        let mut tokens = tokenize(line).unwrap();
+        assert_eq!(tokens.len(), 1, "{:?}", tokens);
+        return tokens.remove(0);
+    }
+
+    #[test]
+    fn string_literals() {
+        assert_eq!(str_token(r#"'a\n\t\r\0\\\'"'"#), Token::Str("a\n\t\r\0\\'\"".to_string()));
+        assert_eq!(str_token(r#""it's""#), Token::Str("it's".to_string()));
+        assert_eq!(str_token(r"'\d'"), Token::Str("\\d".to_string()));
+        // non-ASCII characters and \x escapes are characters in a string, bytes in a bytes literal
+        assert_eq!(str_token("'été ✓'"), Token::Str("été ✓".to_string()));
+        assert_eq!(str_token(r"'\xe9t\xe9'"), Token::Str("été".to_string()));
+        assert_eq!(str_token(r"b'\x01\xff\n'"), Token::Bytes(vec![0x01, 0xff, b'\n']));
+        assert_eq!(str_token(r"B'A\\'"), Token::Bytes(vec![b'A', b'\\']));
+
+        assert_eq!(error_kind(tokenize("b'é'")), "SyntaxError");
+        assert_eq!(error_kind(tokenize("'abc")), "SyntaxError");
+        assert_eq!(error_kind(tokenize(r"'abc\")), "SyntaxError");
+        assert_eq!(error_kind(tokenize(r"'\x4'")), "SyntaxError");
+    }
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            tokenize("s.setsockopt(socket.SOL_LORA, 0x10, -1_000) # comment").unwrap(),
+            vec![
+                Token::Name("s.setsockopt".to_string()),
+                Token::LParen,
+                Token::Name("socket.SOL_LORA".to_string()),
+                Token::Comma,
+                Token::Int(16),
+                Token::Comma,
+                Token::Int(-1000),
+                Token::RParen,
+            ]
+        );
+        assert_eq!(tokenize("x = [1]").unwrap(), vec![
+            Token::Name("x".to_string()),
+            Token::Assign,
+            Token::LBracket,
+            Token::Int(1),
+            Token::RBracket,
+        ]);
+        assert_eq!(error_kind(tokenize("1 + 2")), "SyntaxError");
+        assert_eq!(error_kind(tokenize("12ab")), "SyntaxError");
+    }
+
+    #[test]
+    fn statements() {
+        let stmt = Parser::parse_stmt(tokenize("x = f(1, (2,), k=[3])").unwrap()).unwrap();
+        assert_eq!(stmt.target.as_deref(), Some("x"));
+        match stmt.expr {
+            Expr::Call { func, args, kwargs } => {
+                assert_eq!(func, "f");
+                assert!(matches!(args.as_slice(), [Expr::Lit(Value::Int(1)), Expr::Tuple(t)] if t.len() == 1));
+                assert!(matches!(kwargs.as_slice(), [(k, Expr::List(l))] if k == "k" && l.len() == 1));
+            }
+            e => panic!("{:?}", e),
+        }
+        // a parenthesized expression is not a tuple
+        let stmt = Parser::parse_stmt(tokenize("(1)").unwrap()).unwrap();
+        assert!(stmt.target.is_none());
+        assert!(matches!(stmt.expr, Expr::Lit(Value::Int(1))));
+
+        for line in ["f(1", "f(a=1, 2)", "1 2", "= 1", "x = ", "(a=1)", "f(1,,)", "s.x = 1"] {
+            assert_eq!(error_kind(Parser::parse_stmt(tokenize(line).unwrap())), "SyntaxError", "{}", line);
+        }
+        let err = Parser::parse_stmt(tokenize("f(a=1, 2)").unwrap()).err().unwrap();
+        assert_eq!(err.to_string(), "SyntaxError: positional argument follows keyword argument");
+    }
+
+    #[test]
+    fn evaluation() {
+        let profile = profile();
+        let mut session = Session::new(&profile);
+        assert!(matches!(session.exec("x = bytes([1, 0xab])"), Ok(Flow::Continue)));
+        assert_eq!(eval(&mut session, "x").unwrap(), Value::Bytes(vec![1, 0xab]));
+        assert_eq!(eval(&mut session, "ubinascii.hexlify(x)").unwrap(), Value::Bytes(b"01ab".to_vec()));
+        assert_eq!(eval(&mut session, "ubinascii.unhexlify('01ab')").unwrap(), Value::Bytes(vec![1, 0xab]));
+        assert_eq!(eval(&mut session, "bytes('é')").unwrap(), Value::Bytes(vec![0xc3, 0xa9]));
+        assert_eq!(eval(&mut session, "(True, None, 'été')").unwrap().to_string(), "(True, None, 'été')");
+        assert_eq!(eval(&mut session, r"['a\'\n', b'\x00A']").unwrap().to_string(), r"['a\'\n', b'\x00A']");
+        assert_eq!(eval(&mut session, "app_key").unwrap(), Value::Str("f1c4081b61e9bee79bef58b5347e78a5".to_string()));
+        assert_eq!(eval(&mut session, "LoRa(mode=LoRa.LORAWAN, region=LoRa.EU868)").unwrap(), Value::Lora);
+
+        assert_eq!(eval(&mut session, "time.sleep(0)").unwrap(), Value::None);
+        assert_eq!(error_kind(eval(&mut session, "time.sleep()")), "TypeError");
+        assert_eq!(error_kind(eval(&mut session, "time.sleep('1')")), "TypeError");
+
+        assert_eq!(error_kind(eval(&mut session, "y")), "NameError");
+        assert_eq!(error_kind(eval(&mut session, "ubinascii.unhexlify('0g')")), "ValueError");
+        assert_eq!(error_kind(eval(&mut session, "bytes([256])")), "ValueError");
+        assert_eq!(error_kind(eval(&mut session, "x.hex()")), "AttributeError");
+        assert!(matches!(session.exec("exit()"), Ok(Flow::Exit)));
+        assert!(matches!(session.exec("# comment"), Ok(Flow::Continue)));
+    }
+
+    #[test]
+    fn socket_arguments() {
+        let profile = profile();
+        let mut session = Session::new(&profile);
+        assert_eq!(error_kind(eval(&mut session, "socket.socket(1, 2)")), "ValueError");
+        session.exec("s = socket.socket(socket.AF_LORA, socket.SOCK_RAW)").unwrap();
+        assert_eq!(eval(&mut session, "s").unwrap(), Value::Socket(0));
+        // checked before the device is used
+        assert_eq!(error_kind(eval(&mut session, "s.send(1)")), "TypeError");
+        assert_eq!(error_kind(eval(&mut session, "s.send()")), "TypeError");
+        assert_eq!(error_kind(eval(&mut session, "s.recv('64')")), "TypeError");
+        assert_eq!(error_kind(eval(&mut session, "s.recv(-1)")), "ValueError");
+        assert_eq!(error_kind(eval(&mut session, "s.bind(224)")), "ValueError");
+        assert_eq!(error_kind(eval(&mut session, "s.setsockopt(socket.SOL_LORA, 99, 1)")), "OSError");
+        assert_eq!(error_kind(eval(&mut session, "s.connect()")), "AttributeError");
+        assert_eq!(eval(&mut session, "s.setsockopt(socket.SOL_LORA, socket.SO_CONFIRMED, True)").unwrap(), Value::None);
+        assert_eq!(eval(&mut session, "s.settimeout(None)").unwrap(), Value::None);
+    }
+
+    // the only test using the client and its device, they are process wide
+    #[test]
+    fn mock_round_trip() {
+        let opts = MockOptions {
+            join_delay: Duration::from_millis(50),
+            tx_delay: Duration::from_millis(50),
+            rx_delay: Duration::from_millis(50),
+            downlink_ratio: 1.0,
+            ..MockOptions::default()
+        };
+        let mock = MockSimulator::start("127.0.0.1:0", opts).unwrap();
+        let profile = profile();
+        LwnsimBuilder::new(&mock.url(), &profile.dev_eui).connect().unwrap();
+        let mut session = Session::new(&profile);
+        session.exec("lora = LoRa(mode=LoRa.LORAWAN, region=LoRa.EU868)").unwrap();
+        session.exec("lora.activate()").unwrap();
+        session.exec("lora.join(activation=LoRa.OTAA, auth=(app_eui, app_key), timeout=0)").unwrap();
+        for _ in 0..100 {
+            if eval(&mut session, "lora.has_joined()").unwrap() == Value::Bool(true) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(eval(&mut session, "lora.has_joined()").unwrap(), Value::Bool(true));
+
+        session.exec("s = socket.socket(socket.AF_LORA, socket.SOCK_RAW)").unwrap();
+        session.exec("s.setblocking(True)").unwrap();
+        session.exec("s.settimeout(5)").unwrap();
+        // the uplink is echoed: the UTF-8 bytes of the literal are sent and received
+        assert_eq!(eval(&mut session, "s.send('été ✓')").unwrap(), Value::Int(9));
+        assert_eq!(eval(&mut session, "s.recv(64)").unwrap(), Value::Bytes("été ✓".as_bytes().to_vec()));
+        assert_eq!(eval(&mut session, "s.send('\\xe9')").unwrap(), Value::Int(2));
+        assert_eq!(eval(&mut session, "s.recv(1)").unwrap(), Value::Bytes(vec![0xc3]));
+        session.exec("s.setblocking(False)").unwrap();
+        assert_eq!(eval(&mut session, "s.recv(64)").unwrap(), Value::Bytes(Vec::new()));
+
+        session.exec("lora.unlink()").unwrap();
+        LWNSIM.lock().unwrap().disconnect();
+        mock.stop();
+    }
+}
@@ -0,0 +1,169 @@
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// period at which a real time sleep checks its stop flag
+static STOP_CHECK_PERIOD: Duration = Duration::from_millis(100);
+// real time a virtual sleep leaves to the other threads (socketio callbacks, mock simulator)
+pub static DEFAULT_REAL_STEP: Duration = Duration::from_millis(10);
+
+lazy_static! {
+    static ref CLOCK: RwLock<Arc<dyn Clock>> = RwLock::new(Arc::new(SystemClock));
+}
+
+/// time source of the client: socket timeouts, join waits, scheduled senders, energy model and mock simulator
+/// the command timeouts of the simulator connection stay in real time
+pub trait Clock: Send + Sync + fmt::Debug {
+    fn now(&self) -> Instant;
+    fn utc_now(&self) -> DateTime<Utc>;
+    fn sleep(&self, duration: Duration);
+    /// sleeps for duration unless stop is set first, returns true if the whole duration elapsed
+    fn sleep_or_stop(&self, duration: Duration, stop: &AtomicBool) -> bool;
+    /// real time to wait before checking again for something due in duration
+    fn real_wait(&self, duration: Duration) -> Duration;
+}
+
+/// the clock used by the crate, SystemClock unless set with set_clock()
+pub fn clock() -> Arc<dyn Clock> {
+    return CLOCK.read().unwrap().clone();
+}
+
+/// replaces the clock used by the crate, to be set before linking the device (and starting a mock simulator)
+pub fn set_clock(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap() = clock;
+}
+
+/// real time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        return Instant::now();
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        return Utc::now();
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+
+    fn sleep_or_stop(&self, duration: Duration, stop: &AtomicBool) -> bool {
+        let deadline = Instant::now() + duration;
+        loop {
+            if stop.load(Ordering::Relaxed) {
+                return false;
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return true;
+            }
+            thread::sleep(remaining.min(STOP_CHECK_PERIOD));
+        }
+    }
+
+    fn real_wait(&self, duration: Duration) -> Duration {
+        return duration;
+    }
+}
+
+/// simulated time, advanced by advance() and by the sleeps: a sleep returns after DEFAULT_REAL_STEP
+/// at most, whatever its duration, so hours of uplinks run in seconds against the mock simulator
+/// the clones share the same time, overlapping sleeps of several threads do not add up
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let clock = VirtualClock::new();
+/// set_clock(Arc::new(clock.clone()));
+/// let mock = MockSimulator::start("127.0.0.1:0", MockOptions::default()).unwrap();
+/// // ... link, join and send as usual, the join delay and socket timeouts are in virtual time
+/// clock.advance(Duration::from_secs(3600));
+/// println!("{}", clock.utc_now());
+/// ```
+#[derive(Debug, Clone)]
+pub struct VirtualClock {
+    origin: Instant,
+    utc_origin: DateTime<Utc>,
+    elapsed: Arc<Mutex<Duration>>,
+    real_step: Duration,
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        return VirtualClock::starting_at(Utc::now());
+    }
+}
+
+impl VirtualClock {
+    /// virtual clock starting at the current date
+    pub fn new() -> VirtualClock {
+        return VirtualClock::default();
+    }
+
+    /// virtual clock starting at a given date, e.g. for reproducible sensor readings
+    pub fn starting_at(utc: DateTime<Utc>) -> VirtualClock {
+        VirtualClock {
+            origin: Instant::now(),
+            utc_origin: utc,
+            elapsed: Arc::new(Mutex::new(Duration::ZERO)),
+            real_step: DEFAULT_REAL_STEP,
+        }
+    }
+
+    /// real time a sleep leaves to the other threads, raise it if the simulator events arrive too late
+    pub fn real_step(mut self, real_step: Duration) -> VirtualClock {
+        self.real_step = real_step;
+        self
+    }
+
+    /// virtual time since the clock was created
+    pub fn elapsed(&self) -> Duration {
+        return *self.elapsed.lock().unwrap();
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap() += duration;
+    }
+
+    // advances the clock to target, unless another thread already went further
+    fn advance_to(&self, target: Duration) {
+        let mut elapsed = self.elapsed.lock().unwrap();
+        *elapsed = (*elapsed).max(target);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Instant {
+        return self.origin + self.elapsed();
+    }
+
+    fn utc_now(&self) -> DateTime<Utc> {
+        return self.utc_origin + chrono::Duration::from_std(self.elapsed()).unwrap_or_else(|_| chrono::Duration::zero());
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance_to(self.elapsed() + duration);
+        thread::sleep(self.real_wait(duration));
+    }
+
+    fn sleep_or_stop(&self, duration: Duration, stop: &AtomicBool) -> bool {
+        if stop.load(Ordering::Relaxed) {
+            return false;
+        }
+        self.sleep(duration);
+        return !stop.load(Ordering::Relaxed);
+    }
+
+    fn real_wait(&self, duration: Duration) -> Duration {
+        return duration.min(self.real_step);
+    }
+}
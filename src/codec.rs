@@ -0,0 +1,204 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use std::marker::PhantomData;
+
+use super::error::{Error, Result};
+use super::lpp::{CayenneLpp, LppRecord};
+use super::socket::Socket;
+
+use log::trace;
+
+// buffersize of the typed socket receptions, the maximum LoRaWAN application payload
+pub static DEFAULT_RECV_BUFFERSIZE: usize = 242;
+
+/// application payload format of a product, T is the type of the messages
+pub trait Codec<T> {
+    fn encode(&self, value: &T) -> Result<Vec<u8>>;
+    fn decode(&self, bytes: &[u8]) -> Result<T>;
+}
+
+/// JSON payloads, for any serde type
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for JsonCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        return Ok(serde_json::to_vec(value)?);
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T> {
+        return Ok(serde_json::from_slice(bytes)?);
+    }
+}
+
+/// CBOR payloads (RFC 8949), for any serde type
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+impl<T: Serialize + DeserializeOwned> Codec<T> for CborCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(value, &mut bytes).map_err(|e| Error::PayloadError(format!("CBOR: {}", e)))?;
+        return Ok(bytes);
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T> {
+        return ciborium::de::from_reader(bytes).map_err(|e| Error::PayloadError(format!("CBOR: {}", e)));
+    }
+}
+
+/// payloads sent and received as they are
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RawCodec;
+
+impl Codec<Vec<u8>> for RawCodec {
+    fn encode(&self, value: &Vec<u8>) -> Result<Vec<u8>> {
+        return Ok(value.clone());
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        return Ok(bytes.to_vec());
+    }
+}
+
+impl Codec<String> for RawCodec {
+    fn encode(&self, value: &String) -> Result<Vec<u8>> {
+        return Ok(value.as_bytes().to_vec());
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String> {
+        return String::from_utf8(bytes.to_vec()).map_err(|e| Error::PayloadError(e.to_string()));
+    }
+}
+
+/// Cayenne LPP payloads
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LppCodec;
+
+impl Codec<Vec<LppRecord>> for LppCodec {
+    fn encode(&self, value: &Vec<LppRecord>) -> Result<Vec<u8>> {
+        let lpp = value.iter().fold(CayenneLpp::new(), |lpp, r| lpp.add(r.channel, r.value));
+        return Ok(lpp.bytes());
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<LppRecord>> {
+        return CayenneLpp::decode(bytes);
+    }
+}
+
+/// socket sending Up messages and receiving Down messages, encoded by a codec
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+/// use serde_derive::*;
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Reading { temperature: f32 }
+///
+/// #[derive(Serialize, Deserialize)]
+/// enum Command { Reboot, SetPeriod(u32) }
+///
+/// let mut s = TypedSocket::<_, Reading, Command>::new(Socket::new(AF_LORA, SOCK_RAW), CborCodec);
+/// s.socket_mut().setblocking(true);
+/// s.send(&Reading { temperature: 21.5 }).unwrap();
+/// let cmd = s.recv().unwrap();
+/// ```
+pub struct TypedSocket<C, Up, Down> {
+    socket: Socket,
+    codec: C,
+    buffersize: usize,
+    // fn() keeps the socket Send and Sync whatever the message types
+    messages: PhantomData<fn(&Up) -> Down>,
+}
+
+impl<C, Up, Down> TypedSocket<C, Up, Down>
+where
+    C: Codec<Up> + Codec<Down>,
+{
+    pub fn new(socket: Socket, codec: C) -> TypedSocket<C, Up, Down> {
+        TypedSocket {
+            socket,
+            codec,
+            buffersize: DEFAULT_RECV_BUFFERSIZE,
+            messages: PhantomData,
+        }
+    }
+
+    /// maximum size of the received payloads, longer ones are truncated and fail to decode
+    pub fn set_buffersize(&mut self, buffersize: usize) {
+        self.buffersize = buffersize;
+    }
+
+    /// the socket options (blocking, timeout, port...) are set on the underlying socket
+    pub fn socket(&self) -> &Socket {
+        return &self.socket;
+    }
+
+    pub fn socket_mut(&mut self) -> &mut Socket {
+        return &mut self.socket;
+    }
+
+    pub fn codec(&self) -> &C {
+        return &self.codec;
+    }
+
+    pub fn into_inner(self) -> Socket {
+        return self.socket;
+    }
+
+    /// encodes and sends a message, see Socket::send_bytes()
+    pub fn send(&self, msg: &Up) -> Result<()> {
+        let bytes = Codec::<Up>::encode(&self.codec, msg)?;
+        trace!("[SOCKET][typed send]{} bytes", bytes.len());
+        return self.socket.send_bytes(&bytes);
+    }
+
+    /// receives and decodes a message, see Socket::recv_bytes()
+    pub fn recv(&self) -> Result<Down> {
+        let bytes = self.socket.recv_bytes(self.buffersize)?;
+        trace!("[SOCKET][typed recv]{} bytes", bytes.len());
+        return Codec::<Down>::decode(&self.codec, &bytes);
+    }
+}
+
+/// bytes of a hex string ("0167 00d7"), whitespace is ignored
+///
+/// ```
+/// assert_eq!(lwnsim_api_rs::decode_hex("0167 00d7").unwrap(), vec![0x01, 0x67, 0x00, 0xd7]);
+/// ```
+pub fn decode_hex(hex: &str) -> Result<Vec<u8>> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) || !digits.iter().all(u8::is_ascii_hexdigit) {
+        return Err(Error::PayloadError(format!("invalid hex string {:?}", hex)));
+    }
+    return Ok(digits.chunks(2).map(|pair| (hex_digit(pair[0]) << 4) | hex_digit(pair[1])).collect());
+}
+
+// value of an ASCII hex digit
+fn hex_digit(b: u8) -> u8 {
+    return match b {
+        b'0'..=b'9' => b - b'0',
+        b'a'..=b'f' => b - b'a' + 10,
+        _ => b - b'A' + 10,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_hex_strings() {
+        assert_eq!(decode_hex("").unwrap(), Vec::<u8>::new());
+        assert_eq!(decode_hex("00fFa5").unwrap(), vec![0x00, 0xff, 0xa5]);
+        assert_eq!(decode_hex(" 01 67\t00d7\n").unwrap(), vec![0x01, 0x67, 0x00, 0xd7]);
+    }
+
+    #[test]
+    fn decode_invalid_hex_strings() {
+        for hex in ["0", "0g", "zz", "aéb", "éé", "+1"] {
+            assert!(matches!(decode_hex(hex), Err(Error::PayloadError(_))), "{:?}", hex);
+        }
+    }
+}
@@ -0,0 +1,605 @@
+use serde_derive::*;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::error::{Error, Result};
+use super::lora_dev::{LoraDev, ABP, LORA, OTAA};
+use super::lwnsim_builder::{LwnsimBuilder, DEFAULT_NAMESPACE};
+use super::region::region_from_name;
+use super::energy::EnergyProfile;
+use super::sensor::{SensorBoard, SensorEntry};
+use super::shutdown::LwnsimSession;
+use super::socket::{Socket, AF_LORA, SOCK_RAW, SOL_LORA, SO_CONFIRMED};
+
+use log::{debug, info};
+
+// environment variables overriding the configuration file
+static ENV_URL: &str = "LWNSIM_URL";
+static ENV_NAMESPACE: &str = "LWNSIM_NAMESPACE";
+static ENV_AUTH_TOKEN: &str = "LWNSIM_AUTH_TOKEN";
+static ENV_CMD_TIMEOUT: &str = "LWNSIM_CMD_TIMEOUT"; // seconds
+static ENV_ACK_TIMEOUT: &str = "LWNSIM_ACK_TIMEOUT"; // seconds
+static ENV_RECORD: &str = "LWNSIM_RECORD";
+static ENV_DEVICE: &str = "LWNSIM_DEVICE"; // name of the device profile the following variables apply to
+static ENV_DEV_EUI: &str = "LWNSIM_DEV_EUI";
+static ENV_JOIN_EUI: &str = "LWNSIM_JOIN_EUI";
+static ENV_APP_KEY: &str = "LWNSIM_APP_KEY";
+static ENV_REGION: &str = "LWNSIM_REGION";
+static ENV_CONFIRMED: &str = "LWNSIM_CONFIRMED";
+static ENV_FPORT: &str = "LWNSIM_FPORT";
+
+// name of the device profile created from the environment when the configuration has none
+static DEFAULT_PROFILE_NAME: &str = "default";
+
+/// client configuration, loaded from a TOML file and/or environment variables
+///
+/// ```toml
+/// [simulator]
+/// url = "http://localhost:8000"
+/// cmd_timeout = 30
+///
+/// [[device]]
+/// name = "sensor-1"
+/// dev_eui = "359ac7cd01bc8aff"
+/// activation = "OTAA"
+/// join_eui = "0000000000000000"
+/// app_key = "f1c4081b61e9bee79bef58b5347e78a5"
+/// confirmed = true
+/// fport = 2
+///
+/// [[device.sensor]]
+/// channel = 1
+/// type = "temperature"
+/// mean = 18.0
+/// amplitude = 6.0
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LwnsimConfig {
+    #[serde(default)]
+    pub simulator: SimulatorConfig,
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceProfile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SimulatorConfig {
+    pub url: String,
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    #[serde(default)]
+    pub auth_token: Option<String>,
+    /// seconds
+    #[serde(default)]
+    pub cmd_timeout: Option<u64>,
+    /// seconds
+    #[serde(default)]
+    pub ack_timeout: Option<u64>,
+    #[serde(default)]
+    pub wait_ack: bool,
+    /// JSON Lines file the session is recorded to
+    #[serde(default)]
+    pub record: Option<PathBuf>,
+}
+
+fn default_namespace() -> String {
+    DEFAULT_NAMESPACE.to_string()
+}
+
+impl Default for SimulatorConfig {
+    fn default() -> Self {
+        SimulatorConfig {
+            url: "http://localhost:8000".to_string(),
+            namespace: default_namespace(),
+            auth_token: None,
+            cmd_timeout: None,
+            ack_timeout: None,
+            wait_ack: false,
+            record: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Activation {
+    #[default]
+    OTAA,
+    ABP,
+}
+
+/// device settings, keys must match the device info set in the simulator
+/// (only class A devices are simulated, the device class and address are set in the simulator device info)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceProfile {
+    pub name: String,
+    pub dev_eui: String,
+    #[serde(default)]
+    pub activation: Activation,
+    // OTAA
+    #[serde(default)]
+    pub join_eui: Option<String>,
+    #[serde(default)]
+    pub app_key: Option<String>,
+    // ABP
+    #[serde(default)]
+    pub nwk_skey: Option<String>,
+    #[serde(default)]
+    pub app_skey: Option<String>,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// default for the sockets built from the profile
+    #[serde(default)]
+    pub confirmed: bool,
+    #[serde(default)]
+    pub fport: Option<u8>,
+    /// simulated sensors, see SensorBoard
+    #[serde(default, rename = "sensor")]
+    pub sensors: Vec<SensorEntry>,
+    /// seed of the sensor readings, random readings if not set
+    #[serde(default)]
+    pub sensor_seed: Option<u64>,
+    /// battery and energy consumption model, see EnergyProfile
+    #[serde(default)]
+    pub energy: Option<EnergyProfile>,
+}
+
+fn default_region() -> String {
+    "EU868".to_string()
+}
+
+impl LwnsimConfig {
+    pub fn from_toml_str(s: &str) -> Result<LwnsimConfig> {
+        let config: LwnsimConfig =
+            toml::from_str(s).map_err(|e| Error::ConfigError(e.to_string()))?;
+        config.validate()?;
+        return Ok(config);
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<LwnsimConfig> {
+        debug!("[CONFIG][load]{:?}", path.as_ref());
+        return LwnsimConfig::from_toml_str(&fs::read_to_string(path)?);
+    }
+
+    /// configuration from the environment only
+    pub fn from_env() -> Result<LwnsimConfig> {
+        let mut config = LwnsimConfig::default();
+        config.apply_env()?;
+        config.validate()?;
+        return Ok(config);
+    }
+
+    /// loads the configuration file if any, then applies the environment overrides
+    pub fn load<P: AsRef<Path>>(path: Option<P>) -> Result<LwnsimConfig> {
+        let mut config = match path {
+            Some(p) => LwnsimConfig::from_file(p)?,
+            None => LwnsimConfig::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        return Ok(config);
+    }
+
+    /// overrides the configuration with the LWNSIM_* environment variables
+    /// device variables apply to the LWNSIM_DEVICE profile (the first one by default)
+    pub fn apply_env(&mut self) -> Result<()> {
+        if let Ok(v) = env::var(ENV_URL) {
+            self.simulator.url = v;
+        }
+        if let Ok(v) = env::var(ENV_NAMESPACE) {
+            self.simulator.namespace = v;
+        }
+        if let Ok(v) = env::var(ENV_AUTH_TOKEN) {
+            self.simulator.auth_token = Some(v);
+        }
+        if let Ok(v) = env::var(ENV_CMD_TIMEOUT) {
+            self.simulator.cmd_timeout = Some(parse_env(ENV_CMD_TIMEOUT, &v)?);
+        }
+        if let Ok(v) = env::var(ENV_ACK_TIMEOUT) {
+            self.simulator.ack_timeout = Some(parse_env(ENV_ACK_TIMEOUT, &v)?);
+        }
+        if let Ok(v) = env::var(ENV_RECORD) {
+            self.simulator.record = Some(PathBuf::from(v));
+        }
+
+        let profile = match env::var(ENV_DEVICE) {
+            Ok(name) => Some(
+                self.devices
+                    .iter_mut()
+                    .find(|d| d.name == name)
+                    .ok_or_else(|| Error::ConfigError(format!("no device profile named {}", name)))?,
+            ),
+            Err(_) => {
+                if self.devices.is_empty() {
+                    if let Ok(dev_eui) = env::var(ENV_DEV_EUI) {
+                        self.devices.push(DeviceProfile::new(DEFAULT_PROFILE_NAME, &dev_eui));
+                    }
+                }
+                self.devices.first_mut()
+            }
+        };
+        if let Some(profile) = profile {
+            if let Ok(v) = env::var(ENV_DEV_EUI) {
+                profile.dev_eui = v;
+            }
+            if let Ok(v) = env::var(ENV_JOIN_EUI) {
+                profile.join_eui = Some(v);
+            }
+            if let Ok(v) = env::var(ENV_APP_KEY) {
+                profile.app_key = Some(v);
+            }
+            if let Ok(v) = env::var(ENV_REGION) {
+                profile.region = v;
+            }
+            if let Ok(v) = env::var(ENV_CONFIRMED) {
+                profile.confirmed = parse_env(ENV_CONFIRMED, &v)?;
+            }
+            if let Ok(v) = env::var(ENV_FPORT) {
+                profile.fport = Some(parse_env(ENV_FPORT, &v)?);
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for d in &self.devices {
+            d.validate()?;
+        }
+        return Ok(());
+    }
+
+    /// device profile by name, the first profile if name is None
+    pub fn device(&self, name: Option<&str>) -> Result<&DeviceProfile> {
+        let profile = match name {
+            Some(n) => self.devices.iter().find(|d| d.name == n),
+            None => self.devices.first(),
+        };
+        return profile.ok_or_else(|| {
+            Error::ConfigError(format!("no device profile {}", name.unwrap_or("defined")))
+        });
+    }
+
+    /// client builder for a device profile
+    pub fn builder(&self, profile: &DeviceProfile) -> LwnsimBuilder {
+        let sim = &self.simulator;
+        let mut builder = LwnsimBuilder::new(&sim.url, &profile.dev_eui)
+            .namespace(&sim.namespace)
+            .wait_ack(sim.wait_ack)
+            .log_tag(&profile.name);
+        if let Some(token) = &sim.auth_token {
+            builder = builder.auth_token(token);
+        }
+        if let Some(secs) = sim.cmd_timeout {
+            builder = builder.cmd_timeout(Duration::from_secs(secs));
+        }
+        if let Some(secs) = sim.ack_timeout {
+            builder = builder.ack_timeout(Duration::from_secs(secs));
+        }
+        if let Some(path) = &sim.record {
+            builder = builder.record(path);
+        }
+        return builder;
+    }
+
+    /// connects LWNSIM to the simulator for a device profile and returns the LORA device
+    /// (the client handles a single device, connecting another profile replaces the previous one)
+    /// the device is not unlinked when the returned handle goes out of scope, see session()
+    pub fn connect(&self, name: Option<&str>) -> Result<&'static Mutex<LoraDev>> {
+        let profile = self.device(name)?;
+        info!("[CONFIG][connect]device {}", profile.name);
+        let region = region_from_name(&profile.region)
+            .ok_or_else(|| Error::ConfigError(format!("{}: unsupported region {}", profile.name, profile.region)))?;
+        self.builder(profile).connect()?;
+        let mut lora = LORA.lock().unwrap();
+        lora.set_region(region)?;
+        lora.set_energy_model(profile.energy.clone());
+        return Ok(&LORA);
+    }
+
+    /// connects as connect() and returns a session unlinking the device and disconnecting when dropped
+    pub fn session(&self, name: Option<&str>) -> Result<LwnsimSession> {
+        self.connect(name)?;
+        return Ok(LwnsimSession::new());
+    }
+}
+
+impl DeviceProfile {
+    pub fn new(name: &str, dev_eui: &str) -> DeviceProfile {
+        DeviceProfile {
+            name: name.to_string(),
+            dev_eui: dev_eui.to_string(),
+            activation: Activation::default(),
+            join_eui: None,
+            app_key: None,
+            nwk_skey: None,
+            app_skey: None,
+            region: default_region(),
+            confirmed: false,
+            fport: None,
+            sensors: Vec::new(),
+            sensor_seed: None,
+            energy: None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        if self.dev_eui.len() != 16 || !self.dev_eui.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(Error::ConfigError(format!("{}: invalid dev_eui {}", self.name, self.dev_eui)));
+        }
+        if region_from_name(&self.region).is_none() {
+            return Err(Error::ConfigError(format!("{}: unsupported region {}", self.name, self.region)));
+        }
+        // (name, value, hex length) of the keys sent on join, and the keys of the other activation
+        let (keys, unused) = match self.activation {
+            Activation::OTAA => (
+                [("join_eui", &self.join_eui, 16), ("app_key", &self.app_key, 32)],
+                [("nwk_skey", &self.nwk_skey), ("app_skey", &self.app_skey)],
+            ),
+            Activation::ABP => (
+                [("nwk_skey", &self.nwk_skey, 32), ("app_skey", &self.app_skey, 32)],
+                [("join_eui", &self.join_eui), ("app_key", &self.app_key)],
+            ),
+        };
+        for (field, key, len) in keys {
+            if let Some(k) = key {
+                if k.len() != len || !k.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(Error::ConfigError(format!("{}: invalid {} {}", self.name, field, k)));
+                }
+            }
+        }
+        // the simulator device info keys are used when neither key is set
+        if keys[0].1.is_some() != keys[1].1.is_some() {
+            return Err(Error::ConfigError(format!("{}: {} and {} must be set together", self.name, keys[0].0, keys[1].0)));
+        }
+        if let Some((field, _)) = unused.iter().find(|(_, k)| k.is_some()) {
+            return Err(Error::ConfigError(format!("{}: {} is not used by {:?} activation", self.name, field, self.activation)));
+        }
+        if let Some(port) = self.fport {
+            if !(1..=223).contains(&port) {
+                return Err(Error::ConfigError(format!("{}: invalid fport {}", self.name, port)));
+            }
+        }
+        for (i, sensor) in self.sensors.iter().enumerate() {
+            if let Some(e) = sensor.model.invalid() {
+                return Err(Error::ConfigError(format!("{}: sensor {}: {}", self.name, sensor.channel, e)));
+            }
+            if self.sensors[..i].iter().any(|s| s.channel == sensor.channel) {
+                return Err(Error::ConfigError(format!("{}: duplicate sensor channel {}", self.name, sensor.channel)));
+            }
+        }
+        if let Some(e) = self.energy.as_ref().and_then(|e| e.invalid()) {
+            return Err(Error::ConfigError(format!("{}: energy: {}", self.name, e)));
+        }
+        return Ok(());
+    }
+
+    /// join activation and authentication parameters as expected by LoraDev::join()
+    pub fn join_params(&self) -> (usize, (String, String)) {
+        return match self.activation {
+            Activation::OTAA => (
+                OTAA,
+                (
+                    self.join_eui.clone().unwrap_or_default(),
+                    self.app_key.clone().unwrap_or_default(),
+                ),
+            ),
+            Activation::ABP => (
+                ABP,
+                (
+                    self.nwk_skey.clone().unwrap_or_default(),
+                    self.app_skey.clone().unwrap_or_default(),
+                ),
+            ),
+        };
+    }
+
+    /// starts the join procedure of the device with the profile keys
+    pub fn join(&self, lora: &mut LoraDev) -> Result<()> {
+        let (activation, auth) = self.join_params();
+        return lora.join(activation, auth, Some(0), None);
+    }
+
+    /// board of the profile sensors
+    pub fn sensor_board(&self) -> SensorBoard {
+        return SensorBoard::from_entries(self.sensor_seed, &self.sensors);
+    }
+
+    /// socket with the profile confirmed and fport defaults
+    pub fn socket(&self) -> Result<Socket> {
+        let mut s = Socket::new(AF_LORA, SOCK_RAW);
+        s.setsockopt(SOL_LORA, SO_CONFIRMED, self.confirmed as usize);
+        if let Some(port) = self.fport {
+            s.bind(port as usize)?;
+        }
+        return Ok(s);
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(var: &str, value: &str) -> Result<T> {
+    return value
+        .parse::<T>()
+        .map_err(|_| Error::ConfigError(format!("invalid value {} for {}", value, var)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn otaa_profile() -> DeviceProfile {
+        let mut p = DeviceProfile::new("sensor-1", "359ac7cd01bc8aff");
+        p.join_eui = Some("0000000000000000".to_string());
+        p.app_key = Some("f1c4081b61e9bee79bef58b5347e78a5".to_string());
+        return p;
+    }
+
+    #[test]
+    fn valid_profiles() {
+        assert!(otaa_profile().validate().is_ok());
+        // keys of the simulator device info
+        assert!(DeviceProfile::new("sensor-1", "359ac7cd01bc8aff").validate().is_ok());
+        let mut p = DeviceProfile::new("sensor-1", "359ac7cd01bc8aff");
+        p.activation = Activation::ABP;
+        p.nwk_skey = Some("f1c4081b61e9bee79bef58b5347e78a5".to_string());
+        p.app_skey = Some("2cc172969d5cc26382e0ad054568ce3e".to_string());
+        assert!(p.validate().is_ok());
+    }
+
+    #[test]
+    fn unsupported_settings_are_rejected() {
+        let mut p = otaa_profile();
+        p.region = "US915".to_string();
+        assert!(p.validate().is_err());
+        let mut p = otaa_profile();
+        p.fport = Some(224);
+        assert!(p.validate().is_err());
+    }
+
+    #[test]
+    fn invalid_keys_are_rejected() {
+        let mut p = otaa_profile();
+        p.app_key = Some("f1c4".to_string());
+        assert!(p.validate().is_err());
+        let mut p = otaa_profile();
+        p.join_eui = None;
+        assert!(p.validate().is_err());
+        let mut p = otaa_profile();
+        p.app_skey = Some("2cc172969d5cc26382e0ad054568ce3e".to_string());
+        assert!(p.validate().is_err());
+    }
+
+    static CONFIG: &str = r#"
+        [simulator]
+        url = "http://sim:8000"
+        auth_token = "secret"
+        cmd_timeout = 30
+
+        [[device]]
+        name = "sensor-1"
+        dev_eui = "359ac7cd01bc8aff"
+        join_eui = "0000000000000000"
+        app_key = "f1c4081b61e9bee79bef58b5347e78a5"
+        fport = 2
+
+        [[device]]
+        name = "meter-1"
+        dev_eui = "0004a30b001c0530"
+        activation = "ABP"
+        nwk_skey = "f1c4081b61e9bee79bef58b5347e78a5"
+        app_skey = "2cc172969d5cc26382e0ad054568ce3e"
+        region = "EU868"
+        confirmed = true
+    "#;
+
+    fn config_error(res: Result<LwnsimConfig>) -> String {
+        return match res {
+            Err(Error::ConfigError(msg)) => msg,
+            res => panic!("{:?}", res),
+        };
+    }
+
+    #[test]
+    fn toml_config() {
+        let config = LwnsimConfig::from_toml_str(CONFIG).unwrap();
+        assert_eq!(config.simulator.url, "http://sim:8000");
+        assert_eq!(config.simulator.namespace, DEFAULT_NAMESPACE);
+        assert_eq!((config.simulator.cmd_timeout, config.simulator.ack_timeout), (Some(30), None));
+        assert_eq!(config.devices.len(), 2);
+        let sensor = config.device(None).unwrap();
+        assert_eq!(*sensor, {
+            let mut p = otaa_profile();
+            p.fport = Some(2);
+            p
+        });
+        let meter = config.device(Some("meter-1")).unwrap();
+        assert_eq!(meter.activation, Activation::ABP);
+        assert!(meter.confirmed);
+        assert_eq!(
+            meter.join_params(),
+            (ABP, ("f1c4081b61e9bee79bef58b5347e78a5".to_string(), "2cc172969d5cc26382e0ad054568ce3e".to_string()))
+        );
+        assert!(config.device(Some("meter-2")).is_err());
+        assert_eq!(config.builder(meter).cmd_timeout, Duration::from_secs(30));
+
+        // the simulator settings have defaults, a profile has a name and a DevEUI
+        assert_eq!(LwnsimConfig::from_toml_str("").unwrap(), LwnsimConfig::default());
+        assert!(config_error(LwnsimConfig::from_toml_str("[[device]]\nname = \"x\"")).contains("dev_eui"));
+        assert!(config_error(LwnsimConfig::from_toml_str("[simulator]\nurl = ")).contains("url"));
+    }
+
+    #[test]
+    fn toml_config_rejected() {
+        let profile = |extra: &str| format!("[[device]]\nname = \"d\"\ndev_eui = \"359ac7cd01bc8aff\"\n{}", extra);
+        assert!(LwnsimConfig::from_toml_str(&profile("")).is_ok());
+        // the device class and address are not settings of the profile
+        assert!(config_error(LwnsimConfig::from_toml_str(&profile("class = \"A\""))).contains("unknown field `class`"));
+        assert!(config_error(LwnsimConfig::from_toml_str(&profile("dev_addr = \"26011bda\""))).contains("unknown field `dev_addr`"));
+        assert!(config_error(LwnsimConfig::from_toml_str(&profile("activation = \"OTAB\""))).contains("OTAB"));
+        assert_eq!(config_error(LwnsimConfig::from_toml_str(&profile("fport = 0"))), "d: invalid fport 0");
+        assert_eq!(config_error(LwnsimConfig::from_toml_str(&profile("region = \"AS923\""))), "d: unsupported region AS923");
+        let invalid_eui = "[[device]]\nname = \"d\"\ndev_eui = \"359ac7cd01bc8af\"";
+        assert_eq!(config_error(LwnsimConfig::from_toml_str(invalid_eui)), "d: invalid dev_eui 359ac7cd01bc8af");
+    }
+
+    #[test]
+    fn example_config() {
+        let config = LwnsimConfig::from_file(concat!(env!("CARGO_MANIFEST_DIR"), "/examples/lwnsim.toml")).unwrap();
+        assert_eq!(config.simulator.url, "http://localhost:8000");
+        assert_eq!((config.simulator.cmd_timeout, config.simulator.ack_timeout), (Some(10), Some(2)));
+        let profile = config.device(None).unwrap();
+        assert_eq!((profile.name.as_str(), profile.dev_eui.as_str()), ("simple", "359ac7cd01bc8aff"));
+        assert_eq!(profile.join_params(), otaa_profile().join_params());
+        assert!(profile.confirmed);
+        assert_eq!(profile.fport, Some(2));
+    }
+
+    // the only test setting LWNSIM_* variables: the environment is process wide
+    #[test]
+    fn environment_overrides() {
+        let vars = [
+            (ENV_URL, "http://env:8000"),
+            (ENV_AUTH_TOKEN, "token"),
+            (ENV_CMD_TIMEOUT, "5"),
+            (ENV_DEVICE, "meter-1"),
+            (ENV_DEV_EUI, "0004a30b001c0531"),
+            (ENV_REGION, "EU868"),
+            (ENV_CONFIRMED, "false"),
+            (ENV_FPORT, "10"),
+        ];
+        for (var, value) in vars {
+            env::set_var(var, value);
+        }
+        let mut config = LwnsimConfig::from_toml_str(CONFIG).unwrap();
+        config.apply_env().unwrap();
+        assert_eq!(config.simulator.url, "http://env:8000");
+        assert_eq!(config.simulator.auth_token.as_deref(), Some("token"));
+        assert_eq!(config.simulator.cmd_timeout, Some(5));
+        // device variables apply to the LWNSIM_DEVICE profile only
+        let meter = config.device(Some("meter-1")).unwrap();
+        assert_eq!((meter.dev_eui.as_str(), meter.confirmed, meter.fport), ("0004a30b001c0531", false, Some(10)));
+        assert_eq!(config.device(Some("sensor-1")).unwrap().dev_eui, "359ac7cd01bc8aff");
+
+        env::set_var(ENV_DEVICE, "meter-2");
+        let res = LwnsimConfig::from_toml_str(CONFIG).unwrap().apply_env();
+        assert!(matches!(res, Err(Error::ConfigError(msg)) if msg == "no device profile named meter-2"));
+
+        // first profile by default, created from LWNSIM_DEV_EUI without configuration file
+        env::remove_var(ENV_DEVICE);
+        env::set_var(ENV_JOIN_EUI, "0000000000000001");
+        env::set_var(ENV_APP_KEY, "f1c4081b61e9bee79bef58b5347e78a5");
+        let config = LwnsimConfig::load(None::<&str>).unwrap();
+        let profile = config.device(None).unwrap();
+        assert_eq!((profile.name.as_str(), profile.dev_eui.as_str()), (DEFAULT_PROFILE_NAME, "0004a30b001c0531"));
+        assert_eq!(profile.join_eui.as_deref(), Some("0000000000000001"));
+
+        env::set_var(ENV_FPORT, "port");
+        assert_eq!(config_error(LwnsimConfig::from_env()), "invalid value port for LWNSIM_FPORT");
+        env::set_var(ENV_FPORT, "0");
+        assert_eq!(config_error(LwnsimConfig::from_env()), "default: invalid fport 0");
+
+        for var in [ENV_JOIN_EUI, ENV_APP_KEY].iter().chain(vars.iter().map(|(var, _)| var)) {
+            env::remove_var(var);
+        }
+    }
+}
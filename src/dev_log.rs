@@ -0,0 +1,148 @@
+use chrono::{DateTime, Utc};
+use serde_derive::*;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use log::{log, Level};
+
+// log target of the diagnostics forwarded from the simulator
+static DEV_LOG_TARGET: &str = "lwnsim::simulator";
+
+lazy_static! {
+    pub static ref DEV_LOGS: Mutex<DevLogs> = Mutex::new(DevLogs::new());
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DevLogKind {
+    /// "dev-log" event
+    Log,
+    /// "dev-error" event
+    Error,
+}
+
+/// diagnostic record sent by the simulator for the linked device
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct DevLog {
+    pub kind: DevLogKind,
+    pub level: Level,
+    pub dev_eui: Option<String>,
+    pub msg: String,
+    /// time at which the record was received by the client
+    pub timestamp: DateTime<Utc>,
+}
+
+// dev-log / dev-error payload, plain (non json) strings are used as the message
+#[derive(Deserialize, Debug)]
+struct DevLogPayload {
+    #[serde(rename = "DevEUI", default)]
+    dev_eui: Option<String>,
+    #[serde(rename = "Level", default)]
+    level: Option<String>,
+    #[serde(rename = "Msg", alias = "Error")]
+    msg: String,
+}
+
+impl DevLog {
+    /// parses a dev-log or dev-error payload
+    pub fn parse(kind: DevLogKind, payload: &str) -> DevLog {
+        let default_level = match kind {
+            DevLogKind::Log => Level::Info,
+            DevLogKind::Error => Level::Error,
+        };
+        let (dev_eui, level, msg) = match serde_json::from_str::<DevLogPayload>(payload) {
+            Ok(pl) => (
+                pl.dev_eui,
+                pl.level
+                    .and_then(|l| l.parse::<Level>().ok())
+                    .unwrap_or(default_level),
+                pl.msg,
+            ),
+            Err(_) => (None, default_level, payload.trim_matches('"').to_string()),
+        };
+        DevLog {
+            kind,
+            level,
+            dev_eui,
+            msg,
+            timestamp: Utc::now(),
+        }
+    }
+}
+
+/// forwards the simulator diagnostics to the log crate and to the subscribers
+#[derive(Debug)]
+pub struct DevLogs {
+    subscribers: Vec<Sender<DevLog>>,
+}
+
+impl DevLogs {
+    pub fn new() -> DevLogs {
+        DevLogs {
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<DevLog> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        return rx;
+    }
+
+    pub fn handle_dev_log(&mut self, record: DevLog) {
+        log!(
+            target: DEV_LOG_TARGET,
+            record.level,
+            "[LWNSIM][{:?}][{}]{}",
+            record.kind,
+            record.dev_eui.as_deref().unwrap_or("-"),
+            record.msg
+        );
+        // dropped receivers are unsubscribed
+        self.subscribers.retain(|tx| tx.send(record.clone()).is_ok());
+    }
+}
+
+impl Default for DevLogs {
+    fn default() -> Self {
+        DevLogs::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dev_log_payloads() {
+        let log = DevLog::parse(DevLogKind::Log, r#"{"DevEUI": "0102", "Level": "debug", "Msg": "uplink sent"}"#);
+        assert_eq!(log.kind, DevLogKind::Log);
+        assert_eq!(log.level, Level::Debug);
+        assert_eq!(log.dev_eui.as_deref(), Some("0102"));
+        assert_eq!(log.msg, "uplink sent");
+
+        // default level of the event kind, "Error" key of dev-error
+        let log = DevLog::parse(DevLogKind::Error, r#"{"Error": "device not joined"}"#);
+        assert_eq!((log.level, log.dev_eui, log.msg.as_str()), (Level::Error, None, "device not joined"));
+        let log = DevLog::parse(DevLogKind::Log, r#"{"Level": "verbose", "Msg": "x"}"#);
+        assert_eq!(log.level, Level::Info);
+
+        // plain strings are the message
+        let log = DevLog::parse(DevLogKind::Error, r#""gateway unreachable""#);
+        assert_eq!((log.level, log.msg.as_str()), (Level::Error, "gateway unreachable"));
+        let log = DevLog::parse(DevLogKind::Log, "started");
+        assert_eq!((log.level, log.msg.as_str()), (Level::Info, "started"));
+    }
+
+    #[test]
+    fn dropped_subscribers_are_removed() {
+        let mut logs = DevLogs::new();
+        let rx = logs.subscribe();
+        drop(logs.subscribe());
+        logs.handle_dev_log(DevLog::parse(DevLogKind::Log, "first"));
+        assert_eq!(logs.subscribers.len(), 1);
+        assert_eq!(rx.try_recv().unwrap().msg, "first");
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use chrono::{DateTime, Utc};
+use serde_derive::*;
+use std::collections::VecDeque;
+
+// log
+use log::{trace, warn};
+
+/// default number of downlink frames kept by the client before dropping the oldest ones
+pub static DEFAULT_DOWNLINK_QUEUE_CAPACITY: usize = 16;
+
+/// downlink frame received from the simulator
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Downlink {
+    pub payload: Vec<u8>,
+    pub port: Option<u8>,
+    pub mtype: String,
+    /// time at which the frame was fetched from the simulator
+    pub timestamp: DateTime<Utc>,
+    /// set when the payload has been truncated to the receive buffer size (as MSG_TRUNC for a BSD recv)
+    pub truncated: bool,
+}
+
+impl Downlink {
+    pub fn new(payload: Vec<u8>, port: Option<u8>, mtype: String) -> Downlink {
+        Downlink {
+            payload,
+            port,
+            mtype,
+            timestamp: Utc::now(),
+            truncated: false,
+        }
+    }
+
+    /// truncates the payload to `buffersize` bytes, the remaining bytes are discarded
+    /// as a BSD recv does on a datagram socket
+    pub fn truncate(&mut self, buffersize: usize) {
+        if self.payload.len() > buffersize {
+            self.payload.truncate(buffersize);
+            self.truncated = true;
+        }
+    }
+}
+
+/// bounded FIFO of the downlink frames received by a device
+#[derive(Debug)]
+pub struct DownlinkQueue {
+    frames: VecDeque<Downlink>,
+    capacity: usize,
+    dropped: usize,
+}
+
+impl DownlinkQueue {
+    pub fn new(capacity: usize) -> DownlinkQueue {
+        DownlinkQueue {
+            frames: VecDeque::with_capacity(capacity),
+            capacity,
+            dropped: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        return self.capacity;
+    }
+
+    /// changes the queue capacity, the oldest frames are dropped if the queue is shrunk below its length
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.frames.len() > self.capacity {
+            self.drop_oldest();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        return self.frames.len();
+    }
+
+    /// number of frames dropped since the queue creation because of overflows
+    pub fn dropped(&self) -> usize {
+        return self.dropped;
+    }
+
+    /// queues a frame, drops the oldest one if the queue is full
+    pub fn push(&mut self, frame: Downlink) {
+        if self.capacity == 0 {
+            self.dropped += 1;
+            warn!("[DOWNLINK_QUEUE][overflow]frame dropped (queue capacity is 0)");
+            return;
+        }
+        while self.frames.len() >= self.capacity {
+            self.drop_oldest();
+        }
+        trace!("[DOWNLINK_QUEUE][push]{:?}", frame);
+        self.frames.push_back(frame);
+    }
+
+    pub fn pop(&mut self) -> Option<Downlink> {
+        return self.frames.pop_front();
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    fn drop_oldest(&mut self) {
+        if let Some(frame) = self.frames.pop_front() {
+            self.dropped += 1;
+            warn!(
+                "[DOWNLINK_QUEUE][overflow]frame dropped (total dropped= {}) {:?}",
+                self.dropped, frame
+            );
+        }
+    }
+}
+
+impl Default for DownlinkQueue {
+    fn default() -> Self {
+        DownlinkQueue::new(DEFAULT_DOWNLINK_QUEUE_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn downlink(payload: &[u8]) -> Downlink {
+        return Downlink::new(payload.to_vec(), Some(1), "UnconfirmedDataDown".to_string());
+    }
+
+    #[test]
+    fn truncate_binary_payload() {
+        let mut dl = downlink(&[0x01, 0x67, 0x00, 0xd7]);
+        dl.truncate(3);
+        assert_eq!(dl.payload, vec![0x01, 0x67, 0x00]);
+        assert!(dl.truncated);
+    }
+
+    #[test]
+    fn truncate_text_payload_at_byte_count() {
+        // the cut falls inside the 2 bytes of 'é'
+        let mut dl = downlink("aéb".as_bytes());
+        dl.truncate(2);
+        assert_eq!(dl.payload, vec![b'a', 0xc3]);
+        assert!(dl.truncated);
+    }
+
+    #[test]
+    fn no_truncation_when_payload_fits() {
+        let mut dl = downlink(b"hello");
+        dl.truncate(5);
+        assert_eq!(dl.payload, b"hello".to_vec());
+        assert!(!dl.truncated);
+        dl.truncate(64);
+        assert!(!dl.truncated);
+    }
+
+    #[test]
+    fn queue_is_fifo() {
+        let mut queue = DownlinkQueue::new(4);
+        queue.push(downlink(b"1"));
+        queue.push(downlink(b"2"));
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop().unwrap().payload, b"1".to_vec());
+        assert_eq!(queue.pop().unwrap().payload, b"2".to_vec());
+        assert!(queue.pop().is_none());
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn queue_overflow_drops_oldest() {
+        let mut queue = DownlinkQueue::new(2);
+        for p in [b"1", b"2", b"3"] {
+            queue.push(downlink(p));
+        }
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.pop().unwrap().payload, b"2".to_vec());
+    }
+
+    #[test]
+    fn queue_shrink_and_zero_capacity() {
+        let mut queue = DownlinkQueue::new(3);
+        for p in [b"1", b"2", b"3"] {
+            queue.push(downlink(p));
+        }
+        queue.set_capacity(1);
+        assert_eq!(queue.len(), 1);
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(queue.pop().unwrap().payload, b"3".to_vec());
+
+        queue.set_capacity(0);
+        queue.push(downlink(b"4"));
+        assert_eq!(queue.len(), 0);
+        assert_eq!(queue.dropped(), 3);
+    }
+}
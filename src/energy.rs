@@ -0,0 +1,305 @@
+use serde_derive::*;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use super::clock::clock;
+use super::lora_events::LoraEvents;
+
+// frame overhead of a data uplink: MHDR, FHDR without FOpts, FPort, MIC
+pub static DATA_FRAME_OVERHEAD: usize = 1 + 7 + 1 + 4;
+pub static JOIN_REQUEST_LEN: usize = 23;
+pub static JOIN_ACCEPT_LEN: usize = 17;
+// DevStatusAns battery levels: 0 external power, 1..=254 level, 255 unknown
+static BATTERY_LEVEL_MIN: u8 = 1;
+static BATTERY_LEVEL_MAX: u8 = 254;
+
+lazy_static! {
+    pub(crate) static ref TX_ENERGY: Mutex<TxEnergy> = Mutex::new(TxEnergy::default());
+}
+
+/// electrical characteristics of a device, the defaults are those of a SX1276 based node on a 2400 mAh cell
+///
+/// ```toml
+/// [device.energy]
+/// battery_capacity = 2400.0 # mAh
+/// sleep_current = 5.0 # µA
+/// report_battery = true
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct EnergyProfile {
+    /// V
+    pub voltage: f64,
+    /// mAh
+    pub battery_capacity: f64,
+    /// % at the start of the simulation
+    pub initial_charge: f64,
+    /// TX current in mA at a TX power in dBm, [dBm, mA] points interpolated linearly
+    pub tx_current: Vec<[f64; 2]>,
+    /// mA
+    pub rx_current: f64,
+    /// µA
+    pub sleep_current: f64,
+    /// time a receive window stays open when no frame is received
+    #[serde(with = "millis")]
+    pub rx_window: Duration,
+    /// reports the battery level to the network (DevStatusAns)
+    pub report_battery: bool,
+}
+
+impl Default for EnergyProfile {
+    fn default() -> Self {
+        EnergyProfile {
+            voltage: 3.3,
+            battery_capacity: 2400.0,
+            initial_charge: 100.0,
+            tx_current: vec![[2.0, 24.0], [8.0, 28.0], [14.0, 44.0], [16.0, 87.0]],
+            rx_current: 11.5,
+            sleep_current: 5.0,
+            rx_window: Duration::from_millis(30),
+            report_battery: false,
+        }
+    }
+}
+
+impl EnergyProfile {
+    /// reason why the profile is invalid, None if valid
+    pub fn invalid(&self) -> Option<&'static str> {
+        if self.voltage <= 0.0 || self.battery_capacity <= 0.0 {
+            return Some("voltage and battery capacity must be positive");
+        }
+        if !(0.0..=100.0).contains(&self.initial_charge) {
+            return Some("initial charge must be in 0..=100 %");
+        }
+        if self.tx_current.is_empty() || self.tx_current.windows(2).any(|w| w[0][0] >= w[1][0]) {
+            return Some("TX current points must be sorted by increasing TX power");
+        }
+        if self.rx_current < 0.0 || self.sleep_current < 0.0 || self.tx_current.iter().any(|p| p[1] < 0.0) {
+            return Some("negative current");
+        }
+        return None;
+    }
+
+    /// mA at a TX power in dBm, clamped to the table bounds
+    pub fn tx_current_at(&self, dbm: f64) -> f64 {
+        let points = &self.tx_current;
+        match (points.first(), points.last()) {
+            (Some(first), _) if dbm <= first[0] => return first[1],
+            (_, Some(last)) if dbm >= last[0] => return last[1],
+            (None, _) | (_, None) => return 0.0,
+            _ => {}
+        }
+        for w in points.windows(2) {
+            if dbm <= w[1][0] {
+                let f = (dbm - w[0][0]) / (w[1][0] - w[0][0]);
+                return w[0][1] + f * (w[1][1] - w[0][1]);
+            }
+        }
+        return points[points.len() - 1][1];
+    }
+}
+
+// durations in ms in the configuration files
+mod millis {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        return s.serialize_u64(d.as_millis() as u64);
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        return Ok(Duration::from_millis(u64::deserialize(d)?));
+    }
+}
+
+/// consumption of the device since the model was attached, charges in mAh
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct EnergyReport {
+    pub elapsed_secs: f64,
+    pub uplinks: u32,
+    pub tx_secs: f64,
+    pub rx_secs: f64,
+    pub tx_charge: f64,
+    pub rx_charge: f64,
+    pub sleep_charge: f64,
+    pub total_charge: f64,
+    /// J
+    pub energy: f64,
+    /// % of the battery capacity
+    pub state_of_charge: f64,
+    /// at the average consumption so far, None before any consumption
+    pub estimated_life_hours: Option<f64>,
+}
+
+/// energy accounting of a device: TX from the airtime and TX power, receive windows and sleep current
+/// the sleep time is measured with the crate clock, see set_clock()
+#[derive(Debug, Clone)]
+pub struct EnergyModel {
+    profile: EnergyProfile,
+    start: Instant,
+    uplinks: u32,
+    tx_time: Duration,
+    rx_time: Duration,
+    // mA.s
+    tx_charge: f64,
+    rx_charge: f64,
+    reported_level: Option<u8>,
+}
+
+impl EnergyModel {
+    pub fn new(profile: EnergyProfile) -> EnergyModel {
+        EnergyModel {
+            profile,
+            start: clock().now(),
+            uplinks: 0,
+            tx_time: Duration::ZERO,
+            rx_time: Duration::ZERO,
+            tx_charge: 0.0,
+            rx_charge: 0.0,
+            reported_level: None,
+        }
+    }
+
+    pub fn profile(&self) -> &EnergyProfile {
+        return &self.profile;
+    }
+
+    /// uplink of the given airtime followed by the two receive windows of a class A device
+    pub fn record_uplink(&mut self, airtime: Duration, tx_power_dbm: f64) {
+        self.uplinks += 1;
+        self.tx_time += airtime;
+        self.tx_charge += self.profile.tx_current_at(tx_power_dbm) * airtime.as_secs_f64();
+        self.record_rx(2 * self.profile.rx_window);
+    }
+
+    /// downlink received in a receive window, in addition to the window itself
+    pub fn record_downlink(&mut self, airtime: Duration) {
+        self.record_rx(airtime);
+    }
+
+    fn record_rx(&mut self, time: Duration) {
+        self.rx_time += time;
+        self.rx_charge += self.profile.rx_current * time.as_secs_f64();
+    }
+
+    pub fn report(&self) -> EnergyReport {
+        let elapsed = clock().now().saturating_duration_since(self.start);
+        let sleep_time = elapsed.saturating_sub(self.tx_time + self.rx_time);
+        let sleep_charge = self.profile.sleep_current / 1000.0 * sleep_time.as_secs_f64() / 3600.0;
+        let tx_charge = self.tx_charge / 3600.0;
+        let rx_charge = self.rx_charge / 3600.0;
+        let total_charge = tx_charge + rx_charge + sleep_charge;
+        let initial = self.profile.battery_capacity * self.profile.initial_charge / 100.0;
+        let state_of_charge = if self.profile.battery_capacity > 0.0 {
+            ((initial - total_charge) / self.profile.battery_capacity * 100.0).clamp(0.0, 100.0)
+        } else {
+            0.0
+        };
+        let hours = elapsed.as_secs_f64() / 3600.0;
+        let estimated_life_hours = if total_charge > 0.0 && hours > 0.0 {
+            Some(initial / (total_charge / hours))
+        } else {
+            None
+        };
+        return EnergyReport {
+            elapsed_secs: elapsed.as_secs_f64(),
+            uplinks: self.uplinks,
+            tx_secs: self.tx_time.as_secs_f64(),
+            rx_secs: self.rx_time.as_secs_f64(),
+            tx_charge,
+            rx_charge,
+            sleep_charge,
+            total_charge,
+            energy: total_charge * 3.6 * self.profile.voltage,
+            state_of_charge,
+            estimated_life_hours,
+        };
+    }
+
+    /// battery level as reported in DevStatusAns (1 empty ..= 254 full)
+    pub fn battery_level(&self) -> u8 {
+        let soc = self.report().state_of_charge;
+        let span = (BATTERY_LEVEL_MAX - BATTERY_LEVEL_MIN) as f64;
+        return BATTERY_LEVEL_MIN + (soc / 100.0 * span).round() as u8;
+    }
+
+    /// the battery level to report to the network, if enabled and changed since the last report
+    pub(crate) fn level_to_report(&self) -> Option<u8> {
+        let level = self.battery_level();
+        if self.profile.report_battery && self.reported_level != Some(level) {
+            return Some(level);
+        }
+        return None;
+    }
+
+    pub(crate) fn set_reported_level(&mut self, level: u8) {
+        self.reported_level = Some(level);
+    }
+}
+
+/// uplinks emitted and waiting for their TX event, only the sent ones are accounted
+/// the TX events arrive on the socket thread while the device may be locked by a command,
+/// the device takes the sent uplinks before its next command
+#[derive(Debug, Default)]
+pub(crate) struct TxEnergy {
+    // airtime and TX power (dBm) of the uplinks, oldest first
+    pending: VecDeque<(Duration, f64)>,
+    sent: Vec<(Duration, f64)>,
+}
+
+impl TxEnergy {
+    pub fn push(&mut self, airtime: Duration, tx_power_dbm: f64) {
+        self.pending.push_back((airtime, tx_power_dbm));
+    }
+
+    /// forgets the last uplink pushed, e.g. when it could not be emitted
+    pub fn cancel_last(&mut self) {
+        self.pending.pop_back();
+    }
+
+    pub fn handle_lora_event(&mut self, event_val: LoraEvents) {
+        if event_val.intersects(LoraEvents::TX_PACKET_EVENT | LoraEvents::TX_FAILED_EVENT) {
+            if let Some(uplink) = self.pending.pop_front() {
+                if event_val.contains(LoraEvents::TX_PACKET_EVENT) {
+                    self.sent.push(uplink);
+                }
+            }
+        }
+    }
+
+    /// returns (and consumes) the uplinks sent so far
+    pub fn take_sent(&mut self) -> Vec<(Duration, f64)> {
+        return std::mem::take(&mut self.sent);
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.sent.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_uplinks_are_not_accounted() {
+        let mut tx = TxEnergy::default();
+        tx.push(Duration::from_millis(50), 14.0);
+        tx.push(Duration::from_millis(60), 14.0);
+        tx.push(Duration::from_millis(70), 14.0);
+        tx.cancel_last();
+        tx.handle_lora_event(LoraEvents::TX_FAILED_EVENT);
+        tx.handle_lora_event(LoraEvents::RX_PACKET_EVENT);
+        assert!(tx.take_sent().is_empty());
+        tx.handle_lora_event(LoraEvents::TX_PACKET_EVENT);
+        assert_eq!(tx.take_sent(), vec![(Duration::from_millis(60), 14.0)]);
+        // no uplink waiting for this event
+        tx.handle_lora_event(LoraEvents::TX_PACKET_EVENT);
+        assert!(tx.take_sent().is_empty());
+    }
+}
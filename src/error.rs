@@ -15,6 +15,14 @@ pub enum Error{
    SocketioError(#[from] SocketioErrorKind),
    #[error("Json error : {0}")]
    InvalidJson(#[from] JsonError),
+   #[error("Config error : {0}")]
+   ConfigError(String),
+   #[error("Io error : {0}")]
+   IoError(#[from] std::io::Error),
+   #[error("Replay error : {0}")]
+   ReplayError(String),
+   #[error("Payload error : {0}")]
+   PayloadError(String),
 }
 
 
@@ -10,16 +10,78 @@
 //! The intent is to simulate a LORA-capable device as a rust CLI program.
 //!
 //! This is still work in progress, but I would be happy to share with anyone interested in the simulation of Lora devices.
+//!
+//! **A process drives a single device**: the client (`LWNSIM`) and the device (`LORA`) are process wide singletons.
+//! Several devices, e.g. for load tests, run in several processes: `lwnsim-cli loadgen` spawns one worker process
+//! per device (see `run_device()` and `LoadStats`), the library API cannot drive several devices in one process.
+
+#![allow(clippy::needless_return)]
+#![allow(clippy::result_large_err)]
 
 mod lwnsim;
 mod lwnsim_cmd;
+mod lwnsim_builder;
+mod downlink;
+mod mac_cmd;
+mod adr;
+mod region;
+mod dev_log;
+mod ack;
+mod config;
+mod scenario;
+mod mock_sim;
+mod loadgen;
+mod recorder;
+mod replay;
 mod error;
 mod lora_dev;
 mod lora_events;
+mod lora_stats;
 mod socket;
+mod lpp;
+mod codec;
+mod lorawan;
+mod raw_frame;
+mod sensor;
+mod energy;
+mod clock;
+mod scheduler;
+mod shutdown;
+mod metrics;
 
-pub use lwnsim::LWNSIM;
+pub use lwnsim::{CmdOptions, LwnsimStatus, LWNSIM, DEFAULT_ACK_TIMEOUT, DEFAULT_CMD_TIMEOUT};
+pub use lwnsim_builder::{LwnsimBuilder, ReconnectPolicy, DEFAULT_NAMESPACE};
 pub use lora_dev::*;
 pub use socket::*;
+pub use lpp::{CayenneLpp, LppRecord, LppValue};
+pub use codec::{decode_hex, Codec, CborCodec, JsonCodec, LppCodec, RawCodec, TypedSocket, DEFAULT_RECV_BUFFERSIZE};
+pub use lorawan::{
+    aes_cmac, data_mic, encrypt_frm_payload, key_from_hex, AesKey, FCtrl, Fhdr, FramePayload, JoinAccept, JoinRequest, MType,
+    MacPayload, PhyPayload, SessionKeys,
+};
+pub use lora_events::LoraEvents;
+pub use lora_stats::LoraStats;
+pub use downlink::{Downlink, DEFAULT_DOWNLINK_QUEUE_CAPACITY};
+pub use mac_cmd::{DeviceTimeAns, LinkCheckAns, MacCommand};
+pub use adr::AdrState;
+pub use region::{Channel, Modulation, RegionParams, EU868_PARAMS};
+pub use dev_log::{DevLog, DevLogKind};
+pub use raw_frame::{FrameDirection, RawFrame};
+pub use sensor::{
+    BatteryModel, CounterModel, DiurnalModel, GpsTrack, SensorBoard, SensorConfig, SensorDriver, SensorEntry, SensorModel,
+};
+pub use clock::{clock, set_clock, Clock, SystemClock, VirtualClock, DEFAULT_REAL_STEP};
+pub use scheduler::{Jitter, RetryPolicy, SchedulerStats, UplinkScheduler, DEFAULT_TX_TIMEOUT};
+pub use shutdown::{
+    handle_signals, request_shutdown, shutdown, shutdown_flag, LwnsimSession, DEFAULT_SHUTDOWN_TIMEOUT, DEFAULT_SIGNAL_DEADLINE,
+};
+pub use metrics::{render_metrics, reset_metrics, MetricsServer};
+pub use energy::{EnergyModel, EnergyProfile, EnergyReport};
+pub use scenario::{Scenario, ScenarioFile, ScenarioReport, Step, StepReport, StepStatus, SuiteReport};
+pub use mock_sim::{MockOptions, MockSimulator};
+pub use recorder::{Record, RecordEntry};
+pub use replay::{replay, Replayer};
+pub use loadgen::{run_device, Latency, LoadEvent, LoadProfile, LoadReport, LoadStats, PayloadSize};
+pub use config::{Activation, DeviceProfile, LwnsimConfig, SimulatorConfig};
 pub use error::Error as LwnsimError;
 pub use lwnsim_cmd::CmdErrorKind;
\ No newline at end of file
@@ -0,0 +1,474 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_derive::*;
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::TryRecvError;
+use std::time::{Duration, Instant};
+
+use super::clock::clock;
+use super::config::DeviceProfile;
+use super::error::{Error, Result};
+use super::lora_dev::{LoraDevStatus, LORA};
+use super::lora_events::LoraEvents;
+use super::lwnsim::{CmdOptions, LWNSIM};
+use super::lwnsim_cmd::CmdErrorKind;
+use super::socket::{CONFIRMED_DATA_UP, UNCONFIRMED_DATA_UP};
+
+use log::{info, warn};
+
+// longest wait of the device loop, bounds the reaction time to a stop request
+static LOOP_PERIOD: Duration = Duration::from_millis(200);
+// the lora events are polled, waits follow the crate clock (see set_clock())
+static EVENT_POLL_PERIOD: Duration = Duration::from_millis(10);
+static DOWNLINK_BUFFER_SIZE: usize = 256;
+// uplinks waiting for their downlink, the oldest ones are dropped (most uplinks are not answered)
+static MAX_PENDING_UPLINKS: usize = 64;
+
+/// size of the uplink payloads: "12" (fixed), "8-32" (uniform, bounds included) or "4,12,51" (one of)
+#[derive(Debug, Clone, PartialEq)]
+pub enum PayloadSize {
+    Fixed(usize),
+    Uniform(usize, usize),
+    Choice(Vec<usize>),
+}
+
+impl PayloadSize {
+    pub fn sample<R: Rng>(&self, rng: &mut R) -> usize {
+        return match self {
+            PayloadSize::Fixed(n) => *n,
+            PayloadSize::Uniform(min, max) => rng.gen_range(*min..=*max),
+            PayloadSize::Choice(sizes) => sizes[rng.gen_range(0..sizes.len())],
+        };
+    }
+}
+
+impl FromStr for PayloadSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PayloadSize> {
+        let invalid = || Error::ConfigError(format!("invalid payload size '{}'", s));
+        let parse = |v: &str| v.trim().parse::<usize>().map_err(|_| invalid());
+        if let Some((min, max)) = s.split_once('-') {
+            let (min, max) = (parse(min)?, parse(max)?);
+            if min > max {
+                return Err(invalid());
+            }
+            return Ok(PayloadSize::Uniform(min, max));
+        }
+        if s.contains(',') {
+            let sizes = s.split(',').map(parse).collect::<Result<Vec<_>>>()?;
+            return Ok(PayloadSize::Choice(sizes));
+        }
+        return Ok(PayloadSize::Fixed(parse(s)?));
+    }
+}
+
+/// traffic generated by each device
+#[derive(Debug, Clone)]
+pub struct LoadProfile {
+    /// mean time between two uplinks
+    pub interval: Duration,
+    /// the interval is drawn uniformly in interval +/- jitter
+    pub jitter: Duration,
+    pub payload_size: PayloadSize,
+    /// share of ConfirmedDataUp uplinks, in 0.0..=1.0
+    pub confirmed_ratio: f64,
+    /// simulator default port if None
+    pub fport: Option<u8>,
+    pub join_timeout: Duration,
+    /// join attempts after the first one
+    pub join_retries: u32,
+    /// time to wait for the TX event of an uplink, the next uplink is delayed until then
+    pub tx_timeout: Duration,
+    /// random payloads and intervals are reproducible when set
+    pub seed: Option<u64>,
+}
+
+impl Default for LoadProfile {
+    fn default() -> Self {
+        LoadProfile {
+            interval: Duration::from_secs(60),
+            jitter: Duration::ZERO,
+            payload_size: PayloadSize::Fixed(12),
+            confirmed_ratio: 0.0,
+            fport: None,
+            join_timeout: Duration::from_secs(30),
+            join_retries: 2,
+            tx_timeout: Duration::from_secs(10),
+            seed: None,
+        }
+    }
+}
+
+/// what happened to a device, reported by run_device and aggregated by LoadStats
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LoadEvent {
+    Join { dev_eui: String, ok: bool, ms: u64 },
+    Uplink { confirmed: bool, size: usize },
+    Tx { ok: bool },
+    /// latency_ms is the time between the uplink that caused the downlink and the RX_PACKET_EVENT
+    Downlink { latency_ms: u64 },
+    Error { dev_eui: String, error: String },
+}
+
+/// drives the connected device: links it, joins and sends uplinks as set by load until stop is set
+/// the device is unlinked before returning
+///
+/// **one device per process**: LWNSIM and LORA are process wide singletons, so run_device() drives the
+/// single device of the process and cannot be called from several threads for several devices.
+/// A multi-device load runs one process per device and aggregates their LoadEvents with LoadStats,
+/// as `lwnsim-cli loadgen` does with its hidden `loadgen-worker` subcommand.
+pub fn run_device(profile: &DeviceProfile, load: &LoadProfile, stop: &AtomicBool, emit: &mut dyn FnMut(LoadEvent)) -> Result<()> {
+    let mut rng = match load.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let events = LWNSIM.lock().unwrap().subscribe_lora_events();
+    link(profile)?;
+    let res = run_joined(profile, load, stop, &mut rng, &events, emit);
+    if let Err(e) = LORA.lock().unwrap().unlink_dev() {
+        warn!("[LOADGEN][{}][unlink]{}", profile.dev_eui, e);
+    }
+    return res;
+}
+
+// a device already linked in the simulator is accepted
+fn link(profile: &DeviceProfile) -> Result<()> {
+    let mut lora = LORA.lock().unwrap();
+    match lora.activate() {
+        Err(Error::CmdError(CmdErrorKind::DeviceLinked)) => {
+            lora.set_status(LoraDevStatus::Active);
+        }
+        res => res?,
+    }
+    info!("[LOADGEN][{}]linked", profile.dev_eui);
+    return Ok(());
+}
+
+fn join(profile: &DeviceProfile, load: &LoadProfile, stop: &AtomicBool, emit: &mut dyn FnMut(LoadEvent)) -> Result<()> {
+    let clock = clock();
+    for _ in 0..=load.join_retries {
+        let start = clock.now();
+        profile.join(&mut LORA.lock().unwrap())?;
+        let mut ok = false;
+        while clock.now().saturating_duration_since(start) < load.join_timeout && !stop.load(Ordering::Relaxed) {
+            // the lock is released between polls
+            if LORA.lock().unwrap().has_joined() {
+                ok = true;
+                break;
+            }
+            clock.sleep(LOOP_PERIOD / 4);
+        }
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let ms = clock.now().saturating_duration_since(start).as_millis() as u64;
+        emit(LoadEvent::Join { dev_eui: profile.dev_eui.clone(), ok, ms });
+        if ok {
+            return Ok(());
+        }
+    }
+    return Err(Error::CmdError(CmdErrorKind::DeviceNotJoined));
+}
+
+fn run_joined(
+    profile: &DeviceProfile,
+    load: &LoadProfile,
+    stop: &AtomicBool,
+    rng: &mut StdRng,
+    events: &std::sync::mpsc::Receiver<LoraEvents>,
+    emit: &mut dyn FnMut(LoadEvent),
+) -> Result<()> {
+    join(profile, load, stop, emit)?;
+    let opts = CmdOptions::default();
+    let clock = clock();
+    let mut next_uplink = clock.now() + next_interval(load, rng) / 2;
+    let mut tx_deadline: Option<Instant> = None;
+    // sent time and payload of the uplinks that may still be answered, the oldest first
+    let mut pending: VecDeque<(Instant, Vec<u8>)> = VecDeque::new();
+
+    while !stop.load(Ordering::Relaxed) {
+        let now = clock.now();
+        if tx_deadline.is_some_and(|d| now >= d) {
+            emit(LoadEvent::Error { dev_eui: profile.dev_eui.clone(), error: "TX event timeout".to_string() });
+            tx_deadline = None;
+        }
+        if tx_deadline.is_none() && now >= next_uplink {
+            let size = load.payload_size.sample(rng);
+            let data: Vec<u8> = (0..size).map(|_| rng.gen()).collect();
+            let confirmed = rng.gen_bool(load.confirmed_ratio);
+            let mtype = if confirmed { CONFIRMED_DATA_UP } else { UNCONFIRMED_DATA_UP };
+            match LORA.lock().unwrap().send_bytes(mtype, &data, load.fport, &opts) {
+                Ok(()) => {
+                    emit(LoadEvent::Uplink { confirmed, size });
+                    tx_deadline = Some(now + load.tx_timeout);
+                    if pending.len() == MAX_PENDING_UPLINKS {
+                        pending.pop_front();
+                    }
+                    pending.push_back((now, data));
+                }
+                Err(e) => emit(LoadEvent::Error { dev_eui: profile.dev_eui.clone(), error: e.to_string() }),
+            }
+            next_uplink = now + next_interval(load, rng);
+        }
+
+        let wake_up = [Some(next_uplink), tx_deadline, Some(now + LOOP_PERIOD)].into_iter().flatten().min().unwrap();
+        let ev = match events.try_recv() {
+            Ok(ev) => ev,
+            Err(TryRecvError::Empty) => {
+                clock.sleep(wake_up.saturating_duration_since(clock.now()).min(EVENT_POLL_PERIOD));
+                continue;
+            }
+            Err(TryRecvError::Disconnected) => break,
+        };
+        if ev.intersects(LoraEvents::TX_PACKET_EVENT | LoraEvents::TX_FAILED_EVENT) {
+            tx_deadline = None;
+            let ok = !ev.contains(LoraEvents::TX_FAILED_EVENT);
+            if !ok {
+                // the last uplink, the next one waits for its TX event
+                pending.pop_back();
+            }
+            emit(LoadEvent::Tx { ok });
+        }
+        if ev.contains(LoraEvents::RX_PACKET_EVENT) {
+            let rx_time = clock.now();
+            match LORA.lock().unwrap().recv_downlink(DOWNLINK_BUFFER_SIZE) {
+                Ok(dl) => {
+                    let latency_ms = downlink_latency(&mut pending, &dl.payload, rx_time).as_millis() as u64;
+                    emit(LoadEvent::Downlink { latency_ms });
+                }
+                Err(e) => emit(LoadEvent::Error { dev_eui: profile.dev_eui.clone(), error: e.to_string() }),
+            }
+        }
+        if ev.contains(LoraEvents::UNJOIN_EVENT) {
+            warn!("[LOADGEN][{}]unjoined, joining again", profile.dev_eui);
+            join(profile, load, stop, emit)?;
+        }
+    }
+    return Ok(());
+}
+
+// time since the uplink answered by the downlink: the one it echoes if any, the oldest pending one otherwise
+// (the last uplink is not the right one when the interval is shorter than the RX delays),
+// the downlinks come in order so the uplinks before it are not answered anymore
+fn downlink_latency(pending: &mut VecDeque<(Instant, Vec<u8>)>, payload: &[u8], rx_time: Instant) -> Duration {
+    let index = pending.iter().position(|(_, data)| data == payload).unwrap_or(0);
+    pending.drain(..index);
+    return match pending.pop_front() {
+        Some((sent, _)) => rx_time.saturating_duration_since(sent),
+        None => Duration::ZERO,
+    };
+}
+
+fn next_interval(load: &LoadProfile, rng: &mut StdRng) -> Duration {
+    let jitter = load.jitter.min(load.interval).as_secs_f64();
+    let secs = load.interval.as_secs_f64() + rng.gen_range(-jitter..=jitter);
+    return Duration::from_secs_f64(secs);
+}
+
+/// aggregates the LoadEvents of all the devices (reported by one process per device, see run_device())
+#[derive(Debug, Clone)]
+pub struct LoadStats {
+    start: Instant,
+    join_attempts: u64,
+    joins: u64,
+    uplinks: u64,
+    confirmed: u64,
+    bytes: u64,
+    tx_ok: u64,
+    tx_failed: u64,
+    errors: u64,
+    latencies_ms: Vec<u64>,
+}
+
+/// downlink latency percentiles in ms
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct Latency {
+    pub p50: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub max: u64,
+}
+
+/// load statistics since the start of the run
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct LoadReport {
+    pub elapsed_s: f64,
+    pub join_attempts: u64,
+    pub joins: u64,
+    pub join_success_rate: Option<f64>,
+    pub uplinks: u64,
+    pub confirmed_uplinks: u64,
+    /// uplinks per second
+    pub throughput: f64,
+    pub payload_bytes: u64,
+    pub tx_ok: u64,
+    pub tx_failed: u64,
+    /// TX_FAILED_EVENT share of the TX events
+    pub tx_failed_rate: Option<f64>,
+    pub downlinks: u64,
+    pub downlink_latency_ms: Option<Latency>,
+    pub errors: u64,
+}
+
+impl Default for LoadStats {
+    fn default() -> Self {
+        LoadStats::new()
+    }
+}
+
+impl LoadStats {
+    pub fn new() -> LoadStats {
+        LoadStats {
+            start: clock().now(),
+            join_attempts: 0,
+            joins: 0,
+            uplinks: 0,
+            confirmed: 0,
+            bytes: 0,
+            tx_ok: 0,
+            tx_failed: 0,
+            errors: 0,
+            latencies_ms: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, event: &LoadEvent) {
+        match event {
+            LoadEvent::Join { ok, .. } => {
+                self.join_attempts += 1;
+                self.joins += *ok as u64;
+            }
+            LoadEvent::Uplink { confirmed, size } => {
+                self.uplinks += 1;
+                self.confirmed += *confirmed as u64;
+                self.bytes += *size as u64;
+            }
+            LoadEvent::Tx { ok: true } => self.tx_ok += 1,
+            LoadEvent::Tx { ok: false } => self.tx_failed += 1,
+            LoadEvent::Downlink { latency_ms } => self.latencies_ms.push(*latency_ms),
+            LoadEvent::Error { .. } => self.errors += 1,
+        }
+    }
+
+    pub fn report(&self) -> LoadReport {
+        let elapsed_s = clock().now().saturating_duration_since(self.start).as_secs_f64();
+        let ratio = |n: u64, d: u64| if d == 0 { None } else { Some(n as f64 / d as f64) };
+        let mut latencies = self.latencies_ms.clone();
+        latencies.sort_unstable();
+        // nearest rank
+        let percentile = |p: f64| latencies[((p * latencies.len() as f64).ceil() as usize).clamp(1, latencies.len()) - 1];
+        let downlink_latency_ms = if latencies.is_empty() {
+            None
+        } else {
+            Some(Latency {
+                p50: percentile(0.5),
+                p90: percentile(0.9),
+                p99: percentile(0.99),
+                max: latencies[latencies.len() - 1],
+            })
+        };
+        return LoadReport {
+            elapsed_s,
+            join_attempts: self.join_attempts,
+            joins: self.joins,
+            join_success_rate: ratio(self.joins, self.join_attempts),
+            uplinks: self.uplinks,
+            confirmed_uplinks: self.confirmed,
+            throughput: if elapsed_s > 0.0 { self.uplinks as f64 / elapsed_s } else { 0.0 },
+            payload_bytes: self.bytes,
+            tx_ok: self.tx_ok,
+            tx_failed: self.tx_failed,
+            tx_failed_rate: ratio(self.tx_failed, self.tx_ok + self.tx_failed),
+            downlinks: self.latencies_ms.len() as u64,
+            downlink_latency_ms,
+            errors: self.errors,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_sizes_parsed() {
+        assert_eq!("12".parse::<PayloadSize>().unwrap(), PayloadSize::Fixed(12));
+        assert_eq!("8-32".parse::<PayloadSize>().unwrap(), PayloadSize::Uniform(8, 32));
+        assert_eq!(" 8 - 8 ".parse::<PayloadSize>().unwrap(), PayloadSize::Uniform(8, 8));
+        assert_eq!("4,12, 51".parse::<PayloadSize>().unwrap(), PayloadSize::Choice(vec![4, 12, 51]));
+        for invalid in ["", "a", "-1", "32-8", "8-", "4,,12", "4,x", "1.5"] {
+            let res = invalid.parse::<PayloadSize>();
+            assert!(matches!(res, Err(Error::ConfigError(_))), "{}: {:?}", invalid, res);
+        }
+
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100 {
+            assert!((8..=32).contains(&PayloadSize::Uniform(8, 32).sample(&mut rng)));
+            assert!([4, 12, 51].contains(&PayloadSize::Choice(vec![4, 12, 51]).sample(&mut rng)));
+        }
+    }
+
+    #[test]
+    fn latency_percentiles() {
+        let mut stats = LoadStats::new();
+        assert_eq!(stats.report().downlink_latency_ms, None);
+        stats.record(&LoadEvent::Downlink { latency_ms: 7 });
+        let single = Latency { p50: 7, p90: 7, p99: 7, max: 7 };
+        assert_eq!(stats.report().downlink_latency_ms, Some(single));
+
+        // nearest rank on 1..=10 (recorded out of order)
+        let mut stats = LoadStats::new();
+        for ms in [10, 3, 1, 7, 5, 2, 9, 4, 8, 6] {
+            stats.record(&LoadEvent::Downlink { latency_ms: ms });
+        }
+        let report = stats.report();
+        assert_eq!(report.downlinks, 10);
+        assert_eq!(report.downlink_latency_ms, Some(Latency { p50: 5, p90: 9, p99: 10, max: 10 }));
+
+        // 1..=200: the rank is ceil(p * n)
+        let mut stats = LoadStats::new();
+        for ms in 1..=200 {
+            stats.record(&LoadEvent::Downlink { latency_ms: ms });
+        }
+        let latency = Latency { p50: 100, p90: 180, p99: 198, max: 200 };
+        assert_eq!(stats.report().downlink_latency_ms, Some(latency));
+    }
+
+    #[test]
+    fn counts_and_rates() {
+        let mut stats = LoadStats::new();
+        let dev_eui = "0000000000000001".to_string();
+        stats.record(&LoadEvent::Join { dev_eui: dev_eui.clone(), ok: false, ms: 30000 });
+        stats.record(&LoadEvent::Join { dev_eui: dev_eui.clone(), ok: true, ms: 500 });
+        stats.record(&LoadEvent::Uplink { confirmed: true, size: 12 });
+        stats.record(&LoadEvent::Uplink { confirmed: false, size: 20 });
+        stats.record(&LoadEvent::Tx { ok: true });
+        stats.record(&LoadEvent::Tx { ok: false });
+        stats.record(&LoadEvent::Error { dev_eui, error: "TX event timeout".to_string() });
+        let report = stats.report();
+        assert_eq!((report.join_attempts, report.joins, report.join_success_rate), (2, 1, Some(0.5)));
+        assert_eq!((report.uplinks, report.confirmed_uplinks, report.payload_bytes), (2, 1, 32));
+        assert_eq!((report.tx_ok, report.tx_failed, report.tx_failed_rate), (1, 1, Some(0.5)));
+        assert_eq!((report.downlinks, report.errors), (0, 1));
+    }
+
+    #[test]
+    fn downlinks_matched_to_their_uplink() {
+        let t0 = Instant::now();
+        let s = Duration::from_secs;
+        let mut pending: VecDeque<(Instant, Vec<u8>)> = (0..4).map(|i| (t0 + s(i), vec![i as u8])).collect();
+        // echo of the second uplink, the first one is not answered
+        assert_eq!(downlink_latency(&mut pending, &[1], t0 + s(3)), s(2));
+        assert_eq!(pending.len(), 2);
+        // not an echo: the oldest pending uplink
+        assert_eq!(downlink_latency(&mut pending, b"pong", t0 + s(4)), s(2));
+        assert_eq!(pending, VecDeque::from([(t0 + s(3), vec![3])]));
+        assert_eq!(downlink_latency(&mut pending, &[3], t0 + s(5)), s(2));
+        assert_eq!(downlink_latency(&mut pending, &[3], t0 + s(6)), Duration::ZERO);
+    }
+}
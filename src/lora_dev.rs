@@ -1,15 +1,26 @@
 #![allow(unused)]
 
+use super::adr::{AdrState, ADR_STATE};
+use super::downlink::{Downlink, DownlinkQueue};
+use super::energy::{
+    EnergyModel, EnergyProfile, EnergyReport, DATA_FRAME_OVERHEAD, JOIN_ACCEPT_LEN, JOIN_REQUEST_LEN, TX_ENERGY,
+};
 use super::error::{Error, Result};
 use super::lora_events::{LORA_EVENTS, LoraEvents};
+use super::lora_stats::{LoraStats, LORA_STATS};
 use super::lwnsim::LWNSIM;
 use super::lwnsim::*;
 use super::lwnsim_cmd::*;
+use super::region::{region_params, Channel, RegionParams};
+use super::mac_cmd::{DeviceTimeAns, LinkCheckAns, MacCommand, MAC_COMMANDS};
+use super::raw_frame::{RawFrame, RAW_FRAMES};
 use serde::Serialize;
+use base64::Engine;
 use serde_json::json;
 
 use lazy_static::lazy_static;
 use std::sync::Mutex;
+use std::time::Duration;
 
 // log
 use log::{debug, info, trace, warn};
@@ -22,11 +33,15 @@ pub static EU868: usize = 1;
 pub static ABP: usize = 0;
 pub static OTAA: usize = 1;
 
+// buffer size requested to the simulator when fetching downlinks, large enough for any LoRaWAN FRMPayload
+// truncation to the application buffer size is done on the client side
+static DOWNLINK_FETCH_BUFFER_SIZE: usize = 256;
+
 lazy_static! {
     pub static ref LORA: Mutex<LoraDev> = Mutex::new(LoraDev::new(LORAWAN, EU868));
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize)]
 pub enum LoraDevStatus {
     Inactive,
     Active,
@@ -42,6 +57,9 @@ pub struct LoraDev {
     //   arg: Option<>,
     mode: usize,
     region: usize,
+    downlinks: DownlinkQueue,
+    channels: Vec<Option<Channel>>,
+    energy: Option<EnergyModel>,
 }
 
 impl LoraDev {
@@ -53,31 +71,54 @@ impl LoraDev {
             // handler : None,
             // arg : None,
             status: LoraDevStatus::Inactive,
-            mode: mode,
-            region: region,
+            mode,
+            region,
+            downlinks: DownlinkQueue::default(),
+            channels: region_params(region)
+                .expect("[LORA] unsupported region")
+                .default_channel_plan(),
+            energy: None,
         }
     }
 
+    /// forgets the device state (status, downlinks, events, MAC commands, ADR, stats)
+    /// e.g. after connecting LWNSIM to another device
+    pub fn reset(&mut self, dev_eui: &str) {
+        self.dev_eui = dev_eui.to_string();
+        self.status = LoraDevStatus::Inactive;
+        self.downlinks.clear();
+        self.channels = self.region_params().default_channel_plan();
+        LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::all());
+        MAC_COMMANDS.lock().unwrap().clear();
+        RAW_FRAMES.lock().unwrap().clear();
+        *ADR_STATE.lock().unwrap() = AdrState::new();
+        *LORA_STATS.lock().unwrap() = LoraStats::default();
+        TX_ENERGY.lock().unwrap().clear();
+        // a new device starts with its battery at the initial charge
+        self.energy = self.energy.take().map(|e| EnergyModel::new(e.profile().clone()));
+        debug!("[LORA][reset]{}", self.dev_eui);
+    }
+
     pub fn get_dev_eui(&self) -> &str {
         return &self.dev_eui;
     }
 
+    pub fn get_status(&self) -> LoraDevStatus {
+        return self.status;
+    }
+
     pub fn set_status(&mut self, status: LoraDevStatus) {
         self.status = status;
     }
     pub fn activate(&mut self) -> Result<()> {
+        return self.activate_with_options(&CmdOptions::default());
+    }
+
+    pub fn activate_with_options(&mut self, opts: &CmdOptions) -> Result<()> {
         if self.status == LoraDevStatus::Inactive {
             trace!("[LORA][activate]");
-            let msg = DevExecuteCmd {
-                cmd: CMD_LINK_DEV.to_string(),
-                ack: true,
-                dev_eui: self.get_dev_eui().to_string(),
-            };
-            match self
-                .send_lora_cmd(msg, SendMode::Call)?
-                .unwrap()
-                .get_error()
-            {
+            let msg = Command::LinkDev;
+            match self.call_lora_cmd(msg, opts)?.get_error() {
                 CmdErrorKind::DevCmdOK => {
                     self.set_status(LoraDevStatus::Active);
                     info!("[LORA][activate]OK");
@@ -93,40 +134,53 @@ impl LoraDev {
         }
     }
 
+    /// unlinks the device, the simulator is asked even if the client sees the device unlinked
+    /// (e.g. linked by another process), it answers DeviceNotLinked if it is not
     pub fn unlink_dev(&mut self) -> Result<()> {
-        if self.status != LoraDevStatus::Inactive {
-            let msg = DevExecuteCmd {
-                cmd: CMD_UNLINK_DEV.to_string(),
-                ack: true,
-                dev_eui: self.get_dev_eui().to_string(),
-            };
-            match self
-                .send_lora_cmd(msg, SendMode::Call)?
-                .unwrap()
-                .get_error()
-            {
-                CmdErrorKind::DevCmdOK => {
-                    self.set_status(LoraDevStatus::Inactive);
-                    info!("[LORA][unlink_dev]OK");
-                    return Ok(());
-                }
-                k =>
-                // self.set_status(); device status unchanged
-                {
-                    return Err(Error::CmdError(k))
-                }
+        return self.unlink_dev_with_options(&CmdOptions::default());
+    }
+
+    pub fn unlink_dev_with_options(&mut self, opts: &CmdOptions) -> Result<()> {
+        let msg = Command::UnlinkDev;
+        match self.call_lora_cmd(msg, opts)?.get_error() {
+            CmdErrorKind::DevCmdOK => {
+                self.set_status(LoraDevStatus::Inactive);
+                self.downlinks.clear();
+                MAC_COMMANDS.lock().unwrap().clear();
+                RAW_FRAMES.lock().unwrap().clear();
+                *ADR_STATE.lock().unwrap() = AdrState::new();
+                self.channels = self.region_params().default_channel_plan();
+                *LORA_STATS.lock().unwrap() = LoraStats::default();
+                TX_ENERGY.lock().unwrap().clear();
+                // a relinked device has to join again
+                LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::all());
+                info!("[LORA][unlink_dev]OK");
+                return Ok(());
             }
-        } else {
-            return Err(Error::CmdError(CmdErrorKind::DeviceNotLinked));
+            // device status unchanged
+            k => return Err(Error::CmdError(k)),
         }
     }
 
+    /// starts the join procedure, the keys are sent to the simulator unless both are empty
+    /// dr, if set, is sent as with set_dr_tx_power() before the join request
     pub fn join(
         &mut self,
         activation: usize,
         auth: (String, String),
         timeout: Option<usize>,
         dr: Option<usize>,
+    ) -> Result<()> {
+        return self.join_with_options(activation, auth, timeout, dr, &CmdOptions::default());
+    }
+
+    pub fn join_with_options(
+        &mut self,
+        activation: usize,
+        auth: (String, String),
+        timeout: Option<usize>,
+        dr: Option<usize>,
+        opts: &CmdOptions,
     ) -> Result<()> {
         if let Some(dur) = timeout {
             if dur != 0 {
@@ -134,15 +188,28 @@ impl LoraDev {
                 return Err(Error::CmdError(CmdErrorKind::NIY));
             }
         }
+        let activation = if activation == OTAA {
+            "OTAA"
+        } else if activation == ABP {
+            "ABP"
+        } else {
+            warn!("[LORA][join]invalid activation {}", activation);
+            return Err(Error::CmdError(CmdErrorKind::InvalidArgument));
+        };
         if self.status == LoraDevStatus::Active || self.status == LoraDevStatus::Unjoined {
-            info!("[LORA][join]start");
-            let msg: DevExecuteCmd = DevExecuteCmd {
-                cmd: CMD_JOIN_REQUEST.to_string(),
-                ack: false,
-                dev_eui: self.dev_eui.clone(),
-            };
+            info!("[LORA][join]start {}", activation);
+            // the simulator uses the keys of its device info when none are given
+            let auth = if auth.0.is_empty() && auth.1.is_empty() { None } else { Some(auth) };
+            // data rate of the join request (and of the next uplinks), as SO_DR
+            if let Some(dr) = dr {
+                let dr = u8::try_from(dr).map_err(|_| Error::CmdError(CmdErrorKind::InvalidArgument))?;
+                self.set_dr_tx_power_with_options(dr, None, opts)?;
+            }
+            let msg = Command::JoinRequest { activation: Some(activation.to_string()), auth };
 
-            return self.send_lora_cmd(msg, SendMode::Emit).map(|_| ());
+            self.send_lora_cmd(msg, SendMode::Emit, opts)?;
+            self.record_uplink_energy(JOIN_REQUEST_LEN);
+            return Ok(());
         } else {
             return Err(Error::CmdError(CmdErrorKind::DeviceNotJoined));
         }
@@ -150,6 +217,9 @@ impl LoraDev {
 
     pub fn has_joined(&mut self) -> bool {
         if LORA_EVENTS.lock().unwrap().contains(LoraEvents::JOIN_ACCEPT_EVENT){
+            if self.status != LoraDevStatus::Joined {
+                self.record_downlink_energy(JOIN_ACCEPT_LEN);
+            }
             self.set_status(LoraDevStatus::Joined);
             true
         }else {
@@ -157,55 +227,566 @@ impl LoraDev {
         }
     }
     pub fn send(&mut self, mtype: &str, pl: &str) -> Result<()> {
+        return self.send_with_options(mtype, pl, &CmdOptions::default());
+    }
+
+    pub fn send_with_options(&mut self, mtype: &str, pl: &str, opts: &CmdOptions) -> Result<()> {
+        return self.send_frame(mtype, pl, None, opts);
+    }
+
+    /// sends an uplink on the given FPort (simulator default port if None)
+    pub fn send_frame(&mut self, mtype: &str, pl: &str, fport: Option<u8>, opts: &CmdOptions) -> Result<()> {
+        return self.send_uplink(mtype, pl.to_string(), None, fport, opts);
+    }
+
+    /// sends a binary uplink on the given FPort, the payload is base64 encoded for the simulator
+    pub fn send_bytes(&mut self, mtype: &str, data: &[u8], fport: Option<u8>, opts: &CmdOptions) -> Result<()> {
+        let payload = base64::engine::general_purpose::STANDARD.encode(data);
+        return self.send_uplink(mtype, payload, Some(PayloadEncoding::Base64), fport, opts);
+    }
+
+    fn send_uplink(
+        &mut self,
+        mtype: &str,
+        payload: String,
+        encoding: Option<PayloadEncoding>,
+        fport: Option<u8>,
+        opts: &CmdOptions,
+    ) -> Result<()> {
         if self.status == LoraDevStatus::Joined {
-            let msg: DevExecuteSendUplink = DevExecuteSendUplink {
-                cmd: CMD_SEND_UPLINK.to_string(),
-                ack: false,
-                dev_eui: self.dev_eui.clone(),
+            let payload_len = match encoding {
+                Some(PayloadEncoding::Base64) => base64::engine::general_purpose::STANDARD
+                    .decode(&payload)
+                    .map_or(payload.len(), |data| data.len()),
+                None => payload.len(),
+            };
+            self.report_battery_level(opts);
+            let msg = Command::SendUplink {
                 mtype: mtype.to_string(),
-                payload: pl.to_string(),
+                payload,
+                fport,
+                encoding,
             };
 
-            return self.send_lora_cmd(msg, SendMode::Emit).map(|_| ());
+            // accounted on TX_PACKET_EVENT, which may arrive before the emit returns
+            let queued = self.queue_uplink_energy(payload_len + DATA_FRAME_OVERHEAD);
+            if let Err(e) = self.send_lora_cmd(msg, SendMode::Emit, opts) {
+                if queued {
+                    TX_ENERGY.lock().unwrap().cancel_last();
+                }
+                return Err(e);
+            }
+            return Ok(());
         } else {
             return Err(Error::CmdError(CmdErrorKind::DeviceNotJoined));
         }
     }
 
     // non blocking receive (for blocking receive, use lora socket)
+    // returns the payload of the oldest queued downlink as text, truncated to buffersize bytes
+    // (binary payloads are read with recv_downlink())
     pub fn recv(&mut self, buffersize: usize) -> Result<String> {
+        return self.recv_with_options(buffersize, &CmdOptions::default());
+    }
+
+    pub fn recv_with_options(&mut self, buffersize: usize, opts: &CmdOptions) -> Result<String> {
+        return self
+            .recv_downlink_with_options(buffersize, opts)
+            .map(|dl| String::from_utf8_lossy(&dl.payload).into_owned());
+    }
+
+    /// non blocking receive of the oldest queued downlink frame with its metadata
+    /// the payload is truncated to buffersize bytes, the remaining bytes are discarded (as a BSD recv does)
+    pub fn recv_downlink(&mut self, buffersize: usize) -> Result<Downlink> {
+        return self.recv_downlink_with_options(buffersize, &CmdOptions::default());
+    }
+
+    pub fn recv_downlink_with_options(&mut self, buffersize: usize, opts: &CmdOptions) -> Result<Downlink> {
         if self.status == LoraDevStatus::Joined {
-            let msg: DevExecuteRecvDownlink = DevExecuteRecvDownlink {
-                cmd: CMD_RECV_DOWNLINK.to_string(),
-                ack: false,
-                dev_eui: self.dev_eui.clone(),
-                buffer_size: buffersize,
+            self.fetch_downlinks_with_options(opts)?;
+            match self.downlinks.pop() {
+                Some(mut dl) => {
+                    dl.truncate(buffersize);
+                    trace!("[LORA][recv]{:?}", dl);
+                    return Ok(dl);
+                }
+                None => {
+                    trace!("[LORA][recv][ERROR]{:?}", CmdErrorKind::NoDataDWrecv);
+                    return Err(Error::CmdError(CmdErrorKind::NoDataDWrecv));
+                }
+            }
+        } else {
+            return Err(Error::CmdError(CmdErrorKind::DeviceNotJoined));
+        }
+    }
+
+    /// moves the downlinks buffered by the simulator to the client queue
+    /// at most one queue capacity worth of frames is fetched per call
+    pub fn fetch_downlinks(&mut self) -> Result<usize> {
+        return self.fetch_downlinks_with_options(&CmdOptions::default());
+    }
+
+    pub fn fetch_downlinks_with_options(&mut self, opts: &CmdOptions) -> Result<usize> {
+        let mut fetched = 0;
+        while fetched < self.downlinks.capacity() {
+            let msg = Command::RecvDownlink {
+                buffer_size: DOWNLINK_FETCH_BUFFER_SIZE,
+            };
+
+            match self.call_lora_cmd(msg, opts)? {
+                Response::RecvDownlink(DevResponseRecvDownlink {
+                    error: CmdErrorKind::DevCmdOK,
+                    mtype,
+                    payload: Some(payload),
+                    fport,
+                    encoding,
+                }) => {
+                    let payload = match encoding {
+                        Some(PayloadEncoding::Base64) => base64::engine::general_purpose::STANDARD
+                            .decode(&payload)
+                            .map_err(|e| Error::PayloadError(format!("invalid base64 downlink payload: {}", e)))?,
+                        None => payload.into_bytes(),
+                    };
+                    self.record_downlink_energy(payload.len() + DATA_FRAME_OVERHEAD);
+                    self.downlinks.push(Downlink::new(payload, fport, mtype));
+                    fetched += 1;
+                }
+                resp if resp.get_error() == CmdErrorKind::NoDataDWrecv => break,
+                resp => {
+                    trace!("[LORA][fetch_downlinks][ERROR]{:?}", resp);
+                    return Err(resp.to_error());
+                }
+            }
+        }
+        return Ok(fetched);
+    }
+
+    /// number of downlinks waiting in the client queue
+    pub fn pending_downlinks(&self) -> usize {
+        return self.downlinks.len();
+    }
+
+    /// number of downlinks dropped because the client queue was full
+    pub fn dropped_downlinks(&self) -> usize {
+        return self.downlinks.dropped();
+    }
+
+    pub fn set_downlink_queue_capacity(&mut self, capacity: usize) {
+        self.downlinks.set_capacity(capacity);
+    }
+
+    /// enables or disables Adaptive Data Rate in the simulated device
+    /// when enabled, the network server drives the data rate and TX power (see ADR_CHANGE_EVENT)
+    pub fn set_adr(&mut self, adr: bool) -> Result<()> {
+        return self.set_adr_with_options(adr, &CmdOptions::default());
+    }
+
+    pub fn set_adr_with_options(&mut self, adr: bool, opts: &CmdOptions) -> Result<()> {
+        if self.status != LoraDevStatus::Inactive {
+            let msg = Command::SetAdr { adr };
+            match self.call_lora_cmd(msg, opts)?.get_error() {
+                CmdErrorKind::DevCmdOK => {
+                    ADR_STATE.lock().unwrap().adr = adr;
+                    info!("[LORA][set_adr]{}", adr);
+                    return Ok(());
+                }
+                k => return Err(Error::CmdError(k)),
+            }
+        } else {
+            return Err(Error::CmdError(CmdErrorKind::DeviceNotLinked));
+        }
+    }
+
+    /// sets the data rate used for the next uplinks and optionally the TX power index
+    /// (if ADR is enabled, the network server may change them again)
+    pub fn set_dr_tx_power(&mut self, dr: u8, tx_power: Option<u8>) -> Result<()> {
+        return self.set_dr_tx_power_with_options(dr, tx_power, &CmdOptions::default());
+    }
+
+    pub fn set_dr_tx_power_with_options(&mut self, dr: u8, tx_power: Option<u8>, opts: &CmdOptions) -> Result<()> {
+        let params = self.region_params();
+        if !params.is_valid_dr(dr) || tx_power.is_some_and(|p| !params.is_valid_tx_power(p)) {
+            warn!("[LORA][set_dr_tx_power]invalid DR {} or TX power {:?}", dr, tx_power);
+            return Err(Error::CmdError(CmdErrorKind::InvalidArgument));
+        }
+        if self.status != LoraDevStatus::Inactive {
+            if self.get_adr() {
+                warn!("[LORA][set_dr_tx_power]ADR enabled, the network may override DR {}", dr);
+            }
+            let msg = Command::SetDrTxPower {
+                data_rate: dr,
+                tx_power,
             };
+            match self.call_lora_cmd(msg, opts)?.get_error() {
+                CmdErrorKind::DevCmdOK => {
+                    let mut adr_state = ADR_STATE.lock().unwrap();
+                    adr_state.data_rate = Some(dr);
+                    if tx_power.is_some() {
+                        adr_state.tx_power = tx_power;
+                    }
+                    info!("[LORA][set_dr_tx_power]DR= {} TX power= {:?}", dr, tx_power);
+                    return Ok(());
+                }
+                k => return Err(Error::CmdError(k)),
+            }
+        } else {
+            return Err(Error::CmdError(CmdErrorKind::DeviceNotLinked));
+        }
+    }
+
+    pub fn get_adr(&self) -> bool {
+        return ADR_STATE.lock().unwrap().adr;
+    }
+
+    pub fn get_dr(&self) -> Option<u8> {
+        return ADR_STATE.lock().unwrap().data_rate;
+    }
+
+    pub fn get_tx_power(&self) -> Option<u8> {
+        return ADR_STATE.lock().unwrap().tx_power;
+    }
+
+    /// returns the current ADR state and clears ADR_CHANGE_EVENT
+    pub fn adr_state(&mut self) -> AdrState {
+        LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::ADR_CHANGE_EVENT);
+        return *ADR_STATE.lock().unwrap();
+    }
+
+    pub fn region_params(&self) -> &'static RegionParams {
+        return region_params(self.region).expect("[LORA] unsupported region");
+    }
+
+    /// sets the LoRaWAN region (EU868, ...) and its default channel plan
+    pub fn set_region(&mut self, region: usize) -> Result<()> {
+        if region_params(region).is_none() {
+            warn!("[LORA][set_region]unsupported region {}", region);
+            return Err(Error::CmdError(CmdErrorKind::InvalidArgument));
+        }
+        self.region = region;
+        self.channels = self.region_params().default_channel_plan();
+        return Ok(());
+    }
+
+    /// adds (or replaces) the uplink channel at index, as Pycom lora.add_channel()
+    /// the region default channels can be neither modified nor removed
+    pub fn add_channel(&mut self, index: usize, frequency: u32, dr_min: u8, dr_max: u8) -> Result<()> {
+        return self.add_channel_with_options(index, frequency, dr_min, dr_max, &CmdOptions::default());
+    }
+
+    pub fn add_channel_with_options(&mut self, index: usize, frequency: u32, dr_min: u8, dr_max: u8, opts: &CmdOptions) -> Result<()> {
+        let channel = Channel { frequency, dr_min, dr_max };
+        if !self.region_params().is_valid_channel(index, &channel) {
+            warn!("[LORA][add_channel]invalid channel {} {:?} for region {}", index, channel, self.region_params().name);
+            return Err(Error::CmdError(CmdErrorKind::InvalidArgument));
+        }
+        if self.status != LoraDevStatus::Inactive {
+            let msg = Command::AddChannel { index, channel };
+            match self.call_lora_cmd(msg, opts)?.get_error() {
+                CmdErrorKind::DevCmdOK => {
+                    self.channels[index] = Some(channel);
+                    info!("[LORA][add_channel]{} {:?}", index, channel);
+                    return Ok(());
+                }
+                k => return Err(Error::CmdError(k)),
+            }
+        } else {
+            return Err(Error::CmdError(CmdErrorKind::DeviceNotLinked));
+        }
+    }
+
+    /// removes the uplink channel at index, as Pycom lora.remove_channel()
+    pub fn remove_channel(&mut self, index: usize) -> Result<()> {
+        return self.remove_channel_with_options(index, &CmdOptions::default());
+    }
+
+    pub fn remove_channel_with_options(&mut self, index: usize, opts: &CmdOptions) -> Result<()> {
+        let params = self.region_params();
+        if index < params.default_channels.len() || index >= params.max_channels {
+            warn!("[LORA][remove_channel]channel {} cannot be removed in region {}", index, params.name);
+            return Err(Error::CmdError(CmdErrorKind::InvalidArgument));
+        }
+        if self.status != LoraDevStatus::Inactive {
+            let msg = Command::RemoveChannel { index };
+            match self.call_lora_cmd(msg, opts)?.get_error() {
+                CmdErrorKind::DevCmdOK => {
+                    self.channels[index] = None;
+                    info!("[LORA][remove_channel]{}", index);
+                    return Ok(());
+                }
+                k => return Err(Error::CmdError(k)),
+            }
+        } else {
+            return Err(Error::CmdError(CmdErrorKind::DeviceNotLinked));
+        }
+    }
 
-            let mut resp_cmd = self.send_lora_cmd(msg, SendMode::Call)?.unwrap();
+    /// channel plan as set by the application (index -> channel)
+    pub fn channels(&self) -> &[Option<Channel>] {
+        return &self.channels;
+    }
+
+    /// reads back the active channel mask of the simulated device (bit i set if channel i is enabled)
+    /// the mask reflects the changes made by the network (NewChannelReq, LinkADRReq)
+    pub fn channel_mask(&mut self) -> Result<u16> {
+        return self.channel_mask_with_options(&CmdOptions::default());
+    }
 
-            if resp_cmd.get_error() != CmdErrorKind::DevCmdOK {
-                trace!("[LORA][recv][ERROR]{:?}", resp_cmd.get_error());
-                return Err(Error::CmdError(resp_cmd.get_error()));
-            } else {
-                return Ok(resp_cmd.get_payload());
+    pub fn channel_mask_with_options(&mut self, opts: &CmdOptions) -> Result<u16> {
+        if self.status != LoraDevStatus::Inactive {
+            let msg = Command::GetChannelMask;
+            match self.call_lora_cmd(msg, opts)? {
+                Response::GetChannelMask(DevResponseChannelMask {
+                    error: CmdErrorKind::DevCmdOK,
+                    chmask,
+                }) => {
+                    trace!("[LORA][channel_mask]{:#018b}", chmask);
+                    return Ok(chmask);
+                }
+                resp => return Err(resp.to_error()),
+            }
+        } else {
+            return Err(Error::CmdError(CmdErrorKind::DeviceNotLinked));
+        }
+    }
+
+    /// requests a LinkCheckReq to be sent with the next uplink
+    /// the answer is signaled by LINK_CHECK_ANS_EVENT and read with link_check_ans()
+    pub fn link_check(&mut self) -> Result<()> {
+        return self.link_check_with_options(&CmdOptions::default());
+    }
+
+    pub fn link_check_with_options(&mut self, opts: &CmdOptions) -> Result<()> {
+        return self.request_mac_cmd(Command::LinkCheckReq, opts);
+    }
+
+    /// requests a DeviceTimeReq to be sent with the next uplink
+    /// the answer is signaled by DEVICE_TIME_ANS_EVENT and read with device_time_ans()
+    pub fn device_time(&mut self) -> Result<()> {
+        return self.device_time_with_options(&CmdOptions::default());
+    }
+
+    pub fn device_time_with_options(&mut self, opts: &CmdOptions) -> Result<()> {
+        return self.request_mac_cmd(Command::DeviceTimeReq, opts);
+    }
+
+    /// returns (and consumes) the last LinkCheckAns received
+    pub fn link_check_ans(&mut self) -> Option<LinkCheckAns> {
+        LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::LINK_CHECK_ANS_EVENT);
+        return MAC_COMMANDS.lock().unwrap().take_link_check_ans();
+    }
+
+    /// returns (and consumes) the last DeviceTimeAns received
+    pub fn device_time_ans(&mut self) -> Option<DeviceTimeAns> {
+        LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::DEVICE_TIME_ANS_EVENT);
+        return MAC_COMMANDS.lock().unwrap().take_device_time_ans();
+    }
+
+    /// radio statistics since the device was linked, as Pycom lora.stats()
+    pub fn stats(&self) -> LoraStats {
+        return LORA_STATS.lock().unwrap().snapshot();
+    }
+
+    /// returns and clears the pending lora events, as Pycom lora.events()
+    /// JOIN_ACCEPT_EVENT and UNJOIN_EVENT are kept, has_joined() relies on them
+    pub fn events(&mut self) -> LoraEvents {
+        let mut lora_events = LORA_EVENTS.lock().unwrap();
+        let evts = *lora_events;
+        lora_events.clear_events(!(LoraEvents::JOIN_ACCEPT_EVENT | LoraEvents::UNJOIN_EVENT));
+        return evts;
+    }
+
+    /// returns the pending lora events without clearing them
+    pub fn get_events(&self) -> LoraEvents {
+        return *LORA_EVENTS.lock().unwrap();
+    }
+
+    /// returns (and consumes) the network initiated MAC commands (LinkADRReq, DutyCycleReq, ...) received so far
+    pub fn mac_commands(&mut self) -> Vec<MacCommand> {
+        LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::MAC_CMD_EVENT);
+        return MAC_COMMANDS.lock().unwrap().take_network_cmds();
+    }
+
+    /// enables or disables the raw frame mode: the simulator then sends the PHYPayload of each uplink
+    /// and downlink of the device, signaled by RAW_FRAME_EVENT and read with raw_frames()
+    pub fn set_raw_frames(&mut self, enable: bool) -> Result<()> {
+        return self.set_raw_frames_with_options(enable, &CmdOptions::default());
+    }
+
+    pub fn set_raw_frames_with_options(&mut self, enable: bool, opts: &CmdOptions) -> Result<()> {
+        if self.status != LoraDevStatus::Inactive {
+            let msg = Command::SetRawFrames { enable };
+            match self.call_lora_cmd(msg, opts)?.get_error() {
+                CmdErrorKind::DevCmdOK => {
+                    RAW_FRAMES.lock().unwrap().set_enabled(enable);
+                    info!("[LORA][set_raw_frames]{}", enable);
+                    return Ok(());
+                }
+                k => return Err(Error::CmdError(k)),
+            }
+        } else {
+            return Err(Error::CmdError(CmdErrorKind::DeviceNotLinked));
+        }
+    }
+
+    pub fn get_raw_frames(&self) -> bool {
+        return RAW_FRAMES.lock().unwrap().is_enabled();
+    }
+
+    /// returns (and consumes) the on-air frames received so far, oldest first
+    pub fn raw_frames(&mut self) -> Vec<RawFrame> {
+        LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::RAW_FRAME_EVENT);
+        return RAW_FRAMES.lock().unwrap().take();
+    }
+
+    /// attaches an energy model to the device (None to detach it), the consumption is accounted from now on
+    /// uplinks are accounted at the current data rate and TX power (DR0 and max EIRP if unknown),
+    /// once their TX_PACKET_EVENT is received
+    pub fn set_energy_model(&mut self, profile: Option<EnergyProfile>) {
+        self.energy = profile.map(EnergyModel::new);
+        TX_ENERGY.lock().unwrap().clear();
+    }
+
+    /// consumption and state of charge of the battery, None without energy model
+    pub fn energy_report(&mut self) -> Option<EnergyReport> {
+        self.apply_sent_uplinks();
+        return self.energy.as_ref().map(|e| e.report());
+    }
+
+    /// battery level in DevStatusAns encoding (1 empty ..= 254 full), None without energy model
+    pub fn battery_level(&mut self) -> Option<u8> {
+        self.apply_sent_uplinks();
+        return self.energy.as_ref().map(|e| e.battery_level());
+    }
+
+    // airtime and TX power (dBm) of a frame at the current data rate and TX power
+    fn uplink_airtime(&self, phy_len: usize) -> Option<(Duration, f64)> {
+        let params = self.region_params();
+        let adr_state = *ADR_STATE.lock().unwrap();
+        let dbm = params.tx_power_dbm(adr_state.tx_power.unwrap_or(0));
+        return params
+            .time_on_air(adr_state.data_rate.unwrap_or(0), phy_len)
+            .map(|airtime| (airtime, dbm as f64));
+    }
+
+    fn record_uplink_energy(&mut self, phy_len: usize) {
+        if self.energy.is_none() {
+            return;
+        }
+        if let (Some((airtime, dbm)), Some(energy)) = (self.uplink_airtime(phy_len), self.energy.as_mut()) {
+            energy.record_uplink(airtime, dbm);
+        }
+    }
+
+    // queues a data uplink until its TX event, returns false without energy model
+    fn queue_uplink_energy(&mut self, phy_len: usize) -> bool {
+        if self.energy.is_none() {
+            return false;
+        }
+        return match self.uplink_airtime(phy_len) {
+            Some((airtime, dbm)) => {
+                TX_ENERGY.lock().unwrap().push(airtime, dbm);
+                true
+            }
+            None => false,
+        };
+    }
+
+    // accounts the uplinks whose TX_PACKET_EVENT arrived
+    fn apply_sent_uplinks(&mut self) {
+        let sent = TX_ENERGY.lock().unwrap().take_sent();
+        if let Some(energy) = self.energy.as_mut() {
+            for (airtime, dbm) in sent {
+                energy.record_uplink(airtime, dbm);
+            }
+        }
+    }
+
+    // the downlink is accounted at the uplink data rate (RX1)
+    fn record_downlink_energy(&mut self, phy_len: usize) {
+        if self.energy.is_none() {
+            return;
+        }
+        let params = self.region_params();
+        let dr = self.get_dr().unwrap_or(0);
+        if let (Some(energy), Some(airtime)) = (self.energy.as_mut(), params.time_on_air(dr, phy_len)) {
+            energy.record_downlink(airtime);
+        }
+    }
+
+    // sends the battery level to the simulator before an uplink, if it changed since the last report
+    // a failure does not prevent the uplink
+    fn report_battery_level(&mut self, opts: &CmdOptions) {
+        let level = match self.energy.as_ref().and_then(|e| e.level_to_report()) {
+            Some(level) => level,
+            None => return,
+        };
+        let msg = Command::SetBatteryLevel { battery_level: level };
+        match self.call_lora_cmd(msg, opts).map(|resp| resp.get_error()) {
+            Ok(CmdErrorKind::DevCmdOK) => {
+                if let Some(energy) = self.energy.as_mut() {
+                    energy.set_reported_level(level);
+                }
+                debug!("[LORA][report_battery_level]{}", level);
+            }
+            Ok(k) => warn!("[LORA][report_battery_level]{:?}", k),
+            Err(e) => warn!("[LORA][report_battery_level]{:?}", e),
+        }
+    }
+
+    fn request_mac_cmd(&mut self, cmd: Command, opts: &CmdOptions) -> Result<()> {
+        if self.status == LoraDevStatus::Joined {
+            let cmd_name = cmd.name();
+            match self.call_lora_cmd(cmd, opts)?.get_error() {
+                CmdErrorKind::DevCmdOK => {
+                    debug!("[LORA][{}]queued", cmd_name);
+                    return Ok(());
+                }
+                k => return Err(Error::CmdError(k)),
             }
         } else {
             return Err(Error::CmdError(CmdErrorKind::DeviceNotJoined));
         }
     }
 
+    // applies a join or unjoin event received while the device was locked
+    fn sync_status(&mut self) {
+        self.apply_sent_uplinks();
+        let lora_events = *LORA_EVENTS.lock().unwrap();
+        match self.status {
+            LoraDevStatus::Active | LoraDevStatus::Unjoined
+                if lora_events.contains(LoraEvents::JOIN_ACCEPT_EVENT) =>
+            {
+                self.record_downlink_energy(JOIN_ACCEPT_LEN);
+                self.status = LoraDevStatus::Joined
+            }
+            LoraDevStatus::Joined if lora_events.contains(LoraEvents::UNJOIN_EVENT) => {
+                self.status = LoraDevStatus::Unjoined
+            }
+            _ => {}
+        }
+    }
+
+    // sends a command expecting a response from the simulator
+    fn call_lora_cmd(&mut self, cmd: Command, opts: &CmdOptions) -> Result<Response> {
+        return self
+            .send_lora_cmd(cmd, SendMode::Call, opts)?
+            .ok_or(Error::CmdError(CmdErrorKind::UnexpectedError));
+    }
+
     fn send_lora_cmd(
         &mut self,
-        msg: impl DevExecuteCmdTrait + serde::Serialize,
+        cmd: Command,
         mode: SendMode,
-    ) -> Result<Option<Box<dyn ResponseCmdTrait>>> {
+        opts: &CmdOptions,
+    ) -> Result<Option<Response>> {
+        self.sync_status();
+        let msg = DevExecuteCmd::new(&self.dev_eui, cmd);
         trace!(
             "[LORA][{:?}]{:?}",
             msg.get_cmd(),
             serde_json::to_string(&msg)
         );
-        let cmd_resp = LWNSIM.lock().unwrap().send_cmd(msg, mode);
+        let cmd_resp = LWNSIM.lock().unwrap().send_cmd(msg, mode, opts);
         match cmd_resp {
             Err(Error::CmdError(CmdErrorKind::SimulatorNotRunning))
             | Err(Error::CmdError(CmdErrorKind::NoDeviceWithDevEUI)) => {
@@ -263,3 +844,22 @@ impl LoraDev {
         self.handler=handler;
     } */
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_synced_from_join_events() {
+        LWNSIM.lock().unwrap().set_dev_eui("359ac7cd01bc8aff");
+        let mut lora = LoraDev::new(LORAWAN, EU868);
+        lora.set_status(LoraDevStatus::Active);
+        *LORA_EVENTS.lock().unwrap() = LoraEvents::JOIN_ACCEPT_EVENT;
+        lora.sync_status();
+        assert_eq!(lora.get_status(), LoraDevStatus::Joined);
+        *LORA_EVENTS.lock().unwrap() = LoraEvents::UNJOIN_EVENT;
+        lora.sync_status();
+        assert_eq!(lora.get_status(), LoraDevStatus::Unjoined);
+        *LORA_EVENTS.lock().unwrap() = LoraEvents::empty();
+    }
+}
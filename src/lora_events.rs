@@ -4,13 +4,15 @@ use serde_derive::*;
 use std::fmt;
 
 use lazy_static::lazy_static;
+use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Mutex;
 
 use super::lora_dev::{LoraDevStatus, LORA};
+use super::metrics::record_lora_event;
 use log::trace;
 
 bitflags! {
-    #[derive(Serialize, Deserialize)]
+    #[derive(Serialize, Deserialize, Default)]
     #[serde(transparent)]
     pub struct LoraEvents: u32 {
     const  RX_PACKET_EVENT = 1;
@@ -18,11 +20,32 @@ bitflags! {
     const  TX_FAILED_EVENT = 4;
     const JOIN_ACCEPT_EVENT = 16;
     const  UNJOIN_EVENT = 32;
+    const  LINK_CHECK_ANS_EVENT = 64;
+    const  DEVICE_TIME_ANS_EVENT = 128;
+    const  MAC_CMD_EVENT = 256; // network initiated MAC command received
+    const  ADR_CHANGE_EVENT = 512; // ADR setting, data rate or TX power changed
+    const  RAW_FRAME_EVENT = 1024; // on-air frame received in raw frame mode
     }
 }
 
 lazy_static! {
     pub static ref LORA_EVENTS: Mutex<LoraEvents> = Mutex::new(LoraEvents::new());
+    static ref LORA_EVENT_SUBSCRIBERS: Mutex<Vec<Sender<LoraEvents>>> = Mutex::new(Vec::new());
+}
+
+/// returns a channel receiving each lora event as it is handled
+pub fn subscribe_lora_events() -> Receiver<LoraEvents> {
+    let (tx, rx) = channel();
+    LORA_EVENT_SUBSCRIBERS.lock().unwrap().push(tx);
+    return rx;
+}
+
+fn notify_subscribers(event_val: LoraEvents) {
+    // dropped receivers are unsubscribed
+    LORA_EVENT_SUBSCRIBERS
+        .lock()
+        .unwrap()
+        .retain(|tx| tx.send(event_val).is_ok());
 }
 
 impl LoraEvents {
@@ -31,15 +54,25 @@ impl LoraEvents {
     }
 
     pub fn handle_lora_event(&mut self, event_val: LoraEvents) {
+        record_lora_event(event_val);
+        notify_subscribers(event_val);
+        // the device may be locked by a command waiting for its response on the socket thread,
+        // its status is then synced from the events before its next command
         if LoraEvents::JOIN_ACCEPT_EVENT == event_val {
+            self.remove(LoraEvents::UNJOIN_EVENT);
             self.insert(LoraEvents::JOIN_ACCEPT_EVENT);
             trace!("[LORA_EVENTS]{:?}", self);
-            LORA.lock().unwrap().set_status(LoraDevStatus::Joined);
+            if let Ok(mut lora) = LORA.try_lock() {
+                lora.set_status(LoraDevStatus::Joined);
+            }
             return;
         } else if event_val == LoraEvents::UNJOIN_EVENT {
+            self.remove(LoraEvents::JOIN_ACCEPT_EVENT);
             self.insert(LoraEvents::UNJOIN_EVENT);
             trace!("[LORA_EVENTS]{:?}", self);
-            LORA.lock().unwrap().set_status(LoraDevStatus::Unjoined);
+            if let Ok(mut lora) = LORA.try_lock() {
+                lora.set_status(LoraDevStatus::Unjoined);
+            }
             return;
         }
         // set TX and RX type events
@@ -48,6 +81,24 @@ impl LoraEvents {
         trace!("[LORA_EVENTS]{:?}", self);
     }
 
+    /// event from its name, e.g. "TX_PACKET_EVENT"
+    pub fn from_name(name: &str) -> Option<LoraEvents> {
+        let ev = match name {
+            "RX_PACKET_EVENT" => LoraEvents::RX_PACKET_EVENT,
+            "TX_PACKET_EVENT" => LoraEvents::TX_PACKET_EVENT,
+            "TX_FAILED_EVENT" => LoraEvents::TX_FAILED_EVENT,
+            "JOIN_ACCEPT_EVENT" => LoraEvents::JOIN_ACCEPT_EVENT,
+            "UNJOIN_EVENT" => LoraEvents::UNJOIN_EVENT,
+            "LINK_CHECK_ANS_EVENT" => LoraEvents::LINK_CHECK_ANS_EVENT,
+            "DEVICE_TIME_ANS_EVENT" => LoraEvents::DEVICE_TIME_ANS_EVENT,
+            "MAC_CMD_EVENT" => LoraEvents::MAC_CMD_EVENT,
+            "ADR_CHANGE_EVENT" => LoraEvents::ADR_CHANGE_EVENT,
+            "RAW_FRAME_EVENT" => LoraEvents::RAW_FRAME_EVENT,
+            _ => return None,
+        };
+        return Some(ev);
+    }
+
     pub fn clear_events(&mut self, evts: LoraEvents) {
         self.remove(evts);
         trace!("[LORA_EVENTS]{:?}", self);
@@ -68,3 +119,31 @@ impl fmt::Display for LoraEvents {
         write!(f, "{:032b}", self.bits)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lwnsim::LWNSIM;
+
+    #[test]
+    fn join_events_with_device_locked() {
+        // the socket thread must not wait for a device locked by a pending command
+        LWNSIM.lock().unwrap().set_dev_eui("359ac7cd01bc8aff");
+        let _lora = LORA.lock().unwrap();
+        let mut events = LoraEvents::UNJOIN_EVENT;
+        events.handle_lora_event(LoraEvents::JOIN_ACCEPT_EVENT);
+        assert_eq!(events, LoraEvents::JOIN_ACCEPT_EVENT);
+        events.handle_lora_event(LoraEvents::UNJOIN_EVENT);
+        assert_eq!(events, LoraEvents::UNJOIN_EVENT);
+    }
+
+    #[test]
+    fn tx_rx_events_accumulate() {
+        let mut events = LoraEvents::empty();
+        events.handle_lora_event(LoraEvents::TX_PACKET_EVENT);
+        events.handle_lora_event(LoraEvents::RX_PACKET_EVENT);
+        assert!(events.contains_and_remove_event(LoraEvents::TX_PACKET_EVENT));
+        assert!(!events.contains_and_remove_event(LoraEvents::TX_PACKET_EVENT));
+        assert_eq!(events, LoraEvents::RX_PACKET_EVENT);
+    }
+}
@@ -0,0 +1,50 @@
+use chrono::{DateTime, Utc};
+use serde_derive::*;
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use super::adr::ADR_STATE;
+use super::lora_events::LoraEvents;
+
+lazy_static! {
+    pub static ref LORA_STATS: Mutex<LoraStats> = Mutex::new(LoraStats::default());
+}
+
+/// radio statistics of the device, as Pycom lora.stats()
+/// the counters are maintained from the lora events, the simulator reports neither RSSI nor SNR
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+pub struct LoraStats {
+    /// time the last downlink was received
+    pub rx_timestamp: Option<DateTime<Utc>>,
+    pub rx_counter: u32,
+    pub tx_counter: u32,
+    pub tx_failed: u32,
+    pub data_rate: Option<u8>,
+    pub tx_power: Option<u8>,
+}
+
+impl LoraStats {
+    pub fn handle_lora_event(&mut self, event_val: LoraEvents) {
+        if event_val.contains(LoraEvents::RX_PACKET_EVENT) {
+            self.rx_counter += 1;
+            self.rx_timestamp = Some(Utc::now());
+        }
+        if event_val.contains(LoraEvents::TX_PACKET_EVENT) {
+            self.tx_counter += 1;
+        }
+        if event_val.contains(LoraEvents::TX_FAILED_EVENT) {
+            self.tx_failed += 1;
+        }
+    }
+
+    /// counters with the current data rate and TX power
+    pub fn snapshot(&self) -> LoraStats {
+        let adr_state = *ADR_STATE.lock().unwrap();
+        return LoraStats {
+            data_rate: adr_state.data_rate,
+            tx_power: adr_state.tx_power,
+            ..*self
+        };
+    }
+}
@@ -0,0 +1,611 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use aes::Aes128;
+use cmac::{Cmac, Mac};
+use serde_derive::*;
+
+use super::error::{Error, Result};
+
+/// AES-128 key (AppKey, NwkSKey, AppSKey)
+pub type AesKey = [u8; 16];
+
+static MHDR_LEN: usize = 1;
+static MIC_LEN: usize = 4;
+static FHDR_MIN_LEN: usize = 7;
+static JOIN_REQUEST_LEN: usize = 18;
+static JOIN_ACCEPT_LEN: usize = 12;
+static CF_LIST_LEN: usize = 16;
+
+// FCtrl bits
+static FCTRL_ADR: u8 = 0x80;
+static FCTRL_ADR_ACK_REQ: u8 = 0x40;
+static FCTRL_ACK: u8 = 0x20;
+static FCTRL_FPENDING: u8 = 0x10;
+static FCTRL_FOPTS_LEN: u8 = 0x0f;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MType {
+    JoinRequest,
+    JoinAccept,
+    UnconfirmedDataUp,
+    UnconfirmedDataDown,
+    ConfirmedDataUp,
+    ConfirmedDataDown,
+    Rfu,
+    Proprietary,
+}
+
+impl MType {
+    pub fn from_bits(bits: u8) -> MType {
+        return match bits & 0x07 {
+            0 => MType::JoinRequest,
+            1 => MType::JoinAccept,
+            2 => MType::UnconfirmedDataUp,
+            3 => MType::UnconfirmedDataDown,
+            4 => MType::ConfirmedDataUp,
+            5 => MType::ConfirmedDataDown,
+            6 => MType::Rfu,
+            _ => MType::Proprietary,
+        };
+    }
+
+    pub fn bits(&self) -> u8 {
+        return match self {
+            MType::JoinRequest => 0,
+            MType::JoinAccept => 1,
+            MType::UnconfirmedDataUp => 2,
+            MType::UnconfirmedDataDown => 3,
+            MType::ConfirmedDataUp => 4,
+            MType::ConfirmedDataDown => 5,
+            MType::Rfu => 6,
+            MType::Proprietary => 7,
+        };
+    }
+
+    pub fn is_uplink(&self) -> bool {
+        return matches!(self, MType::JoinRequest | MType::UnconfirmedDataUp | MType::ConfirmedDataUp);
+    }
+
+    pub fn is_data(&self) -> bool {
+        return matches!(
+            self,
+            MType::UnconfirmedDataUp | MType::UnconfirmedDataDown | MType::ConfirmedDataUp | MType::ConfirmedDataDown
+        );
+    }
+}
+
+/// frame control, FOptsLen is given by the length of FOpts
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FCtrl {
+    pub adr: bool,
+    pub adr_ack_req: bool,
+    pub ack: bool,
+    /// FPending in downlinks, ClassB in uplinks
+    pub f_pending: bool,
+}
+
+impl FCtrl {
+    pub fn from_byte(b: u8) -> FCtrl {
+        FCtrl {
+            adr: b & FCTRL_ADR != 0,
+            adr_ack_req: b & FCTRL_ADR_ACK_REQ != 0,
+            ack: b & FCTRL_ACK != 0,
+            f_pending: b & FCTRL_FPENDING != 0,
+        }
+    }
+
+    // FOptsLen is 4 bits long, FOpts holds up to 15 bytes
+    fn to_byte(self, f_opts_len: usize) -> Result<u8> {
+        if f_opts_len > usize::from(FCTRL_FOPTS_LEN) {
+            return Err(frame_error(format!("{} bytes FOpts", f_opts_len)));
+        }
+        let mut b = f_opts_len as u8;
+        for (set, bit) in [
+            (self.adr, FCTRL_ADR),
+            (self.adr_ack_req, FCTRL_ADR_ACK_REQ),
+            (self.ack, FCTRL_ACK),
+            (self.f_pending, FCTRL_FPENDING),
+        ] {
+            if set {
+                b |= bit;
+            }
+        }
+        return Ok(b);
+    }
+}
+
+/// frame header, f_cnt holds the 16 least significant bits of the frame counter
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct Fhdr {
+    pub dev_addr: u32,
+    pub f_ctrl: FCtrl,
+    pub f_cnt: u16,
+    /// MAC commands piggybacked in the header, up to 15 bytes
+    pub f_opts: Vec<u8>,
+}
+
+/// payload of the data frames, frm_payload is encrypted on air
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct MacPayload {
+    pub fhdr: Fhdr,
+    pub f_port: Option<u8>,
+    pub frm_payload: Vec<u8>,
+}
+
+/// EUIs are written as usual (e.g. 0x359ac7cd01bc8aff), they are little endian on air
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinRequest {
+    pub app_eui: u64,
+    pub dev_eui: u64,
+    pub dev_nonce: u16,
+}
+
+/// app_nonce and net_id are 24 bits values
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JoinAccept {
+    pub app_nonce: u32,
+    pub net_id: u32,
+    pub dev_addr: u32,
+    pub dl_settings: u8,
+    pub rx_delay: u8,
+    pub cf_list: Option<[u8; 16]>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum FramePayload {
+    JoinRequest(JoinRequest),
+    JoinAccept(JoinAccept),
+    /// join accept as received, encrypted with the AppKey, MIC included
+    EncryptedJoinAccept(Vec<u8>),
+    Data(MacPayload),
+    Proprietary(Vec<u8>),
+}
+
+/// LoRaWAN 1.0.x frame: MHDR | MACPayload | MIC
+///
+/// ```
+/// use lwnsim_api_rs::*;
+///
+/// let nwk_s_key = key_from_hex("44024241ed4ce9a68c6a8bc055233fd3").unwrap();
+/// let app_s_key = key_from_hex("ec925802ae430ca77fd3dd73cb2cc588").unwrap();
+/// let mut frame = PhyPayload::data(
+///     MType::UnconfirmedDataUp,
+///     MacPayload {
+///         fhdr: Fhdr { dev_addr: 0x26011bda, f_cnt: 1, ..Fhdr::default() },
+///         f_port: Some(1),
+///         frm_payload: b"hello".to_vec(),
+///     },
+/// )
+/// .unwrap();
+/// frame.encrypt_frm_payload(&app_s_key, &nwk_s_key).unwrap();
+/// frame.set_mic(&nwk_s_key).unwrap();
+/// let bytes = frame.to_bytes().unwrap();
+/// assert_eq!(bytes[..9], [0x40, 0xda, 0x1b, 0x01, 0x26, 0x00, 0x01, 0x00, 0x01]);
+///
+/// let parsed = PhyPayload::parse(&bytes).unwrap();
+/// assert!(parsed.validate_mic(&nwk_s_key).unwrap());
+/// assert_eq!(parsed.decrypt_frm_payload(&app_s_key, &nwk_s_key).unwrap(), b"hello");
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PhyPayload {
+    pub mtype: MType,
+    /// 0 for LoRaWAN R1
+    pub major: u8,
+    pub payload: FramePayload,
+    pub mic: [u8; 4],
+}
+
+impl PhyPayload {
+    /// data frame with a zero MIC, see set_mic()
+    pub fn data(mtype: MType, mac_payload: MacPayload) -> Result<PhyPayload> {
+        if !mtype.is_data() {
+            return Err(frame_error(format!("{:?} is not a data frame", mtype)));
+        }
+        return Ok(PhyPayload {
+            mtype,
+            major: 0,
+            payload: FramePayload::Data(mac_payload),
+            mic: [0; 4],
+        });
+    }
+
+    pub fn join_request(join_request: JoinRequest) -> PhyPayload {
+        PhyPayload {
+            mtype: MType::JoinRequest,
+            major: 0,
+            payload: FramePayload::JoinRequest(join_request),
+            mic: [0; 4],
+        }
+    }
+
+    pub fn join_accept(join_accept: JoinAccept) -> PhyPayload {
+        PhyPayload {
+            mtype: MType::JoinAccept,
+            major: 0,
+            payload: FramePayload::JoinAccept(join_accept),
+            mic: [0; 4],
+        }
+    }
+
+    /// parses an on-air frame, the join accepts are left encrypted, see decrypt_join_accept()
+    pub fn parse(bytes: &[u8]) -> Result<PhyPayload> {
+        if bytes.len() < MHDR_LEN + MIC_LEN {
+            return Err(frame_error(format!("{} bytes frame", bytes.len())));
+        }
+        let mtype = MType::from_bits(bytes[0] >> 5);
+        let major = bytes[0] & 0x03;
+        let body = &bytes[MHDR_LEN..bytes.len() - MIC_LEN];
+        let mut mic = [0u8; 4];
+        mic.copy_from_slice(&bytes[bytes.len() - MIC_LEN..]);
+        let payload = match mtype {
+            MType::JoinRequest => FramePayload::JoinRequest(parse_join_request(body)?),
+            MType::JoinAccept => FramePayload::EncryptedJoinAccept(bytes[MHDR_LEN..].to_vec()),
+            MType::Proprietary => FramePayload::Proprietary(body.to_vec()),
+            MType::Rfu => return Err(frame_error("RFU MType".to_string())),
+            _ => FramePayload::Data(parse_mac_payload(body)?),
+        };
+        return Ok(PhyPayload { mtype, major, payload, mic });
+    }
+
+    /// decrypts an on-air join accept with the AppKey, the MIC is not checked, see validate_mic()
+    pub fn decrypt_join_accept(bytes: &[u8], app_key: &AesKey) -> Result<PhyPayload> {
+        let len = bytes.len().saturating_sub(MHDR_LEN);
+        if MType::from_bits(bytes.first().copied().unwrap_or(0) >> 5) != MType::JoinAccept
+            || (len != JOIN_ACCEPT_LEN + MIC_LEN && len != JOIN_ACCEPT_LEN + CF_LIST_LEN + MIC_LEN)
+        {
+            return Err(frame_error("not a join accept".to_string()));
+        }
+        // the network encrypts with aes decrypt, so that the device only needs aes encrypt
+        let mut plain = bytes.to_vec();
+        let cipher = Aes128::new(GenericArray::from_slice(app_key));
+        for block in plain[MHDR_LEN..].chunks_mut(16) {
+            cipher.encrypt_block(GenericArray::from_mut_slice(block));
+        }
+        let body = &plain[MHDR_LEN..plain.len() - MIC_LEN];
+        let mut mic = [0u8; 4];
+        mic.copy_from_slice(&plain[plain.len() - MIC_LEN..]);
+        return Ok(PhyPayload {
+            mtype: MType::JoinAccept,
+            major: plain[0] & 0x03,
+            payload: FramePayload::JoinAccept(parse_join_accept(body)),
+            mic,
+        });
+    }
+
+    /// on-air bytes of a join accept, the MIC must be set first
+    pub fn encrypt_join_accept(&self, app_key: &AesKey) -> Result<Vec<u8>> {
+        if !matches!(self.payload, FramePayload::JoinAccept(_)) {
+            return Err(frame_error("not a decrypted join accept".to_string()));
+        }
+        let mut bytes = self.to_bytes()?;
+        let cipher = Aes128::new(GenericArray::from_slice(app_key));
+        for block in bytes[MHDR_LEN..].chunks_mut(16) {
+            cipher.decrypt_block(GenericArray::from_mut_slice(block));
+        }
+        return Ok(bytes);
+    }
+
+    pub fn mhdr(&self) -> u8 {
+        return (self.mtype.bits() << 5) | (self.major & 0x03);
+    }
+
+    pub fn is_uplink(&self) -> bool {
+        return self.mtype.is_uplink();
+    }
+
+    pub fn mac_payload(&self) -> Option<&MacPayload> {
+        return match &self.payload {
+            FramePayload::Data(mac_payload) => Some(mac_payload),
+            _ => None,
+        };
+    }
+
+    /// on-air bytes of the frame, fails if FOpts is longer than 15 bytes
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.bytes_without_mic()?;
+        bytes.extend_from_slice(&self.mic);
+        return Ok(bytes);
+    }
+
+    // MHDR | MACPayload, the data covered by the MIC
+    fn bytes_without_mic(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![self.mhdr()];
+        match &self.payload {
+            FramePayload::JoinRequest(jr) => {
+                bytes.extend_from_slice(&jr.app_eui.to_le_bytes());
+                bytes.extend_from_slice(&jr.dev_eui.to_le_bytes());
+                bytes.extend_from_slice(&jr.dev_nonce.to_le_bytes());
+            }
+            FramePayload::JoinAccept(ja) => {
+                bytes.extend_from_slice(&ja.app_nonce.to_le_bytes()[..3]);
+                bytes.extend_from_slice(&ja.net_id.to_le_bytes()[..3]);
+                bytes.extend_from_slice(&ja.dev_addr.to_le_bytes());
+                bytes.push(ja.dl_settings);
+                bytes.push(ja.rx_delay);
+                if let Some(cf_list) = &ja.cf_list {
+                    bytes.extend_from_slice(cf_list);
+                }
+            }
+            // encrypted MIC included
+            FramePayload::EncryptedJoinAccept(data) => bytes.extend_from_slice(&data[..data.len().saturating_sub(MIC_LEN)]),
+            FramePayload::Data(mac) => {
+                bytes.extend_from_slice(&mac.fhdr.dev_addr.to_le_bytes());
+                bytes.push(mac.fhdr.f_ctrl.to_byte(mac.fhdr.f_opts.len())?);
+                bytes.extend_from_slice(&mac.fhdr.f_cnt.to_le_bytes());
+                bytes.extend_from_slice(&mac.fhdr.f_opts);
+                if let Some(port) = mac.f_port {
+                    bytes.push(port);
+                    bytes.extend_from_slice(&mac.frm_payload);
+                }
+            }
+            FramePayload::Proprietary(data) => bytes.extend_from_slice(data),
+        }
+        return Ok(bytes);
+    }
+
+    /// MIC of the frame, the key is the NwkSKey for data frames and the AppKey for join frames
+    /// (data frames use the 16 bits FCnt of the header as frame counter, see data_mic())
+    pub fn compute_mic(&self, key: &AesKey) -> Result<[u8; 4]> {
+        let msg = self.bytes_without_mic()?;
+        return match &self.payload {
+            FramePayload::JoinRequest(_) | FramePayload::JoinAccept(_) => Ok(aes_cmac(key, &msg)),
+            FramePayload::Data(mac) => Ok(data_mic(
+                key,
+                self.is_uplink(),
+                mac.fhdr.dev_addr,
+                u32::from(mac.fhdr.f_cnt),
+                &msg,
+            )),
+            FramePayload::EncryptedJoinAccept(_) => Err(frame_error("join accept not decrypted".to_string())),
+            FramePayload::Proprietary(_) => Err(frame_error("no MIC for proprietary frames".to_string())),
+        };
+    }
+
+    pub fn set_mic(&mut self, key: &AesKey) -> Result<()> {
+        self.mic = self.compute_mic(key)?;
+        return Ok(());
+    }
+
+    pub fn validate_mic(&self, key: &AesKey) -> Result<bool> {
+        return Ok(self.compute_mic(key)? == self.mic);
+    }
+
+    /// encrypts the FRMPayload in place with the AppSKey, or the NwkSKey for FPort 0
+    pub fn encrypt_frm_payload(&mut self, app_s_key: &AesKey, nwk_s_key: &AesKey) -> Result<()> {
+        let uplink = self.is_uplink();
+        let mac = match &mut self.payload {
+            FramePayload::Data(mac) => mac,
+            _ => return Err(frame_error("not a data frame".to_string())),
+        };
+        let key = if mac.f_port == Some(0) { nwk_s_key } else { app_s_key };
+        mac.frm_payload = encrypt_frm_payload(key, uplink, mac.fhdr.dev_addr, u32::from(mac.fhdr.f_cnt), &mac.frm_payload);
+        return Ok(());
+    }
+
+    /// decrypted FRMPayload, the encryption is symmetric
+    pub fn decrypt_frm_payload(&self, app_s_key: &AesKey, nwk_s_key: &AesKey) -> Result<Vec<u8>> {
+        let mut frame = self.clone();
+        frame.encrypt_frm_payload(app_s_key, nwk_s_key)?;
+        return Ok(frame.mac_payload().map(|mac| mac.frm_payload.clone()).unwrap_or_default());
+    }
+}
+
+/// NwkSKey and AppSKey of an OTAA session
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionKeys {
+    pub nwk_s_key: AesKey,
+    pub app_s_key: AesKey,
+}
+
+impl SessionKeys {
+    /// derives the session keys from the AppKey, the join accept and the DevNonce of the join request
+    ///
+    /// ```
+    /// use lwnsim_api_rs::*;
+    ///
+    /// let app_key = key_from_hex("f1c4081b61e9bee79bef58b5347e78a5").unwrap();
+    /// let on_air = [
+    ///     0x20, 0xf7, 0xb5, 0x1d, 0xb5, 0xfb, 0x26, 0x75, 0x68, 0xc4, 0xff, 0xde, 0x52, 0xb7, 0x9a, 0x6a, 0xee,
+    ///     0x41, 0xf2, 0xc0, 0xb8, 0x15, 0xa4, 0x98, 0x98, 0x0b, 0x61, 0x9c, 0x2f, 0x45, 0x93, 0x84, 0x65,
+    /// ];
+    /// let frame = PhyPayload::decrypt_join_accept(&on_air, &app_key).unwrap();
+    /// assert!(frame.validate_mic(&app_key).unwrap());
+    /// let FramePayload::JoinAccept(join_accept) = frame.payload else { panic!() };
+    /// assert_eq!(join_accept.dev_addr, 0x26011bda);
+    /// let keys = SessionKeys::derive(&app_key, &join_accept, 0xbeef);
+    /// assert_eq!(keys.nwk_s_key, key_from_hex("6fc84eae64a0682f283fe7e555cebf36").unwrap());
+    /// assert_eq!(keys.app_s_key, key_from_hex("0b698b2b7a43fe9944ae2e48d9bd19ee").unwrap());
+    /// ```
+    pub fn derive(app_key: &AesKey, join_accept: &JoinAccept, dev_nonce: u16) -> SessionKeys {
+        let derive_key = |prefix: u8| {
+            let mut block = [0u8; 16];
+            block[0] = prefix;
+            block[1..4].copy_from_slice(&join_accept.app_nonce.to_le_bytes()[..3]);
+            block[4..7].copy_from_slice(&join_accept.net_id.to_le_bytes()[..3]);
+            block[7..9].copy_from_slice(&dev_nonce.to_le_bytes());
+            let cipher = Aes128::new(GenericArray::from_slice(app_key));
+            cipher.encrypt_block(GenericArray::from_mut_slice(&mut block));
+            block
+        };
+        SessionKeys {
+            nwk_s_key: derive_key(0x01),
+            app_s_key: derive_key(0x02),
+        }
+    }
+}
+
+/// parses a key written in hex, as in the simulator device settings
+pub fn key_from_hex(hex: &str) -> Result<AesKey> {
+    let hex = hex.trim();
+    let mut key = [0u8; 16];
+    if hex.len() != 32 || !hex.is_ascii() {
+        return Err(frame_error(format!("invalid key {:?}", hex)));
+    }
+    for (i, b) in key.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).map_err(|_| frame_error(format!("invalid key {:?}", hex)))?;
+    }
+    return Ok(key);
+}
+
+/// AES-CMAC (RFC 4493) truncated to 4 bytes
+///
+/// ```
+/// use lwnsim_api_rs::*;
+///
+/// // RFC 4493 example 2
+/// let key = key_from_hex("2b7e151628aed2a6abf7158809cf4f3c").unwrap();
+/// let msg = [0x6b, 0xc1, 0xbe, 0xe2, 0x2e, 0x40, 0x9f, 0x96, 0xe9, 0x3d, 0x7e, 0x11, 0x73, 0x93, 0x17, 0x2a];
+/// assert_eq!(aes_cmac(&key, &msg), [0x07, 0x0a, 0x16, 0xb4]);
+/// ```
+pub fn aes_cmac(key: &AesKey, msg: &[u8]) -> [u8; 4] {
+    let mut mac = <Cmac<Aes128> as Mac>::new_from_slice(key).unwrap();
+    mac.update(msg);
+    let mut mic = [0u8; 4];
+    mic.copy_from_slice(&mac.finalize().into_bytes()[..4]);
+    return mic;
+}
+
+/// MIC of a data frame, msg = MHDR | FHDR | FPort | FRMPayload, f_cnt is the 32 bits frame counter
+pub fn data_mic(nwk_s_key: &AesKey, uplink: bool, dev_addr: u32, f_cnt: u32, msg: &[u8]) -> [u8; 4] {
+    let mut b0 = crypto_block(0x49, uplink, dev_addr, f_cnt);
+    b0[15] = msg.len() as u8;
+    let mut data = b0.to_vec();
+    data.extend_from_slice(msg);
+    return aes_cmac(nwk_s_key, &data);
+}
+
+/// FRMPayload encryption, also used to decrypt
+pub fn encrypt_frm_payload(key: &AesKey, uplink: bool, dev_addr: u32, f_cnt: u32, data: &[u8]) -> Vec<u8> {
+    let cipher = Aes128::new(GenericArray::from_slice(key));
+    let mut out = Vec::with_capacity(data.len());
+    for (i, chunk) in data.chunks(16).enumerate() {
+        let mut s = crypto_block(0x01, uplink, dev_addr, f_cnt);
+        s[15] = (i + 1) as u8;
+        cipher.encrypt_block(GenericArray::from_mut_slice(&mut s));
+        out.extend(chunk.iter().zip(s.iter()).map(|(d, s)| d ^ s));
+    }
+    return out;
+}
+
+// A and B0 blocks: prefix | 4 x 0x00 | Dir | DevAddr | FCnt | 0x00 | i or len
+fn crypto_block(prefix: u8, uplink: bool, dev_addr: u32, f_cnt: u32) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[0] = prefix;
+    block[5] = if uplink { 0 } else { 1 };
+    block[6..10].copy_from_slice(&dev_addr.to_le_bytes());
+    block[10..14].copy_from_slice(&f_cnt.to_le_bytes());
+    return block;
+}
+
+fn parse_join_request(body: &[u8]) -> Result<JoinRequest> {
+    if body.len() != JOIN_REQUEST_LEN {
+        return Err(frame_error(format!("{} bytes join request", body.len())));
+    }
+    return Ok(JoinRequest {
+        app_eui: u64::from_le_bytes(body[0..8].try_into().unwrap()),
+        dev_eui: u64::from_le_bytes(body[8..16].try_into().unwrap()),
+        dev_nonce: u16::from_le_bytes([body[16], body[17]]),
+    });
+}
+
+// the length is checked by decrypt_join_accept()
+fn parse_join_accept(body: &[u8]) -> JoinAccept {
+    let u24 = |b: &[u8]| u32::from_le_bytes([b[0], b[1], b[2], 0]);
+    JoinAccept {
+        app_nonce: u24(&body[0..3]),
+        net_id: u24(&body[3..6]),
+        dev_addr: u32::from_le_bytes(body[6..10].try_into().unwrap()),
+        dl_settings: body[10],
+        rx_delay: body[11],
+        cf_list: body.get(JOIN_ACCEPT_LEN..).and_then(|cf| cf.try_into().ok()),
+    }
+}
+
+fn parse_mac_payload(body: &[u8]) -> Result<MacPayload> {
+    if body.len() < FHDR_MIN_LEN {
+        return Err(frame_error(format!("{} bytes MAC payload", body.len())));
+    }
+    let f_ctrl = body[4];
+    let f_opts_end = FHDR_MIN_LEN + usize::from(f_ctrl & FCTRL_FOPTS_LEN);
+    if body.len() < f_opts_end {
+        return Err(frame_error(format!("FOpts truncated, {} bytes MAC payload", body.len())));
+    }
+    let (f_port, frm_payload) = match body.get(f_opts_end) {
+        Some(port) => (Some(*port), body[f_opts_end + 1..].to_vec()),
+        None => (None, Vec::new()),
+    };
+    return Ok(MacPayload {
+        fhdr: Fhdr {
+            dev_addr: u32::from_le_bytes(body[0..4].try_into().unwrap()),
+            f_ctrl: FCtrl::from_byte(f_ctrl),
+            f_cnt: u16::from_le_bytes([body[5], body[6]]),
+            f_opts: body[FHDR_MIN_LEN..f_opts_end].to_vec(),
+        },
+        f_port,
+        frm_payload,
+    });
+}
+
+fn frame_error(msg: String) -> Error {
+    return Error::PayloadError(format!("LoRaWAN frame: {}", msg));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_frame(f_opts: Vec<u8>) -> PhyPayload {
+        return PhyPayload::data(
+            MType::UnconfirmedDataUp,
+            MacPayload {
+                fhdr: Fhdr { dev_addr: 0x26011bda, f_cnt: 1, f_opts, ..Fhdr::default() },
+                f_port: Some(1),
+                frm_payload: b"hello".to_vec(),
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn f_opts_round_trip() {
+        let frame = data_frame(vec![0x02; 15]);
+        let parsed = PhyPayload::parse(&frame.to_bytes().unwrap()).unwrap();
+        assert_eq!(parsed.mac_payload(), frame.mac_payload());
+    }
+
+    #[test]
+    fn f_opts_longer_than_15_bytes_rejected() {
+        let frame = data_frame(vec![0x02; 16]);
+        assert!(frame.to_bytes().is_err());
+        assert!(frame.compute_mic(&[0; 16]).is_err());
+    }
+
+    #[test]
+    fn data_requires_data_mtype() {
+        assert!(PhyPayload::data(MType::JoinRequest, MacPayload::default()).is_err());
+    }
+
+    #[test]
+    fn truncated_f_opts_rejected() {
+        // FOptsLen 5, 2 bytes of FOpts
+        let bytes = [0x40, 0xda, 0x1b, 0x01, 0x26, 0x05, 0x01, 0x00, 0x03, 0x04, 0, 0, 0, 0];
+        assert!(PhyPayload::parse(&bytes).is_err());
+        assert!(PhyPayload::parse(&bytes[..4]).is_err());
+    }
+
+    #[test]
+    fn bad_join_request_length_rejected() {
+        let mut bytes = vec![0x00];
+        bytes.extend_from_slice(&[0x11; 17]);
+        bytes.extend_from_slice(&[0; 4]);
+        assert!(PhyPayload::parse(&bytes).is_err());
+        bytes.insert(1, 0x11);
+        assert!(PhyPayload::parse(&bytes).is_ok());
+    }
+
+    #[test]
+    fn rfu_mtype_rejected() {
+        let bytes = [0xc0, 0xda, 0x1b, 0x01, 0x26, 0x00, 0x01, 0x00, 0, 0, 0, 0];
+        assert!(PhyPayload::parse(&bytes).is_err());
+    }
+}
@@ -0,0 +1,533 @@
+use serde_derive::*;
+
+use super::error::{Error, Result};
+
+// data types, IPSO object id - 3200
+static LPP_DIGITAL_INPUT: u8 = 0;
+static LPP_DIGITAL_OUTPUT: u8 = 1;
+static LPP_ANALOG_INPUT: u8 = 2;
+static LPP_ANALOG_OUTPUT: u8 = 3;
+static LPP_GENERIC_SENSOR: u8 = 100;
+static LPP_ILLUMINANCE: u8 = 101;
+static LPP_PRESENCE: u8 = 102;
+static LPP_TEMPERATURE: u8 = 103;
+static LPP_HUMIDITY: u8 = 104;
+static LPP_ACCELEROMETER: u8 = 113;
+static LPP_BAROMETER: u8 = 115;
+static LPP_VOLTAGE: u8 = 116;
+static LPP_CURRENT: u8 = 117;
+static LPP_FREQUENCY: u8 = 118;
+static LPP_PERCENTAGE: u8 = 120;
+static LPP_ALTITUDE: u8 = 121;
+static LPP_CONCENTRATION: u8 = 125;
+static LPP_POWER: u8 = 128;
+static LPP_DISTANCE: u8 = 130;
+static LPP_ENERGY: u8 = 131;
+static LPP_DIRECTION: u8 = 132;
+static LPP_UNIX_TIME: u8 = 133;
+static LPP_GYROMETER: u8 = 134;
+static LPP_COLOUR: u8 = 135;
+static LPP_GPS: u8 = 136;
+static LPP_SWITCH: u8 = 142;
+
+/// Cayenne LPP value, in the units of the format (resolution in brackets)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum LppValue {
+    DigitalInput { value: u8 },
+    DigitalOutput { value: u8 },
+    /// (0.01 signed)
+    AnalogInput { value: f32 },
+    /// (0.01 signed)
+    AnalogOutput { value: f32 },
+    GenericSensor { value: u32 },
+    /// lux
+    Illuminance { value: u16 },
+    Presence { value: u8 },
+    /// °C (0.1 signed)
+    Temperature { value: f32 },
+    /// %RH (0.5)
+    Humidity { value: f32 },
+    /// G (0.001 signed)
+    Accelerometer { x: f32, y: f32, z: f32 },
+    /// hPa (0.1)
+    Barometer { value: f32 },
+    /// V (0.01)
+    Voltage { value: f32 },
+    /// A (0.001)
+    Current { value: f32 },
+    /// Hz
+    Frequency { value: u32 },
+    /// %
+    Percentage { value: u8 },
+    /// m (signed)
+    Altitude { value: i16 },
+    /// ppm
+    Concentration { value: u16 },
+    /// W
+    Power { value: u16 },
+    /// m (0.001)
+    Distance { value: f64 },
+    /// kWh (0.001)
+    Energy { value: f64 },
+    /// degrees
+    Direction { value: u16 },
+    /// seconds since the unix epoch
+    UnixTime { value: u32 },
+    /// °/s (0.01 signed)
+    Gyrometer { x: f32, y: f32, z: f32 },
+    Colour { r: u8, g: u8, b: u8 },
+    /// degrees (0.0001 signed), altitude in m (0.01 signed)
+    Gps { latitude: f64, longitude: f64, altitude: f64 },
+    Switch { value: u8 },
+}
+
+/// channel and value of a Cayenne LPP payload
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LppRecord {
+    pub channel: u8,
+    #[serde(flatten)]
+    pub value: LppValue,
+}
+
+impl LppValue {
+    /// data type of the value
+    pub fn type_id(&self) -> u8 {
+        return match self {
+            LppValue::DigitalInput { .. } => LPP_DIGITAL_INPUT,
+            LppValue::DigitalOutput { .. } => LPP_DIGITAL_OUTPUT,
+            LppValue::AnalogInput { .. } => LPP_ANALOG_INPUT,
+            LppValue::AnalogOutput { .. } => LPP_ANALOG_OUTPUT,
+            LppValue::GenericSensor { .. } => LPP_GENERIC_SENSOR,
+            LppValue::Illuminance { .. } => LPP_ILLUMINANCE,
+            LppValue::Presence { .. } => LPP_PRESENCE,
+            LppValue::Temperature { .. } => LPP_TEMPERATURE,
+            LppValue::Humidity { .. } => LPP_HUMIDITY,
+            LppValue::Accelerometer { .. } => LPP_ACCELEROMETER,
+            LppValue::Barometer { .. } => LPP_BAROMETER,
+            LppValue::Voltage { .. } => LPP_VOLTAGE,
+            LppValue::Current { .. } => LPP_CURRENT,
+            LppValue::Frequency { .. } => LPP_FREQUENCY,
+            LppValue::Percentage { .. } => LPP_PERCENTAGE,
+            LppValue::Altitude { .. } => LPP_ALTITUDE,
+            LppValue::Concentration { .. } => LPP_CONCENTRATION,
+            LppValue::Power { .. } => LPP_POWER,
+            LppValue::Distance { .. } => LPP_DISTANCE,
+            LppValue::Energy { .. } => LPP_ENERGY,
+            LppValue::Direction { .. } => LPP_DIRECTION,
+            LppValue::UnixTime { .. } => LPP_UNIX_TIME,
+            LppValue::Gyrometer { .. } => LPP_GYROMETER,
+            LppValue::Colour { .. } => LPP_COLOUR,
+            LppValue::Gps { .. } => LPP_GPS,
+            LppValue::Switch { .. } => LPP_SWITCH,
+        };
+    }
+
+    // big endian data bytes, the values are rounded to the type resolution and saturated
+    fn encode_data(&self, out: &mut Vec<u8>) {
+        match *self {
+            LppValue::DigitalInput { value }
+            | LppValue::DigitalOutput { value }
+            | LppValue::Presence { value }
+            | LppValue::Percentage { value }
+            | LppValue::Switch { value } => out.push(value),
+            LppValue::AnalogInput { value } | LppValue::AnalogOutput { value } => put_i16(out, value as f64, 100.0),
+            LppValue::GenericSensor { value } | LppValue::Frequency { value } | LppValue::UnixTime { value } => {
+                out.extend_from_slice(&value.to_be_bytes())
+            }
+            LppValue::Illuminance { value }
+            | LppValue::Concentration { value }
+            | LppValue::Power { value }
+            | LppValue::Direction { value } => out.extend_from_slice(&value.to_be_bytes()),
+            LppValue::Temperature { value } => put_i16(out, value as f64, 10.0),
+            LppValue::Humidity { value } => out.push((value as f64 * 2.0).round() as u8),
+            LppValue::Accelerometer { x, y, z } => {
+                for v in [x, y, z] {
+                    put_i16(out, v as f64, 1000.0);
+                }
+            }
+            LppValue::Barometer { value } => put_u16(out, value as f64, 10.0),
+            LppValue::Voltage { value } => put_u16(out, value as f64, 100.0),
+            LppValue::Current { value } => put_u16(out, value as f64, 1000.0),
+            LppValue::Altitude { value } => out.extend_from_slice(&value.to_be_bytes()),
+            LppValue::Distance { value } | LppValue::Energy { value } => {
+                out.extend_from_slice(&((value * 1000.0).round() as u32).to_be_bytes())
+            }
+            LppValue::Gyrometer { x, y, z } => {
+                for v in [x, y, z] {
+                    put_i16(out, v as f64, 100.0);
+                }
+            }
+            LppValue::Colour { r, g, b } => out.extend_from_slice(&[r, g, b]),
+            LppValue::Gps { latitude, longitude, altitude } => {
+                put_i24(out, latitude, 10000.0);
+                put_i24(out, longitude, 10000.0);
+                put_i24(out, altitude, 100.0);
+            }
+        }
+    }
+
+    // data size of a type, None if the type is unknown
+    fn data_size(type_id: u8) -> Option<usize> {
+        let size = match type_id {
+            t if t == LPP_DIGITAL_INPUT || t == LPP_DIGITAL_OUTPUT || t == LPP_PRESENCE => 1,
+            t if t == LPP_HUMIDITY || t == LPP_PERCENTAGE || t == LPP_SWITCH => 1,
+            t if t == LPP_ANALOG_INPUT || t == LPP_ANALOG_OUTPUT || t == LPP_ILLUMINANCE => 2,
+            t if t == LPP_TEMPERATURE || t == LPP_BAROMETER || t == LPP_VOLTAGE || t == LPP_CURRENT => 2,
+            t if t == LPP_ALTITUDE || t == LPP_CONCENTRATION || t == LPP_POWER || t == LPP_DIRECTION => 2,
+            t if t == LPP_COLOUR => 3,
+            t if t == LPP_GENERIC_SENSOR || t == LPP_FREQUENCY || t == LPP_DISTANCE => 4,
+            t if t == LPP_ENERGY || t == LPP_UNIX_TIME => 4,
+            t if t == LPP_ACCELEROMETER || t == LPP_GYROMETER => 6,
+            t if t == LPP_GPS => 9,
+            _ => return None,
+        };
+        return Some(size);
+    }
+
+    fn decode_data(type_id: u8, d: &[u8]) -> LppValue {
+        let i16_at = |i: usize| i16::from_be_bytes([d[i], d[i + 1]]) as f64;
+        let u16_at = |i: usize| u16::from_be_bytes([d[i], d[i + 1]]);
+        let u32_at = |i: usize| u32::from_be_bytes([d[i], d[i + 1], d[i + 2], d[i + 3]]);
+        // sign extension of a 24 bits value
+        let i24_at = |i: usize| (i32::from_be_bytes([d[i], d[i + 1], d[i + 2], 0]) >> 8) as f64;
+        return match type_id {
+            t if t == LPP_DIGITAL_INPUT => LppValue::DigitalInput { value: d[0] },
+            t if t == LPP_DIGITAL_OUTPUT => LppValue::DigitalOutput { value: d[0] },
+            t if t == LPP_ANALOG_INPUT => LppValue::AnalogInput { value: (i16_at(0) / 100.0) as f32 },
+            t if t == LPP_ANALOG_OUTPUT => LppValue::AnalogOutput { value: (i16_at(0) / 100.0) as f32 },
+            t if t == LPP_GENERIC_SENSOR => LppValue::GenericSensor { value: u32_at(0) },
+            t if t == LPP_ILLUMINANCE => LppValue::Illuminance { value: u16_at(0) },
+            t if t == LPP_PRESENCE => LppValue::Presence { value: d[0] },
+            t if t == LPP_TEMPERATURE => LppValue::Temperature { value: (i16_at(0) / 10.0) as f32 },
+            t if t == LPP_HUMIDITY => LppValue::Humidity { value: d[0] as f32 / 2.0 },
+            t if t == LPP_ACCELEROMETER => LppValue::Accelerometer {
+                x: (i16_at(0) / 1000.0) as f32,
+                y: (i16_at(2) / 1000.0) as f32,
+                z: (i16_at(4) / 1000.0) as f32,
+            },
+            t if t == LPP_BAROMETER => LppValue::Barometer { value: u16_at(0) as f32 / 10.0 },
+            t if t == LPP_VOLTAGE => LppValue::Voltage { value: u16_at(0) as f32 / 100.0 },
+            t if t == LPP_CURRENT => LppValue::Current { value: u16_at(0) as f32 / 1000.0 },
+            t if t == LPP_FREQUENCY => LppValue::Frequency { value: u32_at(0) },
+            t if t == LPP_PERCENTAGE => LppValue::Percentage { value: d[0] },
+            t if t == LPP_ALTITUDE => LppValue::Altitude { value: i16_at(0) as i16 },
+            t if t == LPP_CONCENTRATION => LppValue::Concentration { value: u16_at(0) },
+            t if t == LPP_POWER => LppValue::Power { value: u16_at(0) },
+            t if t == LPP_DISTANCE => LppValue::Distance { value: u32_at(0) as f64 / 1000.0 },
+            t if t == LPP_ENERGY => LppValue::Energy { value: u32_at(0) as f64 / 1000.0 },
+            t if t == LPP_DIRECTION => LppValue::Direction { value: u16_at(0) },
+            t if t == LPP_UNIX_TIME => LppValue::UnixTime { value: u32_at(0) },
+            t if t == LPP_GYROMETER => LppValue::Gyrometer {
+                x: (i16_at(0) / 100.0) as f32,
+                y: (i16_at(2) / 100.0) as f32,
+                z: (i16_at(4) / 100.0) as f32,
+            },
+            t if t == LPP_COLOUR => LppValue::Colour { r: d[0], g: d[1], b: d[2] },
+            t if t == LPP_GPS => LppValue::Gps {
+                latitude: i24_at(0) / 10000.0,
+                longitude: i24_at(3) / 10000.0,
+                altitude: i24_at(6) / 100.0,
+            },
+            t if t == LPP_SWITCH => LppValue::Switch { value: d[0] },
+            _ => unreachable!("unknown types are rejected by data_size()"),
+        };
+    }
+}
+
+fn put_i16(out: &mut Vec<u8>, value: f64, scale: f64) {
+    out.extend_from_slice(&((value * scale).round() as i16).to_be_bytes());
+}
+
+fn put_u16(out: &mut Vec<u8>, value: f64, scale: f64) {
+    out.extend_from_slice(&((value * scale).round() as u16).to_be_bytes());
+}
+
+fn put_i24(out: &mut Vec<u8>, value: f64, scale: f64) {
+    let v = ((value * scale).round() as i32).clamp(-0x80_0000, 0x7f_ffff);
+    out.extend_from_slice(&v.to_be_bytes()[1..]);
+}
+
+/// Cayenne LPP payload builder
+///
+/// ```
+/// use lwnsim_api_rs::*;
+///
+/// let lpp = CayenneLpp::new().temperature(1, 21.5).humidity(2, 48.0);
+/// assert_eq!(lpp.bytes(), vec![0x01, 0x67, 0x00, 0xd7, 0x02, 0x68, 0x60]);
+/// assert_eq!(CayenneLpp::decode(&lpp.bytes()).unwrap(), lpp.records());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CayenneLpp {
+    records: Vec<LppRecord>,
+}
+
+impl CayenneLpp {
+    pub fn new() -> CayenneLpp {
+        CayenneLpp::default()
+    }
+
+    pub fn add(mut self, channel: u8, value: LppValue) -> CayenneLpp {
+        self.records.push(LppRecord { channel, value });
+        self
+    }
+
+    pub fn digital_input(self, channel: u8, value: u8) -> CayenneLpp {
+        return self.add(channel, LppValue::DigitalInput { value });
+    }
+
+    pub fn digital_output(self, channel: u8, value: u8) -> CayenneLpp {
+        return self.add(channel, LppValue::DigitalOutput { value });
+    }
+
+    pub fn analog_input(self, channel: u8, value: f32) -> CayenneLpp {
+        return self.add(channel, LppValue::AnalogInput { value });
+    }
+
+    pub fn analog_output(self, channel: u8, value: f32) -> CayenneLpp {
+        return self.add(channel, LppValue::AnalogOutput { value });
+    }
+
+    pub fn generic_sensor(self, channel: u8, value: u32) -> CayenneLpp {
+        return self.add(channel, LppValue::GenericSensor { value });
+    }
+
+    pub fn illuminance(self, channel: u8, lux: u16) -> CayenneLpp {
+        return self.add(channel, LppValue::Illuminance { value: lux });
+    }
+
+    pub fn presence(self, channel: u8, value: u8) -> CayenneLpp {
+        return self.add(channel, LppValue::Presence { value });
+    }
+
+    pub fn temperature(self, channel: u8, celsius: f32) -> CayenneLpp {
+        return self.add(channel, LppValue::Temperature { value: celsius });
+    }
+
+    pub fn humidity(self, channel: u8, rh: f32) -> CayenneLpp {
+        return self.add(channel, LppValue::Humidity { value: rh });
+    }
+
+    pub fn accelerometer(self, channel: u8, x: f32, y: f32, z: f32) -> CayenneLpp {
+        return self.add(channel, LppValue::Accelerometer { x, y, z });
+    }
+
+    pub fn barometer(self, channel: u8, hpa: f32) -> CayenneLpp {
+        return self.add(channel, LppValue::Barometer { value: hpa });
+    }
+
+    pub fn voltage(self, channel: u8, volts: f32) -> CayenneLpp {
+        return self.add(channel, LppValue::Voltage { value: volts });
+    }
+
+    pub fn current(self, channel: u8, amps: f32) -> CayenneLpp {
+        return self.add(channel, LppValue::Current { value: amps });
+    }
+
+    pub fn frequency(self, channel: u8, hz: u32) -> CayenneLpp {
+        return self.add(channel, LppValue::Frequency { value: hz });
+    }
+
+    pub fn percentage(self, channel: u8, value: u8) -> CayenneLpp {
+        return self.add(channel, LppValue::Percentage { value });
+    }
+
+    pub fn altitude(self, channel: u8, meters: i16) -> CayenneLpp {
+        return self.add(channel, LppValue::Altitude { value: meters });
+    }
+
+    pub fn concentration(self, channel: u8, ppm: u16) -> CayenneLpp {
+        return self.add(channel, LppValue::Concentration { value: ppm });
+    }
+
+    pub fn power(self, channel: u8, watts: u16) -> CayenneLpp {
+        return self.add(channel, LppValue::Power { value: watts });
+    }
+
+    pub fn distance(self, channel: u8, meters: f64) -> CayenneLpp {
+        return self.add(channel, LppValue::Distance { value: meters });
+    }
+
+    pub fn energy(self, channel: u8, kwh: f64) -> CayenneLpp {
+        return self.add(channel, LppValue::Energy { value: kwh });
+    }
+
+    pub fn direction(self, channel: u8, degrees: u16) -> CayenneLpp {
+        return self.add(channel, LppValue::Direction { value: degrees });
+    }
+
+    pub fn unix_time(self, channel: u8, secs: u32) -> CayenneLpp {
+        return self.add(channel, LppValue::UnixTime { value: secs });
+    }
+
+    pub fn gyrometer(self, channel: u8, x: f32, y: f32, z: f32) -> CayenneLpp {
+        return self.add(channel, LppValue::Gyrometer { x, y, z });
+    }
+
+    pub fn colour(self, channel: u8, r: u8, g: u8, b: u8) -> CayenneLpp {
+        return self.add(channel, LppValue::Colour { r, g, b });
+    }
+
+    pub fn gps(self, channel: u8, latitude: f64, longitude: f64, altitude: f64) -> CayenneLpp {
+        return self.add(channel, LppValue::Gps { latitude, longitude, altitude });
+    }
+
+    pub fn switch(self, channel: u8, value: u8) -> CayenneLpp {
+        return self.add(channel, LppValue::Switch { value });
+    }
+
+    pub fn records(&self) -> Vec<LppRecord> {
+        return self.records.clone();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.records.is_empty();
+    }
+
+    /// payload bytes: channel, type and data of each record
+    pub fn bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for r in &self.records {
+            out.push(r.channel);
+            out.push(r.value.type_id());
+            r.value.encode_data(&mut out);
+        }
+        return out;
+    }
+
+    /// parses a payload, fails on an unknown type or a truncated record
+    pub fn decode(data: &[u8]) -> Result<Vec<LppRecord>> {
+        let mut records = Vec::new();
+        let mut i = 0;
+        while i < data.len() {
+            if i + 2 > data.len() {
+                return Err(Error::PayloadError(format!("truncated LPP record at byte {}", i)));
+            }
+            let (channel, type_id) = (data[i], data[i + 1]);
+            let size = LppValue::data_size(type_id)
+                .ok_or_else(|| Error::PayloadError(format!("unknown LPP type {} at byte {}", type_id, i + 1)))?;
+            let d = data
+                .get(i + 2..i + 2 + size)
+                .ok_or_else(|| Error::PayloadError(format!("truncated LPP record at byte {}", i)))?;
+            records.push(LppRecord {
+                channel,
+                value: LppValue::decode_data(type_id, d),
+            });
+            i += 2 + size;
+        }
+        return Ok(records);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_type_is_encoded_and_decoded() {
+        let lpp = CayenneLpp::new()
+            .digital_input(1, 1)
+            .digital_output(2, 0)
+            .analog_input(3, -12.25)
+            .analog_output(4, 3.5)
+            .generic_sensor(5, 70000)
+            .illuminance(6, 1000)
+            .presence(7, 1)
+            .temperature(8, -4.5)
+            .humidity(9, 48.5)
+            .accelerometer(10, -1.0, 0.5, 0.25)
+            .barometer(11, 1013.5)
+            .voltage(12, 3.25)
+            .current(13, 0.5)
+            .frequency(14, 868_100_000)
+            .percentage(15, 75)
+            .altitude(16, -20)
+            .concentration(17, 410)
+            .power(18, 1500)
+            .distance(19, 12.5)
+            .energy(20, 2.25)
+            .direction(21, 270)
+            .unix_time(22, 1_679_306_400)
+            .gyrometer(23, 1.5, -0.25, 0.0)
+            .colour(24, 255, 128, 0)
+            .gps(25, 42.3519, -87.9094, 10.0)
+            .switch(26, 1);
+        let bytes: Vec<u8> = [
+            &[1, 0, 1][..],
+            &[2, 1, 0],
+            &[3, 2, 0xfb, 0x37],
+            &[4, 3, 0x01, 0x5e],
+            &[5, 100, 0x00, 0x01, 0x11, 0x70],
+            &[6, 101, 0x03, 0xe8],
+            &[7, 102, 1],
+            &[8, 103, 0xff, 0xd3],
+            &[9, 104, 0x61],
+            &[10, 113, 0xfc, 0x18, 0x01, 0xf4, 0x00, 0xfa],
+            &[11, 115, 0x27, 0x97],
+            &[12, 116, 0x01, 0x45],
+            &[13, 117, 0x01, 0xf4],
+            &[14, 118, 0x33, 0xbe, 0x27, 0xa0],
+            &[15, 120, 75],
+            &[16, 121, 0xff, 0xec],
+            &[17, 125, 0x01, 0x9a],
+            &[18, 128, 0x05, 0xdc],
+            &[19, 130, 0x00, 0x00, 0x30, 0xd4],
+            &[20, 131, 0x00, 0x00, 0x08, 0xca],
+            &[21, 132, 0x01, 0x0e],
+            &[22, 133, 0x64, 0x18, 0x2e, 0xa0],
+            &[23, 134, 0x00, 0x96, 0xff, 0xe7, 0x00, 0x00],
+            &[24, 135, 255, 128, 0],
+            &[25, 136, 0x06, 0x76, 0x5f, 0xf2, 0x96, 0x0a, 0x00, 0x03, 0xe8],
+            &[26, 142, 1],
+        ]
+        .concat();
+        assert_eq!(lpp.bytes(), bytes);
+        // the values are exact at the type resolution
+        assert_eq!(CayenneLpp::decode(&bytes).unwrap(), lpp.records());
+    }
+
+    #[test]
+    fn values_are_rounded_and_saturated() {
+        let decoded = |lpp: CayenneLpp| CayenneLpp::decode(&lpp.bytes()).unwrap()[0].value;
+        assert_eq!(decoded(CayenneLpp::new().temperature(1, 21.56)), LppValue::Temperature { value: 21.6 });
+        assert_eq!(decoded(CayenneLpp::new().temperature(1, -21.56)), LppValue::Temperature { value: -21.6 });
+        assert_eq!(decoded(CayenneLpp::new().analog_input(1, 1.236)), LppValue::AnalogInput { value: 1.24 });
+        assert_eq!(decoded(CayenneLpp::new().humidity(1, 48.3)), LppValue::Humidity { value: 48.5 });
+
+        assert_eq!(CayenneLpp::new().temperature(1, 5000.0).bytes(), vec![1, 103, 0x7f, 0xff]);
+        assert_eq!(CayenneLpp::new().temperature(1, -5000.0).bytes(), vec![1, 103, 0x80, 0x00]);
+        assert_eq!(CayenneLpp::new().humidity(1, 150.0).bytes(), vec![1, 104, 0xff]);
+        assert_eq!(CayenneLpp::new().humidity(1, -1.0).bytes(), vec![1, 104, 0x00]);
+        assert_eq!(CayenneLpp::new().voltage(1, -1.0).bytes(), vec![1, 116, 0x00, 0x00]);
+        assert_eq!(CayenneLpp::new().voltage(1, 1000.0).bytes(), vec![1, 116, 0xff, 0xff]);
+        assert_eq!(CayenneLpp::new().distance(1, 5e6).bytes(), vec![1, 130, 0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(
+            CayenneLpp::new().gps(1, 1000.0, -1000.0, 0.0).bytes(),
+            vec![1, 136, 0x7f, 0xff, 0xff, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn invalid_payloads_are_rejected() {
+        assert_eq!(CayenneLpp::decode(&[]).unwrap(), vec![]);
+        let error = |data: &[u8]| match CayenneLpp::decode(data) {
+            Err(Error::PayloadError(msg)) => msg,
+            res => panic!("{:?}", res),
+        };
+        assert_eq!(error(&[1]), "truncated LPP record at byte 0");
+        assert_eq!(error(&[1, 103, 0x00]), "truncated LPP record at byte 0");
+        assert_eq!(error(&[1, 0, 1, 2, 136, 0, 0, 0, 0, 0, 0, 0, 0]), "truncated LPP record at byte 3");
+        assert_eq!(error(&[1, 0, 1, 2, 99, 0]), "unknown LPP type 99 at byte 4");
+    }
+
+    #[test]
+    fn records_serialized_by_type() {
+        let record = LppRecord {
+            channel: 3,
+            value: LppValue::Colour { r: 1, g: 2, b: 3 },
+        };
+        let json = serde_json::to_value(record).unwrap();
+        assert_eq!(json, serde_json::json!({"channel": 3, "type": "colour", "r": 1, "g": 2, "b": 3}));
+        assert_eq!(serde_json::from_value::<LppRecord>(json).unwrap(), record);
+    }
+}
@@ -1,22 +1,39 @@
 use rust_socketio::client::Client;
 use rust_socketio::{ClientBuilder, Payload, RawClient};
 
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use lazy_static::lazy_static;
 use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Mutex;
 
 
 
 use super::error::{Error, Result};
-use super::lora_events::{LORA_EVENTS,LoraEvents};
+use super::lora_events::{subscribe_lora_events, LORA_EVENTS, LoraEvents};
+use super::mac_cmd::{MacCommand, MAC_COMMANDS};
+use super::adr::{AdrState, ADR_STATE};
+use super::ack::PENDING_ACKS;
+use super::energy::TX_ENERGY;
+use super::lora_stats::LORA_STATS;
+use super::dev_log::{DevLog, DevLogKind, DEV_LOGS};
+use super::raw_frame::{RawFrame, RAW_FRAMES};
 //use super::lora_dev::LORA;
 use super::lwnsim_cmd::*;
+use super::lwnsim_builder::LwnsimBuilder;
+use super::recorder::{record, record_event, Recorder, RecordEntry, RECORDER};
+use super::replay::Replayer;
+use super::metrics::{record_cmd, record_connect, record_reconnect};
+use serde_derive::*;
+use serde_json::Value;
+use std::path::Path;
 
 // log
 use log::{info, trace, warn};
 
+/// connection status of the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
 pub enum LwnsimStatus {
     ConnNOK,
     ConnInit,
@@ -34,8 +51,11 @@ pub enum LwnsimStatus {
 // events sent by simulator, call back function defined in ClientBuilder.on()
 static DEV_EVENT_ACK_CMD: &str = "ack-cmd";
 static DEV_EVENT_LORA: &str = "lora-event";
-//static DEV_EVENT_LOG: &str = "dev-log"; // unused
-// static DEV_EVENT_ERROR: &str = "dev-error"; //unused
+static DEV_EVENT_MAC_CMD: &str = "mac-command";
+static DEV_EVENT_ADR_CHANGE: &str = "adr-change";
+static DEV_EVENT_LOG: &str = "dev-log";
+static DEV_EVENT_ERROR: &str = "dev-error";
+static DEV_EVENT_RAW_FRAME: &str = "raw-frame";
 // static DEV_EVENT_RESPONSE_CMD: &str = "response-cmd"; // is handled by emit_with_ack call back function
 
 // use CMD_<cmd name> defined in lwnsim_cmd.rs as event name
@@ -49,18 +69,54 @@ lazy_static! {
     pub static ref LWNSIM: Mutex<Lwnsim> = Mutex::new(Lwnsim::new());
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum SendMode {
     Call,
     Emit,
 }
 
+// default timeouts, see Lwnsim::set_cmd_timeout() and Lwnsim::set_ack_timeout()
+pub static DEFAULT_CMD_TIMEOUT: Duration = Duration::from_secs(10);
+pub static DEFAULT_ACK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// per call options of the LoraDev operations, unset values fall back to the Lwnsim settings
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CmdOptions {
+    /// time to wait for the simulator response of a command
+    pub cmd_timeout: Option<Duration>,
+    /// time to wait for the socketio ack of a command (and for the ack-cmd event when waiting for acks)
+    pub ack_timeout: Option<Duration>,
+}
+
+impl CmdOptions {
+    pub fn new() -> CmdOptions {
+        CmdOptions::default()
+    }
+
+    pub fn cmd_timeout(mut self, timeout: Duration) -> CmdOptions {
+        self.cmd_timeout = Some(timeout);
+        self
+    }
+
+    pub fn ack_timeout(mut self, timeout: Duration) -> CmdOptions {
+        self.ack_timeout = Some(timeout);
+        self
+    }
+}
+
 pub struct Lwnsim {
     url: Option<String>,
     dev_eui: Option<String>,
     socket: Option<Client>,
+    // replaces the socket when replaying a recording
+    replay: Option<Replayer>,
     status: LwnsimStatus,
     ack_cmd: bool,
-    timeout_cmd: u64,
+    wait_ack: bool,
+    timeout_cmd: Duration,
+    timeout_ack: Duration,
+    log_tag: String,
 
     //    handle_response_cmd: Box<dyn FnMut(Payload,RawClient)->()>,
 }
@@ -70,115 +126,264 @@ impl Lwnsim {
         Lwnsim {
             status: LwnsimStatus::ConnNOK,
             socket: None,
+            replay: None,
             ack_cmd: true,
-            timeout_cmd: 10,
+            wait_ack: false,
+            timeout_cmd: DEFAULT_CMD_TIMEOUT,
+            timeout_ack: DEFAULT_ACK_TIMEOUT,
             dev_eui: None,
             url: None,
+            log_tag: "[LWNSIM]".to_string(),
         }
     }
 
+    pub fn get_status(&self) -> LwnsimStatus {
+        return self.status;
+    }
+
     pub fn get_dev_eui(&self) -> &str {
         return self.dev_eui.as_ref().expect("[LWNSIM] devEUI not defined");
     }
 
-/*     fn set_cmd_timeout(&mut self, timeout: u64){
-        self.timeout_cmd=timeout;
-    } */
+    #[cfg(test)]
+    pub(crate) fn set_dev_eui(&mut self, dev_eui: &str) {
+        self.dev_eui = Some(dev_eui.to_string());
+    }
+
+    /// when set, commands sent with SendMode::Emit (join-request, send-uplink) wait for the
+    /// simulator ack-cmd event and fail with DevCmdAckTimeout if it does not arrive in time
+    pub fn set_wait_ack(&mut self, wait_ack: bool) {
+        self.wait_ack = wait_ack;
+    }
+
+    /// number of commands sent with Ack=true (join-request, send-uplink) and not yet acknowledged
+    pub fn outstanding_cmds(&self) -> usize {
+        return PENDING_ACKS.lock().unwrap().outstanding();
+    }
+
+    /// sets the default time to wait for the simulator response of a command
+    pub fn set_cmd_timeout(&mut self, timeout: Duration) {
+        self.timeout_cmd = timeout;
+    }
+
+    /// sets the default time to wait for the ack of a command
+    pub fn set_ack_timeout(&mut self, timeout: Duration) {
+        self.timeout_ack = timeout;
+    }
+
+
+    /// records the traffic with the simulator to a JSON Lines file (see recorder::Record),
+    /// the recording restarts from a connect record
+    pub fn start_recording<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let mut recorder = Recorder::create(path)?;
+        if let (Some(url), Some(dev_eui)) = (&self.url, &self.dev_eui) {
+            recorder.write(RecordEntry::Connect {
+                url: url.clone(),
+                dev_eui: dev_eui.clone(),
+            })?;
+        }
+        *RECORDER.lock().unwrap() = Some(recorder);
+        return Ok(());
+    }
 
+    pub fn stop_recording(&mut self) {
+        *RECORDER.lock().unwrap() = None;
+    }
 
-    pub fn connect(&mut self, url: &str, dev_eui: &str) {
-        self.url = Some(url.to_string());
-        self.dev_eui= Some(dev_eui.to_string());
+    /// connects to the simulator with the default settings (see LwnsimBuilder for the other options)
+    /// LWNSIM is never dropped: a linked device stays linked on exit unless unlinked, see LwnsimSession
+    pub fn connect(&mut self, url: &str, dev_eui: &str) -> Result<()> {
+        return self.connect_with(LwnsimBuilder::new(url, dev_eui));
+    }
+
+    pub fn connect_with(&mut self, builder: LwnsimBuilder) -> Result<()> {
+        self.url = Some(builder.url_with_query());
+        self.dev_eui= Some(builder.dev_eui.clone());
+        self.timeout_cmd = builder.cmd_timeout;
+        self.timeout_ack = builder.ack_timeout;
+        self.wait_ack = builder.wait_ack;
+        self.log_tag = builder.log_prefix();
         self.status = LwnsimStatus::ConnInit;
+        self.replay = None;
+        if let Some(path) = &builder.record {
+            self.start_recording(path)?;
+        }
 
-        let socket = ClientBuilder::new(url.to_string())
-            .namespace("/dev")
-            .on("open", |_, _| info!("[LWNSIM][Socket event] Connected"))
-            .on("close", |_, _| info!("[LWNSIM][Socket event] Disconnected"))
-            .on(DEV_EVENT_ACK_CMD, |payload, _: RawClient| {
-                if let Payload::String(s) = payload {
-                    trace!("[LWNSIM][CMD_ACK][cmd]{:?}", s);
-                };
-            })
-            .on(DEV_EVENT_LORA, |payload, _: RawClient| {
-
-                if let Payload::String(pl_str) = payload {
-                    trace!("[LWNSIM][LORA EVENT]{:?}", pl_str);
-                    let lora_event: DevLoraEvent = serde_json::from_str(&pl_str)
-                        .expect("[LWNSIM][ParseDevLoraEventError]json error");
-                    LWNSIM.lock().unwrap().push_lora_event(lora_event.event);
-                } else {
-                    warn!("[LWNSIM][ParseDevLoraEventError]not the String variant");
+        let (tag_open, tag_close) = (self.log_tag.clone(), self.log_tag.clone());
+        // the first open event of the client is its connection, the next ones are reconnections
+        let reconnected = AtomicBool::new(false);
+        let mut socket_builder = ClientBuilder::new(builder.url_with_query())
+            .namespace(builder.namespace.clone())
+            .reconnect(builder.reconnect.enabled)
+            .reconnect_delay(
+                builder.reconnect.delay_min.as_millis() as u64,
+                builder.reconnect.delay_max.as_millis() as u64,
+            )
+            .on("open", move |_, _| {
+                info!("{}[Socket event] Connected", tag_open);
+                if reconnected.swap(true, Ordering::Relaxed) {
+                    record_reconnect();
                 }
             })
+            .on("close", move |_, _| info!("{}[Socket event] Disconnected", tag_close))
+            .on(DEV_EVENT_ACK_CMD, self.event_handler(DEV_EVENT_ACK_CMD))
+            .on(DEV_EVENT_LORA, self.event_handler(DEV_EVENT_LORA))
+            .on(DEV_EVENT_MAC_CMD, self.event_handler(DEV_EVENT_MAC_CMD))
+            .on(DEV_EVENT_ADR_CHANGE, self.event_handler(DEV_EVENT_ADR_CHANGE))
+            .on(DEV_EVENT_LOG, self.event_handler(DEV_EVENT_LOG))
+            .on(DEV_EVENT_ERROR, self.event_handler(DEV_EVENT_ERROR))
+            .on(DEV_EVENT_RAW_FRAME, self.event_handler(DEV_EVENT_RAW_FRAME));
             // .on("error", |err, _| eprintln!("Error: {:#?}", err))
-            .opening_header("accept-encoding", "application/json")
-            .connect()
-            .expect("Connection failed");
-        info!("[LWNSIM][connect]");
-        self.socket = Some(socket);
+        if let Some(attempts) = builder.reconnect.max_attempts {
+            socket_builder = socket_builder.max_reconnect_attempts(attempts);
+        }
+        if let Some(auth) = builder.auth {
+            socket_builder = socket_builder.auth(auth);
+        }
+        for (key, value) in builder.headers {
+            socket_builder = socket_builder.opening_header(key, value);
+        }
+
+        match socket_builder.connect() {
+            Ok(socket) => {
+                info!("{}[connect]", self.log_tag);
+                record_connect();
+                self.socket = Some(socket);
+                self.status = LwnsimStatus::ConnOK;
+                return Ok(());
+            }
+            Err(e) => {
+                warn!("{}[connect]failed {:?}", self.log_tag, e);
+                self.status = LwnsimStatus::ConnNOK;
+                return Err(Error::SocketioError(e));
+            }
+        }
+    }
+
+    // socket callback of a simulator event
+    fn event_handler(&self, event: &'static str) -> impl FnMut(Payload, RawClient) + Send + 'static {
+        let tag = self.log_tag.clone();
+        return move |payload, _| handle_event(&tag, event, payload);
+    }
+
+    /// connects to a recording instead of the simulator, see replay::replay()
+    pub(crate) fn connect_replay(&mut self, replayer: Replayer) {
+        info!("{}[connect_replay]{}", self.log_tag, replayer.url());
+        replayer.set_log_tag(&self.log_tag);
+        self.url = Some(replayer.url().to_string());
+        self.dev_eui = Some(replayer.dev_eui().to_string());
+        self.socket = None;
+        self.replay = Some(replayer);
         self.status = LwnsimStatus::ConnOK;
     }
 
-    pub fn disconnect(&self) {
-        if let Some(s) = &self.socket {
-            info!("[LWNSIM][disconnect]");
-            s.disconnect().expect("Disconnect failed");
+    /// disconnects from the simulator (or stops the replay), nothing is done if not connected
+    pub fn disconnect(&mut self) {
+        if let Some(r) = self.replay.take() {
+            r.stop();
         }
+        if let Some(s) = self.socket.take() {
+            info!("{}[disconnect]", self.log_tag);
+            if let Err(e) = s.disconnect() {
+                warn!("{}[disconnect]{:?}", self.log_tag, e);
+            }
+        }
+        self.status = LwnsimStatus::ConnNOK;
     }
 
 
 
-    pub fn send_cmd(
+    pub fn send_cmd(&mut self, msg: DevExecuteCmd, mode: SendMode, opts: &CmdOptions) -> Result<Option<Response>> {
+        let cmd = msg.get_cmd();
+        let start = Instant::now();
+        let res = self.exec_cmd(msg, mode, opts);
+        record_cmd(cmd, start.elapsed(), &res);
+        return res;
+    }
+
+    fn exec_cmd(
         &mut self,
-        mut msg: impl DevExecuteCmdTrait + serde::Serialize,
+        mut msg: DevExecuteCmd,
         mode: SendMode,
-    ) -> Result<Option<Box<dyn ResponseCmdTrait>>> {
+        opts: &CmdOptions,
+    ) -> Result<Option<Response>> {
+        let timeout_cmd = opts.cmd_timeout.unwrap_or(self.timeout_cmd);
+        let timeout_ack = opts.ack_timeout.unwrap_or(self.timeout_ack);
 
-        if self.ack_cmd {
-            msg.set_ack(true);
+        // e.g. a device used after disconnect()
+        if self.socket.is_none() && self.replay.is_none() {
+            warn!("{}[{}]not connected", self.log_tag, msg.get_cmd());
+            return Err(Error::CmdError(CmdErrorKind::NotConnected));
+        }
+        // the response of a call acknowledges the command
+        if mode == SendMode::Emit && (self.ack_cmd || self.wait_ack) {
+            msg.ack = true;
         }
-        let msg_json = serde_json::to_value(&msg).expect("serialization to value failed");
         let event_name=msg.get_cmd();
+        let mut ack_rx = None;
+        if msg.ack {
+            let max_age = timeout_ack.max(timeout_cmd);
+            let mut pending_acks = PENDING_ACKS.lock().unwrap();
+            if self.wait_ack {
+                let (cmd_id, rx) = pending_acks.register_waiting(event_name, max_age);
+                msg.cmd_id = Some(cmd_id);
+                ack_rx = Some(rx);
+            } else {
+                msg.cmd_id = Some(pending_acks.register(event_name, max_age));
+            }
+        }
+        let msg_json = serde_json::to_value(&msg).expect("serialization to value failed");
+        record(RecordEntry::Cmd {
+            mode,
+            cmd: msg_json.clone(),
+        });
         match mode {
             SendMode::Emit => {
                 trace!(
-                    "[LWNSIM][CMD_EMIT][{}]{:?}",
+                    "{}[CMD_EMIT][{}]{:?}",
+                    self.log_tag,
                     event_name,
                     //serde_json::to_string(&msg)
                     msg_json
                 );
-                self.socket
-                    .as_ref()
-                    .expect("socket unset")
-                    .emit(event_name, msg_json)
-                    .expect("emit failed");
+                match &self.replay {
+                    Some(replayer) => {
+                        replayer.send_cmd(&msg, mode)?;
+                    }
+                    None => self
+                        .socket
+                        .as_ref()
+                        .ok_or(Error::CmdError(CmdErrorKind::NotConnected))?
+                        .emit(event_name, msg_json)
+                        .map_err(Error::SocketioError)?,
+                }
+                if let (Some(rx), Some(cmd_id)) = (ack_rx, msg.cmd_id) {
+                    if let Err(e) = rx.recv_timeout(timeout_ack) {
+                        trace!("{}[CMD_ACK][TIMEOUT][{}]id= {} {:?}", self.log_tag, event_name, cmd_id, e);
+                        PENDING_ACKS.lock().unwrap().cancel(cmd_id);
+                        return Err(Error::CmdError(CmdErrorKind::DevCmdAckTimeout));
+                    }
+                }
                 return Ok(None);
             }
             SendMode::Call => {
-                let (tx, rx): (Sender<Payload>, Receiver<Payload>) = channel();
-                trace!(
-                    "[LWNSIM][CMD_CALL][{}]{:?}",
-                    msg.get_cmd(),
-                    serde_json::to_string(&msg)
-                );
-                self.socket
-                    .as_ref()
-                    .expect("socket undefined")
-                    .emit_with_ack(
-                        event_name,
-                        msg_json,
-                        Duration::from_secs(2),
-                        move |message: Payload, _: RawClient| {
-                            trace!("[LWNSIM][CMD_RESP]{:?}", message);
-                            // send the result to the channel
-                            tx.send(message).unwrap();
-                        },
-                    ).expect("emit_with_ack failed");
-
-                match rx.recv_timeout(Duration::from_secs(self.timeout_cmd)){
-                    Ok(resp_msg)=> {
+                let resp_msg = match &self.replay {
+                    Some(replayer) => replayer.send_cmd(&msg, mode)?,
+                    None => self.call(event_name, msg_json, timeout_ack, timeout_cmd)?,
+                };
+                match resp_msg {
+                    Some(resp_msg) => {
+                        if let Payload::String(s) = &resp_msg {
+                            record(RecordEntry::Response {
+                                cmd: event_name.to_string(),
+                                response: serde_json::from_str(s).unwrap_or(Value::Null),
+                            });
+                        }
                         let resp_cmd= parse_resp_cmd(resp_msg)?;
+                        if resp_cmd.get_cmd() != event_name {
+                            warn!("{}[CMD_RESP]response to {} received for {}", self.log_tag, resp_cmd.get_cmd(), event_name);
+                            return Err(Error::CmdError(CmdErrorKind::UnexpectedError));
+                        }
                         // handle simulator level errors 
                         if resp_cmd.get_error() == CmdErrorKind::SimulatorNotRunning
                             || resp_cmd.get_error() == CmdErrorKind::NoDeviceWithDevEUI
@@ -191,16 +396,126 @@ impl Lwnsim {
                             return Ok(Some(resp_cmd));
                         }
                     },
-                    Err(e) => {
-                        trace!("[LWNSIM][CMD_RESP][TIMEOUT]{:?}", e);
+                    None => {
+                        trace!("{}[CMD_RESP][TIMEOUT][{}]", self.log_tag, event_name);
+                        record(RecordEntry::Timeout { cmd: event_name.to_string() });
                         return Err(Error::CmdError(CmdErrorKind::DevCmdTimeout));},
                 }
             }
         }
     }
 
+    // emits a command to the simulator and waits for its response, None on timeout
+    fn call(
+        &self,
+        event_name: &'static str,
+        msg_json: Value,
+        timeout_ack: Duration,
+        timeout_cmd: Duration,
+    ) -> Result<Option<Payload>> {
+        let (tx, rx): (Sender<Payload>, Receiver<Payload>) = channel();
+        let tag = self.log_tag.clone();
+        trace!(
+            "{}[CMD_CALL][{}]{:?}",
+            self.log_tag,
+            event_name,
+            msg_json
+        );
+        self.socket
+            .as_ref()
+            .ok_or(Error::CmdError(CmdErrorKind::NotConnected))?
+            .emit_with_ack(
+                event_name,
+                msg_json,
+                // the response is dropped by the socketio client once this window is over
+                timeout_ack.max(timeout_cmd),
+                move |message: Payload, _: RawClient| {
+                    trace!("{}[CMD_RESP]{:?}", tag, message);
+                    // send the result to the channel, closed if the command already timed out
+                    let _ = tx.send(message);
+                },
+            )
+            .map_err(Error::SocketioError)?;
+        return Ok(rx.recv_timeout(timeout_cmd).ok());
+    }
+
+
+    /// returns a channel receiving the dev-log and dev-error records sent by the simulator
+    pub fn subscribe_dev_logs(&self) -> Receiver<DevLog> {
+        return DEV_LOGS.lock().unwrap().subscribe();
+    }
+
+    /// returns a channel receiving the on-air frames, see LoraDev::set_raw_frames()
+    pub fn subscribe_raw_frames(&self) -> Receiver<RawFrame> {
+        return RAW_FRAMES.lock().unwrap().subscribe();
+    }
+
+    /// returns a channel receiving the lora events as they arrive
+    pub fn subscribe_lora_events(&self) -> Receiver<LoraEvents> {
+        return subscribe_lora_events();
+    }
 
     pub fn push_lora_event(&self, event_val: LoraEvents) {
-        LORA_EVENTS.lock().unwrap().handle_lora_event(event_val);
+        dispatch_lora_event(event_val);
+    }
+}
+
+// handles the events sent by the simulator (or replayed), called from the socket thread
+// it must not lock LWNSIM which is held while a command waits for its response
+pub(crate) fn handle_event(tag: &str, event: &str, payload: Payload) {
+    let pl_str = match payload {
+        Payload::String(s) => s,
+        _ => return warn!("{}[{}]not the String variant", tag, event),
+    };
+    record_event(event, &pl_str);
+    match event {
+        e if e == DEV_EVENT_ACK_CMD => {
+            trace!("{}[CMD_ACK][cmd]{:?}", tag, pl_str);
+            match serde_json::from_str::<DevAckCmd>(&pl_str) {
+                Ok(ack) => PENDING_ACKS.lock().unwrap().handle_ack(&ack),
+                Err(e) => warn!("{}[ParseDevAckCmdError]{:?}", tag, e),
+            }
+        }
+        e if e == DEV_EVENT_LORA => {
+            trace!("{}[LORA EVENT]{:?}", tag, pl_str);
+            match serde_json::from_str::<DevLoraEvent>(&pl_str) {
+                Ok(lora_event) => dispatch_lora_event(lora_event.event),
+                Err(e) => warn!("{}[ParseDevLoraEventError]{:?}", tag, e),
+            }
+        }
+        e if e == DEV_EVENT_MAC_CMD => {
+            trace!("{}[MAC COMMAND]{:?}", tag, pl_str);
+            match serde_json::from_str::<MacCommand>(&pl_str) {
+                Ok(mac_cmd) => MAC_COMMANDS.lock().unwrap().handle_mac_command(mac_cmd),
+                Err(e) => warn!("{}[ParseMacCommandError]{:?}", tag, e),
+            }
+        }
+        e if e == DEV_EVENT_ADR_CHANGE => {
+            trace!("{}[ADR CHANGE]{:?}", tag, pl_str);
+            match serde_json::from_str::<AdrState>(&pl_str) {
+                Ok(adr_state) => ADR_STATE.lock().unwrap().handle_adr_change(adr_state),
+                Err(e) => warn!("{}[ParseAdrChangeError]{:?}", tag, e),
+            }
+        }
+        e if e == DEV_EVENT_LOG => {
+            DEV_LOGS.lock().unwrap().handle_dev_log(DevLog::parse(DevLogKind::Log, &pl_str));
+        }
+        e if e == DEV_EVENT_ERROR => {
+            DEV_LOGS.lock().unwrap().handle_dev_log(DevLog::parse(DevLogKind::Error, &pl_str));
+        }
+        e if e == DEV_EVENT_RAW_FRAME => {
+            trace!("{}[RAW FRAME]{:?}", tag, pl_str);
+            match RawFrame::parse(&pl_str) {
+                Ok(frame) => RAW_FRAMES.lock().unwrap().handle_raw_frame(frame),
+                Err(e) => warn!("{}[ParseRawFrameError]{:?}", tag, e),
+            }
+        }
+        _ => warn!("{}[unknown event]{}", tag, event),
     }
 }
+
+fn dispatch_lora_event(event_val: LoraEvents) {
+    LORA_STATS.lock().unwrap().handle_lora_event(event_val);
+    TX_ENERGY.lock().unwrap().handle_lora_event(event_val);
+    LORA_EVENTS.lock().unwrap().handle_lora_event(event_val);
+}
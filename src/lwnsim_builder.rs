@@ -0,0 +1,249 @@
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use super::error::Result;
+use super::lora_dev::LORA;
+use super::lwnsim::{DEFAULT_ACK_TIMEOUT, DEFAULT_CMD_TIMEOUT, LWNSIM};
+use super::shutdown::LwnsimSession;
+
+// socketio namespace of the simulator device API
+pub static DEFAULT_NAMESPACE: &str = "/dev";
+
+/// socketio reconnection policy, delays are the bounds of the exponential backoff
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReconnectPolicy {
+    pub enabled: bool,
+    pub delay_min: Duration,
+    pub delay_max: Duration,
+    /// unlimited if None
+    pub max_attempts: Option<u8>,
+}
+
+impl ReconnectPolicy {
+    pub fn disabled() -> ReconnectPolicy {
+        ReconnectPolicy {
+            enabled: false,
+            ..ReconnectPolicy::default()
+        }
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            enabled: true,
+            delay_min: Duration::from_millis(1000),
+            delay_max: Duration::from_millis(5000),
+            max_attempts: None,
+        }
+    }
+}
+
+/// configuration of the connection to the simulator
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+/// use std::time::Duration;
+///
+/// LwnsimBuilder::new("http://localhost:8000", "359ac7cd01bc8aff")
+///     .auth_token("secret")
+///     .cmd_timeout(Duration::from_secs(30))
+///     .log_tag("dev1")
+///     .connect()
+///     .expect("Connection failed");
+/// ```
+#[derive(Debug, Clone)]
+pub struct LwnsimBuilder {
+    pub(crate) url: String,
+    pub(crate) dev_eui: String,
+    pub(crate) namespace: String,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) query: Vec<(String, String)>,
+    pub(crate) auth: Option<Value>,
+    pub(crate) reconnect: ReconnectPolicy,
+    pub(crate) cmd_timeout: Duration,
+    pub(crate) ack_timeout: Duration,
+    pub(crate) wait_ack: bool,
+    pub(crate) log_tag: Option<String>,
+    pub(crate) record: Option<PathBuf>,
+}
+
+impl LwnsimBuilder {
+    pub fn new(url: &str, dev_eui: &str) -> LwnsimBuilder {
+        LwnsimBuilder {
+            url: url.to_string(),
+            dev_eui: dev_eui.to_string(),
+            namespace: DEFAULT_NAMESPACE.to_string(),
+            headers: vec![("accept-encoding".to_string(), "application/json".to_string())],
+            query: Vec::new(),
+            auth: None,
+            reconnect: ReconnectPolicy::default(),
+            cmd_timeout: DEFAULT_CMD_TIMEOUT,
+            ack_timeout: DEFAULT_ACK_TIMEOUT,
+            wait_ack: false,
+            log_tag: None,
+            record: None,
+        }
+    }
+
+    pub fn namespace(mut self, namespace: &str) -> LwnsimBuilder {
+        self.namespace = namespace.to_string();
+        self
+    }
+
+    /// adds a header to the socketio opening request
+    pub fn header(mut self, key: &str, value: &str) -> LwnsimBuilder {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// adds a query parameter to the simulator url
+    pub fn query_param(mut self, key: &str, value: &str) -> LwnsimBuilder {
+        self.query.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    /// sets the socketio handshake auth payload
+    pub fn auth(mut self, auth: Value) -> LwnsimBuilder {
+        self.auth = Some(auth);
+        self
+    }
+
+    /// sends a bearer token both as Authorization header and as socketio auth payload
+    pub fn auth_token(self, token: &str) -> LwnsimBuilder {
+        return self
+            .header("Authorization", &format!("Bearer {}", token))
+            .auth(json!({ "token": token }));
+    }
+
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> LwnsimBuilder {
+        self.reconnect = policy;
+        self
+    }
+
+    /// default time to wait for the simulator response of a command
+    pub fn cmd_timeout(mut self, timeout: Duration) -> LwnsimBuilder {
+        self.cmd_timeout = timeout;
+        self
+    }
+
+    /// default time to wait for the ack of a command
+    pub fn ack_timeout(mut self, timeout: Duration) -> LwnsimBuilder {
+        self.ack_timeout = timeout;
+        self
+    }
+
+    /// see Lwnsim::set_wait_ack()
+    pub fn wait_ack(mut self, wait_ack: bool) -> LwnsimBuilder {
+        self.wait_ack = wait_ack;
+        self
+    }
+
+    /// tag added to the LWNSIM log messages, to tell several clients apart
+    pub fn log_tag(mut self, tag: &str) -> LwnsimBuilder {
+        self.log_tag = Some(tag.to_string());
+        self
+    }
+
+    /// records the session to a JSON Lines file, see Lwnsim::start_recording()
+    pub fn record<P: Into<PathBuf>>(mut self, path: P) -> LwnsimBuilder {
+        self.record = Some(path.into());
+        self
+    }
+
+    /// connects the LWNSIM client with this configuration and resets the LORA device for the DevEUI
+    /// the client and its device are process wide statics, nothing is unlinked when they go out of scope:
+    /// use session() (or shutdown()) to unlink the device and disconnect on exit
+    pub fn connect(self) -> Result<()> {
+        let dev_eui = self.dev_eui.clone();
+        LWNSIM.lock().unwrap().connect_with(self)?;
+        // the state of a previous connection is cleared, the region and energy model are kept
+        LORA.lock().unwrap().reset(&dev_eui);
+        return Ok(());
+    }
+
+    /// connects and returns a session unlinking the device and disconnecting when dropped
+    pub fn session(self) -> Result<LwnsimSession> {
+        self.connect()?;
+        return Ok(LwnsimSession::new());
+    }
+
+    pub(crate) fn log_prefix(&self) -> String {
+        return match &self.log_tag {
+            Some(tag) => format!("[LWNSIM][{}]", tag),
+            None => "[LWNSIM]".to_string(),
+        };
+    }
+
+    pub(crate) fn url_with_query(&self) -> String {
+        if self.query.is_empty() {
+            return self.url.clone();
+        }
+        let query: Vec<String> = self
+            .query
+            .iter()
+            .map(|(k, v)| format!("{}={}", url_encode(k), url_encode(v)))
+            .collect();
+        let sep = match self.url.find('?') {
+            // the url already ends with a separator
+            Some(_) if self.url.ends_with('?') || self.url.ends_with('&') => "",
+            Some(_) => "&",
+            None => "?",
+        };
+        return format!("{}{}{}", self.url, sep, query.join("&"));
+    }
+}
+
+// percent-encodes everything but the unreserved characters (RFC 3986)
+fn url_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    return encoded;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_encoding() {
+        assert_eq!(url_encode("AZaz09-_.~"), "AZaz09-_.~");
+        assert_eq!(url_encode("a b"), "a%20b");
+        assert_eq!(url_encode(":/?#[]@!$&'()*+,;=%"), "%3A%2F%3F%23%5B%5D%40%21%24%26%27%28%29%2A%2B%2C%3B%3D%25");
+        assert_eq!(url_encode("été ✓"), "%C3%A9t%C3%A9%20%E2%9C%93");
+        assert_eq!(url_encode(""), "");
+    }
+
+    #[test]
+    fn query_appended_to_url() {
+        let builder = |url: &str| LwnsimBuilder::new(url, "0102");
+        assert_eq!(builder("http://localhost:8000").url_with_query(), "http://localhost:8000");
+        assert_eq!(
+            builder("http://localhost:8000")
+                .query_param("token", "a b&c=d")
+                .query_param("tenant", "été")
+                .url_with_query(),
+            "http://localhost:8000?token=a%20b%26c%3Dd&tenant=%C3%A9t%C3%A9"
+        );
+        assert_eq!(
+            builder("http://localhost:8000/?EIO=4").query_param("k", "v").url_with_query(),
+            "http://localhost:8000/?EIO=4&k=v"
+        );
+        assert_eq!(builder("http://localhost:8000/?").query_param("k", "v").url_with_query(), "http://localhost:8000/?k=v");
+        assert_eq!(builder("http://localhost:8000/?a=1&").query_param("k", "v").url_with_query(), "http://localhost:8000/?a=1&k=v");
+    }
+
+    #[test]
+    fn log_prefix_with_tag() {
+        assert_eq!(LwnsimBuilder::new("http://localhost:8000", "0102").log_prefix(), "[LWNSIM]");
+        assert_eq!(LwnsimBuilder::new("http://localhost:8000", "0102").log_tag("dev1").log_prefix(), "[LWNSIM][dev1]");
+    }
+}
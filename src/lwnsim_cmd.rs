@@ -2,199 +2,226 @@
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use typetag;
 
 use log::{error, warn};
 use rust_socketio::Payload;
 //use std::error::Error;
 use super::error::{Error, Result};
 use super::lora_events::LoraEvents;
+use super::region::Channel;
 
-// simulator commands CMD_LINK_DEV,CMD_UNLINK_DEV,CMD_JOIN_REQUEST,CMD_SEND_UPLINK,CMD_RECV_DOWNLINK
-pub static CMD_LINK_DEV: &str = "link-dev";
-pub static CMD_UNLINK_DEV: &str = "unlink-dev";
-pub static CMD_JOIN_REQUEST: &str = "join-request";
-pub static CMD_SEND_UPLINK: &str = "send-uplink";
-pub static CMD_RECV_DOWNLINK: &str = "recv-downlink";
-
-pub trait DevExecuteCmdTrait {
-    fn get_cmd(&self) -> &str;
-    fn get_ack(&self) -> bool;
-    fn set_ack(&mut self, ack_cmd: bool);
-    fn get_dev_eui(&self) -> &str;
-}
+// declares the simulator commands from a single table: each entry gives the command name,
+// the Command variant with its arguments and the payload of the matching Response variant,
+// so a command cannot be added without its response (and its name)
+macro_rules! lwnsim_commands {
+    ($(
+        $(#[$meta:meta])*
+        $cmd_name:ident = $name:literal, $variant:ident $({ $($fields:tt)* })? => $resp:ty;
+    )*) => {
+        $(
+            $(#[$meta])*
+            pub static $cmd_name: &str = $name;
+        )*
 
-#[derive(Debug, Serialize)]
-pub struct DevExecuteCmd {
-    #[serde(rename = "Cmd")]
-    pub cmd: String,
-    #[serde(rename = "Ack")]
-    pub ack: bool,
-    #[serde(rename = "DevEUI")]
-    pub dev_eui: String,
+        /// simulator commands and their arguments
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        #[serde(tag = "Cmd")]
+        pub enum Command {
+            $(
+                #[serde(rename = $name)]
+                $variant $({ $($fields)* })?,
+            )*
+        }
+
+        impl Command {
+            /// command name, also used as the socketio event name
+            pub fn name(&self) -> &'static str {
+                return match self {
+                    $(Command::$variant { .. } => $cmd_name,)*
+                };
+            }
+        }
+
+        /// simulator responses, one variant per command
+        /// serialized as {"cmd": <cmd name>, "error": .., <results>...}
+        #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+        #[serde(tag = "cmd")]
+        pub enum Response {
+            $(
+                #[serde(rename = $name)]
+                $variant($resp),
+            )*
+        }
+
+        impl Response {
+            pub fn get_cmd(&self) -> &'static str {
+                return match self {
+                    $(Response::$variant(_) => $cmd_name,)*
+                };
+            }
+
+            pub fn get_error(&self) -> CmdErrorKind {
+                return match self {
+                    $(Response::$variant(r) => r.error.clone(),)*
+                };
+            }
+        }
+    };
 }
 
-impl DevExecuteCmdTrait for DevExecuteCmd {
-    fn get_cmd(&self) -> &str {
-        return &self.cmd;
-    }
-    fn get_ack(&self) -> bool {
-        return self.ack;
-    }
-    fn set_ack(&mut self, ack_cmd: bool) -> () {
-        return self.ack = ack_cmd;
-    }
-    fn get_dev_eui(&self) -> &str {
-        return &self.dev_eui;
-    }
+lwnsim_commands! {
+    CMD_LINK_DEV = "link-dev", LinkDev => DevResponse;
+    CMD_UNLINK_DEV = "unlink-dev", UnlinkDev => DevResponse;
+    CMD_JOIN_REQUEST = "join-request", JoinRequest {
+        #[serde(rename = "Activation", default, skip_serializing_if = "Option::is_none")]
+        activation: Option<String>, // "OTAA" or "ABP", simulator device info if not set
+        #[serde(rename = "Auth", default, skip_serializing_if = "Option::is_none")]
+        auth: Option<(String, String)>, // (JoinEUI, AppKey) or (NwkSKey, AppSKey), simulator device info if not set
+    } => DevResponse;
+    CMD_SEND_UPLINK = "send-uplink", SendUplink {
+        #[serde(rename = "MType")]
+        mtype: String,
+        #[serde(rename = "Payload")]
+        payload: String,
+        #[serde(rename = "FPort", default, skip_serializing_if = "Option::is_none")]
+        fport: Option<u8>, // simulator default port if not set
+        #[serde(rename = "PayloadEncoding", default, skip_serializing_if = "Option::is_none")]
+        encoding: Option<PayloadEncoding>, // plain text payload if not set
+    } => DevResponse;
+    CMD_RECV_DOWNLINK = "recv-downlink", RecvDownlink {
+        #[serde(rename = "BufferSize")]
+        buffer_size: usize,
+    } => DevResponseRecvDownlink;
+    // MAC commands are queued by the simulator and sent with the next uplink
+    CMD_LINK_CHECK_REQ = "link-check-req", LinkCheckReq => DevResponse;
+    CMD_DEVICE_TIME_REQ = "device-time-req", DeviceTimeReq => DevResponse;
+    CMD_SET_ADR = "set-adr", SetAdr {
+        #[serde(rename = "ADR")]
+        adr: bool,
+    } => DevResponse;
+    CMD_SET_DR_TX_POWER = "set-dr-txpower", SetDrTxPower {
+        #[serde(rename = "DataRate")]
+        data_rate: u8,
+        #[serde(rename = "TXPower", default, skip_serializing_if = "Option::is_none")]
+        tx_power: Option<u8>, // TX power unchanged if not set
+    } => DevResponse;
+    CMD_ADD_CHANNEL = "add-channel", AddChannel {
+        #[serde(rename = "Index")]
+        index: usize,
+        #[serde(flatten)]
+        channel: Channel,
+    } => DevResponse;
+    CMD_REMOVE_CHANNEL = "remove-channel", RemoveChannel {
+        #[serde(rename = "Index")]
+        index: usize,
+    } => DevResponse;
+    CMD_GET_CHANNEL_MASK = "get-channel-mask", GetChannelMask => DevResponseChannelMask;
+    // debug mode: the on-air frames of the device are sent in "raw-frame" events
+    CMD_SET_RAW_FRAMES = "set-raw-frames", SetRawFrames {
+        #[serde(rename = "Enable")]
+        enable: bool,
+    } => DevResponse;
+    // battery level reported by the device in DevStatusAns
+    CMD_SET_BATTERY_LEVEL = "set-battery-level", SetBatteryLevel {
+        #[serde(rename = "BatteryLevel")]
+        battery_level: u8, // DevStatusAns encoding: 0 external power, 1..=254 level, 255 unknown
+    } => DevResponse;
 }
 
-#[derive(Debug, Serialize)]
-pub struct DevExecuteRecvDownlink {
-    #[serde(rename = "Cmd")]
-    pub cmd: String,
-    #[serde(rename = "Ack")]
+/// command executed by the simulator for a device
+/// serialized as {"Cmd": <cmd name>, <cmd args>..., "Ack": .., "DevEUI": ..}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DevExecuteCmd {
+    #[serde(flatten)]
+    pub cmd: Command,
+    #[serde(rename = "Ack", default)]
     pub ack: bool,
     #[serde(rename = "DevEUI")]
     pub dev_eui: String,
-    #[serde(rename = "BufferSize")]
-    pub buffer_size: usize,
-}
-impl DevExecuteCmdTrait for DevExecuteRecvDownlink {
-    fn get_cmd(&self) -> &str {
-        return &self.cmd;
-    }
-    fn get_ack(&self) -> bool {
-        return self.ack;
-    }
-    fn set_ack(&mut self, ack_cmd: bool) -> () {
-        return self.ack = ack_cmd;
-    }
-    fn get_dev_eui(&self) -> &str {
-        return &self.dev_eui;
-    }
-}
-#[derive(Debug, Serialize)]
-pub struct DevExecuteSendUplink {
-    #[serde(rename = "Cmd")]
-    pub cmd: String,
-    #[serde(rename = "DevEUI")]
-    pub dev_eui: String,
-    #[serde(rename = "Ack")]
-    pub ack: bool,
-    #[serde(rename = "MType")]
-    pub mtype: String,
-    #[serde(rename = "Payload")]
-    pub payload: String,
+    #[serde(rename = "CmdId", skip_serializing_if = "Option::is_none")]
+    pub cmd_id: Option<u64>, // set when the ack is tracked
 }
 
-impl DevExecuteCmdTrait for DevExecuteSendUplink {
-    fn get_cmd(&self) -> &str {
-        return &self.cmd;
-    }
-    fn get_ack(&self) -> bool {
-        return self.ack;
-    }
-    fn set_ack(&mut self, ack_cmd: bool) -> () {
-        return self.ack = ack_cmd;
-    }
-    fn get_dev_eui(&self) -> &str {
-        return &self.dev_eui;
+impl DevExecuteCmd {
+    pub fn new(dev_eui: &str, cmd: Command) -> DevExecuteCmd {
+        DevExecuteCmd {
+            cmd,
+            ack: false,
+            dev_eui: dev_eui.to_string(),
+            cmd_id: None,
+        }
     }
-}
 
-#[derive(Debug, Deserialize)]
-pub struct DevAckCmd {
-    #[serde(rename = "Cmd")]
-    cmd: String,
-    #[serde(rename = "Args")]
-    args: String,
+    pub fn get_cmd(&self) -> &'static str {
+        return self.cmd.name();
+    }
 }
 
-#[typetag::serde(tag = "type")]
-pub trait ResponseCmdTrait {
-    fn get_cmd(&self) -> &str;
-    fn get_error(&self) -> CmdErrorKind;
-    // following metyhods for LoraDev commands
-    fn get_mtype(&self) -> &str {
-        return "";
-    }
-    fn get_payload(&mut self) -> String {
-        return "".to_string();
-    }
+/// encoding of a binary payload sent as a string
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PayloadEncoding {
+    #[serde(rename = "base64")]
+    Base64,
 }
 
+/// "ack-cmd" event sent by the simulator for the commands sent with Ack=true
 #[derive(Debug, Serialize, Deserialize)]
-pub struct DevResponseCmd {
+pub struct DevAckCmd {
+    #[serde(rename = "Cmd")]
     pub cmd: String,
-    pub error: CmdErrorKind,
+    #[serde(rename = "Args", default)]
+    pub args: Value,
+    #[serde(rename = "CmdId", default)]
+    pub cmd_id: Option<u64>, // echoed by the simulator if set in the command
 }
 
-#[typetag::serde]
-impl ResponseCmdTrait for DevResponseCmd {
-    fn get_cmd(&self) -> &str {
-        return &self.cmd;
-    }
-    fn get_error(&self) -> CmdErrorKind {
-        return self.error.clone();
-    }
+/// response of the simulator to a command without result
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DevResponse {
+    pub error: CmdErrorKind,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DevNoResponseCmd {}
-
-#[typetag::serde]
-impl ResponseCmdTrait for DevNoResponseCmd {
-    fn get_cmd(&self) -> &str {
-        return "";
-    }
-    fn get_error(&self) -> CmdErrorKind {
-        return CmdErrorKind::DevCmdOK;
-    }
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DevResponseRecvDownlink {
+    pub error: CmdErrorKind,
+    #[serde(default)]
+    pub mtype: String,
+    #[serde(default)]
+    pub payload: Option<String>, // not sent when no downlink was received
+    #[serde(default)]
+    pub fport: Option<u8>, // not sent by older simulator versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<PayloadEncoding>, // plain text payload if not set
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DevResponseRecvDownlinkCmd {
-    pub cmd: String,
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DevResponseChannelMask {
     pub error: CmdErrorKind,
-    pub mtype: String,
-    pub payload: Option<String>, // is an Option so that String can be moved out with take()
+    #[serde(default)]
+    pub chmask: u16,
 }
 
-#[typetag::serde]
-impl ResponseCmdTrait for DevResponseRecvDownlinkCmd {
-    fn get_cmd(&self) -> &str {
-        return &self.cmd;
-    }
-    fn get_error(&self) -> CmdErrorKind {
-        return self.error.clone();
-    }
-    fn get_mtype(&self) -> &str {
-        return &self.mtype;
-    }
-    fn get_payload(&mut self) -> String {
-        return self.payload.take().unwrap();
+impl Response {
+    /// error to return when the response is not the one expected by the caller
+    /// (an OK response of the wrong kind is reported as an unexpected error)
+    pub fn to_error(&self) -> Error {
+        return match self.get_error() {
+            CmdErrorKind::DevCmdOK => Error::CmdError(CmdErrorKind::UnexpectedError),
+            k => Error::CmdError(k),
+        };
     }
 }
 
-pub fn parse_resp_cmd(resp_msg: Payload) -> Result<Box<dyn ResponseCmdTrait>> {
+/// parses the emit_with_ack response payload, a json array whose first item is the response
+pub fn parse_resp_cmd(resp_msg: Payload) -> Result<Response> {
     if let Payload::String(json_str) = resp_msg {
-        let object: Value = serde_json::from_str(&json_str).unwrap();
-        if let Value::String(cmd_name) = &object[0]["cmd"] {
-            if cmd_name == CMD_LINK_DEV || cmd_name == CMD_UNLINK_DEV {
-                let resp_cmd: DevResponseCmd =
-                    serde_json::from_value(object[0].clone()).expect("json deserialization failed");
-                return Ok(Box::new(resp_cmd));
-            } else
-            /*if cmd_name == CMD_RECV_DOWNLINK*/
-            {
-                let resp_cmd: DevResponseRecvDownlinkCmd =
-                    serde_json::from_value(object[0].clone()).expect("json deserialization failed");
-                return Ok(Box::new(resp_cmd));
+        let object: Value = serde_json::from_str(&json_str)?;
+        match serde_json::from_value::<Response>(object[0].clone()) {
+            Ok(resp_cmd) => return Ok(resp_cmd),
+            Err(e) => {
+                warn!("[LWNSIM] DevResponseCmd json error {:?}", e);
+                return Err(Error::CmdError(CmdErrorKind::PayloadJsonError));
             }
-        } else {
-            warn!("[LWNSIM] DevResponseCmd json error");
-            return Err(Error::CmdError(CmdErrorKind::PayloadJsonError));
         }
     } else {
         return Err(Error::CmdError(CmdErrorKind::PayloadNotStringVariant));
@@ -227,6 +254,10 @@ pub enum CmdErrorKind {
     PayloadNotStringVariant=10,
     PayloadJsonError=11,
     UnexpectedError=12,
+    InvalidArgument=13,
+    DevCmdAckTimeout=14,
+    TxFailed=15, // client side, TX_FAILED_EVENT received for a blocking send
+    NotConnected=16, // client side, the client is not connected to the simulator
 }
 use std::fmt;
 impl fmt::Display for CmdErrorKind {
@@ -247,9 +278,146 @@ impl fmt::Display for CmdErrorKind {
             CmdErrorKind::PayloadNotStringVariant => "Payload not the String variant",
             CmdErrorKind::PayloadJsonError => "Error in parsing json",
             CmdErrorKind::UnexpectedError => "Unexpected error",
+            CmdErrorKind::InvalidArgument => "Invalid argument",
+            CmdErrorKind::DevCmdAckTimeout => "Cmd ack timeout",
+            CmdErrorKind::TxFailed => "Uplink transmission failed",
+            CmdErrorKind::NotConnected => "Not connected to the simulator",
         };
         write!(f, "{}", name)
     }
 }
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dev_execute_cmd_round_trip() {
+        let mut msg = DevExecuteCmd::new(
+            "359ac7cd01bc8aff",
+            Command::SendUplink {
+                mtype: "ConfirmedDataUp".to_string(),
+                payload: "AQI=".to_string(),
+                fport: Some(2),
+                encoding: Some(PayloadEncoding::Base64),
+            },
+        );
+        msg.ack = true;
+        msg.cmd_id = Some(7);
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["Cmd"], "send-uplink");
+        let parsed: DevExecuteCmd = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.cmd, msg.cmd);
+        assert!(parsed.ack);
+        assert_eq!(parsed.cmd_id, Some(7));
+        assert_eq!(parsed.dev_eui, "359ac7cd01bc8aff");
+    }
+
+    #[test]
+    fn dev_execute_cmd_optional_fields() {
+        let json = r#"{"Cmd": "send-uplink", "MType": "UnConfirmedDataUp", "Payload": "hi", "DevEUI": "0102"}"#;
+        let parsed: DevExecuteCmd = serde_json::from_str(json).unwrap();
+        assert!(!parsed.ack);
+        assert_eq!(
+            parsed.cmd,
+            Command::SendUplink {
+                mtype: "UnConfirmedDataUp".to_string(),
+                payload: "hi".to_string(),
+                fport: None,
+                encoding: None,
+            }
+        );
+    }
+
+    #[test]
+    fn join_request_keys_are_optional() {
+        let msg = DevExecuteCmd::new("0102", Command::JoinRequest { activation: None, auth: None });
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(json["Cmd"], "join-request");
+        assert!(json.get("Activation").is_none() && json.get("Auth").is_none());
+
+        let json = r#"{"Cmd": "join-request", "Activation": "OTAA", "Auth": ["0000000000000000", "f1c4"], "DevEUI": "0102"}"#;
+        let parsed: DevExecuteCmd = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            parsed.cmd,
+            Command::JoinRequest {
+                activation: Some("OTAA".to_string()),
+                auth: Some(("0000000000000000".to_string(), "f1c4".to_string())),
+            }
+        );
+    }
+
+    fn parse(json: &str) -> Result<Response> {
+        return parse_resp_cmd(Payload::String(json.to_string()));
+    }
+
+    #[test]
+    fn every_response_is_parsed() {
+        let names = [
+            CMD_LINK_DEV,
+            CMD_UNLINK_DEV,
+            CMD_JOIN_REQUEST,
+            CMD_SEND_UPLINK,
+            CMD_RECV_DOWNLINK,
+            CMD_LINK_CHECK_REQ,
+            CMD_DEVICE_TIME_REQ,
+            CMD_SET_ADR,
+            CMD_SET_DR_TX_POWER,
+            CMD_ADD_CHANNEL,
+            CMD_REMOVE_CHANNEL,
+            CMD_GET_CHANNEL_MASK,
+            CMD_SET_RAW_FRAMES,
+            CMD_SET_BATTERY_LEVEL,
+        ];
+        for name in names {
+            let resp = parse(&format!(r#"[{{"cmd": "{}", "error": 0}}]"#, name)).unwrap();
+            assert_eq!(resp.get_cmd(), name);
+            assert_eq!(resp.get_error(), CmdErrorKind::DevCmdOK);
+            let resp = parse(&format!(r#"[{{"cmd": "{}", "error": 4}}]"#, name)).unwrap();
+            assert_eq!(resp.get_error(), CmdErrorKind::DeviceNotLinked);
+            assert!(matches!(resp.to_error(), Error::CmdError(CmdErrorKind::DeviceNotLinked)));
+        }
+    }
+
+    #[test]
+    fn response_results_are_parsed() {
+        let resp = parse(r#"[{"cmd": "recv-downlink", "error": 0, "mtype": "UnConfirmedDataDown", "payload": "AQI=", "fport": 3, "encoding": "base64"}]"#).unwrap();
+        assert_eq!(
+            resp,
+            Response::RecvDownlink(DevResponseRecvDownlink {
+                error: CmdErrorKind::DevCmdOK,
+                mtype: "UnConfirmedDataDown".to_string(),
+                payload: Some("AQI=".to_string()),
+                fport: Some(3),
+                encoding: Some(PayloadEncoding::Base64),
+            })
+        );
+        let resp = parse(r#"[{"cmd": "recv-downlink", "error": 8}]"#).unwrap();
+        assert!(matches!(&resp, Response::RecvDownlink(r) if r.payload.is_none() && r.fport.is_none()));
+        assert_eq!(resp.get_error(), CmdErrorKind::NoDataDWrecv);
+
+        let resp = parse(r#"[{"cmd": "get-channel-mask", "error": 0, "chmask": 255}]"#).unwrap();
+        assert_eq!(
+            resp,
+            Response::GetChannelMask(DevResponseChannelMask { error: CmdErrorKind::DevCmdOK, chmask: 255 })
+        );
+        // an OK response of the wrong kind is an unexpected error for the caller
+        assert!(matches!(resp.to_error(), Error::CmdError(CmdErrorKind::UnexpectedError)));
+    }
+
+    #[test]
+    fn invalid_responses_are_rejected() {
+        let payload_json_error = |r: Result<Response>| matches!(r, Err(Error::CmdError(CmdErrorKind::PayloadJsonError)));
+        assert!(payload_json_error(parse(r#"[{"cmd": "no-such-cmd", "error": 0}]"#)));
+        assert!(payload_json_error(parse(r#"[{"cmd": "link-dev"}]"#)));
+        assert!(payload_json_error(parse(r#"[{"cmd": "link-dev", "error": 99}]"#)));
+        assert!(payload_json_error(parse(r#"[]"#)));
+        assert!(matches!(parse("not json"), Err(Error::InvalidJson(_))));
+        assert!(matches!(
+            parse_resp_cmd(Payload::Binary(vec![1, 2].into())),
+            Err(Error::CmdError(CmdErrorKind::PayloadNotStringVariant))
+        ));
+    }
+}
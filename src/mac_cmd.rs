@@ -0,0 +1,245 @@
+use chrono::{DateTime, Duration as ChronoDuration, TimeZone, Utc};
+use serde_derive::*;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use super::lora_events::{LoraEvents, LORA_EVENTS};
+use log::{debug, trace, warn};
+
+// GPS epoch (1980-01-06T00:00:00Z) as a unix timestamp
+static GPS_EPOCH_UNIX_SECS: i64 = 315_964_800;
+// GPS time is ahead of UTC by the leap seconds inserted since the GPS epoch
+static GPS_UTC_LEAP_SECS: i64 = 18;
+// maximum number of network initiated MAC commands kept until read by the application
+static MAC_CMD_QUEUE_CAPACITY: usize = 32;
+
+lazy_static! {
+    pub static ref MAC_COMMANDS: Mutex<MacCommands> = Mutex::new(MacCommands::new());
+}
+
+/// answer to a LinkCheckReq
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct LinkCheckAns {
+    /// link margin in dB of the last successfully received LinkCheckReq
+    #[serde(rename = "Margin")]
+    pub margin: u8,
+    /// number of gateways that received the last LinkCheckReq
+    #[serde(rename = "GwCnt")]
+    pub gw_cnt: u8,
+}
+
+/// answer to a DeviceTimeReq
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy)]
+pub struct DeviceTimeAns {
+    /// seconds since the GPS epoch
+    #[serde(rename = "Seconds")]
+    pub seconds: u32,
+    /// fractional second in 1/256 s steps
+    #[serde(rename = "FractionalSeconds")]
+    pub fractional_seconds: u8,
+}
+
+impl DeviceTimeAns {
+    /// time elapsed since the GPS epoch
+    pub fn gps_time(&self) -> Duration {
+        return Duration::from_secs(self.seconds as u64)
+            + Duration::from_micros(self.fractional_seconds as u64 * 1_000_000 / 256);
+    }
+
+    /// GPS time converted to UTC
+    pub fn to_utc(&self) -> DateTime<Utc> {
+        let secs = GPS_EPOCH_UNIX_SECS + self.seconds as i64 - GPS_UTC_LEAP_SECS;
+        return Utc.timestamp_opt(secs, 0).unwrap()
+            + ChronoDuration::microseconds(self.fractional_seconds as i64 * 1_000_000 / 256);
+    }
+}
+
+/// MAC commands received by the device, as sent by the simulator in a "mac-command" event
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "Cid")]
+pub enum MacCommand {
+    // answers to device requests
+    LinkCheckAns(LinkCheckAns),
+    DeviceTimeAns(DeviceTimeAns),
+    // network initiated commands
+    LinkADRReq {
+        #[serde(rename = "DataRate")]
+        data_rate: u8,
+        #[serde(rename = "TXPower")]
+        tx_power: u8,
+        #[serde(rename = "ChMask")]
+        ch_mask: u16,
+        #[serde(rename = "ChMaskCntl")]
+        ch_mask_cntl: u8,
+        #[serde(rename = "NbTrans")]
+        nb_trans: u8,
+    },
+    DutyCycleReq {
+        /// aggregated duty cycle is 1/2^max_duty_cycle
+        #[serde(rename = "MaxDutyCycle")]
+        max_duty_cycle: u8,
+    },
+    RXParamSetupReq {
+        #[serde(rename = "RX1DROffset")]
+        rx1_dr_offset: u8,
+        #[serde(rename = "RX2DataRate")]
+        rx2_data_rate: u8,
+        #[serde(rename = "Frequency")]
+        frequency: u32,
+    },
+    DevStatusReq,
+    NewChannelReq {
+        #[serde(rename = "ChIndex")]
+        ch_index: u8,
+        #[serde(rename = "Frequency")]
+        frequency: u32,
+        #[serde(rename = "MinDR")]
+        min_dr: u8,
+        #[serde(rename = "MaxDR")]
+        max_dr: u8,
+    },
+    RXTimingSetupReq {
+        /// RX1 delay in seconds
+        #[serde(rename = "Delay")]
+        delay: u8,
+    },
+    DlChannelReq {
+        #[serde(rename = "ChIndex")]
+        ch_index: u8,
+        #[serde(rename = "Frequency")]
+        frequency: u32,
+    },
+}
+
+impl MacCommand {
+    pub fn is_network_initiated(&self) -> bool {
+        return !matches!(
+            self,
+            MacCommand::LinkCheckAns(_) | MacCommand::DeviceTimeAns(_)
+        );
+    }
+}
+
+/// MAC command answers and network initiated MAC commands received by the device
+#[derive(Debug)]
+pub struct MacCommands {
+    link_check_ans: Option<LinkCheckAns>,
+    device_time_ans: Option<DeviceTimeAns>,
+    network_cmds: VecDeque<MacCommand>,
+}
+
+impl MacCommands {
+    pub fn new() -> MacCommands {
+        MacCommands {
+            link_check_ans: None,
+            device_time_ans: None,
+            network_cmds: VecDeque::new(),
+        }
+    }
+
+    pub fn handle_mac_command(&mut self, mac_cmd: MacCommand) {
+        debug!("[MAC_COMMANDS]{:?}", mac_cmd);
+        let event = match mac_cmd {
+            MacCommand::LinkCheckAns(ans) => {
+                self.link_check_ans = Some(ans);
+                LoraEvents::LINK_CHECK_ANS_EVENT
+            }
+            MacCommand::DeviceTimeAns(ans) => {
+                self.device_time_ans = Some(ans);
+                LoraEvents::DEVICE_TIME_ANS_EVENT
+            }
+            cmd => {
+                if self.network_cmds.len() >= MAC_CMD_QUEUE_CAPACITY {
+                    let dropped = self.network_cmds.pop_front();
+                    warn!("[MAC_COMMANDS][overflow]dropped {:?}", dropped);
+                }
+                self.network_cmds.push_back(cmd);
+                LoraEvents::MAC_CMD_EVENT
+            }
+        };
+        LORA_EVENTS.lock().unwrap().handle_lora_event(event);
+    }
+
+    pub fn take_link_check_ans(&mut self) -> Option<LinkCheckAns> {
+        return self.link_check_ans.take();
+    }
+
+    pub fn take_device_time_ans(&mut self) -> Option<DeviceTimeAns> {
+        return self.device_time_ans.take();
+    }
+
+    /// returns and removes the network initiated MAC commands received so far
+    pub fn take_network_cmds(&mut self) -> Vec<MacCommand> {
+        trace!("[MAC_COMMANDS][take]{} commands", self.network_cmds.len());
+        return self.network_cmds.drain(..).collect();
+    }
+
+    pub fn clear(&mut self) {
+        self.link_check_ans = None;
+        self.device_time_ans = None;
+        self.network_cmds.clear();
+    }
+}
+
+impl Default for MacCommands {
+    fn default() -> Self {
+        MacCommands::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mac_command_tagged_by_cid() {
+        let cmd: MacCommand = serde_json::from_str(r#"{"Cid": "LinkCheckAns", "Margin": 20, "GwCnt": 2}"#).unwrap();
+        assert_eq!(cmd, MacCommand::LinkCheckAns(LinkCheckAns { margin: 20, gw_cnt: 2 }));
+        assert!(!cmd.is_network_initiated());
+        let json = r#"{"Cid": "LinkADRReq", "DataRate": 5, "TXPower": 1, "ChMask": 7, "ChMaskCntl": 0, "NbTrans": 1}"#;
+        let cmd: MacCommand = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            cmd,
+            MacCommand::LinkADRReq { data_rate: 5, tx_power: 1, ch_mask: 7, ch_mask_cntl: 0, nb_trans: 1 }
+        );
+        assert!(cmd.is_network_initiated());
+        let cmd: MacCommand = serde_json::from_str(r#"{"Cid": "DevStatusReq"}"#).unwrap();
+        assert_eq!(cmd, MacCommand::DevStatusReq);
+        assert!(serde_json::from_str::<MacCommand>(r#"{"Cid": "PingSlotInfoReq"}"#).is_err());
+    }
+
+    #[test]
+    fn device_time_to_utc() {
+        // 2020-01-01T00:00:00.5Z, 18 leap seconds since the GPS epoch
+        let ans = DeviceTimeAns { seconds: 1_261_872_018, fractional_seconds: 128 };
+        assert_eq!(ans.to_utc(), Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap() + ChronoDuration::milliseconds(500));
+        assert_eq!(ans.gps_time(), Duration::from_millis(1_261_872_018_500));
+    }
+
+    #[test]
+    fn answers_are_taken_once() {
+        let mut cmds = MacCommands::new();
+        cmds.handle_mac_command(MacCommand::LinkCheckAns(LinkCheckAns { margin: 7, gw_cnt: 1 }));
+        cmds.handle_mac_command(MacCommand::DeviceTimeAns(DeviceTimeAns { seconds: 1, fractional_seconds: 0 }));
+        assert_eq!(cmds.take_link_check_ans(), Some(LinkCheckAns { margin: 7, gw_cnt: 1 }));
+        assert_eq!(cmds.take_link_check_ans(), None);
+        assert!(cmds.take_device_time_ans().is_some());
+        assert!(cmds.take_network_cmds().is_empty());
+    }
+
+    #[test]
+    fn network_cmds_queue_drops_oldest() {
+        let mut cmds = MacCommands::new();
+        for delay in 0..=MAC_CMD_QUEUE_CAPACITY {
+            cmds.handle_mac_command(MacCommand::RXTimingSetupReq { delay: delay as u8 });
+        }
+        let taken = cmds.take_network_cmds();
+        assert_eq!(taken.len(), MAC_CMD_QUEUE_CAPACITY);
+        assert_eq!(taken[0], MacCommand::RXTimingSetupReq { delay: 1 });
+        assert_eq!(taken[MAC_CMD_QUEUE_CAPACITY - 1], MacCommand::RXTimingSetupReq { delay: MAC_CMD_QUEUE_CAPACITY as u8 });
+        assert!(cmds.take_network_cmds().is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use super::error::{Error, Result};
+use super::lora_events::LoraEvents;
+use super::lwnsim_cmd::{CmdErrorKind, Response};
+
+use log::{debug, info, warn};
+
+// upper bounds of the command latency buckets, seconds (the Prometheus client defaults)
+static LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+static CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+lazy_static! {
+    // never held while locking another global, it is updated from the socketio callbacks
+    static ref METRICS: Mutex<Metrics> = Mutex::new(Metrics::default());
+}
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    // cumulative counts, one per LATENCY_BUCKETS bound
+    buckets: [u64; 11],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, secs: f64) {
+        for (bucket, le) in self.buckets.iter_mut().zip(LATENCY_BUCKETS.iter()) {
+            if secs <= *le {
+                *bucket += 1;
+            }
+        }
+        self.sum += secs;
+        self.count += 1;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Metrics {
+    commands: BTreeMap<&'static str, u64>,
+    latency: BTreeMap<&'static str, Histogram>,
+    cmd_errors: BTreeMap<(&'static str, String), u64>,
+    lora_events: BTreeMap<String, u64>,
+    connects: u64,
+    reconnects: u64,
+}
+
+fn metrics() -> std::sync::MutexGuard<'static, Metrics> {
+    return METRICS.lock().unwrap_or_else(PoisonError::into_inner);
+}
+
+/// command sent by Lwnsim::send_cmd(), with its outcome
+pub(crate) fn record_cmd(cmd: &'static str, elapsed: Duration, res: &Result<Option<Response>>) {
+    let kind = match res {
+        Ok(Some(resp)) if resp.get_error() != CmdErrorKind::DevCmdOK => Some(resp.get_error()),
+        Err(Error::CmdError(kind)) => Some(kind.clone()),
+        _ => None,
+    };
+    let mut m = metrics();
+    *m.commands.entry(cmd).or_default() += 1;
+    m.latency.entry(cmd).or_default().observe(elapsed.as_secs_f64());
+    if let Some(kind) = kind {
+        *m.cmd_errors.entry((cmd, format!("{:?}", kind))).or_default() += 1;
+    }
+}
+
+/// event received by LoraEvents::handle_lora_event()
+pub(crate) fn record_lora_event(event_val: LoraEvents) {
+    if event_val.is_empty() {
+        return;
+    }
+    let mut m = metrics();
+    for name in format!("{:?}", event_val).split(" | ") {
+        *m.lora_events.entry(name.to_string()).or_default() += 1;
+    }
+}
+
+pub(crate) fn record_connect() {
+    metrics().connects += 1;
+}
+
+pub(crate) fn record_reconnect() {
+    metrics().reconnects += 1;
+}
+
+/// clears all the metrics, e.g. between two test runs
+pub fn reset_metrics() {
+    *metrics() = Metrics::default();
+}
+
+/// metrics of the client in the Prometheus text format:
+/// - lwnsim_commands_total{cmd} and lwnsim_command_duration_seconds{cmd} (command latency histogram)
+/// - lwnsim_command_errors_total{cmd,kind}, kind being the CmdErrorKind of a failed command or response
+/// - lwnsim_lora_events_total{event}: TX_PACKET_EVENT, TX_FAILED_EVENT, RX_PACKET_EVENT, JOIN_ACCEPT_EVENT...
+/// - lwnsim_connects_total and lwnsim_reconnects_total (socketio reconnections)
+///
+/// the joins are counted by lwnsim_commands_total{cmd="join-request"} and lwnsim_lora_events_total{event="JOIN_ACCEPT_EVENT"}
+///
+/// ```
+/// let text = lwnsim_api_rs::render_metrics();
+/// assert!(text.contains("# TYPE lwnsim_commands_total counter"));
+/// ```
+pub fn render_metrics() -> String {
+    let m = metrics();
+    let mut out = String::new();
+
+    header(&mut out, "lwnsim_commands_total", "counter", "Commands sent to the simulator.");
+    for (cmd, n) in &m.commands {
+        let _ = writeln!(out, "lwnsim_commands_total{{cmd=\"{}\"}} {}", cmd, n);
+    }
+
+    header(&mut out, "lwnsim_command_duration_seconds", "histogram", "Time from sending a command to its response or ack.");
+    for (cmd, h) in &m.latency {
+        for (le, n) in LATENCY_BUCKETS.iter().zip(h.buckets.iter()) {
+            let _ = writeln!(out, "lwnsim_command_duration_seconds_bucket{{cmd=\"{}\",le=\"{}\"}} {}", cmd, le, n);
+        }
+        let _ = writeln!(out, "lwnsim_command_duration_seconds_bucket{{cmd=\"{}\",le=\"+Inf\"}} {}", cmd, h.count);
+        let _ = writeln!(out, "lwnsim_command_duration_seconds_sum{{cmd=\"{}\"}} {}", cmd, h.sum);
+        let _ = writeln!(out, "lwnsim_command_duration_seconds_count{{cmd=\"{}\"}} {}", cmd, h.count);
+    }
+
+    header(&mut out, "lwnsim_command_errors_total", "counter", "Commands failed with a CmdErrorKind.");
+    for ((cmd, kind), n) in &m.cmd_errors {
+        let _ = writeln!(out, "lwnsim_command_errors_total{{cmd=\"{}\",kind=\"{}\"}} {}", cmd, kind, n);
+    }
+
+    header(&mut out, "lwnsim_lora_events_total", "counter", "Lora events received from the simulator.");
+    for (event, n) in &m.lora_events {
+        let _ = writeln!(out, "lwnsim_lora_events_total{{event=\"{}\"}} {}", event, n);
+    }
+
+    header(&mut out, "lwnsim_connects_total", "counter", "Connections to the simulator.");
+    let _ = writeln!(out, "lwnsim_connects_total {}", m.connects);
+    header(&mut out, "lwnsim_reconnects_total", "counter", "Reconnections to the simulator after a connection loss.");
+    let _ = writeln!(out, "lwnsim_reconnects_total {}", m.reconnects);
+    return out;
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// embedded HTTP endpoint serving render_metrics() on GET /metrics, stopped when dropped
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+///
+/// let server = MetricsServer::start("0.0.0.0:9090").unwrap();
+/// // scrape http://<host>:9090/metrics
+/// ```
+#[derive(Debug)]
+pub struct MetricsServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+}
+
+impl MetricsServer {
+    /// starts the endpoint on addr ("127.0.0.1:0" for any free port)
+    pub fn start(addr: &str) -> Result<MetricsServer> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let accept_stop = stop.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                match stream {
+                    Ok(stream) => {
+                        thread::spawn(move || serve_connection(stream));
+                    }
+                    Err(e) => warn!("[METRICS][accept]{}", e),
+                }
+            }
+            debug!("[METRICS]stopped");
+        });
+        info!("[METRICS]listening on {}", addr);
+        return Ok(MetricsServer { addr, stop });
+    }
+
+    pub fn url(&self) -> String {
+        return format!("http://{}/metrics", self.addr);
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    pub fn stop(&self) {
+        if !self.stop.swap(true, Ordering::Relaxed) {
+            // wakes up the accept loop
+            let _ = TcpStream::connect(self.addr);
+        }
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn serve_connection(stream: TcpStream) {
+    let mut writer = match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+        return;
+    }
+    // the headers are ignored
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).unwrap_or(0) == 0 || header.trim_end().is_empty() {
+            break;
+        }
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let (status, content_type, body) = match (method, path.split('?').next().unwrap_or("")) {
+        ("GET", "/metrics") => ("200 OK", CONTENT_TYPE, render_metrics()),
+        ("GET", _) => ("404 Not Found", "text/plain", "not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "text/plain", "method not allowed\n".to_string()),
+    };
+    let res = write!(
+        writer,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+    .and_then(|_| writer.flush());
+    if let Err(e) = res {
+        debug!("[METRICS][write]{}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lwnsim_cmd::DevResponse;
+    use std::io::Read;
+
+    // the metrics are process wide: the tests use their own command names
+    fn lines_of(text: &str, cmd: &str) -> Vec<String> {
+        let label = format!("cmd=\"{}\"", cmd);
+        return text.lines().filter(|l| l.contains(&label)).map(|l| l.to_string()).collect();
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let mut h = Histogram::default();
+        for secs in [0.003, 0.2, 3.0, 20.0] {
+            h.observe(secs);
+        }
+        assert_eq!(h.buckets, [1, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3]);
+        assert_eq!(h.count, 4);
+        assert!((h.sum - 23.203).abs() < 1e-9);
+    }
+
+    #[test]
+    fn commands_are_aggregated() {
+        let cmd = "metrics-test-cmd";
+        record_cmd(cmd, Duration::from_millis(20), &Ok(None));
+        let not_linked = Response::LinkDev(DevResponse { error: CmdErrorKind::DeviceNotLinked });
+        record_cmd(cmd, Duration::from_millis(300), &Ok(Some(not_linked)));
+        record_cmd(cmd, Duration::from_millis(1), &Err(Error::CmdError(CmdErrorKind::DevCmdTimeout)));
+        let ok = Response::LinkDev(DevResponse { error: CmdErrorKind::DevCmdOK });
+        record_cmd(cmd, Duration::from_secs(30), &Ok(Some(ok)));
+
+        let lines = lines_of(&render_metrics(), cmd);
+        for expected in [
+            "lwnsim_commands_total{cmd=\"metrics-test-cmd\"} 4",
+            "lwnsim_command_duration_seconds_bucket{cmd=\"metrics-test-cmd\",le=\"0.005\"} 1",
+            "lwnsim_command_duration_seconds_bucket{cmd=\"metrics-test-cmd\",le=\"0.025\"} 2",
+            "lwnsim_command_duration_seconds_bucket{cmd=\"metrics-test-cmd\",le=\"0.5\"} 3",
+            "lwnsim_command_duration_seconds_bucket{cmd=\"metrics-test-cmd\",le=\"10\"} 3",
+            "lwnsim_command_duration_seconds_bucket{cmd=\"metrics-test-cmd\",le=\"+Inf\"} 4",
+            "lwnsim_command_duration_seconds_count{cmd=\"metrics-test-cmd\"} 4",
+            "lwnsim_command_errors_total{cmd=\"metrics-test-cmd\",kind=\"DeviceNotLinked\"} 1",
+            "lwnsim_command_errors_total{cmd=\"metrics-test-cmd\",kind=\"DevCmdTimeout\"} 1",
+        ] {
+            assert!(lines.iter().any(|l| l == expected), "{} not in {:#?}", expected, lines);
+        }
+        assert_eq!(lines.iter().filter(|l| l.starts_with("lwnsim_command_errors_total")).count(), 2);
+        let sum = lines.iter().find(|l| l.starts_with("lwnsim_command_duration_seconds_sum")).unwrap();
+        let sum: f64 = sum.rsplit(' ').next().unwrap().parse().unwrap();
+        assert!((sum - 30.321).abs() < 1e-9, "{}", sum);
+    }
+
+    #[test]
+    fn lora_events_are_counted_by_flag() {
+        let count = |text: &str, event: &str| -> u64 {
+            let prefix = format!("lwnsim_lora_events_total{{event=\"{}\"}} ", event);
+            return text.lines().find_map(|l| l.strip_prefix(&prefix)).map_or(0, |n| n.parse().unwrap());
+        };
+        let before = render_metrics();
+        record_lora_event(LoraEvents::UNJOIN_EVENT | LoraEvents::DEVICE_TIME_ANS_EVENT);
+        record_lora_event(LoraEvents::empty());
+        let after = render_metrics();
+        assert!(count(&after, "UNJOIN_EVENT") > count(&before, "UNJOIN_EVENT"));
+        assert!(count(&after, "DEVICE_TIME_ANS_EVENT") > count(&before, "DEVICE_TIME_ANS_EVENT"));
+        assert!(!after.contains(" | "));
+    }
+
+    fn http(server: &MetricsServer, request: &str) -> String {
+        let mut stream = TcpStream::connect(server.addr()).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        return response;
+    }
+
+    #[test]
+    fn metrics_endpoint() {
+        let server = MetricsServer::start("127.0.0.1:0").unwrap();
+        let response = http(&server, "GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains(CONTENT_TYPE));
+        assert!(response.contains("# TYPE lwnsim_command_duration_seconds histogram"));
+        assert!(http(&server, "GET /other HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 404"));
+        assert!(http(&server, "POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.1 405"));
+    }
+}
@@ -0,0 +1,687 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::clock::clock;
+use super::error::Result;
+use super::lora_events::LoraEvents;
+use super::lorawan::{AesKey, FCtrl, Fhdr, MType, MacPayload, PhyPayload};
+use super::lwnsim_cmd::*;
+use super::mac_cmd::{DeviceTimeAns, LinkCheckAns, MacCommand};
+use super::raw_frame::FrameDirection;
+
+use base64::Engine;
+use log::{debug, info, trace, warn};
+
+// engine.io packet separator of the polling transport
+static PACKET_SEPARATOR: char = '\x1e';
+// an idle long poll is answered with a ping, well before the client HTTP timeout
+static POLL_WAIT: Duration = Duration::from_secs(10);
+static DOWNLINK_MTYPE: &str = "UnconfirmedDataDown";
+// channel mask of a device just linked (EU868 default channels)
+static DEFAULT_CHMASK: u16 = 0b111;
+// GPS epoch (1980-01-06T00:00:00Z) as a unix timestamp, with the leap seconds since then
+static GPS_EPOCH_UNIX_SECS: i64 = 315_964_800;
+static GPS_UTC_LEAP_SECS: i64 = 18;
+
+static DEV_EVENT_ACK_CMD: &str = "ack-cmd";
+static DEV_EVENT_LORA: &str = "lora-event";
+static DEV_EVENT_MAC_CMD: &str = "mac-command";
+static DEV_EVENT_RAW_FRAME: &str = "raw-frame";
+// radio metadata of the raw frames
+static RAW_FRAME_FREQUENCIES: [u32; 3] = [868_100_000, 868_300_000, 868_500_000];
+static RAW_FRAME_DR: u8 = 5;
+static RAW_FRAME_RSSI: i16 = -60;
+static RAW_FRAME_SNR: f32 = 9.5;
+
+/// behavior of the mock simulator, ratios are probabilities in 0.0..=1.0
+#[derive(Debug, Clone)]
+pub struct MockOptions {
+    /// time between a join request and the JOIN_ACCEPT_EVENT
+    pub join_delay: Duration,
+    pub join_accept_ratio: f64,
+    /// time between an uplink and its TX_PACKET_EVENT / TX_FAILED_EVENT
+    pub tx_delay: Duration,
+    pub tx_failed_ratio: f64,
+    /// probability that an uplink is answered by a downlink, signaled rx_delay after the TX event
+    pub downlink_ratio: f64,
+    pub rx_delay: Duration,
+    pub downlink_port: u8,
+    /// the uplink payload is echoed if None
+    pub downlink_payload: Option<String>,
+    /// DevEUIs of the simulator devices, any DevEUI is accepted if None
+    pub devices: Option<Vec<String>>,
+    pub seed: u64,
+    /// session keys of the raw frames (see LoraDev::set_raw_frames())
+    pub nwk_s_key: AesKey,
+    pub app_s_key: AesKey,
+}
+
+impl Default for MockOptions {
+    fn default() -> Self {
+        MockOptions {
+            join_delay: Duration::from_millis(500),
+            join_accept_ratio: 1.0,
+            tx_delay: Duration::from_millis(200),
+            tx_failed_ratio: 0.0,
+            downlink_ratio: 0.0,
+            rx_delay: Duration::from_millis(1000),
+            downlink_port: 10,
+            downlink_payload: None,
+            devices: None,
+            seed: 0,
+            nwk_s_key: [0; 16],
+            app_s_key: [0; 16],
+        }
+    }
+}
+
+/// in-process stand-in for LWN-Simulator serving the device API (socketio over engine.io polling)
+/// for offline tests: commands are answered at once, lora events are sent after the configured delays
+/// the delays are in crate clock time, with a VirtualClock long scenarios run faster than real time
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+///
+/// let mock = MockSimulator::start("127.0.0.1:0", MockOptions::default()).unwrap();
+/// LwnsimBuilder::new(&mock.url(), "359ac7cd01bc8aff").connect().unwrap();
+/// ```
+pub struct MockSimulator {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    opts: MockOptions,
+    stop: AtomicBool,
+    next_sid: AtomicU64,
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+    devices: Mutex<HashMap<String, MockDevice>>,
+    rng: Mutex<StdRng>,
+}
+
+#[derive(Debug)]
+struct MockDevice {
+    linked: bool,
+    joined: bool,
+    // payload as sent to the client, with its encoding, and FPort
+    downlinks: VecDeque<(String, Option<PayloadEncoding>, u8)>,
+    chmask: u16,
+    link_check_req: bool,
+    device_time_req: bool,
+    raw_frames: bool,
+    f_cnt_up: u32,
+    f_cnt_down: u32,
+}
+
+impl MockDevice {
+    fn new() -> MockDevice {
+        MockDevice {
+            linked: false,
+            joined: false,
+            downlinks: VecDeque::new(),
+            chmask: DEFAULT_CHMASK,
+            link_check_req: false,
+            device_time_req: false,
+            raw_frames: false,
+            f_cnt_up: 0,
+            f_cnt_down: 0,
+        }
+    }
+}
+
+// engine.io session: packets waiting for the next long poll
+struct Session {
+    sid: String,
+    queue: Mutex<SessionQueue>,
+    cond: Condvar,
+}
+
+#[derive(Default)]
+struct SessionQueue {
+    ready: VecDeque<String>,
+    scheduled: Vec<(Instant, String)>,
+    closed: bool,
+}
+
+impl Session {
+    fn push(&self, packet: String) {
+        self.schedule(Duration::ZERO, packet);
+    }
+
+    fn schedule(&self, delay: Duration, packet: String) {
+        let mut q = self.queue.lock().unwrap();
+        if delay.is_zero() {
+            q.ready.push_back(packet);
+        } else {
+            q.scheduled.push((clock().now() + delay, packet));
+        }
+        self.cond.notify_all();
+    }
+
+    fn close(&self) {
+        self.queue.lock().unwrap().closed = true;
+        self.cond.notify_all();
+    }
+
+    // waits for packets (at most max_wait in real time), returns the packets ready to be sent
+    // the packets are scheduled in crate clock time
+    fn wait(&self, max_wait: Duration, stop: &AtomicBool) -> Vec<String> {
+        let clock = clock();
+        let deadline = Instant::now() + max_wait;
+        let mut q = self.queue.lock().unwrap();
+        loop {
+            let now = clock.now();
+            let (due, later): (Vec<_>, Vec<_>) = q.scheduled.drain(..).partition(|(t, _)| *t <= now);
+            q.scheduled = later;
+            let mut due = due;
+            due.sort_by_key(|(t, _)| *t);
+            q.ready.extend(due.into_iter().map(|(_, p)| p));
+            let poll_wait = deadline.saturating_duration_since(Instant::now());
+            if !q.ready.is_empty() || q.closed || stop.load(Ordering::Relaxed) || poll_wait.is_zero() {
+                return q.ready.drain(..).collect();
+            }
+            let next = q.scheduled.iter().map(|(t, _)| clock.real_wait(t.saturating_duration_since(now))).min();
+            q = self.cond.wait_timeout(q, next.map_or(poll_wait, |w| w.min(poll_wait))).unwrap().0;
+        }
+    }
+}
+
+impl MockSimulator {
+    /// starts the mock on addr ("127.0.0.1:0" for any free port)
+    pub fn start(addr: &str, opts: MockOptions) -> Result<MockSimulator> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            rng: Mutex::new(StdRng::seed_from_u64(opts.seed)),
+            opts,
+            stop: AtomicBool::new(false),
+            next_sid: AtomicU64::new(1),
+            sessions: Mutex::new(HashMap::new()),
+            devices: Mutex::new(HashMap::new()),
+        });
+        let accept_shared = shared.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if accept_shared.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                match stream {
+                    Ok(stream) => {
+                        let conn_shared = accept_shared.clone();
+                        thread::spawn(move || serve_connection(stream, &conn_shared));
+                    }
+                    Err(e) => warn!("[MOCK_SIM][accept]{}", e),
+                }
+            }
+            debug!("[MOCK_SIM]stopped");
+        });
+        info!("[MOCK_SIM]listening on {}", addr);
+        return Ok(MockSimulator { addr, shared });
+    }
+
+    pub fn url(&self) -> String {
+        return format!("http://{}", self.addr);
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        return self.addr;
+    }
+
+    /// number of devices currently linked
+    pub fn linked_devices(&self) -> usize {
+        return self.shared.devices.lock().unwrap().values().filter(|d| d.linked).count();
+    }
+
+    pub fn stop(&self) {
+        if !self.shared.stop.swap(true, Ordering::Relaxed) {
+            for s in self.shared.sessions.lock().unwrap().values() {
+                s.close();
+            }
+            // wakes up the accept loop
+            let _ = TcpStream::connect(self.addr);
+        }
+    }
+}
+
+impl Drop for MockSimulator {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+// ---------------------------------------------------------------------------
+// HTTP / engine.io polling transport
+
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    body: String,
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> Option<Request> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).ok()? == 0 {
+        return None;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?.to_string();
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = header.split_once(':') {
+            if k.eq_ignore_ascii_case("content-length") {
+                content_length = v.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).ok()?;
+    let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = query
+        .split('&')
+        .filter_map(|kv| kv.split_once('='))
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    return Some(Request {
+        method,
+        path: path.to_string(),
+        query,
+        body: String::from_utf8_lossy(&body).to_string(),
+    });
+}
+
+fn write_response(stream: &mut TcpStream, status: &str, body: &str) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=UTF-8\r\nContent-Length: {}\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    return stream.flush();
+}
+
+fn serve_connection(stream: TcpStream, shared: &Arc<Shared>) {
+    let mut writer = match stream.try_clone() {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    while let Some(req) = read_request(&mut reader) {
+        if shared.stop.load(Ordering::Relaxed) {
+            break;
+        }
+        let (status, body) = handle_request(&req, shared);
+        if write_response(&mut writer, status, &body).is_err() {
+            break;
+        }
+    }
+}
+
+fn handle_request(req: &Request, shared: &Arc<Shared>) -> (&'static str, String) {
+    if !req.path.starts_with("/socket.io") {
+        return ("404 Not Found", String::new());
+    }
+    let session = match req.query.get("sid") {
+        None if req.method == "GET" => return ("200 OK", open_session(shared)),
+        None => return ("400 Bad Request", String::new()),
+        Some(sid) => match shared.sessions.lock().unwrap().get(sid) {
+            Some(s) => s.clone(),
+            None => return ("400 Bad Request", json!({"code": 1, "message": "Session ID unknown"}).to_string()),
+        },
+    };
+    if req.method == "POST" {
+        for packet in req.body.split(PACKET_SEPARATOR) {
+            handle_engine_packet(packet, &session, shared);
+        }
+        return ("200 OK", "ok".to_string());
+    }
+    let packets = session.wait(POLL_WAIT, &shared.stop);
+    if session.queue.lock().unwrap().closed {
+        shared.sessions.lock().unwrap().remove(&session.sid);
+        return ("200 OK", "1".to_string());
+    }
+    if packets.is_empty() {
+        // ping, the client answers with a pong
+        return ("200 OK", "2".to_string());
+    }
+    return ("200 OK", packets.join(&PACKET_SEPARATOR.to_string()));
+}
+
+fn open_session(shared: &Arc<Shared>) -> String {
+    let sid = format!("mock{:012x}", shared.next_sid.fetch_add(1, Ordering::Relaxed));
+    let session = Arc::new(Session {
+        sid: sid.clone(),
+        queue: Mutex::new(SessionQueue::default()),
+        cond: Condvar::new(),
+    });
+    shared.sessions.lock().unwrap().insert(sid.clone(), session);
+    debug!("[MOCK_SIM][open]{}", sid);
+    let handshake = json!({
+        "sid": sid,
+        "upgrades": [],
+        "pingInterval": 25000,
+        "pingTimeout": 20000,
+        "maxPayload": 1000000,
+    });
+    return format!("0{}", handshake);
+}
+
+fn handle_engine_packet(packet: &str, session: &Session, shared: &Shared) {
+    trace!("[MOCK_SIM][{}][recv]{}", session.sid, packet);
+    match packet.chars().next() {
+        Some('1') => session.close(),
+        Some('4') => handle_socketio_packet(&packet[1..], session, shared),
+        // pong, noop
+        _ => {}
+    }
+}
+
+// ---------------------------------------------------------------------------
+// socketio protocol
+
+// socketio packet: type, namespace, ack id, json data
+fn parse_socketio(packet: &str) -> Option<(char, String, Option<i64>, Option<Value>)> {
+    let kind = packet.chars().next()?;
+    let mut rest = &packet[1..];
+    let mut nsp = "/".to_string();
+    if rest.starts_with('/') {
+        let (n, r) = rest.split_once(',').unwrap_or((rest, ""));
+        nsp = n.to_string();
+        rest = r;
+    }
+    let digits = rest.chars().take_while(|c| c.is_ascii_digit()).count();
+    let id = rest[..digits].parse().ok();
+    let data = serde_json::from_str(&rest[digits..]).ok();
+    return Some((kind, nsp, id, data));
+}
+
+fn socketio_packet(kind: char, nsp: &str, id: Option<i64>, data: &Value) -> String {
+    let mut packet = format!("4{}", kind);
+    if nsp != "/" {
+        packet.push_str(nsp);
+        packet.push(',');
+    }
+    if let Some(id) = id {
+        packet.push_str(&id.to_string());
+    }
+    packet.push_str(&data.to_string());
+    return packet;
+}
+
+fn event_packet(nsp: &str, event: &str, data: Value) -> String {
+    return socketio_packet('2', nsp, None, &json!([event, data]));
+}
+
+fn lora_event_packet(nsp: &str, event: LoraEvents) -> String {
+    return event_packet(nsp, DEV_EVENT_LORA, json!({ "event": event }));
+}
+
+// bytes of a payload sent as a string
+fn decode_payload(payload: &str, encoding: Option<PayloadEncoding>) -> Vec<u8> {
+    return match encoding {
+        Some(PayloadEncoding::Base64) => base64::engine::general_purpose::STANDARD
+            .decode(payload)
+            .unwrap_or_default(),
+        None => payload.as_bytes().to_vec(),
+    };
+}
+
+// raw-frame event of a data frame of the device, its DevAddr is the low half of the DevEUI
+fn raw_frame_packet(
+    nsp: &str,
+    dev_eui: &str,
+    dev: &MockDevice,
+    opts: &MockOptions,
+    mtype: MType,
+    fport: u8,
+    payload: &[u8],
+) -> String {
+    let uplink = mtype.is_uplink();
+    let f_cnt = if uplink { dev.f_cnt_up } else { dev.f_cnt_down };
+    let dev_addr = u64::from_str_radix(dev_eui, 16).unwrap_or_default() as u32;
+    let mut frame = PhyPayload::data(
+        mtype,
+        MacPayload {
+            fhdr: Fhdr {
+                dev_addr,
+                f_ctrl: FCtrl::default(),
+                f_cnt: f_cnt as u16,
+                f_opts: Vec::new(),
+            },
+            f_port: Some(fport),
+            frm_payload: payload.to_vec(),
+        },
+    )
+    .unwrap();
+    // data frames without FOpts, none of these calls can fail
+    frame.encrypt_frm_payload(&opts.app_s_key, &opts.nwk_s_key).unwrap();
+    frame.set_mic(&opts.nwk_s_key).unwrap();
+    let mut data = json!({
+        "DevEUI": dev_eui,
+        "Direction": if uplink { FrameDirection::Uplink } else { FrameDirection::Downlink },
+        "PHYPayload": base64::engine::general_purpose::STANDARD.encode(frame.to_bytes().unwrap()),
+        "Frequency": RAW_FRAME_FREQUENCIES[f_cnt as usize % RAW_FRAME_FREQUENCIES.len()],
+        "DataRate": RAW_FRAME_DR,
+    });
+    if !uplink {
+        data["RSSI"] = json!(RAW_FRAME_RSSI);
+        data["SNR"] = json!(RAW_FRAME_SNR);
+    }
+    return event_packet(nsp, DEV_EVENT_RAW_FRAME, data);
+}
+
+fn handle_socketio_packet(packet: &str, session: &Session, shared: &Shared) {
+    let (kind, nsp, id, data) = match parse_socketio(packet) {
+        Some(p) => p,
+        None => return warn!("[MOCK_SIM][invalid packet]{}", packet),
+    };
+    match kind {
+        // connect
+        '0' => session.push(socketio_packet('0', &nsp, None, &json!({ "sid": session.sid }))),
+        // event
+        '2' => {
+            let payload = data.as_ref().and_then(|d| d.get(1)).cloned().unwrap_or(Value::Null);
+            match serde_json::from_value::<DevExecuteCmd>(payload.clone()) {
+                Ok(msg) => {
+                    let resp = execute(&msg, &nsp, session, shared);
+                    if msg.ack {
+                        let ack = DevAckCmd {
+                            cmd: msg.get_cmd().to_string(),
+                            args: payload,
+                            cmd_id: msg.cmd_id,
+                        };
+                        session.push(event_packet(&nsp, DEV_EVENT_ACK_CMD, json!(ack)));
+                    }
+                    if id.is_some() {
+                        session.push(socketio_packet('3', &nsp, id, &json!([resp])));
+                    }
+                }
+                Err(e) => warn!("[MOCK_SIM][invalid command]{} {:?}", e, payload),
+            }
+        }
+        _ => {}
+    }
+}
+
+// executes a device command, returns the response and schedules the resulting events
+fn execute(msg: &DevExecuteCmd, nsp: &str, session: &Session, shared: &Shared) -> Response {
+    let opts = &shared.opts;
+    let known = opts
+        .devices
+        .as_ref()
+        .is_none_or(|d| d.iter().any(|e| e.eq_ignore_ascii_case(&msg.dev_eui)));
+    if !known {
+        return response(&msg.cmd, CmdErrorKind::NoDeviceWithDevEUI);
+    }
+    let mut devices = shared.devices.lock().unwrap();
+    let dev = devices.entry(msg.dev_eui.to_lowercase()).or_insert_with(MockDevice::new);
+    let mut rng = shared.rng.lock().unwrap();
+    debug!("[MOCK_SIM][{}][{}]", msg.dev_eui, msg.get_cmd());
+
+    let linked_error = if dev.linked { None } else { Some(CmdErrorKind::DeviceNotLinked) };
+    let joined_error = if dev.joined { None } else { Some(CmdErrorKind::DeviceNotJoined) };
+    let error = match &msg.cmd {
+        Command::LinkDev if dev.linked => Some(CmdErrorKind::DeviceLinked),
+        Command::LinkDev => {
+            dev.linked = true;
+            None
+        }
+        Command::UnlinkDev => {
+            if dev.linked {
+                *dev = MockDevice::new();
+            }
+            linked_error
+        }
+        Command::JoinRequest { .. } => {
+            if dev.linked && rng.gen_bool(opts.join_accept_ratio) {
+                dev.joined = true;
+                session.schedule(opts.join_delay, lora_event_packet(nsp, LoraEvents::JOIN_ACCEPT_EVENT));
+            }
+            linked_error
+        }
+        Command::SendUplink { mtype, payload, fport, encoding } if dev.joined => {
+            if rng.gen_bool(opts.tx_failed_ratio) {
+                session.schedule(opts.tx_delay, lora_event_packet(nsp, LoraEvents::TX_FAILED_EVENT));
+            } else {
+                let confirmed = mtype == "ConfirmedDataUp";
+                if dev.raw_frames {
+                    let bytes = decode_payload(payload, *encoding);
+                    let mtype = if confirmed { MType::ConfirmedDataUp } else { MType::UnconfirmedDataUp };
+                    let frame = raw_frame_packet(nsp, &msg.dev_eui, dev, opts, mtype, fport.unwrap_or(1), &bytes);
+                    session.schedule(opts.tx_delay, frame);
+                }
+                dev.f_cnt_up += 1;
+                session.schedule(opts.tx_delay, lora_event_packet(nsp, LoraEvents::TX_PACKET_EVENT));
+                let rx_delay = opts.tx_delay + opts.rx_delay;
+                if dev.link_check_req {
+                    dev.link_check_req = false;
+                    let ans = MacCommand::LinkCheckAns(LinkCheckAns { margin: 20, gw_cnt: 1 });
+                    session.schedule(rx_delay, event_packet(nsp, DEV_EVENT_MAC_CMD, json!(ans)));
+                }
+                if dev.device_time_req {
+                    dev.device_time_req = false;
+                    let now = clock().utc_now();
+                    let ans = MacCommand::DeviceTimeAns(DeviceTimeAns {
+                        seconds: (now.timestamp() - GPS_EPOCH_UNIX_SECS + GPS_UTC_LEAP_SECS) as u32,
+                        fractional_seconds: (now.timestamp_subsec_millis() * 256 / 1000) as u8,
+                    });
+                    session.schedule(rx_delay, event_packet(nsp, DEV_EVENT_MAC_CMD, json!(ans)));
+                }
+                if rng.gen_bool(opts.downlink_ratio) {
+                    // the uplink is echoed with its encoding
+                    let (pl, pl_encoding) = match &opts.downlink_payload {
+                        Some(pl) => (pl.clone(), None),
+                        None => (payload.clone(), *encoding),
+                    };
+                    if dev.raw_frames {
+                        let mtype = MType::UnconfirmedDataDown;
+                        let bytes = decode_payload(&pl, pl_encoding);
+                        let frame = raw_frame_packet(nsp, &msg.dev_eui, dev, opts, mtype, opts.downlink_port, &bytes);
+                        session.schedule(rx_delay, frame);
+                        dev.f_cnt_down += 1;
+                    }
+                    dev.downlinks.push_back((pl, pl_encoding, opts.downlink_port));
+                    session.schedule(rx_delay, lora_event_packet(nsp, LoraEvents::RX_PACKET_EVENT));
+                }
+            }
+            None
+        }
+        Command::RecvDownlink { .. } if dev.joined => match dev.downlinks.pop_front() {
+            Some((payload, encoding, fport)) => {
+                return Response::RecvDownlink(DevResponseRecvDownlink {
+                    error: CmdErrorKind::DevCmdOK,
+                    mtype: DOWNLINK_MTYPE.to_string(),
+                    payload: Some(payload),
+                    fport: Some(fport),
+                    encoding,
+                });
+            }
+            None => Some(CmdErrorKind::NoDataDWrecv),
+        },
+        Command::LinkCheckReq if dev.joined => {
+            dev.link_check_req = true;
+            None
+        }
+        Command::DeviceTimeReq if dev.joined => {
+            dev.device_time_req = true;
+            None
+        }
+        Command::SendUplink { .. } | Command::RecvDownlink { .. } | Command::LinkCheckReq | Command::DeviceTimeReq => {
+            joined_error
+        }
+        Command::SetAdr { .. } | Command::SetDrTxPower { .. } => linked_error,
+        Command::AddChannel { index, .. } if dev.linked => {
+            dev.chmask |= 1 << index;
+            None
+        }
+        Command::RemoveChannel { index } if dev.linked => {
+            dev.chmask &= !(1 << index);
+            None
+        }
+        Command::GetChannelMask if dev.linked => {
+            return Response::GetChannelMask(DevResponseChannelMask {
+                error: CmdErrorKind::DevCmdOK,
+                chmask: dev.chmask,
+            });
+        }
+        Command::SetRawFrames { enable } if dev.linked => {
+            dev.raw_frames = *enable;
+            None
+        }
+        Command::SetBatteryLevel { battery_level } if dev.linked => {
+            debug!("[MOCK_SIM][{}]battery level {}", msg.dev_eui, battery_level);
+            None
+        }
+        Command::AddChannel { .. }
+        | Command::RemoveChannel { .. }
+        | Command::GetChannelMask
+        | Command::SetRawFrames { .. }
+        | Command::SetBatteryLevel { .. } => linked_error,
+    };
+    return response(&msg.cmd, error.unwrap_or(CmdErrorKind::DevCmdOK));
+}
+
+// response without result to a command
+fn response(cmd: &Command, error: CmdErrorKind) -> Response {
+    let r = DevResponse { error: error.clone() };
+    return match cmd {
+        Command::LinkDev => Response::LinkDev(r),
+        Command::UnlinkDev => Response::UnlinkDev(r),
+        Command::JoinRequest { .. } => Response::JoinRequest(r),
+        Command::SendUplink { .. } => Response::SendUplink(r),
+        Command::RecvDownlink { .. } => Response::RecvDownlink(DevResponseRecvDownlink {
+            error,
+            mtype: String::new(),
+            payload: None,
+            fport: None,
+            encoding: None,
+        }),
+        Command::LinkCheckReq => Response::LinkCheckReq(r),
+        Command::DeviceTimeReq => Response::DeviceTimeReq(r),
+        Command::SetAdr { .. } => Response::SetAdr(r),
+        Command::SetDrTxPower { .. } => Response::SetDrTxPower(r),
+        Command::AddChannel { .. } => Response::AddChannel(r),
+        Command::RemoveChannel { .. } => Response::RemoveChannel(r),
+        Command::GetChannelMask => Response::GetChannelMask(DevResponseChannelMask { error, chmask: 0 }),
+        Command::SetRawFrames { .. } => Response::SetRawFrames(r),
+        Command::SetBatteryLevel { .. } => Response::SetBatteryLevel(r),
+    };
+}
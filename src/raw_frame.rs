@@ -0,0 +1,213 @@
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use serde_derive::*;
+use std::collections::VecDeque;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use super::error::{Error, Result};
+use super::lora_events::{LoraEvents, LORA_EVENTS};
+use super::lorawan::PhyPayload;
+use log::{debug, warn};
+
+// maximum number of raw frames kept until read by the application
+static RAW_FRAME_QUEUE_CAPACITY: usize = 64;
+
+lazy_static! {
+    pub static ref RAW_FRAMES: Mutex<RawFrames> = Mutex::new(RawFrames::new());
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FrameDirection {
+    Uplink,
+    Downlink,
+}
+
+/// on-air frame of the device, as sent by the simulator in a "raw-frame" event
+/// when the raw frame mode is enabled (see LoraDev::set_raw_frames())
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RawFrame {
+    pub direction: FrameDirection,
+    pub dev_eui: Option<String>,
+    /// PHYPayload: MHDR | MACPayload | MIC
+    pub phy_payload: Vec<u8>,
+    /// Hz
+    pub frequency: Option<u32>,
+    pub data_rate: Option<u8>,
+    /// dBm and dB, set for the downlinks
+    pub rssi: Option<i16>,
+    pub snr: Option<f32>,
+    /// time at which the frame was received by the client
+    pub timestamp: DateTime<Utc>,
+}
+
+// raw-frame payload, the PHYPayload is base64 encoded
+#[derive(Deserialize, Debug)]
+struct RawFramePayload {
+    #[serde(rename = "DevEUI", default)]
+    dev_eui: Option<String>,
+    #[serde(rename = "Direction")]
+    direction: FrameDirection,
+    #[serde(rename = "PHYPayload")]
+    phy_payload: String,
+    #[serde(rename = "Frequency", default)]
+    frequency: Option<u32>,
+    #[serde(rename = "DataRate", default)]
+    data_rate: Option<u8>,
+    #[serde(rename = "RSSI", default)]
+    rssi: Option<i16>,
+    #[serde(rename = "SNR", default)]
+    snr: Option<f32>,
+}
+
+impl RawFrame {
+    /// parses a raw-frame payload
+    pub fn parse(payload: &str) -> Result<RawFrame> {
+        let pl: RawFramePayload = serde_json::from_str(payload)?;
+        let phy_payload = base64::engine::general_purpose::STANDARD
+            .decode(&pl.phy_payload)
+            .map_err(|e| Error::PayloadError(format!("PHYPayload: {}", e)))?;
+        return Ok(RawFrame {
+            direction: pl.direction,
+            dev_eui: pl.dev_eui,
+            phy_payload,
+            frequency: pl.frequency,
+            data_rate: pl.data_rate,
+            rssi: pl.rssi,
+            snr: pl.snr,
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// decoded frame, the FRMPayload is left encrypted (see PhyPayload::decrypt_frm_payload())
+    pub fn frame(&self) -> Result<PhyPayload> {
+        return PhyPayload::parse(&self.phy_payload);
+    }
+
+    pub fn hex(&self) -> String {
+        return self.phy_payload.iter().map(|b| format!("{:02x}", b)).collect();
+    }
+}
+
+/// raw frames received by the device and their subscribers
+#[derive(Debug)]
+pub struct RawFrames {
+    enabled: bool,
+    frames: VecDeque<RawFrame>,
+    subscribers: Vec<Sender<RawFrame>>,
+}
+
+impl RawFrames {
+    pub fn new() -> RawFrames {
+        RawFrames {
+            enabled: false,
+            frames: VecDeque::new(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        return self.enabled;
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<RawFrame> {
+        let (tx, rx) = channel();
+        self.subscribers.push(tx);
+        return rx;
+    }
+
+    pub fn handle_raw_frame(&mut self, frame: RawFrame) {
+        debug!("[RAW_FRAMES][{:?}]{}", frame.direction, frame.hex());
+        // dropped receivers are unsubscribed
+        self.subscribers.retain(|tx| tx.send(frame.clone()).is_ok());
+        if self.frames.len() >= RAW_FRAME_QUEUE_CAPACITY {
+            let dropped = self.frames.pop_front();
+            warn!("[RAW_FRAMES][overflow]dropped {:?}", dropped.map(|f| f.hex()));
+        }
+        self.frames.push_back(frame);
+        LORA_EVENTS.lock().unwrap().handle_lora_event(LoraEvents::RAW_FRAME_EVENT);
+    }
+
+    /// returns and removes the raw frames received so far
+    pub fn take(&mut self) -> Vec<RawFrame> {
+        return self.frames.drain(..).collect();
+    }
+
+    /// clears the frames and disables the mode, the subscribers are kept
+    pub fn clear(&mut self) {
+        self.enabled = false;
+        self.frames.clear();
+    }
+}
+
+impl Default for RawFrames {
+    fn default() -> Self {
+        RawFrames::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lora_events::subscribe_lora_events;
+    use crate::lorawan::{FramePayload, MType};
+
+    static UPLINK: &str = r#"{"DevEUI": "0102", "Direction": "Uplink", "PHYPayload": "QNobASYAAQABqgECAwQ=", "Frequency": 868100000, "DataRate": 5}"#;
+
+    #[test]
+    fn raw_frame_parsed() {
+        let frame = RawFrame::parse(UPLINK).unwrap();
+        assert_eq!(frame.direction, FrameDirection::Uplink);
+        assert_eq!(frame.dev_eui.as_deref(), Some("0102"));
+        assert_eq!((frame.frequency, frame.data_rate, frame.rssi, frame.snr), (Some(868_100_000), Some(5), None, None));
+        assert_eq!(frame.hex(), "40da1b012600010001aa01020304");
+        let phy = frame.frame().unwrap();
+        assert_eq!(phy.mtype, MType::UnconfirmedDataUp);
+        assert_eq!(phy.mic, [1, 2, 3, 4]);
+        match phy.payload {
+            FramePayload::Data(mac) => {
+                assert_eq!((mac.fhdr.dev_addr, mac.fhdr.f_cnt, mac.f_port), (0x26011bda, 1, Some(1)));
+                assert_eq!(mac.frm_payload, vec![0xaa]);
+            }
+            p => panic!("{:?}", p),
+        }
+
+        let downlink = RawFrame::parse(r#"{"Direction": "Downlink", "PHYPayload": "YA==", "RSSI": -60, "SNR": 7.5}"#).unwrap();
+        assert_eq!((downlink.direction, downlink.rssi, downlink.snr), (FrameDirection::Downlink, Some(-60), Some(7.5)));
+        assert!(downlink.frame().is_err());
+
+        assert!(matches!(
+            RawFrame::parse(r#"{"Direction": "Uplink", "PHYPayload": "not base64!"}"#),
+            Err(Error::PayloadError(_))
+        ));
+        assert!(matches!(RawFrame::parse(r#"{"Direction": "Sideways", "PHYPayload": ""}"#), Err(Error::InvalidJson(_))));
+    }
+
+    #[test]
+    fn raw_frames_queued_and_published() {
+        let events = subscribe_lora_events();
+        let mut frames = RawFrames::new();
+        let rx = frames.subscribe();
+        let frame = RawFrame::parse(UPLINK).unwrap();
+        for _ in 0..RAW_FRAME_QUEUE_CAPACITY + 2 {
+            frames.handle_raw_frame(frame.clone());
+        }
+        assert_eq!(rx.try_iter().count(), RAW_FRAME_QUEUE_CAPACITY + 2);
+        assert!(events.try_iter().any(|e| e.contains(LoraEvents::RAW_FRAME_EVENT)));
+        // the oldest frames are dropped
+        assert_eq!(frames.take().len(), RAW_FRAME_QUEUE_CAPACITY);
+        assert!(frames.take().is_empty());
+
+        frames.set_enabled(true);
+        frames.handle_raw_frame(frame);
+        frames.clear();
+        assert!(!frames.is_enabled());
+        assert!(frames.take().is_empty());
+    }
+}
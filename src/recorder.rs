@@ -0,0 +1,146 @@
+use chrono::{DateTime, Utc};
+use serde_derive::*;
+use serde_json::Value;
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use std::sync::Mutex;
+
+use super::error::{Error, Result};
+use super::lwnsim::SendMode;
+
+use log::{info, warn};
+
+lazy_static! {
+    pub static ref RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+}
+
+/// line of a recording, e.g.
+/// {"time":"2023-03-20T10:00:00.123Z","type":"cmd","mode":"call","cmd":{"Cmd":"link-dev","DevEUI":"359ac7cd01bc8aff"}}
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Record {
+    pub time: DateTime<Utc>,
+    #[serde(flatten)]
+    pub entry: RecordEntry,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum RecordEntry {
+    /// start of the session
+    Connect { url: String, dev_eui: String },
+    /// command sent to the simulator
+    Cmd { mode: SendMode, cmd: Value },
+    /// simulator response to the last command sent with SendMode::Call
+    Response { cmd: String, response: Value },
+    /// no response received in time
+    Timeout { cmd: String },
+    /// event received from the simulator (ack-cmd, lora-event, mac-command...)
+    Event { event: String, data: Value },
+}
+
+/// writes the traffic with the simulator to a JSON Lines file
+#[derive(Debug)]
+pub struct Recorder {
+    writer: BufWriter<File>,
+}
+
+impl Recorder {
+    /// creates (or truncates) the recording file
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Recorder> {
+        info!("[RECORDER][create]{:?}", path.as_ref());
+        return Ok(Recorder {
+            writer: BufWriter::new(File::create(path)?),
+        });
+    }
+
+    pub fn write(&mut self, entry: RecordEntry) -> Result<()> {
+        let record = Record { time: Utc::now(), entry };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        // the last records are the interesting ones when the client crashes
+        self.writer.flush()?;
+        return Ok(());
+    }
+}
+
+/// appends an entry to the recording, if one is in progress
+pub fn record(entry: RecordEntry) {
+    if let Some(recorder) = RECORDER.lock().unwrap().as_mut() {
+        if let Err(e) = recorder.write(entry) {
+            warn!("[RECORDER][write]{}", e);
+        }
+    }
+}
+
+/// the payload is recorded as json when it is valid json, as a string otherwise
+pub fn record_event(event: &str, payload: &str) {
+    if RECORDER.lock().unwrap().is_none() {
+        return;
+    }
+    let data = serde_json::from_str(payload).unwrap_or_else(|_| Value::String(payload.to_string()));
+    record(RecordEntry::Event {
+        event: event.to_string(),
+        data,
+    });
+}
+
+/// reads a recording, blank lines are skipped
+pub fn read_records<P: AsRef<Path>>(path: P) -> Result<Vec<Record>> {
+    let mut records = Vec::new();
+    for (i, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line)
+            .map_err(|e| Error::ReplayError(format!("line {}: {}", i + 1, e)))?;
+        records.push(record);
+    }
+    return Ok(records);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_are_read_back() {
+        let path = std::env::temp_dir().join(format!("lwnsim-recorder-{}.jsonl", std::process::id()));
+        let mut recorder = Recorder::create(&path).unwrap();
+        let entries = vec![
+            RecordEntry::Connect {
+                url: "http://localhost:8000".to_string(),
+                dev_eui: "0102".to_string(),
+            },
+            RecordEntry::Cmd {
+                mode: SendMode::Call,
+                cmd: serde_json::json!({"Cmd": "link-dev", "DevEUI": "0102"}),
+            },
+            RecordEntry::Response {
+                cmd: "link-dev".to_string(),
+                response: serde_json::json!([{"cmd": "link-dev", "error": 0}]),
+            },
+            RecordEntry::Timeout { cmd: "recv-downlink".to_string() },
+            RecordEntry::Event {
+                event: "dev-log".to_string(),
+                data: Value::String("started".to_string()),
+            },
+        ];
+        for entry in entries.iter().cloned() {
+            recorder.write(entry).unwrap();
+        }
+        drop(recorder);
+        let read: Vec<RecordEntry> = read_records(&path).unwrap().into_iter().map(|r| r.entry).collect();
+        assert_eq!(read, entries);
+
+        // blank lines are skipped, invalid ones are reported with their number
+        std::fs::write(&path, "\n{\"time\":\"2023-03-20T10:00:00Z\",\"type\":\"timeout\",\"cmd\":\"x\"}\n\nnot json\n").unwrap();
+        let res = read_records(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(res, Err(Error::ReplayError(msg)) if msg.starts_with("line 4:")));
+    }
+}
@@ -0,0 +1,205 @@
+use serde_derive::*;
+use std::time::Duration;
+
+use super::lora_dev::EU868;
+
+// LoRa frame settings of the LoRaWAN uplinks: preamble symbols, coding rate 4/5, explicit header, CRC
+static LORA_PREAMBLE_SYMBOLS: f64 = 8.0;
+static LORA_CODING_RATE: u32 = 1;
+// FSK frame overhead in bytes: preamble, sync word, length, CRC
+static FSK_OVERHEAD_BYTES: usize = 5 + 3 + 1 + 2;
+
+/// uplink channel of the device channel plan
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Channel {
+    /// center frequency in Hz
+    #[serde(rename = "Frequency")]
+    pub frequency: u32,
+    #[serde(rename = "DRMin")]
+    pub dr_min: u8,
+    #[serde(rename = "DRMax")]
+    pub dr_max: u8,
+}
+
+/// modulation of a data rate, bandwidth in Hz and bit rate in bit/s
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Modulation {
+    Lora { sf: u8, bandwidth: u32 },
+    Fsk { bitrate: u32 },
+}
+
+/// LoRaWAN regional parameters used to validate the client settings
+#[derive(Debug)]
+pub struct RegionParams {
+    pub name: &'static str,
+    pub min_frequency: u32,
+    pub max_frequency: u32,
+    pub max_dr: u8,
+    pub max_tx_power: u8,
+    pub max_channels: usize,
+    /// channels defined by the region, they can be neither modified nor removed
+    pub default_channels: &'static [Channel],
+    /// modulation of each data rate
+    pub data_rates: &'static [Modulation],
+    /// dBm, TX power index 0
+    pub max_eirp: u8,
+}
+
+static EU868_DEFAULT_CHANNELS: [Channel; 3] = [
+    Channel { frequency: 868_100_000, dr_min: 0, dr_max: 5 },
+    Channel { frequency: 868_300_000, dr_min: 0, dr_max: 5 },
+    Channel { frequency: 868_500_000, dr_min: 0, dr_max: 5 },
+];
+
+static EU868_DATA_RATES: [Modulation; 8] = [
+    Modulation::Lora { sf: 12, bandwidth: 125_000 },
+    Modulation::Lora { sf: 11, bandwidth: 125_000 },
+    Modulation::Lora { sf: 10, bandwidth: 125_000 },
+    Modulation::Lora { sf: 9, bandwidth: 125_000 },
+    Modulation::Lora { sf: 8, bandwidth: 125_000 },
+    Modulation::Lora { sf: 7, bandwidth: 125_000 },
+    Modulation::Lora { sf: 7, bandwidth: 250_000 },
+    Modulation::Fsk { bitrate: 50_000 },
+];
+
+pub static EU868_PARAMS: RegionParams = RegionParams {
+    name: "EU868",
+    min_frequency: 863_000_000,
+    max_frequency: 870_000_000,
+    max_dr: 7,
+    max_tx_power: 7,
+    max_channels: 16,
+    default_channels: &EU868_DEFAULT_CHANNELS,
+    data_rates: &EU868_DATA_RATES,
+    max_eirp: 16,
+};
+
+/// returns the region (EU868, ...) from its name
+pub fn region_from_name(name: &str) -> Option<usize> {
+    if name.eq_ignore_ascii_case(EU868_PARAMS.name) {
+        return Some(EU868);
+    }
+    return None;
+}
+
+/// returns the regional parameters of a region (EU868, ...)
+pub fn region_params(region: usize) -> Option<&'static RegionParams> {
+    if region == EU868 {
+        return Some(&EU868_PARAMS);
+    }
+    return None;
+}
+
+impl RegionParams {
+    pub fn is_valid_dr(&self, dr: u8) -> bool {
+        return dr <= self.max_dr;
+    }
+
+    pub fn is_valid_tx_power(&self, tx_power: u8) -> bool {
+        return tx_power <= self.max_tx_power;
+    }
+
+    /// checks that a channel can be added at index
+    pub fn is_valid_channel(&self, index: usize, channel: &Channel) -> bool {
+        return index >= self.default_channels.len()
+            && index < self.max_channels
+            && channel.frequency >= self.min_frequency
+            && channel.frequency <= self.max_frequency
+            && channel.dr_min <= channel.dr_max
+            && self.is_valid_dr(channel.dr_max);
+    }
+
+    /// TX power in dBm of a TX power index, max EIRP - 2 dB per index
+    pub fn tx_power_dbm(&self, tx_power: u8) -> i8 {
+        return self.max_eirp as i8 - 2 * tx_power.min(self.max_tx_power) as i8;
+    }
+
+    /// time on air of a PHYPayload of phy_len bytes (Semtech AN1200.13), None for an unknown data rate
+    ///
+    /// ```
+    /// use lwnsim_api_rs::*;
+    /// use std::time::Duration;
+    ///
+    /// // 13 bytes frame (empty payload) at SF7BW125
+    /// assert_eq!(EU868_PARAMS.time_on_air(5, 13), Some(Duration::from_micros(46_336)));
+    /// ```
+    pub fn time_on_air(&self, dr: u8, phy_len: usize) -> Option<Duration> {
+        let secs = match self.data_rates.get(dr as usize)? {
+            Modulation::Lora { sf, bandwidth } => {
+                let sf = *sf as i64;
+                let t_sym = (1u64 << sf) as f64 / *bandwidth as f64;
+                // low data rate optimization
+                let de = if sf >= 11 && *bandwidth == 125_000 { 1 } else { 0 };
+                let bits = 8 * phy_len as i64 - 4 * sf + 28 + 16;
+                let symbols = (bits.max(0) as f64 / (4 * (sf - 2 * de)) as f64).ceil() * (LORA_CODING_RATE + 4) as f64;
+                (LORA_PREAMBLE_SYMBOLS + 4.25 + 8.0 + symbols) * t_sym
+            }
+            Modulation::Fsk { bitrate } => ((phy_len + FSK_OVERHEAD_BYTES) * 8) as f64 / *bitrate as f64,
+        };
+        return Some(Duration::from_secs_f64(secs));
+    }
+
+    /// channel plan of a device just after activation
+    pub fn default_channel_plan(&self) -> Vec<Option<Channel>> {
+        let mut plan: Vec<Option<Channel>> = vec![None; self.max_channels];
+        for (i, ch) in self.default_channels.iter().enumerate() {
+            plan[i] = Some(*ch);
+        }
+        return plan;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_airtime(dr: u8, phy_len: usize, expected_ms: f64) {
+        let airtime = EU868_PARAMS.time_on_air(dr, phy_len).unwrap();
+        assert!((airtime.as_secs_f64() * 1000.0 - expected_ms).abs() < 1e-3, "DR{} {} bytes: {:?}", dr, phy_len, airtime);
+    }
+
+    #[test]
+    fn time_on_air_matches_semtech_calculator() {
+        // SF7BW125
+        assert_airtime(5, 13, 46.336);
+        assert_airtime(5, 51, 102.656);
+        // SF12BW125, low data rate optimization
+        assert_airtime(0, 13, 1155.072);
+        assert_airtime(0, 51, 2465.792);
+        // FSK 50 kbit/s
+        assert_airtime(7, 13, 3.84);
+        assert_eq!(EU868_PARAMS.time_on_air(8, 13), None);
+    }
+
+    #[test]
+    fn channel_validation() {
+        let ch = Channel { frequency: 867_100_000, dr_min: 0, dr_max: 5 };
+        assert!(EU868_PARAMS.is_valid_channel(3, &ch));
+        assert!(EU868_PARAMS.is_valid_channel(15, &ch));
+        // default channels and out of range indexes
+        assert!(!EU868_PARAMS.is_valid_channel(0, &ch));
+        assert!(!EU868_PARAMS.is_valid_channel(16, &ch));
+        assert!(!EU868_PARAMS.is_valid_channel(3, &Channel { frequency: 915_000_000, ..ch }));
+        assert!(!EU868_PARAMS.is_valid_channel(3, &Channel { dr_min: 5, dr_max: 2, ..ch }));
+        assert!(!EU868_PARAMS.is_valid_channel(3, &Channel { dr_max: 8, ..ch }));
+    }
+
+    #[test]
+    fn default_channel_plan() {
+        let plan = EU868_PARAMS.default_channel_plan();
+        assert_eq!(plan.len(), 16);
+        assert_eq!(plan[0], Some(Channel { frequency: 868_100_000, dr_min: 0, dr_max: 5 }));
+        assert_eq!(plan[2].map(|c| c.frequency), Some(868_500_000));
+        assert!(plan[3..].iter().all(|c| c.is_none()));
+    }
+
+    #[test]
+    fn tx_power_and_region_names() {
+        assert_eq!(EU868_PARAMS.tx_power_dbm(0), 16);
+        assert_eq!(EU868_PARAMS.tx_power_dbm(7), 2);
+        assert_eq!(EU868_PARAMS.tx_power_dbm(9), 2);
+        assert_eq!(region_from_name("eu868"), Some(EU868));
+        assert_eq!(region_from_name("US915"), None);
+        assert!(region_params(EU868).is_some());
+    }
+}
@@ -0,0 +1,249 @@
+use chrono::{DateTime, Utc};
+use rust_socketio::Payload;
+use serde_json::Value;
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::error::{Error, Result};
+use super::lora_dev::{LoraDev, LORA};
+use super::lwnsim::{handle_event, SendMode, LWNSIM};
+use super::lwnsim_cmd::DevExecuteCmd;
+use super::recorder::{read_records, Record, RecordEntry};
+
+use log::{debug, info, warn};
+
+static EVENT_ACK_CMD: &str = "ack-cmd";
+
+/// replays a recording without the simulator: LWNSIM is connected to the recording
+/// and returns the LORA device, reset for the recorded DevEUI
+///
+/// The commands sent by the client must be the recorded ones, in the same order. The events
+/// recorded before a command are delivered before its response, the other ones are delivered
+/// in the background with the recorded delays divided by speed (f64::INFINITY for no delay).
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+///
+/// let lora = replay("session.jsonl", 1.0).unwrap();
+/// lora.lock().unwrap().activate().unwrap();
+/// ```
+pub fn replay<P: AsRef<Path>>(path: P, speed: f64) -> Result<&'static Mutex<LoraDev>> {
+    let replayer = Replayer::open(path, speed)?;
+    let dev_eui = replayer.dev_eui.clone();
+    LWNSIM.lock().unwrap().connect_replay(replayer);
+    LORA.lock().unwrap().reset(&dev_eui);
+    return Ok(&LORA);
+}
+
+/// transport of LWNSIM feeding a recording back to the client
+pub struct Replayer {
+    url: String,
+    dev_eui: String,
+    shared: Arc<ReplayShared>,
+}
+
+struct ReplayShared {
+    state: Mutex<ReplayState>,
+    cond: Condvar,
+    speed: f64,
+}
+
+struct ReplayState {
+    records: VecDeque<Record>,
+    // recorded CmdId -> CmdId of the replayed command, the acks refer to the recorded ones
+    cmd_ids: HashMap<u64, u64>,
+    // time of the last record replayed, in the recording and now
+    last_time: Option<DateTime<Utc>>,
+    last_instant: Instant,
+    stopped: bool,
+    // log tag of the client the events are delivered to
+    log_tag: String,
+}
+
+impl Replayer {
+    pub fn open<P: AsRef<Path>>(path: P, speed: f64) -> Result<Replayer> {
+        if speed.is_nan() || speed <= 0.0 {
+            return Err(Error::ReplayError(format!("invalid speed {}", speed)));
+        }
+        info!("[REPLAY][open]{:?}", path.as_ref());
+        let mut records: VecDeque<Record> = read_records(path)?.into();
+        let (url, dev_eui) = match records.pop_front().map(|r| r.entry) {
+            Some(RecordEntry::Connect { url, dev_eui }) => (url, dev_eui),
+            _ => return Err(Error::ReplayError("the recording does not start with a connect record".to_string())),
+        };
+        let shared = Arc::new(ReplayShared {
+            state: Mutex::new(ReplayState {
+                records,
+                cmd_ids: HashMap::new(),
+                last_time: None,
+                last_instant: Instant::now(),
+                stopped: false,
+                log_tag: "[LWNSIM]".to_string(),
+            }),
+            cond: Condvar::new(),
+            speed,
+        });
+        let thread_shared = shared.clone();
+        thread::spawn(move || thread_shared.deliver_events());
+        return Ok(Replayer { url, dev_eui, shared });
+    }
+
+    pub fn url(&self) -> &str {
+        return &self.url;
+    }
+
+    pub fn dev_eui(&self) -> &str {
+        return &self.dev_eui;
+    }
+
+    pub(crate) fn set_log_tag(&self, tag: &str) {
+        self.shared.state.lock().unwrap().log_tag = tag.to_string();
+    }
+
+    /// number of records not replayed yet
+    pub fn remaining(&self) -> usize {
+        return self.shared.state.lock().unwrap().records.len();
+    }
+
+    /// matches a command with the next recorded one, returns its response when sent with SendMode::Call
+    /// (None if the recorded command timed out)
+    pub(crate) fn send_cmd(&self, msg: &DevExecuteCmd, mode: SendMode) -> Result<Option<Payload>> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.deliver_pending();
+        let cmd_name = msg.get_cmd();
+        let recorded = match state.records.pop_front() {
+            Some(record) => {
+                state.advance(record.time);
+                record.entry
+            }
+            None => return Err(Error::ReplayError(format!("end of the recording, {} sent", cmd_name))),
+        };
+        match recorded {
+            RecordEntry::Cmd { mode: rec_mode, cmd } if rec_mode == mode && same_cmd(&cmd, msg) => {
+                if let (Some(rec_id), Some(id)) = (cmd["CmdId"].as_u64(), msg.cmd_id) {
+                    state.cmd_ids.insert(rec_id, id);
+                }
+            }
+            other => {
+                let expected = serde_json::to_string(&other).unwrap_or_default();
+                warn!("[REPLAY][{}]recording diverges, {} expected", cmd_name, expected);
+                return Err(Error::ReplayError(format!("{} sent, {} expected", cmd_name, expected)));
+            }
+        }
+        self.shared.cond.notify_all();
+        if mode == SendMode::Emit {
+            return Ok(None);
+        }
+        // the events received before the response are delivered first
+        state.deliver_pending();
+        let resp = match state.records.pop_front() {
+            Some(Record {
+                time,
+                entry: RecordEntry::Response { cmd, response },
+            }) if cmd == cmd_name => {
+                state.advance(time);
+                Some(Payload::String(response.to_string()))
+            }
+            Some(Record {
+                time,
+                entry: RecordEntry::Timeout { cmd },
+            }) if cmd == cmd_name => {
+                state.advance(time);
+                None
+            }
+            other => {
+                let found = other.map(|r| serde_json::to_string(&r.entry).unwrap_or_default());
+                return Err(Error::ReplayError(format!("no response to {} in the recording, {:?} found", cmd_name, found)));
+            }
+        };
+        self.shared.cond.notify_all();
+        return Ok(resp);
+    }
+
+    pub fn stop(&self) {
+        self.shared.state.lock().unwrap().stopped = true;
+        self.shared.cond.notify_all();
+    }
+}
+
+// same command and arguments, the ack settings may differ
+fn same_cmd(recorded: &Value, msg: &DevExecuteCmd) -> bool {
+    let strip = |v: &Value| {
+        let mut v = v.clone();
+        if let Some(obj) = v.as_object_mut() {
+            obj.remove("Ack");
+            obj.remove("CmdId");
+        }
+        v
+    };
+    let sent = serde_json::to_value(msg).unwrap_or_default();
+    return strip(recorded) == strip(&sent);
+}
+
+impl Drop for Replayer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl ReplayShared {
+    // delivers the events with their recorded delays, up to the next command
+    fn deliver_events(&self) {
+        let mut state = self.state.lock().unwrap();
+        while !state.stopped {
+            let due = match state.records.front() {
+                Some(Record { time, entry: RecordEntry::Event { .. } }) => {
+                    let delay = match state.last_time {
+                        Some(last) => (*time - last).to_std().unwrap_or_default().div_f64(self.speed),
+                        None => Duration::ZERO,
+                    };
+                    Some(state.last_instant + delay)
+                }
+                Some(_) => None,
+                None => break,
+            };
+            match due {
+                Some(due) if due <= Instant::now() => state.deliver_next(),
+                Some(due) => state = self.cond.wait_timeout(state, due.saturating_duration_since(Instant::now())).unwrap().0,
+                // waiting for the command
+                None => state = self.cond.wait(state).unwrap(),
+            }
+        }
+        debug!("[REPLAY]done");
+    }
+}
+
+impl ReplayState {
+    fn advance(&mut self, time: DateTime<Utc>) {
+        self.last_time = Some(time);
+        self.last_instant = Instant::now();
+    }
+
+    // delivers the events preceding the next command or response
+    fn deliver_pending(&mut self) {
+        while let Some(Record { entry: RecordEntry::Event { .. }, .. }) = self.records.front() {
+            self.deliver_next();
+        }
+    }
+
+    // the event is handled with the state locked, to keep the recorded order
+    fn deliver_next(&mut self) {
+        if let Some(Record { time, entry: RecordEntry::Event { event, mut data } }) = self.records.pop_front() {
+            self.advance(time);
+            if event == EVENT_ACK_CMD {
+                if let Some(id) = data["CmdId"].as_u64().and_then(|id| self.cmd_ids.get(&id)) {
+                    data["CmdId"] = Value::from(*id);
+                }
+            }
+            let payload = match data {
+                Value::String(s) => s,
+                v => v.to_string(),
+            };
+            handle_event(&self.log_tag, &event, Payload::String(payload));
+        }
+    }
+}
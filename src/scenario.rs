@@ -0,0 +1,698 @@
+use serde_derive::*;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use super::clock::clock;
+use super::codec::decode_hex;
+use super::config::{DeviceProfile, LwnsimConfig};
+use super::error::{Error, Result};
+use super::lora_dev::{LoraDevStatus, LORA};
+use super::lora_events::{subscribe_lora_events, LoraEvents};
+use super::lwnsim_cmd::CmdErrorKind;
+
+use log::{info, warn};
+
+// polling period when waiting for the simulator
+static POLL_PERIOD: Duration = Duration::from_millis(200);
+// buffer size used to read the downlinks
+static RECV_BUFFER_SIZE: usize = 256;
+
+/// scenario file: sequences of device steps with assertions, run one after the other
+///
+/// ```toml
+/// [[scenario]]
+/// name = "confirmed uplinks"
+/// device = "sensor-1"
+///
+/// [[scenario.step]]
+/// action = "join"
+/// timeout = 30
+///
+/// [[scenario.step]]
+/// action = "send"
+/// text = "hello"
+/// confirmed = true
+/// port = 2
+/// count = 3
+/// interval = 10
+///
+/// [[scenario.step]]
+/// action = "expect_downlink"
+/// port = 10
+/// timeout = 20
+///
+/// [[scenario.step]]
+/// action = "unlink"
+/// ```
+///
+/// The file may also hold the `[simulator]` and `[[device]]` tables of LwnsimConfig.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ScenarioFile {
+    #[serde(default, rename = "scenario")]
+    pub scenarios: Vec<Scenario>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    /// device profile of the configuration, the first one if None
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default, rename = "step")]
+    pub steps: Vec<Step>,
+}
+
+/// scenario step, durations are in seconds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Step {
+    Link,
+    Unlink,
+    /// links the device if needed and waits for the JoinAccept
+    Join {
+        #[serde(default = "default_join_timeout")]
+        timeout: u64,
+    },
+    /// sends count uplinks, interval seconds apart
+    Send {
+        #[serde(default)]
+        text: Option<String>,
+        #[serde(default)]
+        hex: Option<String>,
+        /// profile default if None
+        #[serde(default)]
+        confirmed: Option<bool>,
+        /// profile default if None
+        #[serde(default)]
+        port: Option<u8>,
+        #[serde(default = "default_count")]
+        count: u32,
+        #[serde(default)]
+        interval: u64,
+    },
+    /// waits for a downlink, optionally checking its port and payload
+    ExpectDownlink {
+        #[serde(default)]
+        port: Option<u8>,
+        #[serde(default)]
+        text: Option<String>,
+        #[serde(default)]
+        hex: Option<String>,
+        #[serde(default = "default_expect_timeout")]
+        timeout: u64,
+    },
+    /// waits for a lora event (e.g. "TX_PACKET_EVENT"), events received since the previous
+    /// expect_event step (or the scenario start) are taken into account
+    ExpectEvent {
+        event: String,
+        #[serde(default = "default_expect_timeout")]
+        timeout: u64,
+    },
+    Wait {
+        seconds: u64,
+    },
+}
+
+fn default_join_timeout() -> u64 {
+    30
+}
+
+fn default_expect_timeout() -> u64 {
+    10
+}
+
+fn default_count() -> u32 {
+    1
+}
+
+impl Step {
+    pub fn action(&self) -> &'static str {
+        return match self {
+            Step::Link => "link",
+            Step::Unlink => "unlink",
+            Step::Join { .. } => "join",
+            Step::Send { .. } => "send",
+            Step::ExpectDownlink { .. } => "expect_downlink",
+            Step::ExpectEvent { .. } => "expect_event",
+            Step::Wait { .. } => "wait",
+        };
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        match self {
+            Step::Send { text, hex, port, .. } => {
+                if text.is_some() == hex.is_some() {
+                    return Err(Error::ConfigError("send: exactly one of text or hex is required".to_string()));
+                }
+                if let Some(h) = hex {
+                    decode_hex(h).map_err(|e| Error::ConfigError(e.to_string()))?;
+                }
+                check_port(*port)?;
+            }
+            Step::ExpectDownlink { text, hex, port, .. } => {
+                if text.is_some() && hex.is_some() {
+                    return Err(Error::ConfigError("expect_downlink: text and hex are exclusive".to_string()));
+                }
+                if let Some(h) = hex {
+                    decode_hex(h).map_err(|e| Error::ConfigError(e.to_string()))?;
+                }
+                check_port(*port)?;
+            }
+            Step::ExpectEvent { event, .. } => {
+                LoraEvents::from_name(event)
+                    .ok_or_else(|| Error::ConfigError(format!("expect_event: unknown event {}", event)))?;
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+}
+
+fn check_port(port: Option<u8>) -> Result<()> {
+    if let Some(p) = port {
+        if !(1..=223).contains(&p) {
+            return Err(Error::ConfigError(format!("invalid port {}", p)));
+        }
+    }
+    return Ok(());
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Passed,
+    Failed,
+    /// not run because a previous step failed
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StepReport {
+    pub index: usize,
+    pub action: String,
+    pub status: StepStatus,
+    /// seconds
+    pub duration: f64,
+    #[serde(default)]
+    pub message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScenarioReport {
+    pub name: String,
+    pub device: String,
+    pub passed: bool,
+    /// seconds
+    pub duration: f64,
+    pub steps: Vec<StepReport>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SuiteReport {
+    pub passed: bool,
+    pub scenarios: Vec<ScenarioReport>,
+}
+
+impl ScenarioFile {
+    pub fn from_toml_str(s: &str) -> Result<ScenarioFile> {
+        let file: ScenarioFile = toml::from_str(s).map_err(|e| Error::ConfigError(e.to_string()))?;
+        file.validate()?;
+        return Ok(file);
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ScenarioFile> {
+        return ScenarioFile::from_toml_str(&fs::read_to_string(path)?);
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        for sc in &self.scenarios {
+            for step in &sc.steps {
+                step.validate()
+                    .map_err(|e| Error::ConfigError(format!("scenario {}: {}", sc.name, e)))?;
+            }
+        }
+        return Ok(());
+    }
+
+    /// runs the scenarios in order (the client handles one device at a time)
+    pub fn run(&self, config: &LwnsimConfig) -> SuiteReport {
+        let scenarios: Vec<ScenarioReport> = self.scenarios.iter().map(|sc| sc.run(config)).collect();
+        return SuiteReport {
+            passed: scenarios.iter().all(|r| r.passed),
+            scenarios,
+        };
+    }
+}
+
+impl Scenario {
+    /// connects to the scenario device and runs the steps until the first failure
+    /// a failed scenario unlinks the device so that the next one starts from a clean state
+    pub fn run(&self, config: &LwnsimConfig) -> ScenarioReport {
+        let start = Instant::now();
+        let mut report = ScenarioReport {
+            name: self.name.clone(),
+            device: self.device.clone().unwrap_or_default(),
+            passed: false,
+            duration: 0.0,
+            steps: Vec::new(),
+        };
+        info!("[SCENARIO][{}]start", self.name);
+
+        let mut runner = match self.connect(config) {
+            Ok((profile, events)) => {
+                report.device = profile.name.clone();
+                Some(StepRunner { profile, events })
+            }
+            Err(e) => {
+                warn!("[SCENARIO][{}][connect]{}", self.name, e);
+                report.steps.push(StepReport {
+                    index: 0,
+                    action: "connect".to_string(),
+                    status: StepStatus::Failed,
+                    duration: start.elapsed().as_secs_f64(),
+                    message: Some(e.to_string()),
+                });
+                None
+            }
+        };
+        for (i, step) in self.steps.iter().enumerate() {
+            let step_start = Instant::now();
+            let (status, message) = match runner.as_mut() {
+                Some(r) => match r.run_step(step) {
+                    Ok(()) => (StepStatus::Passed, None),
+                    Err(msg) => {
+                        warn!("[SCENARIO][{}][step {} {}]{}", self.name, i + 1, step.action(), msg);
+                        runner = None;
+                        (StepStatus::Failed, Some(msg))
+                    }
+                },
+                None => (StepStatus::Skipped, None),
+            };
+            report.steps.push(StepReport {
+                index: i + 1,
+                action: step.action().to_string(),
+                status,
+                duration: step_start.elapsed().as_secs_f64(),
+                message,
+            });
+        }
+
+        report.passed = report.steps.iter().all(|s| s.status == StepStatus::Passed);
+        if !report.passed {
+            let mut lora = LORA.lock().unwrap();
+            if lora.get_status() != LoraDevStatus::Inactive {
+                let _ = lora.unlink_dev();
+            }
+        }
+        report.duration = start.elapsed().as_secs_f64();
+        info!("[SCENARIO][{}]passed= {}", self.name, report.passed);
+        return report;
+    }
+
+    fn connect(&self, config: &LwnsimConfig) -> Result<(DeviceProfile, Receiver<LoraEvents>)> {
+        let profile = config.device(self.device.as_deref())?.clone();
+        config.connect(Some(&profile.name))?;
+        return Ok((profile, subscribe_lora_events()));
+    }
+}
+
+struct StepRunner {
+    profile: DeviceProfile,
+    events: Receiver<LoraEvents>,
+}
+
+impl StepRunner {
+    // returns the failure message
+    fn run_step(&mut self, step: &Step) -> std::result::Result<(), String> {
+        match step {
+            Step::Link => LORA.lock().unwrap().activate().map_err(|e| e.to_string()),
+            Step::Unlink => LORA.lock().unwrap().unlink_dev().map_err(|e| e.to_string()),
+            Step::Join { timeout } => self.join(Duration::from_secs(*timeout)),
+            Step::Send {
+                text,
+                hex,
+                confirmed,
+                port,
+                count,
+                interval,
+            } => {
+                let mut s = self.profile.socket().map_err(|e| e.to_string())?;
+                if let Some(c) = confirmed {
+                    s.setsockopt(super::socket::SOL_LORA, super::socket::SO_CONFIRMED, *c as usize);
+                }
+                if let Some(p) = port {
+                    s.bind(*p as usize).map_err(|e| e.to_string())?;
+                }
+                for n in 0..*count {
+                    if n > 0 {
+                        clock().sleep(Duration::from_secs(*interval));
+                    }
+                    let res = match (text, hex) {
+                        (Some(t), _) => s.send(t),
+                        (None, Some(h)) => s.send_bytes(&decode_hex(h).map_err(|e| e.to_string())?),
+                        (None, None) => return Err("no payload".to_string()),
+                    };
+                    res.map_err(|e| format!("frame {}: {}", n + 1, e))?;
+                }
+                Ok(())
+            }
+            Step::ExpectDownlink {
+                port,
+                text,
+                hex,
+                timeout,
+            } => self.expect_downlink(*port, text.as_deref(), hex.as_deref(), Duration::from_secs(*timeout)),
+            Step::ExpectEvent { event, timeout } => {
+                // validated with the scenario file
+                let expected = LoraEvents::from_name(event).unwrap_or_default();
+                let clock = clock();
+                let deadline = clock.now() + Duration::from_secs(*timeout);
+                loop {
+                    match self.events.try_recv() {
+                        Ok(ev) if ev.contains(expected) => return Ok(()),
+                        Ok(_) => {}
+                        Err(TryRecvError::Empty) if clock.now() < deadline => clock.sleep(POLL_PERIOD),
+                        Err(_) => return Err(format!("no {} within {} s", event, timeout)),
+                    }
+                }
+            }
+            Step::Wait { seconds } => {
+                clock().sleep(Duration::from_secs(*seconds));
+                Ok(())
+            }
+        }
+    }
+
+    fn join(&self, timeout: Duration) -> std::result::Result<(), String> {
+        {
+            let mut lora = LORA.lock().unwrap();
+            if lora.get_status() == LoraDevStatus::Inactive {
+                lora.activate().map_err(|e| e.to_string())?;
+            }
+            self.profile.join(&mut lora).map_err(|e| e.to_string())?;
+        }
+        let clock = clock();
+        let deadline = clock.now() + timeout;
+        // the lock is released between polls, the JoinAccept event handler needs it
+        while !LORA.lock().unwrap().has_joined() {
+            if clock.now() > deadline {
+                return Err(format!("no JoinAccept within {} s", timeout.as_secs()));
+            }
+            clock.sleep(POLL_PERIOD);
+        }
+        return Ok(());
+    }
+
+    fn expect_downlink(
+        &self,
+        port: Option<u8>,
+        text: Option<&str>,
+        hex: Option<&str>,
+        timeout: Duration,
+    ) -> std::result::Result<(), String> {
+        let clock = clock();
+        let deadline = clock.now() + timeout;
+        let dl = loop {
+            match LORA.lock().unwrap().recv_downlink(RECV_BUFFER_SIZE) {
+                Ok(dl) => break dl,
+                Err(Error::CmdError(CmdErrorKind::NoDataDWrecv)) if clock.now() < deadline => {}
+                Err(Error::CmdError(CmdErrorKind::NoDataDWrecv)) => {
+                    return Err(format!("no downlink within {} s", timeout.as_secs()))
+                }
+                Err(e) => return Err(e.to_string()),
+            }
+            clock.sleep(POLL_PERIOD);
+        };
+        if port.is_some() && dl.port != port {
+            return Err(format!("downlink on port {:?}, expected {:?}", dl.port, port));
+        }
+        if let Some(t) = text {
+            if dl.payload != t.as_bytes() {
+                return Err(format!("downlink payload {:?}, expected {:?}", String::from_utf8_lossy(&dl.payload), t));
+            }
+        }
+        if let Some(h) = hex {
+            let expected = decode_hex(h).map_err(|e| e.to_string())?;
+            if dl.payload != expected {
+                return Err(format!("downlink payload {:02x?}, expected {}", dl.payload, h));
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl SuiteReport {
+    pub fn failures(&self) -> usize {
+        return self.scenarios.iter().filter(|s| !s.passed).count();
+    }
+
+    /// JUnit XML report, one testsuite per scenario and one testcase per step
+    pub fn to_junit_xml(&self) -> String {
+        let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        let tests: usize = self.scenarios.iter().map(|s| s.steps.len()).sum();
+        let time: f64 = self.scenarios.iter().map(|s| s.duration).sum();
+        let _ = writeln!(
+            xml,
+            "<testsuites name=\"lwnsim\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">",
+            tests,
+            self.count_steps(StepStatus::Failed),
+            time
+        );
+        for sc in &self.scenarios {
+            let failures = sc.steps.iter().filter(|s| s.status == StepStatus::Failed).count();
+            let skipped = sc.steps.iter().filter(|s| s.status == StepStatus::Skipped).count();
+            let _ = writeln!(
+                xml,
+                "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+                xml_escape(&sc.name),
+                sc.steps.len(),
+                failures,
+                skipped,
+                sc.duration
+            );
+            for step in &sc.steps {
+                let _ = write!(
+                    xml,
+                    "    <testcase classname=\"{}\" name=\"{} {}\" time=\"{:.3}\"",
+                    xml_escape(&format!("{}.{}", sc.device, sc.name)),
+                    step.index,
+                    step.action,
+                    step.duration
+                );
+                match step.status {
+                    StepStatus::Passed => xml.push_str("/>\n"),
+                    StepStatus::Skipped => xml.push_str(">\n      <skipped/>\n    </testcase>\n"),
+                    StepStatus::Failed => {
+                        let _ = writeln!(
+                            xml,
+                            ">\n      <failure message=\"{}\"/>\n    </testcase>",
+                            xml_escape(step.message.as_deref().unwrap_or_default())
+                        );
+                    }
+                }
+            }
+            xml.push_str("  </testsuite>\n");
+        }
+        xml.push_str("</testsuites>\n");
+        return xml;
+    }
+
+    fn count_steps(&self, status: StepStatus) -> usize {
+        return self
+            .scenarios
+            .iter()
+            .flat_map(|s| &s.steps)
+            .filter(|s| s.status == status)
+            .count();
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step_report(index: usize, action: &str, status: StepStatus, message: Option<&str>) -> StepReport {
+        return StepReport {
+            index,
+            action: action.to_string(),
+            status,
+            duration: 0.5,
+            message: message.map(|m| m.to_string()),
+        };
+    }
+
+    #[test]
+    fn steps_parsed() {
+        let file = ScenarioFile::from_toml_str(
+            r#"
+            [[scenario]]
+            name = "uplinks"
+            device = "sensor-1"
+
+            [[scenario.step]]
+            action = "join"
+
+            [[scenario.step]]
+            action = "send"
+            hex = "01ff"
+            confirmed = true
+            port = 2
+            count = 3
+            interval = 10
+
+            [[scenario.step]]
+            action = "expect_downlink"
+            text = "pong"
+            timeout = 20
+
+            [[scenario.step]]
+            action = "expect_event"
+            event = "RX_PACKET_EVENT"
+
+            [[scenario.step]]
+            action = "wait"
+            seconds = 5
+
+            [[scenario.step]]
+            action = "unlink"
+
+            [[scenario]]
+            name = "empty"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.scenarios.len(), 2);
+        let sc = &file.scenarios[0];
+        assert_eq!(sc.device.as_deref(), Some("sensor-1"));
+        assert_eq!(
+            sc.steps,
+            vec![
+                Step::Join { timeout: 30 },
+                Step::Send {
+                    text: None,
+                    hex: Some("01ff".to_string()),
+                    confirmed: Some(true),
+                    port: Some(2),
+                    count: 3,
+                    interval: 10,
+                },
+                Step::ExpectDownlink {
+                    port: None,
+                    text: Some("pong".to_string()),
+                    hex: None,
+                    timeout: 20,
+                },
+                Step::ExpectEvent {
+                    event: "RX_PACKET_EVENT".to_string(),
+                    timeout: 10,
+                },
+                Step::Wait { seconds: 5 },
+                Step::Unlink,
+            ]
+        );
+        let actions: Vec<&str> = sc.steps.iter().map(|s| s.action()).collect();
+        assert_eq!(actions, ["join", "send", "expect_downlink", "expect_event", "wait", "unlink"]);
+        assert_eq!(file.scenarios[1].device, None);
+        assert!(file.scenarios[1].steps.is_empty());
+    }
+
+    #[test]
+    fn invalid_steps_are_rejected() {
+        let invalid = [
+            // unknown action
+            "action = \"reboot\"",
+            // missing field
+            "action = \"wait\"",
+            // no payload, then both payloads
+            "action = \"send\"",
+            "action = \"send\"\ntext = \"a\"\nhex = \"61\"",
+            "action = \"send\"\nhex = \"6\"",
+            "action = \"send\"\nhex = \"zz\"",
+            "action = \"send\"\ntext = \"a\"\nport = 0",
+            "action = \"send\"\ntext = \"a\"\nport = 224",
+            "action = \"expect_downlink\"\ntext = \"a\"\nhex = \"61\"",
+            "action = \"expect_downlink\"\nport = 250",
+            "action = \"expect_event\"\nevent = \"NO_SUCH_EVENT\"",
+        ];
+        for step in invalid {
+            let toml = format!("[[scenario]]\nname = \"bad\"\n\n[[scenario.step]]\n{}\n", step);
+            let res = ScenarioFile::from_toml_str(&toml);
+            assert!(matches!(res, Err(Error::ConfigError(_))), "{}: {:?}", step, res);
+        }
+        let res = ScenarioFile::from_toml_str("[[scenario]]\nname = \"bad\"\n\n[[scenario.step]]\naction = \"send\"\n");
+        match res {
+            Err(Error::ConfigError(msg)) => assert!(msg.starts_with("scenario bad: "), "{}", msg),
+            res => panic!("{:?}", res),
+        }
+    }
+
+    #[test]
+    fn junit_xml_report() {
+        let report = SuiteReport {
+            passed: false,
+            scenarios: vec![
+                ScenarioReport {
+                    name: "joins".to_string(),
+                    device: "sensor-1".to_string(),
+                    passed: true,
+                    duration: 1.25,
+                    steps: vec![step_report(1, "join", StepStatus::Passed, None)],
+                },
+                ScenarioReport {
+                    name: "<uplinks> & \"downlinks\"".to_string(),
+                    device: "sensor-'2'".to_string(),
+                    passed: false,
+                    duration: 2.0,
+                    steps: vec![
+                        step_report(1, "send", StepStatus::Passed, None),
+                        step_report(2, "expect_downlink", StepStatus::Failed, Some("payload \"a<b\" & 'c>d'")),
+                        step_report(3, "unlink", StepStatus::Skipped, None),
+                    ],
+                },
+            ],
+        };
+        assert_eq!(report.failures(), 1);
+        assert_eq!(
+            report.to_junit_xml(),
+            concat!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                "<testsuites name=\"lwnsim\" tests=\"4\" failures=\"1\" time=\"3.250\">\n",
+                "  <testsuite name=\"joins\" tests=\"1\" failures=\"0\" skipped=\"0\" time=\"1.250\">\n",
+                "    <testcase classname=\"sensor-1.joins\" name=\"1 join\" time=\"0.500\"/>\n",
+                "  </testsuite>\n",
+                "  <testsuite name=\"&lt;uplinks&gt; &amp; &quot;downlinks&quot;\" tests=\"3\" failures=\"1\" ",
+                "skipped=\"1\" time=\"2.000\">\n",
+                "    <testcase classname=\"sensor-&apos;2&apos;.&lt;uplinks&gt; &amp; &quot;downlinks&quot;\" ",
+                "name=\"1 send\" time=\"0.500\"/>\n",
+                "    <testcase classname=\"sensor-&apos;2&apos;.&lt;uplinks&gt; &amp; &quot;downlinks&quot;\" ",
+                "name=\"2 expect_downlink\" time=\"0.500\">\n",
+                "      <failure message=\"payload &quot;a&lt;b&quot; &amp; &apos;c&gt;d&apos;\"/>\n",
+                "    </testcase>\n",
+                "    <testcase classname=\"sensor-&apos;2&apos;.&lt;uplinks&gt; &amp; &quot;downlinks&quot;\" ",
+                "name=\"3 unlink\" time=\"0.500\">\n",
+                "      <skipped/>\n",
+                "    </testcase>\n",
+                "  </testsuite>\n",
+                "</testsuites>\n",
+            )
+        );
+    }
+}
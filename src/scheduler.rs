@@ -0,0 +1,305 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_derive::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use super::clock::clock;
+use super::downlink::Downlink;
+use super::error::{Error, Result};
+use super::lora_dev::LORA;
+use super::lora_events::{LoraEvents, LORA_EVENTS};
+use super::lwnsim_cmd::CmdErrorKind;
+use super::shutdown::{handle_signals, DEFAULT_SIGNAL_DEADLINE};
+use super::socket::{Socket, SOL_LORA, SO_CONFIRMED};
+
+use log::{debug, info, warn};
+
+// default TX timeout of the scheduled uplinks, seconds
+pub static DEFAULT_TX_TIMEOUT: usize = 10;
+// the downlinks of a class A device are received in the RX windows, checked this long after each uplink
+static RX_WINDOWS_WAIT: Duration = Duration::from_secs(5);
+static RX_POLL_PERIOD: Duration = Duration::from_millis(500);
+// buffersize of the downlinks delivered to the handler
+static DOWNLINK_BUFFER_SIZE: usize = 256;
+
+type DownlinkHandler = Box<dyn FnMut(&Downlink)>;
+
+/// random variation of the interval between two uplinks
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Jitter {
+    #[default]
+    None,
+    /// uniform in interval ± duration
+    Uniform(Duration),
+    /// uniform in interval ± ratio * interval (0.0..=1.0)
+    Ratio(f64),
+}
+
+/// retries of a failed uplink, the delay doubles from delay_min up to delay_max
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    pub delay_min: Duration,
+    pub delay_max: Duration,
+    /// the payload is dropped after max_retries failed retries, the next one is sent at the next interval
+    pub max_retries: u32,
+}
+
+impl RetryPolicy {
+    /// failed uplinks are dropped at once
+    pub fn disabled() -> RetryPolicy {
+        RetryPolicy {
+            max_retries: 0,
+            ..RetryPolicy::default()
+        }
+    }
+
+    // delay before the retry number `retry` (1 for the first one)
+    fn delay(&self, retry: u32) -> Duration {
+        let factor = 1u32.checked_shl(retry.saturating_sub(1)).unwrap_or(u32::MAX);
+        return self.delay_min.saturating_mul(factor).min(self.delay_max);
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            delay_min: Duration::from_secs(5),
+            delay_max: Duration::from_secs(300),
+            max_retries: 3,
+        }
+    }
+}
+
+/// counters of a scheduler run
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SchedulerStats {
+    pub uplinks: u64,
+    pub failed: u64,
+    pub retries: u64,
+    /// payloads given up after max_retries
+    pub dropped: u64,
+    pub downlinks: u64,
+}
+
+/// sends the payloads of a provider every interval (with jitter) through a blocking socket
+/// and delivers the downlinks received in between to a handler
+/// an uplink fails (and is retried) on a command error, a TX timeout or a TX_FAILED_EVENT
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+/// use std::time::Duration;
+///
+/// let mut counter = 0u16;
+/// let stats = UplinkScheduler::new(Socket::new(AF_LORA, SOCK_RAW), Duration::from_secs(60), move || {
+///     counter += 1;
+///     counter.to_be_bytes().to_vec()
+/// })
+/// .jitter(Jitter::Ratio(0.1))
+/// .confirmed(true)
+/// .port(2)
+/// .on_downlink(|dl| println!("{:?}", dl.payload))
+/// .run_until_ctrlc()
+/// .unwrap();
+/// println!("{:?}", stats);
+/// ```
+pub struct UplinkScheduler<P> {
+    socket: Socket,
+    interval: Duration,
+    payload: P,
+    jitter: Jitter,
+    retry: RetryPolicy,
+    tx_timeout: usize,
+    count: Option<u64>,
+    downlink_poll: Option<Duration>,
+    on_downlink: Option<DownlinkHandler>,
+    rng: StdRng,
+    // invalid setting, returned by run()
+    error: Option<Error>,
+}
+
+impl<P: FnMut() -> Vec<u8>> UplinkScheduler<P> {
+    pub fn new(socket: Socket, interval: Duration, payload: P) -> UplinkScheduler<P> {
+        UplinkScheduler {
+            socket,
+            interval,
+            payload,
+            jitter: Jitter::None,
+            retry: RetryPolicy::default(),
+            tx_timeout: DEFAULT_TX_TIMEOUT,
+            count: None,
+            downlink_poll: None,
+            on_downlink: None,
+            rng: StdRng::from_entropy(),
+            error: None,
+        }
+    }
+
+    pub fn jitter(mut self, jitter: Jitter) -> UplinkScheduler<P> {
+        self.jitter = jitter;
+        self
+    }
+
+    /// seed of the jitter, for reproducible schedules
+    pub fn seed(mut self, seed: u64) -> UplinkScheduler<P> {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    pub fn confirmed(mut self, confirmed: bool) -> UplinkScheduler<P> {
+        self.socket.setsockopt(SOL_LORA, SO_CONFIRMED, confirmed as usize);
+        self
+    }
+
+    /// FPort of the uplinks, see Socket::bind(), run() fails if it is invalid
+    pub fn port(mut self, port: u8) -> UplinkScheduler<P> {
+        if let Err(e) = self.socket.bind(port as usize) {
+            self.error = Some(e);
+        }
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> UplinkScheduler<P> {
+        self.retry = retry;
+        self
+    }
+
+    /// seconds to wait for the TX event of an uplink
+    pub fn tx_timeout(mut self, tx_timeout: usize) -> UplinkScheduler<P> {
+        self.tx_timeout = tx_timeout;
+        self
+    }
+
+    /// stops after count uplinks sent (failed ones are not counted)
+    pub fn count(mut self, count: u64) -> UplinkScheduler<P> {
+        self.count = Some(count);
+        self
+    }
+
+    /// polls the downlinks all along the intervals (class C devices)
+    /// by default they are only checked in the seconds following each uplink (class A devices)
+    pub fn downlink_poll(mut self, period: Duration) -> UplinkScheduler<P> {
+        self.downlink_poll = Some(period);
+        self
+    }
+
+    pub fn on_downlink<H: FnMut(&Downlink) + 'static>(mut self, handler: H) -> UplinkScheduler<P> {
+        self.on_downlink = Some(Box::new(handler));
+        self
+    }
+
+    pub fn socket_mut(&mut self) -> &mut Socket {
+        return &mut self.socket;
+    }
+
+    /// sends the payloads until stop is set (or count uplinks are sent), the first one at once
+    pub fn run(&mut self, stop: &AtomicBool) -> Result<SchedulerStats> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.socket.setblocking(true);
+        self.socket.settimeout(Some(self.tx_timeout));
+        let mut stats = SchedulerStats::default();
+        let mut pending: Option<Vec<u8>> = None;
+        let mut retries = 0;
+        info!("[SCHEDULER][run]interval= {:?} jitter= {:?}", self.interval, self.jitter);
+
+        while !stop.load(Ordering::Relaxed) {
+            let data = pending.take().unwrap_or_else(|| (self.payload)());
+            let delay = match self.socket.send_bytes(&data) {
+                Ok(()) => {
+                    stats.uplinks += 1;
+                    retries = 0;
+                    debug!("[SCHEDULER][send]{} bytes", data.len());
+                    self.next_interval()
+                }
+                Err(e) if retries < self.retry.max_retries => {
+                    stats.failed += 1;
+                    stats.retries += 1;
+                    retries += 1;
+                    warn!("[SCHEDULER][send]{}, retry {}/{}", e, retries, self.retry.max_retries);
+                    pending = Some(data);
+                    self.retry.delay(retries)
+                }
+                Err(e) => {
+                    stats.failed += 1;
+                    stats.dropped += 1;
+                    retries = 0;
+                    warn!("[SCHEDULER][send]{}, payload dropped", e);
+                    self.next_interval()
+                }
+            };
+            if self.count.is_some_and(|c| stats.uplinks >= c) {
+                self.wait(RX_WINDOWS_WAIT, stop, &mut stats);
+                break;
+            }
+            if !self.wait(delay, stop, &mut stats) {
+                break;
+            }
+        }
+        info!("[SCHEDULER][stop]{:?}", stats);
+        return Ok(stats);
+    }
+
+    /// runs until Ctrl-C or SIGTERM (or count uplinks are sent), see handle_signals()
+    pub fn run_until_ctrlc(&mut self) -> Result<SchedulerStats> {
+        return self.run(handle_signals(DEFAULT_SIGNAL_DEADLINE));
+    }
+
+    fn next_interval(&mut self) -> Duration {
+        let jitter = match self.jitter {
+            Jitter::None => return self.interval,
+            Jitter::Uniform(d) => d.min(self.interval),
+            Jitter::Ratio(r) => self.interval.mul_f64(r.clamp(0.0, 1.0)),
+        };
+        let secs = self.interval.as_secs_f64() + self.rng.gen_range(-1.0..=1.0) * jitter.as_secs_f64();
+        return Duration::from_secs_f64(secs.max(0.0));
+    }
+
+    // waits for delay while delivering the downlinks, returns false if stopped
+    fn wait(&mut self, delay: Duration, stop: &AtomicBool, stats: &mut SchedulerStats) -> bool {
+        let clock = clock();
+        let deadline = clock.now() + delay;
+        let rx_end = clock.now() + RX_WINDOWS_WAIT;
+        loop {
+            self.deliver_downlinks(stats);
+            let now = clock.now();
+            let remaining = deadline.saturating_duration_since(now);
+            if remaining.is_zero() {
+                return true;
+            }
+            let step = if now < rx_end {
+                RX_POLL_PERIOD
+            } else {
+                self.downlink_poll.unwrap_or(remaining)
+            };
+            if !clock.sleep_or_stop(step.min(remaining), stop) {
+                return false;
+            }
+        }
+    }
+
+    fn deliver_downlinks(&mut self, stats: &mut SchedulerStats) {
+        if !LORA_EVENTS.lock().unwrap().contains_and_remove_event(LoraEvents::RX_PACKET_EVENT) {
+            return;
+        }
+        loop {
+            // the device is unlocked before calling the handler, it may send an uplink
+            let res = LORA.lock().unwrap().recv_downlink(DOWNLINK_BUFFER_SIZE);
+            match res {
+                Ok(dl) => {
+                    stats.downlinks += 1;
+                    debug!("[SCHEDULER][downlink]{:?}", dl);
+                    if let Some(handler) = self.on_downlink.as_mut() {
+                        handler(&dl);
+                    }
+                }
+                Err(Error::CmdError(CmdErrorKind::NoDataDWrecv)) => return,
+                Err(e) => {
+                    warn!("[SCHEDULER][downlink]{}", e);
+                    return;
+                }
+            }
+        }
+    }
+}
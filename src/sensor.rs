@@ -0,0 +1,534 @@
+use chrono::{DateTime, Timelike, Utc};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde_derive::*;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use super::clock::clock;
+use super::codec::Codec;
+use super::error::{Error, Result};
+use super::lpp::{LppRecord, LppValue};
+use super::socket::Socket;
+
+use log::{debug, warn};
+
+// mean earth radius in m, for the GPS track distances
+static EARTH_RADIUS: f64 = 6_371_000.0;
+// m per degree of latitude
+static METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// source of the readings of a sensor
+pub trait SensorModel: Send {
+    /// reading at time now, the random values are drawn from rng, the sensor own generator
+    fn read(&mut self, now: DateTime<Utc>, rng: &mut StdRng) -> LppValue;
+}
+
+/// value following a daily cycle: mean + amplitude * cos(2π (hour - peak_hour) / 24) + gaussian noise
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DiurnalModel {
+    pub mean: f64,
+    pub amplitude: f64,
+    /// UTC hour of the maximum
+    #[serde(default)]
+    pub peak_hour: f64,
+    /// standard deviation of the noise
+    #[serde(default)]
+    pub noise: f64,
+}
+
+impl DiurnalModel {
+    pub fn value(&self, now: DateTime<Utc>, rng: &mut StdRng) -> f64 {
+        let hour = now.num_seconds_from_midnight() as f64 / 3600.0;
+        let cycle = (2.0 * PI * (hour - self.peak_hour) / 24.0).cos();
+        return self.mean + self.amplitude * cycle + self.noise * gaussian(rng);
+    }
+}
+
+/// position moving at constant speed along a path of [latitude, longitude, altitude] points
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GpsTrack {
+    pub path: Vec<[f64; 3]>,
+    /// m/s
+    pub speed: f64,
+    /// back to the first point at the end of the path, stays at the last point otherwise
+    #[serde(default, rename = "loop")]
+    pub looped: bool,
+    /// standard deviation of the position error in m
+    #[serde(default)]
+    pub noise: f64,
+    #[serde(skip)]
+    start: Option<DateTime<Utc>>,
+}
+
+impl GpsTrack {
+    pub fn new(path: Vec<[f64; 3]>, speed: f64, looped: bool) -> GpsTrack {
+        GpsTrack {
+            path,
+            speed,
+            looped,
+            noise: 0.0,
+            start: None,
+        }
+    }
+
+    /// position after travelling distance m from the first point
+    pub fn position(&self, distance: f64) -> [f64; 3] {
+        let mut points = self.path.clone();
+        if self.looped && points.len() > 1 {
+            points.push(points[0]);
+        }
+        let total: f64 = points.windows(2).map(|w| haversine(&w[0], &w[1])).sum();
+        let mut remaining = if self.looped && total > 0.0 { distance % total } else { distance };
+        for w in points.windows(2) {
+            let len = haversine(&w[0], &w[1]);
+            if remaining <= len && len > 0.0 {
+                let f = remaining / len;
+                return [0, 1, 2].map(|i| w[0][i] + f * (w[1][i] - w[0][i]));
+            }
+            remaining -= len;
+        }
+        return points.last().copied().unwrap_or_default();
+    }
+}
+
+impl SensorModel for GpsTrack {
+    fn read(&mut self, now: DateTime<Utc>, rng: &mut StdRng) -> LppValue {
+        let start = *self.start.get_or_insert(now);
+        let elapsed = (now - start).num_milliseconds().max(0) as f64 / 1000.0;
+        let [lat, lon, alt] = self.position(self.speed * elapsed);
+        let d_lat = self.noise * gaussian(rng) / METERS_PER_DEGREE;
+        let d_lon = self.noise * gaussian(rng) / (METERS_PER_DEGREE * lat.to_radians().cos().max(0.01));
+        return LppValue::Gps {
+            latitude: lat + d_lat,
+            longitude: lon + d_lon,
+            altitude: alt,
+        };
+    }
+}
+
+/// battery voltage, discharged linearly from level % to 0 % in life_hours
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BatteryModel {
+    pub full_voltage: f64,
+    pub empty_voltage: f64,
+    pub life_hours: f64,
+    /// % at the first reading
+    #[serde(default = "default_battery_level")]
+    pub level: f64,
+    #[serde(skip)]
+    start: Option<DateTime<Utc>>,
+}
+
+fn default_battery_level() -> f64 {
+    100.0
+}
+
+impl BatteryModel {
+    pub fn new(full_voltage: f64, empty_voltage: f64, life_hours: f64) -> BatteryModel {
+        BatteryModel {
+            full_voltage,
+            empty_voltage,
+            life_hours,
+            level: default_battery_level(),
+            start: None,
+        }
+    }
+
+    /// % at time now
+    pub fn level_at(&self, now: DateTime<Utc>) -> f64 {
+        let hours = match self.start {
+            Some(start) => (now - start).num_seconds().max(0) as f64 / 3600.0,
+            None => 0.0,
+        };
+        return (self.level - 100.0 * hours / self.life_hours).clamp(0.0, 100.0);
+    }
+}
+
+impl SensorModel for BatteryModel {
+    fn read(&mut self, now: DateTime<Utc>, _: &mut StdRng) -> LppValue {
+        self.start.get_or_insert(now);
+        let level = self.level_at(now);
+        let voltage = self.empty_voltage + (self.full_voltage - self.empty_voltage) * level / 100.0;
+        return LppValue::Voltage { value: voltage as f32 };
+    }
+}
+
+/// number of events since the first reading, the events occur at random (Poisson process)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CounterModel {
+    /// mean number of events per hour
+    pub rate: f64,
+    #[serde(skip)]
+    count: u32,
+    #[serde(skip)]
+    last: Option<DateTime<Utc>>,
+}
+
+impl CounterModel {
+    pub fn new(rate: f64) -> CounterModel {
+        CounterModel {
+            rate,
+            count: 0,
+            last: None,
+        }
+    }
+}
+
+impl SensorModel for CounterModel {
+    fn read(&mut self, now: DateTime<Utc>, rng: &mut StdRng) -> LppValue {
+        if let Some(last) = self.last {
+            let hours = (now - last).num_milliseconds().max(0) as f64 / 3_600_000.0;
+            self.count = self.count.saturating_add(poisson(rng, self.rate * hours));
+        }
+        self.last = Some(now);
+        return LppValue::GenericSensor { value: self.count };
+    }
+}
+
+/// built-in sensor models, as set in a device profile
+///
+/// ```toml
+/// [[device.sensor]]
+/// channel = 1
+/// type = "temperature"
+/// mean = 18.0
+/// amplitude = 6.0
+/// peak_hour = 15.0
+/// noise = 0.2
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SensorConfig {
+    /// °C
+    Temperature(DiurnalModel),
+    /// %RH, clamped to 0..=100
+    Humidity(DiurnalModel),
+    Gps(GpsTrack),
+    Battery(BatteryModel),
+    Counter(CounterModel),
+}
+
+impl SensorModel for SensorConfig {
+    fn read(&mut self, now: DateTime<Utc>, rng: &mut StdRng) -> LppValue {
+        return match self {
+            SensorConfig::Temperature(m) => LppValue::Temperature { value: m.value(now, rng) as f32 },
+            SensorConfig::Humidity(m) => LppValue::Humidity { value: m.value(now, rng).clamp(0.0, 100.0) as f32 },
+            SensorConfig::Gps(m) => m.read(now, rng),
+            SensorConfig::Battery(m) => m.read(now, rng),
+            SensorConfig::Counter(m) => m.read(now, rng),
+        };
+    }
+}
+
+impl SensorConfig {
+    /// reason why the settings are invalid, if they are
+    pub fn invalid(&self) -> Option<&'static str> {
+        return match self {
+            SensorConfig::Gps(m) if m.path.is_empty() => Some("empty GPS path"),
+            SensorConfig::Gps(m) if m.speed < 0.0 => Some("negative GPS speed"),
+            SensorConfig::Battery(m) if m.life_hours <= 0.0 => Some("battery life must be positive"),
+            SensorConfig::Counter(m) if m.rate < 0.0 => Some("negative counter rate"),
+            _ => None,
+        };
+    }
+}
+
+/// sensor of a device profile, on an LPP channel
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SensorEntry {
+    pub channel: u8,
+    #[serde(flatten)]
+    pub model: SensorConfig,
+}
+
+struct BoardSensor {
+    channel: u8,
+    model: Box<dyn SensorModel>,
+    rng: StdRng,
+}
+
+/// sensors of a device, read together
+///
+/// Each sensor draws from its own generator, seeded from the board seed and its channel,
+/// so that the readings of a sensor do not depend on the other sensors.
+///
+/// ```
+/// use lwnsim_api_rs::*;
+/// use chrono::{TimeZone, Utc};
+///
+/// let board = || {
+///     SensorBoard::new(Some(42))
+///         .add(1, SensorConfig::Temperature(DiurnalModel { mean: 18.0, amplitude: 6.0, peak_hour: 15.0, noise: 0.5 }))
+///         .add(2, CounterModel::new(12.0))
+/// };
+/// let now = Utc.with_ymd_and_hms(2023, 3, 20, 15, 0, 0).unwrap();
+/// assert_eq!(board().read(now), board().read(now));
+/// ```
+pub struct SensorBoard {
+    seed: Option<u64>,
+    sensors: Vec<BoardSensor>,
+}
+
+impl SensorBoard {
+    /// random readings if seed is None
+    pub fn new(seed: Option<u64>) -> SensorBoard {
+        SensorBoard {
+            seed,
+            sensors: Vec::new(),
+        }
+    }
+
+    pub fn from_entries(seed: Option<u64>, entries: &[SensorEntry]) -> SensorBoard {
+        return entries
+            .iter()
+            .fold(SensorBoard::new(seed), |board, e| board.add(e.channel, e.model.clone()));
+    }
+
+    pub fn add<M: SensorModel + 'static>(mut self, channel: u8, model: M) -> SensorBoard {
+        let rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed ^ (u64::from(channel) << 56)),
+            None => StdRng::from_entropy(),
+        };
+        self.sensors.push(BoardSensor {
+            channel,
+            model: Box::new(model),
+            rng,
+        });
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.sensors.is_empty();
+    }
+
+    /// readings of all the sensors at time now
+    pub fn read(&mut self, now: DateTime<Utc>) -> Vec<LppRecord> {
+        return self
+            .sensors
+            .iter_mut()
+            .map(|s| LppRecord {
+                channel: s.channel,
+                value: s.model.read(now, &mut s.rng),
+            })
+            .collect();
+    }
+}
+
+/// periodically reads a sensor board and sends the encoded readings through a socket
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+/// use std::sync::atomic::AtomicBool;
+/// use std::time::Duration;
+///
+/// let board = SensorBoard::new(Some(1)).add(1, BatteryModel::new(4.2, 3.3, 24.0 * 365.0));
+/// let mut driver = SensorDriver::new(board, Socket::new(AF_LORA, SOCK_RAW), LppCodec, Duration::from_secs(60));
+/// driver.run(&AtomicBool::new(false), |readings| println!("{:?}", readings)).unwrap();
+/// ```
+pub struct SensorDriver<C> {
+    board: SensorBoard,
+    socket: Socket,
+    codec: C,
+    interval: Duration,
+}
+
+impl<C: Codec<Vec<LppRecord>>> SensorDriver<C> {
+    pub fn new(board: SensorBoard, socket: Socket, codec: C, interval: Duration) -> SensorDriver<C> {
+        SensorDriver {
+            board,
+            socket,
+            codec,
+            interval,
+        }
+    }
+
+    pub fn socket_mut(&mut self) -> &mut Socket {
+        return &mut self.socket;
+    }
+
+    /// reads the sensors at the current time of the crate clock and sends the readings, returns them
+    pub fn send_reading(&mut self) -> Result<Vec<LppRecord>> {
+        let readings = self.board.read(clock().utc_now());
+        let bytes = self.codec.encode(&readings)?;
+        debug!("[SENSOR][send]{} readings, {} bytes", readings.len(), bytes.len());
+        self.socket.send_bytes(&bytes)?;
+        return Ok(readings);
+    }
+
+    /// sends a reading every interval until stop is set, on_send is called with each reading sent
+    /// a failed send is logged and retried at the next interval
+    pub fn run<F: FnMut(&[LppRecord])>(&mut self, stop: &AtomicBool, mut on_send: F) -> Result<()> {
+        while !stop.load(Ordering::Relaxed) {
+            match self.send_reading() {
+                Ok(readings) => on_send(&readings),
+                Err(e @ (Error::PayloadError(_) | Error::InvalidJson(_))) => return Err(e),
+                Err(e) => warn!("[SENSOR][send]{}", e),
+            }
+            clock().sleep_or_stop(self.interval, stop);
+        }
+        return Ok(());
+    }
+}
+
+// standard normal value (Box-Muller)
+fn gaussian(rng: &mut StdRng) -> f64 {
+    let u1: f64 = rng.gen_range(f64::EPSILON..1.0);
+    let u2: f64 = rng.gen();
+    return (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+}
+
+// Poisson distributed value of mean lambda (Knuth, normal approximation for large means)
+fn poisson(rng: &mut StdRng, lambda: f64) -> u32 {
+    if lambda <= 0.0 {
+        return 0;
+    }
+    if lambda > 30.0 {
+        return (lambda + lambda.sqrt() * gaussian(rng)).round().max(0.0) as u32;
+    }
+    let limit = (-lambda).exp();
+    let mut k = 0;
+    let mut p: f64 = rng.gen();
+    while p > limit {
+        k += 1;
+        p *= rng.gen::<f64>();
+    }
+    return k;
+}
+
+// great-circle distance in m between two [latitude, longitude, altitude] points
+fn haversine(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    let (lat1, lat2) = (a[0].to_radians(), b[0].to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (b[1] - a[1]).to_radians();
+    let h = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    return 2.0 * EARTH_RADIUS * h.sqrt().asin();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        return Utc.with_ymd_and_hms(2023, 3, 20, hour, 0, 0).unwrap();
+    }
+
+    fn rng() -> StdRng {
+        return StdRng::seed_from_u64(1);
+    }
+
+    #[test]
+    fn diurnal_cycle() {
+        let model = DiurnalModel { mean: 18.0, amplitude: 6.0, peak_hour: 15.0, noise: 0.0 };
+        assert!((model.value(at(15), &mut rng()) - 24.0).abs() < 1e-9);
+        assert!((model.value(at(3), &mut rng()) - 12.0).abs() < 1e-9);
+        assert!((model.value(at(9), &mut rng()) - 18.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn gps_track_position() {
+        // 1 degree of longitude on the equator
+        let track = GpsTrack::new(vec![[0.0, 0.0, 0.0], [0.0, 1.0, 100.0]], 10.0, false);
+        let len = haversine(&track.path[0], &track.path[1]);
+        assert!((len - 111_195.0).abs() < 1.0, "{}", len);
+        let mid = track.position(len / 2.0);
+        assert!((mid[1] - 0.5).abs() < 1e-9 && (mid[2] - 50.0).abs() < 1e-6, "{:?}", mid);
+        assert_eq!(track.position(2.0 * len), [0.0, 1.0, 100.0]);
+
+        // back to the first point
+        let looped = GpsTrack::new(track.path.clone(), 10.0, true);
+        let back = looped.position(1.5 * len);
+        assert!((back[1] - 0.5).abs() < 1e-9, "{:?}", back);
+        assert!(looped.position(2.0 * len)[1].abs() < 1e-9);
+
+        // moving at speed m/s from the first reading
+        let mut track = GpsTrack::new(vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]], METERS_PER_DEGREE / 3600.0, false);
+        assert_eq!(track.read(at(1), &mut rng()), LppValue::Gps { latitude: 0.0, longitude: 0.0, altitude: 0.0 });
+        match track.read(at(2), &mut rng()) {
+            LppValue::Gps { latitude, .. } => assert!((latitude - 1.0).abs() < 0.01, "{}", latitude),
+            v => panic!("{:?}", v),
+        }
+    }
+
+    #[test]
+    fn battery_discharge() {
+        let mut battery = BatteryModel::new(4.2, 3.2, 10.0);
+        assert_eq!(battery.read(at(0), &mut rng()), LppValue::Voltage { value: 4.2 });
+        assert!((battery.level_at(at(5)) - 50.0).abs() < 1e-9);
+        assert_eq!(battery.read(at(5), &mut rng()), LppValue::Voltage { value: 3.7 });
+        assert_eq!(battery.read(at(20), &mut rng()), LppValue::Voltage { value: 3.2 });
+    }
+
+    #[test]
+    fn counter_rate() {
+        let mut counter = CounterModel::new(12.0);
+        let mut rng = rng();
+        let start = at(0);
+        let mut last = 0;
+        for h in 0..=1000 {
+            match counter.read(start + chrono::Duration::hours(h), &mut rng) {
+                LppValue::GenericSensor { value } => {
+                    assert!(value >= last);
+                    last = value;
+                }
+                v => panic!("{:?}", v),
+            }
+        }
+        assert!((11_400..=12_600).contains(&last), "{}", last);
+        assert_eq!(poisson(&mut rng, 0.0), 0);
+    }
+
+    #[test]
+    fn sensor_entries_parsed() {
+        let toml = r#"
+            [[sensor]]
+            channel = 1
+            type = "temperature"
+            mean = 18.0
+            amplitude = 6.0
+
+            [[sensor]]
+            channel = 2
+            type = "gps"
+            path = [[45.0, 5.0, 200.0], [45.1, 5.1, 250.0]]
+            speed = 1.5
+            loop = true
+
+            [[sensor]]
+            channel = 3
+            type = "battery"
+            full_voltage = 4.2
+            empty_voltage = 3.3
+            life_hours = 0.0
+        "#;
+        #[derive(Deserialize)]
+        struct Entries {
+            sensor: Vec<SensorEntry>,
+        }
+        let entries = toml::from_str::<Entries>(toml).unwrap().sensor;
+        assert_eq!(
+            entries[0].model,
+            SensorConfig::Temperature(DiurnalModel { mean: 18.0, amplitude: 6.0, peak_hour: 0.0, noise: 0.0 })
+        );
+        assert!(matches!(&entries[1].model, SensorConfig::Gps(m) if m.looped && m.path.len() == 2));
+        assert_eq!(entries[1].model.invalid(), None);
+        assert_eq!(entries[2].model.invalid(), Some("battery life must be positive"));
+        assert!(toml::from_str::<Entries>("[[sensor]]\nchannel = 1\ntype = \"sonar\"").is_err());
+    }
+
+    #[test]
+    fn board_readings_are_seeded_per_channel() {
+        let temperature = SensorConfig::Temperature(DiurnalModel { mean: 18.0, amplitude: 6.0, peak_hour: 15.0, noise: 1.0 });
+        let mut one = SensorBoard::new(Some(7)).add(1, temperature.clone());
+        let mut two = SensorBoard::new(Some(7)).add(2, CounterModel::new(5.0)).add(1, temperature.clone());
+        let mut other_seed = SensorBoard::new(Some(8)).add(1, temperature);
+        for h in 0..24 {
+            let reading = one.read(at(h));
+            assert_eq!(reading.len(), 1);
+            assert_eq!(two.read(at(h))[1], reading[0]);
+            assert_ne!(other_seed.read(at(h)), reading);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::channel;
+use std::sync::{Mutex, Once, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use super::error::Result;
+use super::lora_dev::{LoraDev, LoraDevStatus, LORA};
+use super::lwnsim::{CmdOptions, LWNSIM};
+
+use log::{info, warn};
+
+/// time given to the unlink command when a session is dropped
+pub static DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+/// deadline of the shutdown after a signal, see handle_signals()
+pub static DEFAULT_SIGNAL_DEADLINE: Duration = Duration::from_secs(30);
+// exit code of a process stopped by a signal handled by handle_signals() (128 + SIGINT)
+static SIGNAL_EXIT_CODE: i32 = 130;
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static SIGNAL_HANDLER: Once = Once::new();
+
+/// unlinks the device (if linked) and disconnects from the simulator
+/// each command waits at most timeout, the client is disconnected even if the unlink fails
+pub fn shutdown(timeout: Duration) -> Result<()> {
+    let opts = CmdOptions::new().cmd_timeout(timeout).ack_timeout(timeout);
+    // a panicking thread must not prevent the device from being unlinked
+    let res = {
+        let mut lora = LORA.lock().unwrap_or_else(PoisonError::into_inner);
+        if lora.get_status() != LoraDevStatus::Inactive {
+            lora.unlink_dev_with_options(&opts)
+        } else {
+            Ok(())
+        }
+    };
+    if let Err(e) = &res {
+        warn!("[SHUTDOWN][unlink]{}", e);
+    }
+    LWNSIM.lock().unwrap_or_else(PoisonError::into_inner).disconnect();
+    info!("[SHUTDOWN]done");
+    return res;
+}
+
+/// set once a shutdown has been requested by a signal (see handle_signals()) or request_shutdown()
+/// to be used as the stop flag of the long running loops (UplinkScheduler::run(), SensorDriver::run()...)
+pub fn shutdown_flag() -> &'static AtomicBool {
+    return &SHUTDOWN;
+}
+
+pub fn request_shutdown() {
+    SHUTDOWN.store(true, Ordering::Relaxed);
+}
+
+/// opt-in handling of Ctrl-C (SIGINT) and SIGTERM, returns the flag set when a signal is received
+///
+/// on the first signal, the flag is set so that the application stops its loops and drops its LwnsimSession;
+/// if the process is still running after half the deadline, the device is unlinked and the client
+/// disconnected (within the rest of the deadline) and the process exits, a second signal exits at once
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+/// use std::time::Duration;
+///
+/// let stop = handle_signals(Duration::from_secs(10));
+/// let session = LwnsimBuilder::new("http://localhost:8000", "359ac7cd01bc8aff").session().unwrap();
+/// // ... link, join
+/// UplinkScheduler::new(Socket::new(AF_LORA, SOCK_RAW), Duration::from_secs(60), || b"Hello".to_vec())
+///     .run(stop)
+///     .unwrap();
+/// // dropping the session unlinks the device and disconnects
+/// ```
+pub fn handle_signals(deadline: Duration) -> &'static AtomicBool {
+    SIGNAL_HANDLER.call_once(|| {
+        let handler = move || {
+            if SHUTDOWN.swap(true, Ordering::Relaxed) {
+                warn!("[SHUTDOWN][signal]second signal, exiting");
+                process::exit(SIGNAL_EXIT_CODE);
+            }
+            info!("[SHUTDOWN][signal]stopping within {:?}", deadline);
+            // the handler returns at once so that a second signal is handled
+            thread::spawn(move || {
+                thread::sleep(deadline / 2);
+                warn!("[SHUTDOWN][signal]still running, forcing the shutdown");
+                let (done, wait_done) = channel();
+                thread::spawn(move || {
+                    let _ = shutdown(deadline / 2);
+                    let _ = done.send(());
+                });
+                let _ = wait_done.recv_timeout(deadline / 2);
+                process::exit(SIGNAL_EXIT_CODE);
+            });
+        };
+        if let Err(e) = ctrlc::set_handler(handler) {
+            warn!("[SHUTDOWN][signal]{}", e);
+        }
+    });
+    return &SHUTDOWN;
+}
+
+/// connection to the simulator and its device: the device is unlinked and the client disconnected
+/// when the session is closed or dropped (also when unwinding from a panic)
+///
+/// ```no_run
+/// use lwnsim_api_rs::*;
+///
+/// let session = LwnsimBuilder::new("http://localhost:8000", "359ac7cd01bc8aff").session().unwrap();
+/// session.lora().lock().unwrap().activate().unwrap();
+/// // ...
+/// session.close().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct LwnsimSession {
+    timeout: Duration,
+    closed: bool,
+}
+
+impl LwnsimSession {
+    pub(crate) fn new() -> LwnsimSession {
+        LwnsimSession {
+            timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            closed: false,
+        }
+    }
+
+    /// time given to each command of the shutdown
+    pub fn set_shutdown_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// the device of the session
+    pub fn lora(&self) -> &'static Mutex<LoraDev> {
+        return &LORA;
+    }
+
+    /// unlinks the device and disconnects, returns the unlink error if any
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        return shutdown(self.timeout);
+    }
+}
+
+impl Drop for LwnsimSession {
+    fn drop(&mut self) {
+        if !self.closed {
+            // errors are logged by shutdown()
+            let _ = shutdown(self.timeout);
+        }
+    }
+}
@@ -1,13 +1,16 @@
 #![allow(unused)]
 
+use super::clock::clock;
 use super::lora_dev::*;
+use super::lwnsim::CmdOptions;
 use super::lora_events::{LORA_EVENTS,LoraEvents};
 use super::lwnsim_cmd::CmdErrorKind;
 use super::error::{Result,Error};
+use super::lpp::{CayenneLpp, LppRecord};
+use super::downlink::Downlink;
 // log
 use log::{debug, info, trace, warn};
-use std::thread;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
 pub static AF_LORA: usize = 1;
 pub static SOCK_RAW: usize = 1;
@@ -17,6 +20,9 @@ pub static SOL_LORA: usize = 1;
 pub static SO_DR: usize = 1;
 pub static SO_CONFIRMED: usize = 2;
 
+// period at which a blocking socket checks the lora events, in crate clock time
+static EVENT_POLL_PERIOD: Duration = Duration::from_secs(1);
+
 pub static UNCONFIRMED_DATA_UP: &str = "UnconfirmedDataUp";
 pub static CONFIRMED_DATA_UP: &str = "ConfirmedDataUp";
 
@@ -37,6 +43,8 @@ pub struct Socket {
     blocking: bool,
     timeout: Option<usize>,
     dr: usize,
+    cmd_options: CmdOptions,
+    port: Option<u8>,
 }
 
 
@@ -49,6 +57,8 @@ impl Socket {
             blocking: false,
             timeout: None,
             dr: 0,
+            cmd_options: CmdOptions::default(),
+            port: None,
         }
 
         // self.stack.callback(trigger=(LoRa.LoraEvents::TX_PACKET_EVENT | LoRa.LoraEvents::TX_FAILED_EVENT), handler=self.set_blocking_send_status, arg=())
@@ -58,10 +68,33 @@ impl Socket {
         assert_eq!(level, SOL_LORA);
 
         if optname == SO_CONFIRMED {
-            self.confirmed = true;
-        } else if value == SO_DR {
-            self.dr = value;
+            self.confirmed = value != 0;
+        } else if optname == SO_DR {
+            // forwarded to the simulated device, as with a Pycom lora socket
+            match u8::try_from(value) {
+                Ok(dr) => match LORA.lock().unwrap().set_dr_tx_power_with_options(dr, None, &self.cmd_options) {
+                    Ok(()) => self.dr = value,
+                    Err(e) => warn!("[SOCKET][setsockopt][SO_DR]{:?}", e),
+                },
+                Err(_) => warn!("[SOCKET][setsockopt][SO_DR]invalid DR {}", value),
+            }
+        }
+    }
+
+    /// timeouts of the simulator commands sent by the socket (uplinks, downlink fetches, SO_DR)
+    pub fn set_cmd_options(&mut self, opts: CmdOptions) {
+        self.cmd_options = opts;
+    }
+
+    /// sets the FPort of the uplinks sent through the socket, as Pycom s.bind(port)
+    /// the FPort must be in 1..=223
+    pub fn bind(&mut self, port: usize) -> Result<()> {
+        if !(1..=223).contains(&port) {
+            warn!("[SOCKET][bind]invalid FPort {}", port);
+            return Err(Error::CmdError(CmdErrorKind::InvalidArgument));
         }
+        self.port = Some(port as u8);
+        return Ok(());
     }
 
     pub fn setblocking(&mut self, block: bool) {
@@ -76,7 +109,29 @@ impl Socket {
 
 /// send a string as a Lora data payload
 /// the payload will be encoded as base64 by the simulator
+/// a blocking send waits for the TX event and fails with TxFailed on TX_FAILED_EVENT
     pub fn send(&self, data: &str) -> Result<()> {
+        return self.send_uplink(data, |lora, mtype, port| {
+            lora.send_frame(mtype, data, port, &self.cmd_options)
+        });
+    }
+
+/// send binary data as a Lora data payload
+    pub fn send_bytes(&self, data: &[u8]) -> Result<()> {
+        return self.send_uplink(&format!("{:02x?}", data), |lora, mtype, port| {
+            lora.send_bytes(mtype, data, port, &self.cmd_options)
+        });
+    }
+
+/// send a Cayenne LPP payload
+    pub fn send_lpp(&self, lpp: &CayenneLpp) -> Result<()> {
+        return self.send_bytes(&lpp.bytes());
+    }
+
+    fn send_uplink<F>(&self, data: &str, send: F) -> Result<()>
+    where
+        F: FnOnce(&mut LoraDev, &str, Option<u8>) -> Result<()>,
+    {
         let mut mtype: &str = UNCONFIRMED_DATA_UP;
         if self.confirmed {
             mtype = CONFIRMED_DATA_UP;
@@ -89,63 +144,79 @@ impl Socket {
             debug!("[SOCKET][send]MType= {} data= {}", mtype, data);
         }
 
-        LORA.lock().unwrap().send(mtype, data)?;
+        send(&mut LORA.lock().unwrap(), mtype, self.port)?;
 
         if self.blocking {
             if let Some(dur) = self.timeout {
-                let start_time = Instant::now();
+                let start_time = clock().now();
                 while ! LORA_EVENTS.lock().unwrap().intersects(LoraEvents::TX_PACKET_EVENT|LoraEvents::TX_FAILED_EVENT) {
-                    if start_time.elapsed().as_secs() > dur.try_into().unwrap() {
+                    if clock().now().saturating_duration_since(start_time).as_secs() > dur.try_into().unwrap() {
                         return Err(Error::CmdError(CmdErrorKind::DevCmdTimeout));
                     }
-                    thread::sleep(Duration::from_secs(1));
+                    clock().sleep(EVENT_POLL_PERIOD);
                 }
             } else {
                 while ! LORA_EVENTS.lock().unwrap().intersects(LoraEvents::TX_PACKET_EVENT|LoraEvents::TX_FAILED_EVENT) {
-                    thread::sleep(Duration::from_secs(1));
+                    clock().sleep(EVENT_POLL_PERIOD);
                 }
             }
-            if LORA_EVENTS.lock().unwrap().contains_and_remove_event(LoraEvents::TX_PACKET_EVENT) {
+            let sent = LORA_EVENTS.lock().unwrap().contains_and_remove_event(LoraEvents::TX_PACKET_EVENT);
+            let failed = LORA_EVENTS.lock().unwrap().contains_and_remove_event(LoraEvents::TX_FAILED_EVENT);
+            if sent {
                 debug!("[SOCKET][blocking send]OK");
-            }
-            if LORA_EVENTS.lock().unwrap().contains_and_remove_event(LoraEvents::TX_FAILED_EVENT){
+            } else if failed {
                 debug!("[SOCKET][blocking send]failed");
+                return Err(Error::CmdError(CmdErrorKind::TxFailed));
             }
         }
         Ok(())
     }
 
+    /// receives a downlink payload as text, see recv_downlink()
     pub fn recv(&self, buffersize: usize) -> Result<String> {
+        return self
+            .recv_downlink(buffersize)
+            .map(|dl| String::from_utf8_lossy(&dl.payload).into_owned());
+    }
+
+    /// receives a downlink payload as bytes, see recv_downlink()
+    pub fn recv_bytes(&self, buffersize: usize) -> Result<Vec<u8>> {
+        return self.recv_downlink(buffersize).map(|dl| dl.payload);
+    }
+
+    /// receives the oldest downlink frame, truncated to buffersize bytes
+    /// a blocking socket waits for the RX event (up to its timeout), otherwise NoDataDWrecv is returned at once
+    pub fn recv_downlink(&self, buffersize: usize) -> Result<Downlink> {
         if self.blocking {
             debug!("[SOCKET][blocking recv]Buffersize={}", buffersize);
             LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::RX_PACKET_EVENT);
         }else {
             debug!("[SOCKET][recv]Buffersize={}", buffersize);
         }
-        let mut recv_buf = LORA.lock().unwrap().recv(buffersize);
+        let mut recv_buf = LORA.lock().unwrap().recv_downlink_with_options(buffersize, &self.cmd_options);
         match recv_buf {
             Err(Error::CmdError(CmdErrorKind::NoDataDWrecv)) => {
                 if self.blocking {
                     if let Some(dur) = self.timeout {
 
-                        let start_time = Instant::now();
+                        let start_time = clock().now();
                         while ! LORA_EVENTS.lock().unwrap().contains(LoraEvents::RX_PACKET_EVENT) {
-                            if start_time.elapsed().as_secs() > dur.try_into().unwrap() {
+                            if clock().now().saturating_duration_since(start_time).as_secs() > dur.try_into().unwrap() {
                                 debug!("[SOCKET][blocking recv][error]timeout");
                                 return Err(Error::CmdError(CmdErrorKind::DevCmdTimeout));
                             }
-                            thread::sleep(Duration::from_secs(1));
+                            clock().sleep(EVENT_POLL_PERIOD);
                         }
 
                         LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::RX_PACKET_EVENT);
-                        return LORA.lock().unwrap().recv(buffersize);
+                        return LORA.lock().unwrap().recv_downlink_with_options(buffersize, &self.cmd_options);
 
                     } else { // due to Lorawan protocol, blocking without timeout will prevent sending new Lora frames and consequently receiving data
                         while ! LORA_EVENTS.lock().unwrap().contains(LoraEvents::RX_PACKET_EVENT) {
-                            thread::sleep(Duration::from_secs(1));
+                            clock().sleep(EVENT_POLL_PERIOD);
                         }
                         LORA_EVENTS.lock().unwrap().clear_events(LoraEvents::RX_PACKET_EVENT);
-                        return LORA.lock().unwrap().recv(buffersize);
+                        return LORA.lock().unwrap().recv_downlink_with_options(buffersize, &self.cmd_options);
                     }
                     debug!("[SOCKET][blocking recv] success");
                 }else{
@@ -154,8 +225,13 @@ impl Socket {
                 }
             }
             Err(e)=> return Err(e),
-            Ok(s) => return Ok(s),        
+            Ok(dl) => return Ok(dl),
         };
 
     }
+
+    /// receives a downlink and decodes its Cayenne LPP payload
+    pub fn recv_lpp(&self, buffersize: usize) -> Result<Vec<LppRecord>> {
+        return CayenneLpp::decode(&self.recv_bytes(buffersize)?);
+    }
 }
@@ -0,0 +1,82 @@
+#![allow(clippy::needless_return)]
+
+use lwnsim_api_rs::*;
+use serde_derive::*;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Reading {
+    temperature: f32,
+    label: String,
+}
+
+// the downlinks echo the uplinks, with their encoding
+fn echo_mock() -> MockSimulator {
+    let opts = MockOptions {
+        join_delay: Duration::from_millis(50),
+        tx_delay: Duration::from_millis(50),
+        rx_delay: Duration::from_millis(50),
+        downlink_ratio: 1.0,
+        ..MockOptions::default()
+    };
+    return MockSimulator::start("127.0.0.1:0", opts).unwrap();
+}
+
+fn join() {
+    LORA.lock()
+        .unwrap()
+        .join(OTAA, ("0".to_string(), "0".to_string()), Some(0), Some(0))
+        .unwrap();
+    let start = Instant::now();
+    while !LORA.lock().unwrap().has_joined() {
+        assert!(start.elapsed() < Duration::from_secs(10), "join timeout");
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// a single test: the client and its device are process wide singletons
+#[test]
+fn downlink_round_trip() {
+    let mock = echo_mock();
+    let session = LwnsimBuilder::new(&mock.url(), "359ac7cd01bc8aff").session().unwrap();
+    session.lora().lock().unwrap().activate().unwrap();
+    join();
+
+    let mut s = Socket::new(AF_LORA, SOCK_RAW);
+    s.setblocking(true);
+    s.settimeout(Some(10));
+
+    // binary payload, base64 encoded both ways
+    let lpp = CayenneLpp::new().temperature(1, 21.5);
+    assert_eq!(lpp.bytes(), vec![1, 103, 0, 215]);
+    s.send_lpp(&lpp).unwrap();
+    assert_eq!(s.recv_lpp(64).unwrap(), lpp.records());
+
+    // text payload
+    s.send("hello").unwrap();
+    assert_eq!(s.recv(64).unwrap(), "hello");
+
+    // binary payload truncated to the buffer size
+    s.send_bytes(&[0x00, 0xff, 0x80, 0x7f]).unwrap();
+    let dl = s.recv_downlink(3).unwrap();
+    assert_eq!(dl.payload, vec![0x00, 0xff, 0x80]);
+    assert!(dl.truncated);
+
+    // typed messages, decoded from the downlink bytes
+    let reading = Reading {
+        temperature: 21.5,
+        label: "été".to_string(),
+    };
+    let typed = TypedSocket::<_, Reading, Reading>::new(s, CborCodec);
+    typed.send(&reading).unwrap();
+    assert_eq!(typed.recv().unwrap(), reading);
+    let typed = TypedSocket::<_, Reading, Reading>::new(typed.into_inner(), JsonCodec);
+    typed.send(&reading).unwrap();
+    assert_eq!(typed.recv().unwrap(), reading);
+    let typed = TypedSocket::<_, Vec<LppRecord>, Vec<LppRecord>>::new(typed.into_inner(), LppCodec);
+    typed.send(&lpp.records()).unwrap();
+    assert_eq!(typed.recv().unwrap(), lpp.records());
+
+    session.close().unwrap();
+}
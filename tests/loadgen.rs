@@ -0,0 +1,56 @@
+#![allow(clippy::needless_return)]
+
+use lwnsim_api_rs::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+static DEV_EUI: &str = "359ac7cd01bc8aff";
+static STOP: AtomicBool = AtomicBool::new(false);
+
+// a single test: the client, its device and the clock are process wide
+#[test]
+fn downlink_latency_with_short_interval() {
+    // the device loop polls its events every 10 ms, a shorter real step runs faster than real time
+    set_clock(Arc::new(VirtualClock::new().real_step(Duration::from_millis(2))));
+    // every uplink is echoed 200 ms + 1000 ms later, after the next uplink is sent
+    let opts = MockOptions {
+        downlink_ratio: 1.0,
+        ..MockOptions::default()
+    };
+    let mock = MockSimulator::start("127.0.0.1:0", opts).unwrap();
+    LwnsimBuilder::new(&mock.url(), DEV_EUI).connect().unwrap();
+
+    let load = LoadProfile {
+        interval: Duration::from_secs(1),
+        payload_size: PayloadSize::Uniform(4, 16),
+        seed: Some(1),
+        ..LoadProfile::default()
+    };
+    let mut stats = LoadStats::new();
+    let mut downlinks = 0;
+    run_device(&DeviceProfile::new("sensor-1", DEV_EUI), &load, &STOP, &mut |ev| {
+        if matches!(ev, LoadEvent::Downlink { .. }) {
+            downlinks += 1;
+            if downlinks == 20 {
+                STOP.store(true, Ordering::Relaxed);
+            }
+        }
+        stats.record(&ev);
+    })
+    .unwrap();
+
+    let report = stats.report();
+    assert_eq!((report.join_attempts, report.joins), (1, 1), "{:?}", report);
+    assert!(report.uplinks >= 20, "{:?}", report);
+    assert_eq!(report.tx_failed, 0, "{:?}", report);
+    assert_eq!(report.errors, 0, "{:?}", report);
+    assert_eq!(report.downlinks, 20, "{:?}", report);
+    // measured from the uplink that caused the downlink: about 200 ms from the last uplink,
+    // 1000 ms more from the one before it
+    let latency = report.downlink_latency_ms.unwrap();
+    assert!(latency.p50 >= 1200 && latency.max < 2200, "{:?}", latency);
+
+    LWNSIM.lock().unwrap().disconnect();
+    mock.stop();
+}
@@ -0,0 +1,72 @@
+#![allow(clippy::needless_return)]
+
+use lwnsim_api_rs::*;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+static DEV_EUI: &str = "359ac7cd01bc8aff";
+
+fn wait_event(event: LoraEvents) {
+    let start = Instant::now();
+    while !LORA.lock().unwrap().get_events().contains(event) {
+        assert!(start.elapsed() < Duration::from_secs(10), "{:?} timeout", event);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+// join, uplink answered by a downlink and receive
+fn session(lora: &std::sync::Mutex<LoraDev>) {
+    lora.lock().unwrap().activate().unwrap();
+    lora.lock()
+        .unwrap()
+        .join(OTAA, ("0".to_string(), "0".to_string()), Some(0), None)
+        .unwrap();
+    wait_event(LoraEvents::JOIN_ACCEPT_EVENT);
+    assert!(lora.lock().unwrap().has_joined());
+    lora.lock().unwrap().send("UnConfirmedDataUp", "ping").unwrap();
+    wait_event(LoraEvents::RX_PACKET_EVENT);
+    assert_eq!(lora.lock().unwrap().recv(64).unwrap(), "pong");
+}
+
+// a single test: the client and its device are process wide singletons
+#[test]
+fn record_then_replay() {
+    let path = std::env::temp_dir().join(format!("lwnsim-replay-{}.jsonl", std::process::id()));
+    let opts = MockOptions {
+        join_delay: Duration::from_millis(50),
+        tx_delay: Duration::from_millis(50),
+        rx_delay: Duration::from_millis(50),
+        downlink_ratio: 1.0,
+        downlink_payload: Some("pong".to_string()),
+        ..MockOptions::default()
+    };
+    let mock = MockSimulator::start("127.0.0.1:0", opts).unwrap();
+    LwnsimBuilder::new(&mock.url(), DEV_EUI).record(&path).connect().unwrap();
+    session(&LORA);
+    LWNSIM.lock().unwrap().stop_recording();
+    LWNSIM.lock().unwrap().disconnect();
+
+    let recording = fs::read_to_string(&path).unwrap();
+    let records: Vec<Record> = recording.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert!(matches!(&records[0].entry, RecordEntry::Connect { dev_eui, .. } if dev_eui == DEV_EUI));
+    assert!(records
+        .iter()
+        .any(|r| matches!(&r.entry, RecordEntry::Response { cmd, .. } if cmd == "recv-downlink")));
+    assert!(records.iter().any(|r| matches!(&r.entry, RecordEntry::Event { .. })));
+
+    // same commands, the responses and events come from the recording
+    let lora = replay(&path, f64::INFINITY).unwrap();
+    session(lora);
+    let res = lora.lock().unwrap().send("UnConfirmedDataUp", "ping");
+    assert!(matches!(res, Err(LwnsimError::ReplayError(_))), "{:?}", res);
+
+    // a command that is not the recorded one
+    let lora = replay(&path, f64::INFINITY).unwrap();
+    lora.lock().unwrap().set_status(LoraDevStatus::Joined);
+    let res = lora.lock().unwrap().link_check();
+    assert!(matches!(res, Err(LwnsimError::ReplayError(_))), "{:?}", res);
+
+    LWNSIM.lock().unwrap().disconnect();
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,90 @@
+#![allow(clippy::needless_return)]
+
+use lwnsim_api_rs::*;
+use std::time::Duration;
+
+static SCENARIOS: &str = r#"
+[[scenario]]
+name = "echo"
+
+[[scenario.step]]
+action = "join"
+timeout = 5
+
+[[scenario.step]]
+action = "send"
+text = "ping"
+count = 2
+
+[[scenario.step]]
+action = "expect_event"
+event = "TX_PACKET_EVENT"
+timeout = 5
+
+[[scenario.step]]
+action = "expect_downlink"
+port = 10
+text = "ping"
+timeout = 5
+
+[[scenario.step]]
+action = "unlink"
+
+[[scenario]]
+name = "wrong downlink"
+
+[[scenario.step]]
+action = "join"
+timeout = 5
+
+[[scenario.step]]
+action = "send"
+text = "ping"
+
+[[scenario.step]]
+action = "expect_downlink"
+text = "pong"
+timeout = 5
+
+[[scenario.step]]
+action = "unlink"
+"#;
+
+// a single test: the client and its device are process wide singletons
+#[test]
+fn scenarios_against_the_mock() {
+    let opts = MockOptions {
+        join_delay: Duration::from_millis(50),
+        tx_delay: Duration::from_millis(50),
+        rx_delay: Duration::from_millis(50),
+        downlink_ratio: 1.0,
+        ..MockOptions::default()
+    };
+    let mock = MockSimulator::start("127.0.0.1:0", opts).unwrap();
+    let config = LwnsimConfig::from_toml_str(&format!(
+        "[simulator]\nurl = \"{}\"\n\n[[device]]\nname = \"sensor-1\"\ndev_eui = \"359ac7cd01bc8aff\"\n",
+        mock.url()
+    ))
+    .unwrap();
+
+    let report = ScenarioFile::from_toml_str(SCENARIOS).unwrap().run(&config);
+    assert!(!report.passed);
+    assert_eq!(report.failures(), 1);
+    let echo = &report.scenarios[0];
+    assert!(echo.passed, "{:?}", echo);
+    assert_eq!(echo.device, "sensor-1");
+    assert_eq!(echo.steps.len(), 5);
+
+    // the echoed payload is not the expected one, the next step is skipped
+    let wrong = &report.scenarios[1];
+    assert!(!wrong.passed);
+    let statuses: Vec<StepStatus> = wrong.steps.iter().map(|s| s.status).collect();
+    assert_eq!(statuses, [StepStatus::Passed, StepStatus::Passed, StepStatus::Failed, StepStatus::Skipped]);
+    let message = wrong.steps[2].message.as_deref().unwrap();
+    assert_eq!(message, "downlink payload \"ping\", expected \"pong\"");
+    // the failed scenario unlinked the device
+    assert_eq!(LORA.lock().unwrap().get_status(), LoraDevStatus::Inactive);
+
+    LWNSIM.lock().unwrap().disconnect();
+    mock.stop();
+}
@@ -0,0 +1,63 @@
+#![allow(clippy::needless_return)]
+
+use lwnsim_api_rs::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+static STOP: AtomicBool = AtomicBool::new(false);
+
+// a single test: the client, its device and the clock are process wide
+#[test]
+fn failed_uplinks_are_retried() {
+    set_clock(Arc::new(VirtualClock::new()));
+    let opts = MockOptions {
+        tx_failed_ratio: 1.0,
+        ..MockOptions::default()
+    };
+    let mock = MockSimulator::start("127.0.0.1:0", opts).unwrap();
+    let session = LwnsimBuilder::new(&mock.url(), "359ac7cd01bc8aff").session().unwrap();
+    {
+        let mut lora = session.lora().lock().unwrap();
+        lora.activate().unwrap();
+        lora.join(OTAA, ("0".to_string(), "0".to_string()), Some(0), Some(0)).unwrap();
+    }
+    while !LORA.lock().unwrap().has_joined() {
+        clock().sleep(Duration::from_millis(100));
+    }
+
+    // a blocking send reports the TX failure
+    let mut s = Socket::new(AF_LORA, SOCK_RAW);
+    s.setblocking(true);
+    s.settimeout(Some(10));
+    let res = s.send_bytes(&[1, 2, 3]);
+    assert!(matches!(res, Err(LwnsimError::CmdError(CmdErrorKind::TxFailed))), "{:?}", res);
+
+    let retry = RetryPolicy {
+        delay_min: Duration::from_secs(5),
+        delay_max: Duration::from_secs(20),
+        max_retries: 2,
+    };
+    // an invalid FPort is reported by run()
+    assert!(s.bind(0).is_err());
+    let mut invalid = UplinkScheduler::new(Socket::new(AF_LORA, SOCK_RAW), Duration::from_secs(60), Vec::new).port(0);
+    assert!(matches!(invalid.run(&STOP), Err(LwnsimError::CmdError(CmdErrorKind::InvalidArgument))));
+
+    let mut scheduler = UplinkScheduler::new(s, Duration::from_secs(60), || vec![1, 2, 3]).retry(retry);
+    // a few virtual hours of uplinks
+    let stopper = thread::spawn(|| {
+        thread::sleep(Duration::from_secs(3));
+        STOP.store(true, Ordering::Relaxed);
+    });
+    let stats = scheduler.run(&STOP).unwrap();
+    stopper.join().unwrap();
+
+    assert_eq!(stats.uplinks, 0);
+    assert!(stats.dropped >= 1, "{:?}", stats);
+    // each dropped payload was retried max_retries times
+    assert!(stats.retries >= 2 * stats.dropped, "{:?}", stats);
+    assert_eq!(stats.failed, stats.retries + stats.dropped, "{:?}", stats);
+
+    session.close().unwrap();
+}
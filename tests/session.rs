@@ -0,0 +1,23 @@
+#![allow(clippy::needless_return)]
+
+use lwnsim_api_rs::*;
+use std::time::Duration;
+
+// a single test: the client and its device are process wide singletons
+#[test]
+fn commands_after_close_fail_without_panic() {
+    let mock = MockSimulator::start("127.0.0.1:0", MockOptions::default()).unwrap();
+    let session = LwnsimBuilder::new(&mock.url(), "359ac7cd01bc8aff").session().unwrap();
+    session.lora().lock().unwrap().activate().unwrap();
+    assert_eq!(mock.linked_devices(), 1);
+    session.close().unwrap();
+    assert_eq!(mock.linked_devices(), 0);
+
+    let res = LORA.lock().unwrap().activate();
+    assert!(matches!(res, Err(LwnsimError::CmdError(CmdErrorKind::NotConnected))), "{:?}", res);
+
+    // a shutdown retry after a failed unlink
+    LORA.lock().unwrap().set_status(LoraDevStatus::Active);
+    let res = shutdown(Duration::from_secs(1));
+    assert!(matches!(res, Err(LwnsimError::CmdError(CmdErrorKind::NotConnected))), "{:?}", res);
+}